@@ -0,0 +1,460 @@
+#!/usr/bin/env python3
+"""Generates the FFV1 fixtures of pixel formats FFmpeg-made files lack.
+
+Frames are encoded by a minimal FFV1 version 3 encoder written from
+RFC 9043, independently of the decoder, using the range coder with the
+default state transition table. Every fixture is a Matroska file holding
+a single keyframe, along with a '.ref' file containing its samples as
+planar raw video, samples wider than 8 bits being little-endian 16-bit
+words, like FFmpeg's rawvideo output of the same pixel format. RGB planes
+are stored in FFmpeg's GBR order.
+
+Usage: python3 data/generate.py
+"""
+
+import os
+import struct
+
+CONTEXT_SIZE = 32
+
+# 3.8.1.4. State Transition Table
+DEFAULT_STATE_TRANSITION = [
+    0, 0, 0, 0, 0, 0, 0, 0, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    32, 33, 34, 35, 36, 37, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48,
+    49, 50, 51, 52, 53, 54, 55, 56, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65,
+    66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 75, 76, 77, 78, 79, 80, 81, 82,
+    83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 94, 95, 96, 97, 98, 99,
+    100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114,
+    114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 128,
+    129, 130, 131, 132, 133, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142,
+    143, 144, 145, 146, 147, 148, 149, 150, 151, 152, 152, 153, 154, 155, 156,
+    157, 158, 159, 160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171,
+    171, 172, 173, 174, 175, 176, 177, 178, 179, 180, 181, 182, 183, 184, 185,
+    186, 187, 188, 189, 190, 190, 191, 192, 194, 194, 195, 196, 197, 198, 199,
+    200, 201, 202, 202, 204, 205, 206, 207, 208, 209, 209, 210, 211, 212, 213,
+    215, 215, 216, 217, 218, 219, 220, 220, 222, 223, 224, 225, 226, 227, 227,
+    229, 229, 230, 231, 232, 234, 234, 235, 236, 237, 238, 239, 240, 241, 242,
+    243, 244, 245, 246, 247, 248, 248, 0, 0, 0, 0, 0, 0, 0,
+]
+
+# Run lengths of the quantization tables, see 4.9. Quantization Table Set.
+# Differences of 0, 1, 2-3 and 4+ get their own context, separately for
+# each of the first three context inputs.
+QUANT_RUNS = [[1, 1, 2, 124], [1, 1, 2, 124], [1, 1, 2, 124], [128], [128]]
+QUANT_TABLE_SETS = 2
+
+
+class RangeEncoder:
+    """3.8.1. Range Coding Mode, mirrored for encoding."""
+
+    def __init__(self):
+        self.out = bytearray()
+        self.low = 0
+        self.range = 0xFF00
+        self.outstanding_count = 0
+        self.outstanding_byte = -1
+        self.one_state = DEFAULT_STATE_TRANSITION
+        self.zero_state = [0] * 256
+        for i in range(1, 255):
+            self.zero_state[i] = 256 - self.one_state[256 - i]
+
+    def renorm(self):
+        while self.range < 0x100:
+            if self.outstanding_byte < 0:
+                self.outstanding_byte = self.low >> 8
+            elif self.low <= 0xFF00:
+                self.out.append(self.outstanding_byte)
+                self.out += b"\xff" * self.outstanding_count
+                self.outstanding_count = 0
+                self.outstanding_byte = self.low >> 8
+            elif self.low >= 0x10000:
+                self.out.append(self.outstanding_byte + 1)
+                self.out += b"\x00" * self.outstanding_count
+                self.outstanding_count = 0
+                self.outstanding_byte = (self.low >> 8) - 0x100
+            else:
+                self.outstanding_count += 1
+            self.low = (self.low & 0xFF) << 8
+            self.range <<= 8
+
+    def put(self, state, i, bit):
+        range1 = (self.range * state[i]) >> 8
+        if not bit:
+            self.range -= range1
+            state[i] = self.zero_state[state[i]]
+        else:
+            self.low += self.range - range1
+            self.range = range1
+            state[i] = self.one_state[state[i]]
+        self.renorm()
+
+    def symbol(self, state, v, signed=False):
+        """3.8.1.2. Range Non Binary Values"""
+        if v == 0:
+            self.put(state, 0, 1)
+            return
+        a = abs(v)
+        e = a.bit_length() - 1
+        self.put(state, 0, 0)
+        for i in range(e):
+            self.put(state, 1 + min(i, 9), 1)
+        self.put(state, 1 + min(e, 9), 0)
+        for i in range(e - 1, -1, -1):
+            self.put(state, 22 + min(i, 9), (a >> i) & 1)
+        if signed:
+            self.put(state, 11 + min(e, 10), v < 0)
+
+    def terminate(self):
+        """3.8.1.1.1. Termination"""
+        self.range = 0xFF
+        self.low += 0xFF
+        self.renorm()
+        self.range = 0xFF
+        self.renorm()
+        return bytes(self.out)
+
+
+def crc32_mpeg2(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04C11DB7 if crc & 0x80000000 else crc << 1)
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def seal(data):
+    """Appends the CRC parity making the CRC of the result zero."""
+    data = data + struct.pack(">I", crc32_mpeg2(data))
+    assert crc32_mpeg2(data) == 0
+    return data
+
+
+def quant_tables():
+    """Returns the 5 quantization tables and the context count."""
+    tables = []
+    scale = 1
+    for runs in QUANT_RUNS:
+        table = [0] * 256
+        k = 0
+        for v, run in enumerate(runs):
+            for _ in range(run):
+                table[k] = scale * v
+                k += 1
+        for k in range(1, 128):
+            table[256 - k] = -table[k]
+        table[128] = -table[127]
+        tables.append(table)
+        scale *= 2 * len(runs) - 1
+    return tables, (scale + 1) // 2
+
+
+class Format:
+    def __init__(self, name, bits, chroma_planes, h_shift, v_shift, alpha,
+                 width, height, slices, states_coded=False, rgb=False):
+        self.name = name
+        self.bits = bits
+        self.chroma_planes = chroma_planes
+        self.h_shift = h_shift
+        self.v_shift = v_shift
+        self.alpha = alpha
+        self.width = width
+        self.height = height
+        self.h_slices, self.v_slices = slices
+        self.states_coded = states_coded
+        self.rgb = rgb
+
+    def planes(self):
+        """Returns the dimensions and quantization table set index of the
+        planes, in coding order."""
+        full = (self.width, self.height)
+        chroma = (-(-self.width >> self.h_shift),
+                  -(-self.height >> self.v_shift))
+        planes = [(full, 0)]
+        if self.chroma_planes:
+            planes += [(chroma, 1), (chroma, 1)]
+        if self.alpha:
+            planes.append((full, 2))
+        return planes
+
+    def initial_states(self, context_count):
+        """Returns the initial states of the contexts of a quantization
+        table set, see 3.8.1.3. Initial Values for the Context Model."""
+        if not self.states_coded:
+            return [[128] * CONTEXT_SIZE for _ in range(context_count)]
+        return [[64 + (j * 7 + k * 13) % 128 for k in range(CONTEXT_SIZE)]
+                for j in range(context_count)]
+
+    def coded_bits(self):
+        """Returns the bit depth of the coded samples, one more for RGB
+        since JPEG2000-RCT chroma differences are signed."""
+        return self.bits + self.rgb
+
+    def plane_count(self):
+        """4.5.5. quant_table_set_index_count, for version 3."""
+        return 2 + self.alpha
+
+
+def config_record(fmt):
+    """4.2. Configuration Record"""
+    c = RangeEncoder()
+    state = [128] * CONTEXT_SIZE
+    c.symbol(state, 3)  # version
+    c.symbol(state, 4)  # micro_version
+    c.symbol(state, 1)  # coder_type
+    c.symbol(state, fmt.rgb)  # colorspace_type
+    c.symbol(state, fmt.bits)
+    c.put(state, 0, fmt.chroma_planes)
+    c.symbol(state, fmt.h_shift)
+    c.symbol(state, fmt.v_shift)
+    c.put(state, 0, fmt.alpha)
+    c.symbol(state, fmt.h_slices - 1)
+    c.symbol(state, fmt.v_slices - 1)
+    # Like FFmpeg, code two quantization table sets and only use the
+    # first one.
+    c.symbol(state, QUANT_TABLE_SETS)
+    for _ in range(QUANT_TABLE_SETS):
+        for runs in QUANT_RUNS:
+            quant_state = [128] * CONTEXT_SIZE
+            for run in runs:
+                c.symbol(quant_state, run - 1)
+    # Both sets get the same initial states, as they are used
+    # interchangeably.
+    _, context_count = quant_tables()
+    initial_states = fmt.initial_states(context_count)
+    for _ in range(QUANT_TABLE_SETS):
+        c.put(state, 0, fmt.states_coded)
+        if fmt.states_coded:
+            # 4.1.15. initial_state_delta
+            for j, context in enumerate(initial_states):
+                for k, v in enumerate(context):
+                    pred = initial_states[j - 1][k] if j else 128
+                    c.symbol(state, v - pred, signed=True)
+    c.symbol(state, 1)  # ec
+    c.symbol(state, 1)  # intra
+    return seal(c.terminate())
+
+
+def source_plane(width, height, bits, seed):
+    """Returns a deterministic plane mixing gradients, edges and noise."""
+    maximum = (1 << bits) - 1
+    rand = seed
+    rows = []
+    for y in range(height):
+        row = []
+        for x in range(width):
+            rand = (rand * 1103515245 + 12345) & 0x7FFFFFFF
+            v = (x * 7 + y * 3 + seed * 11) * maximum // 255
+            if (x // 8 + y // 8) % 3 == 0:
+                v += maximum // 3
+            if (x + y) % 5 == 0:
+                v += (rand >> 8) % (maximum // 16 + 1)
+            row.append(v % (maximum + 1))
+        rows.append(row)
+    return rows
+
+
+def encode_line(c, states, tables, plane, x0, y0, width, y, bits):
+    """4.7. Line, for line 'y' of a single plane of a slice."""
+
+    def sample(x, y):
+        # 3.1. Border
+        if y < 0:
+            return 0
+        if x < 0:
+            return sample(0, y - 1) if x == -1 else 0
+        if x >= width:
+            return sample(width - 1, y)
+        return plane[y0 + y][x0 + x]
+
+    mask = (1 << bits) - 1
+    for x in range(width):
+        # 3.2. Samples
+        l, t = sample(x - 1, y), sample(x, y - 1)
+        tl, tr = sample(x - 1, y - 1), sample(x + 1, y - 1)
+        big_l, big_t = sample(x - 2, y), sample(x, y - 2)
+        # 3.4. Context
+        context = (tables[0][(l - tl) & 255] + tables[1][(tl - t) & 255]
+                   + tables[2][(t - tr) & 255]
+                   + tables[3][(big_l - l) & 255]
+                   + tables[4][(big_t - t) & 255])
+        # 3.3. Median Predictor
+        pred = sorted([l, t, l + t - tl])[1]
+        diff = (plane[y0 + y][x0 + x] - pred) & mask
+        if diff > mask >> 1:
+            diff -= mask + 1
+        if context < 0:
+            context, diff = -context, -diff
+        c.symbol(states[context], diff, signed=True)
+
+
+def rct(g, b, r, bits):
+    """Returns the JPEG2000-RCT planes of GBR planes, see 3.7.2. RGB."""
+    y_plane, cb_plane, cr_plane = [], [], []
+    for g_row, b_row, r_row in zip(g, b, r):
+        cb = [bv - gv for gv, bv in zip(g_row, b_row)]
+        cr = [rv - gv for gv, rv in zip(g_row, r_row)]
+        y_plane.append([gv + ((u + v) >> 2)
+                        for gv, u, v in zip(g_row, cb, cr)])
+        cb_plane.append([u + (1 << bits) for u in cb])
+        cr_plane.append([v + (1 << bits) for v in cr])
+    return [y_plane, cb_plane, cr_plane]
+
+
+def encode_frame(fmt, planes):
+    """Encodes a keyframe of coded planes, see 4.3. Frame."""
+    tables, context_count = quant_tables()
+    layout = fmt.planes()
+    slices = []
+    for sy in range(fmt.v_slices):
+        for sx in range(fmt.h_slices):
+            c = RangeEncoder()
+            header_state = [128] * CONTEXT_SIZE
+            if not slices:
+                c.put([128], 0, 1)  # keyframe
+            # 4.5. Slice Header
+            c.symbol(header_state, sx)
+            c.symbol(header_state, sy)
+            c.symbol(header_state, 0)
+            c.symbol(header_state, 0)
+            for _ in range(fmt.plane_count()):
+                c.symbol(header_state, 0)
+            c.symbol(header_state, 3)  # picture_structure
+            c.symbol(header_state, 1)  # sar_num
+            c.symbol(header_state, 1)  # sar_den
+
+            # 4.6.3. slice_pixel_height, 4.6.4. slice_pixel_y,
+            # 4.7.2. slice_pixel_width, 4.7.3. slice_pixel_x
+            x0 = sx * fmt.width // fmt.h_slices
+            x1 = (sx + 1) * fmt.width // fmt.h_slices
+            y0 = sy * fmt.height // fmt.v_slices
+            y1 = (sy + 1) * fmt.height // fmt.v_slices
+
+            states = [fmt.initial_states(context_count) for _ in range(3)]
+            lines = []
+            for (plane, ((_, _), quant)) in zip(planes, layout):
+                if quant == 1:
+                    h, v = fmt.h_shift, fmt.v_shift
+                else:
+                    h, v = 0, 0
+                px0, py0 = x0 >> h, y0 >> v
+                pw, ph = -(-(x1 - x0) >> h), -(-(y1 - y0) >> v)
+                lines.append([(states[quant], plane, px0, py0, pw, y)
+                              for y in range(ph)])
+            # 4.6. Slice Content: RGB lines are interleaved, while YCbCr
+            # planes are coded one after the other.
+            if fmt.rgb:
+                lines = [line for group in zip(*lines) for line in group]
+            else:
+                lines = [line for plane in lines for line in plane]
+            for (plane_states, plane, px0, py0, pw, y) in lines:
+                encode_line(c, plane_states, tables, plane, px0, py0, pw, y,
+                            fmt.coded_bits())
+
+            # 4.8. Slice Footer
+            data = c.terminate()
+            data += struct.pack(">I", len(data))[1:] + b"\x00"
+            slices.append(seal(data))
+    return b"".join(slices)
+
+
+def element(id, payload):
+    """Returns an EBML element."""
+    size = len(payload)
+    length = 1
+    while size >= (1 << (7 * length)) - 1:
+        length += 1
+    size_bytes = ((1 << (7 * length)) | size).to_bytes(length, "big")
+    id_bytes = id.to_bytes((id.bit_length() + 7) // 8, "big")
+    return id_bytes + size_bytes + payload
+
+
+def uint(id, value):
+    return element(id, value.to_bytes(max(1, (value.bit_length() + 7) // 8),
+                                      "big"))
+
+
+def matroska(fmt, record, packet):
+    """Returns a Matroska file with a single FFV1 track and packet."""
+    # BITMAPINFOHEADER, as per the Matroska spec for VFW CodecPrivate
+    bitmap_info = struct.pack("<IiiHH4sIiiII", 40 + len(record), fmt.width,
+                              fmt.height, 1, 24, b"FFV1",
+                              fmt.width * fmt.height * 3, 0, 0, 0, 0)
+    header = element(0x1A45DFA3, b"".join([
+        uint(0x4286, 1), uint(0x42F7, 1), uint(0x42F2, 4), uint(0x42F3, 8),
+        element(0x4282, b"matroska"), uint(0x4287, 4), uint(0x4285, 2),
+    ]))
+    info = element(0x1549A966, b"".join([
+        uint(0x2AD7B1, 1000000),
+        element(0x4D80, b"generate.py"),
+        element(0x5741, b"generate.py"),
+    ]))
+    video = element(0xE0, uint(0xB0, fmt.width) + uint(0xBA, fmt.height))
+    track = element(0xAE, b"".join([
+        uint(0xD7, 1), uint(0x73C5, 1), uint(0x9C, 0),
+        element(0x22B59C, b"und"), uint(0x83, 1),
+        element(0x86, b"V_MS/VFW/FOURCC"), video,
+        element(0x63A2, bitmap_info + record),
+    ]))
+    tracks = element(0x1654AE6B, track)
+    # SimpleBlock of track 1 at timestamp 0, flagged as a keyframe
+    block = element(0xA3, b"\x81\x00\x00\x80" + packet)
+    cluster = element(0x1F43B675, uint(0xE7, 0) + block)
+    return header + element(0x18538067, info + tracks + cluster)
+
+
+def reference(fmt, planes):
+    """Returns the samples of the planes as planar raw video."""
+    out = bytearray()
+    for plane in planes:
+        for row in plane:
+            for v in row:
+                if fmt.bits == 8:
+                    out.append(v)
+                else:
+                    out += struct.pack("<H", v)
+    return bytes(out)
+
+
+# Slice boundaries are kept on multiples of the chroma subsampling, where
+# chroma slices do not overlap.
+FORMATS = [
+    Format("gray", 8, 0, 0, 0, 0, 64, 48, (2, 2)),
+    Format("gray16le", 16, 0, 0, 0, 0, 64, 48, (2, 2)),
+    Format("yuv422p", 8, 1, 1, 0, 0, 64, 48, (2, 2)),
+    Format("yuv411p", 8, 1, 2, 0, 0, 64, 48, (2, 2)),
+    Format("yuv440p", 8, 1, 0, 1, 0, 64, 48, (2, 2)),
+    # Odd dimensions
+    Format("yuv420p_33x17", 8, 1, 1, 1, 0, 33, 17, (2, 2)),
+    Format("yuv411p_35x19", 8, 1, 2, 0, 0, 35, 19, (1, 2)),
+    # Coded initial states
+    Format("yuv420p_states", 8, 1, 1, 1, 0, 64, 48, (2, 2),
+           states_coded=True),
+    # Extended bit depths
+    Format("yuv420p10le", 10, 1, 1, 1, 0, 64, 48, (2, 2)),
+    Format("yuv422p12le", 12, 1, 1, 0, 0, 64, 48, (2, 2)),
+    Format("yuv444p14le", 14, 1, 0, 0, 0, 64, 48, (2, 2)),
+    Format("gbrp12le", 12, 1, 0, 0, 0, 64, 48, (2, 2), rgb=True),
+]
+
+
+def main():
+    directory = os.path.dirname(os.path.abspath(__file__))
+    for fmt in FORMATS:
+        planes = [
+            source_plane(width, height, fmt.bits, seed)
+            for (seed, ((width, height), _)) in enumerate(fmt.planes())
+        ]
+        record = config_record(fmt)
+        coded = rct(*planes, fmt.bits) if fmt.rgb else planes
+        packet = encode_frame(fmt, coded)
+        base = os.path.join(directory, "ffv1_v3_" + fmt.name)
+        with open(base + ".mkv", "wb") as f:
+            f.write(matroska(fmt, record, packet))
+        with open(base + ".ref", "wb") as f:
+            f.write(reference(fmt, planes))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,169 @@
+//! Decoding benchmark of the ffv1 tracks of a matroska file.
+//!
+//! Every frame is decoded without being written anywhere, and the decoding
+//! speed, the time spent in every stage and how busy every slice thread
+//! was are printed for every track.
+
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+
+use ffv1::constants::ColorSpace;
+use ffv1::decoder::Decoder;
+
+use ffv1_util::demux::{next_packet, open_input};
+
+// Reads the number of slice threads of every decoder, if given.
+pub fn parse_threads(matches: &ArgMatches) -> io::Result<Option<usize>> {
+    let threads = match matches.value_of("threads") {
+        Some(threads) => threads,
+        None => return Ok(None),
+    };
+    threads
+        .parse::<usize>()
+        .ok()
+        .filter(|&threads| threads > 0)
+        .map(Some)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid number of threads: {}", threads),
+            )
+        })
+}
+
+// Describes how the frames of 'decoder' are decoded in parallel: only the
+// slices of a frame are, so streams with a single slice per frame are
+// decoded on a single thread.
+pub fn parallelism(decoder: &Decoder) -> String {
+    let record = decoder.config_record();
+    let slices = (record.num_h_slices_minus1 as usize + 1)
+        * (record.num_v_slices_minus1 as usize + 1);
+    let threads = decoder.threads().min(slices);
+    if threads > 1 {
+        format!(
+            "slice, {} threads for up to {} slices per frame",
+            threads, slices
+        )
+    } else if slices > 1 {
+        "none, a single thread".to_owned()
+    } else if record.colorspace_type == ColorSpace::Rgb
+        && decoder.threads() > 1
+    {
+        format!(
+            "JPEG2000-RCT rows, {} threads for a single slice per frame",
+            decoder.threads()
+        )
+    } else {
+        "none, a single slice per frame".to_owned()
+    }
+}
+
+// A benchmarked track.
+struct Track {
+    stream_index: isize,
+    decoder: Decoder,
+    frames: usize,
+    errors: usize,
+    bytes: usize,
+    elapsed: Duration,
+}
+
+// Decodes every frame of every ffv1 track of the input file and prints
+// how fast, and how busy the slice threads were.
+pub fn bench(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    let threads = parse_threads(matches)?;
+
+    let (mut demuxer, track_params) = open_input(input_path)?;
+    let mut tracks: Vec<_> = track_params
+        .iter()
+        .map(|params| {
+            let mut decoder = params.decoder();
+            if let Some(threads) = threads {
+                decoder.set_threads(threads);
+            }
+            decoder.set_stats_enabled(true);
+            Track {
+                stream_index: params.stream_index,
+                decoder,
+                frames: 0,
+                errors: 0,
+                bytes: 0,
+                elapsed: Duration::default(),
+            }
+        })
+        .collect();
+
+    // Packets are read before timing their decoding
+    loop {
+        let pkt = match next_packet(&mut demuxer) {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+        let track = match tracks
+            .iter_mut()
+            .find(|track| track.stream_index == pkt.stream_index)
+        {
+            Some(track) => track,
+            None => continue,
+        };
+        let start = Instant::now();
+        let result = track.decoder.decode_frame(&pkt.data);
+        track.elapsed += start.elapsed();
+        track.frames += 1;
+        track.bytes += pkt.data.len();
+        if let Err(err) = result {
+            eprintln!("track {}: {}", track.stream_index, err);
+            track.errors += 1;
+        }
+    }
+
+    for track in &tracks {
+        let seconds = track.elapsed.as_secs_f64().max(f64::EPSILON);
+        println!("track {}:", track.stream_index);
+        println!("  parallelism: {}", parallelism(&track.decoder));
+        println!(
+            "  {} frames, {} errors, {:.1} fps, {:.1} MB/s",
+            track.frames,
+            track.errors,
+            track.frames as f64 / seconds,
+            track.bytes as f64 / seconds / 1e6
+        );
+        if let Some(stats) = track.decoder.stats() {
+            println!(
+                "  footers {:?}, crc {:?}, slice headers {:?}, samples {:?}, \
+                 rct {:?}",
+                stats.footers,
+                stats.crc,
+                stats.slice_headers,
+                stats.samples,
+                stats.rct
+            );
+        }
+        let thread_stats = match track.decoder.thread_stats() {
+            Some(stats) if stats.frames > 0 => stats,
+            _ => continue,
+        };
+        println!(
+            "  {} frames on several threads, {:?} in slice threads",
+            thread_stats.frames, thread_stats.wall
+        );
+        for (thread, busy) in thread_stats.busy.iter().enumerate() {
+            println!(
+                "  thread {}: busy {:?}, {:.1}% utilization",
+                thread,
+                busy,
+                thread_stats.utilization(thread) * 100.0
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Coding statistics of the ffv1 tracks of a matroska file.
+//!
+//! Every frame is decoded while counting the contexts its symbols use in
+//! every quantization table set, the length of its runs and the magnitude
+//! of its sample differences, e.g. for encoder developers to design
+//! better quantization tables.
+
+use std::io;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use ffv1::decoder::Decoder;
+use ffv1::stats::CodingStats;
+
+use ffv1_util::demux::{next_packet, open_input};
+
+// An analyzed track.
+struct Track {
+    stream_index: isize,
+    decoder: Decoder,
+    frames: usize,
+    total: CodingStats,
+}
+
+// Describes the contexts, runs and magnitudes of 'stats'.
+fn summary(stats: &CodingStats) -> String {
+    let used: usize = (0..stats.contexts.len())
+        .map(|table_set| stats.used_contexts(table_set))
+        .sum();
+    let contexts: usize = stats.contexts.iter().map(Vec::len).sum();
+    let (runs, mean) = stats.run_lengths();
+    // Only the magnitudes of some difference are listed
+    let magnitudes: Vec<_> = stats
+        .magnitudes
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(bits, count)| format!("{}:{}", bits, count))
+        .collect();
+    format!(
+        "{} symbols, {}/{} contexts, {} runs of {:.1} samples, \
+         magnitudes {}",
+        stats.symbols,
+        used,
+        contexts,
+        runs,
+        mean,
+        magnitudes.join(" ")
+    )
+}
+
+// Decodes every frame of every ffv1 track of the input file and prints
+// the coding statistics of every frame, and of every slice if asked.
+pub fn coding(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    let slices = matches.is_present("slices");
+    let contexts = matches.is_present("contexts");
+
+    let (mut demuxer, track_params) = open_input(input_path)?;
+    let mut tracks: Vec<_> = track_params
+        .iter()
+        .map(|params| {
+            let mut decoder = params.decoder();
+            decoder.set_coding_stats_enabled(true);
+            Track {
+                stream_index: params.stream_index,
+                decoder,
+                frames: 0,
+                total: CodingStats::default(),
+            }
+        })
+        .collect();
+
+    loop {
+        let pkt = match next_packet(&mut demuxer) {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+        let track = match tracks
+            .iter_mut()
+            .find(|track| track.stream_index == pkt.stream_index)
+        {
+            Some(track) => track,
+            None => continue,
+        };
+        let frame = track.frames;
+        track.frames += 1;
+        if let Err(err) = track.decoder.decode_frame(&pkt.data) {
+            println!("track {} frame {}: {}", track.stream_index, frame, err);
+            continue;
+        }
+
+        let slice_stats = track.decoder.coding_stats().unwrap_or_default();
+        let mut frame_stats = CodingStats::default();
+        slice_stats.iter().for_each(|stats| frame_stats.add(stats));
+        println!(
+            "track {} frame {}: {}",
+            track.stream_index,
+            frame,
+            summary(&frame_stats)
+        );
+        if slices {
+            for (i, stats) in slice_stats.iter().enumerate() {
+                println!("  slice {}: {}", i, summary(stats));
+            }
+        }
+        track.total.add(&frame_stats);
+    }
+
+    for track in &tracks {
+        println!(
+            "track {}: {} frames, {}",
+            track.stream_index,
+            track.frames,
+            summary(&track.total)
+        );
+        if !contexts {
+            continue;
+        }
+        // Unused contexts are left out
+        for (table_set, counts) in track.total.contexts.iter().enumerate() {
+            for (context, &count) in counts.iter().enumerate() {
+                if count > 0 {
+                    println!(
+                        "  quant table set {} context {}: {}",
+                        table_set, context, count
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Conformance report of the ffv1 tracks of a matroska file.
+//!
+//! Every track is checked against the requirements of the specification,
+//! and a pass or fail line is printed for every rule, e.g. to enforce the
+//! ingest policy of an archive.
+
+use std::io;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use ffv1::conformance::ConformanceChecker;
+
+use ffv1_util::demux::{open_input, read_packet};
+
+// Prints the conformance report of every ffv1 track of the input file, and
+// exits with an error if any track does not conform.
+pub fn conformance(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    // SHOULD requirements fail the check too
+    let strict = matches.is_present("strict");
+
+    let (mut demuxer, tracks) = open_input(input_path)?;
+    let mut checkers: Vec<_> = tracks
+        .iter()
+        .map(|track| {
+            let checker = ConformanceChecker::new(
+                &track.extradata,
+                track.width,
+                track.height,
+            );
+            (track.stream_index, checker)
+        })
+        .collect();
+
+    while let Some(pkt) = read_packet(&mut demuxer) {
+        if let Some((_, checker)) = checkers
+            .iter_mut()
+            .find(|(index, _)| *index == pkt.stream_index)
+        {
+            checker.check_packet(&pkt.data);
+        }
+    }
+
+    let mut conforming = true;
+    for (index, checker) in checkers {
+        let report = checker.finish();
+        println!("track {}:\n{}", index, report);
+        conforming &= if strict {
+            report.is_clean()
+        } else {
+            report.conforms()
+        };
+    }
+
+    if !conforming {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,302 @@
+//! Frame by frame comparison of a ffv1 matroska file with another one,
+//! with a raw or Y4M reference, or with the checksums of a framemd5 file.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use av_format::demuxer::Context;
+
+use ffv1::constants::ColorSpace;
+use ffv1::convert::{to_packed, with_packed_planes};
+use ffv1::decoder::{Decoder, Frame};
+use ffv1::framemd5::FrameChecksums;
+use ffv1::y4m;
+
+use ffv1_util::demux::{open_first_track, read_packet, DecParams};
+
+use crate::image::{sample, write_gray_png};
+
+// Decoded ffv1 track.
+struct Decoded {
+    demuxer: Context,
+    params: DecParams,
+    decoder: Decoder,
+}
+
+impl Decoded {
+    // Opens the first ffv1 track of a matroska file.
+    fn open(path: &Path) -> io::Result<Self> {
+        let (demuxer, params) = open_first_track(path)?;
+        Ok(Self {
+            demuxer,
+            decoder: params.decoder(),
+            params,
+        })
+    }
+
+    // Decodes the next frame of the track.
+    fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        while let Some(pkt) = read_packet(&mut self.demuxer) {
+            if pkt.stream_index != self.params.stream_index {
+                continue;
+            }
+            return self.decoder.decode_frame(&pkt.data).map(Some).map_err(
+                |err| io::Error::new(io::ErrorKind::InvalidData, err),
+            );
+        }
+        Ok(None)
+    }
+}
+
+// Where the reference frames come from.
+enum Reference {
+    Decoded(Box<Decoded>),
+    // Raw frames, with planar planes in the order of a Frame, and samples
+    // wider than 8 bits in the given endianness.
+    Raw {
+        reader: BufReader<File>,
+        big_endian: bool,
+    },
+    // Y4M stream, whose frames have no alpha plane
+    Y4m(y4m::Reader<BufReader<File>>),
+}
+
+impl Reference {
+    // Reads the next reference frame, with the same properties as 'frame'.
+    fn next_frame(&mut self, frame: &Frame) -> io::Result<Option<Frame>> {
+        let (reader, big_endian) = match self {
+            Reference::Decoded(decoded) => return decoded.next_frame(),
+            Reference::Y4m(reader) => return reader.read_frame(),
+            Reference::Raw { reader, big_endian } => (reader, *big_endian),
+        };
+
+        let mut buf = Vec::new();
+        let mut buf16 = Vec::new();
+        let sample_size = if frame.bit_depth() == 8 { 1 } else { 2 };
+        for i in 0..frame.num_planes() {
+            let (width, height) = frame.plane_dimensions(i);
+            let mut plane = vec![0; width * height * sample_size];
+            match reader.read_exact(&mut plane) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(err) => return Err(err),
+            }
+            if frame.bit_depth() == 8 {
+                buf.push(plane);
+            } else {
+                buf16.push(
+                    plane
+                        .chunks_exact(2)
+                        .map(|b| {
+                            if big_endian {
+                                u16::from_be_bytes([b[0], b[1]])
+                            } else {
+                                u16::from_le_bytes([b[0], b[1]])
+                            }
+                        })
+                        .collect(),
+                );
+            }
+        }
+        Ok(Some(with_packed_planes(frame, buf, buf16)))
+    }
+}
+
+// First sample differing between two frames.
+struct Mismatch {
+    plane: usize,
+    x: u32,
+    y: u32,
+    decoded: i32,
+    reference: i32,
+}
+
+// Returns the plane coordinates of the luma pixel 'x', 'y'.
+fn plane_coordinates(
+    frame: &Frame,
+    plane: usize,
+    x: u32,
+    y: u32,
+) -> (u32, u32) {
+    if frame.color_space() == ColorSpace::YCbCr && (plane == 1 || plane == 2) {
+        (
+            x >> frame.chroma_subsample_h(),
+            y >> frame.chroma_subsample_v(),
+        )
+    } else {
+        (x, y)
+    }
+}
+
+// Compares two frames, returning the first differing sample in raster
+// order and a map of the largest difference of every pixel, in 8 bits.
+fn compare(decoded: &Frame, reference: &Frame) -> (Option<Mismatch>, Vec<u8>) {
+    let shift = decoded.bit_depth() - 8;
+    let mut first = None;
+    let mut map =
+        Vec::with_capacity((decoded.width() * decoded.height()) as usize);
+    for y in 0..decoded.height() {
+        for x in 0..decoded.width() {
+            let mut largest = 0;
+            for plane in 0..decoded.num_planes() {
+                let a = sample(decoded, plane, x, y);
+                let b = sample(reference, plane, x, y);
+                if a != b && first.is_none() {
+                    let (x, y) = plane_coordinates(decoded, plane, x, y);
+                    first = Some(Mismatch {
+                        plane,
+                        x,
+                        y,
+                        decoded: a,
+                        reference: b,
+                    });
+                }
+                largest = largest.max((a - b).abs());
+            }
+            // Any difference is visible, larger ones are brighter.
+            map.push(if largest == 0 {
+                0
+            } else {
+                (128 + ((largest >> shift).min(255) / 2)) as u8
+            });
+        }
+    }
+    (first, map)
+}
+
+// Writes a map of differences as a grayscale PNG image.
+fn write_map(frame: &Frame, map: Vec<u8>, path: &Path) -> io::Result<()> {
+    write_gray_png(frame.width(), frame.height(), &map, path)
+}
+
+// Returns whether two frames have the same dimensions and pixel format.
+fn same_format(a: &Frame, b: &Frame) -> bool {
+    (a.width(), a.height(), a.bit_depth(), a.color_space())
+        == (b.width(), b.height(), b.bit_depth(), b.color_space())
+        && (a.has_chroma(), a.has_alpha()) == (b.has_chroma(), b.has_alpha())
+        && (a.chroma_subsample_h(), a.chroma_subsample_v())
+            == (b.chroma_subsample_h(), b.chroma_subsample_v())
+}
+
+// Decodes the input file and checks every frame against the checksums of
+// the first video stream of a framemd5 or framecrc file written by FFmpeg,
+// reporting every mismatching frame.
+fn verify_checksums(mut input: Decoded, path: &Path) -> io::Result<()> {
+    let checksums = FrameChecksums::parse(BufReader::new(File::open(path)?))?;
+    let stream = checksums.video_streams.first().copied().unwrap_or(0);
+    let mut expected = checksums.stream(stream);
+
+    let mut index = 0;
+    let mut mismatches = 0;
+    while let Some(frame) = input.next_frame()? {
+        let entry = match expected.next() {
+            Some(entry) => entry,
+            None => {
+                println!("reference ends after {} frames", index);
+                std::process::exit(1);
+            }
+        };
+        let checksum = entry.checksum.of_frame(&frame);
+        if checksum != entry.checksum {
+            println!(
+                "frame {} (pts {}): {} instead of {}",
+                index, entry.pts, checksum, entry.checksum
+            );
+            mismatches += 1;
+        }
+        index += 1;
+    }
+
+    if expected.next().is_some() {
+        println!("input ends after {} frames", index);
+        std::process::exit(1);
+    }
+    if mismatches > 0 {
+        println!("{} of {} frames mismatching", mismatches, index);
+        std::process::exit(1);
+    }
+    println!("{} frames matching their checksums", index);
+    Ok(())
+}
+
+// Decodes the input file and the reference frame by frame, and reports the
+// first mismatching frame, plane and sample.
+pub fn diff(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    // Get the path to the reference, a matroska file, raw frames, a Y4M
+    // stream or a framemd5 file
+    let reference_path = matches.value_of("reference").map(Path::new).unwrap();
+
+    let mut input = Decoded::open(input_path)?;
+    if matches.is_present("framemd5") {
+        return verify_checksums(input, reference_path);
+    }
+    let mut reference = if matches.is_present("raw") {
+        Reference::Raw {
+            reader: BufReader::new(File::open(reference_path)?),
+            big_endian: matches.value_of("endianness") == Some("be"),
+        }
+    } else if matches.is_present("y4m") {
+        Reference::Y4m(y4m::Reader::new(BufReader::new(File::open(
+            reference_path,
+        )?))?)
+    } else {
+        Reference::Decoded(Box::new(Decoded::open(reference_path)?))
+    };
+
+    let mut index = 0;
+    while let Some(frame) = input.next_frame()? {
+        let expected = match reference.next_frame(&frame)? {
+            Some(expected) => expected,
+            None => {
+                println!("reference ends after {} frames", index);
+                std::process::exit(1);
+            }
+        };
+        if !same_format(&frame, &expected) {
+            println!("frame {}: the pixel formats differ", index);
+            std::process::exit(1);
+        }
+
+        let (frame, expected) = (to_packed(&frame), to_packed(&expected));
+        let (mismatch, map) = compare(&frame, &expected);
+        if let Some(mismatch) = mismatch {
+            println!(
+                "frame {} plane {} sample {}x{}: {} instead of {}",
+                index,
+                mismatch.plane,
+                mismatch.x,
+                mismatch.y,
+                mismatch.decoded,
+                mismatch.reference
+            );
+            if let Some(path) = matches.value_of("diff-image") {
+                write_map(&frame, map, Path::new(path))?;
+                eprintln!("Differences written to {}", path);
+            }
+            std::process::exit(1);
+        }
+        index += 1;
+    }
+
+    let reference_left = match reference {
+        Reference::Decoded(ref mut decoded) => decoded.next_frame()?.is_some(),
+        Reference::Raw { ref mut reader, .. } => {
+            !reader.fill_buf()?.is_empty()
+        }
+        Reference::Y4m(ref mut reader) => reader.read_frame()?.is_some(),
+    };
+    if reference_left {
+        println!("input ends after {} frames", index);
+        std::process::exit(1);
+    }
+    println!("{} identical frames", index);
+    Ok(())
+}
@@ -0,0 +1,235 @@
+//! This tool decodes every matroska file of a directory tree, e.g. for
+//! fixity audits of whole collections.
+//!
+//! Files are decoded by a bounded pool of workers, whose slice threads
+//! share a single limit, and a summary table with the frames, the CRC
+//! failures and the decoding speed of every file is printed once all of
+//! them are decoded.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{App, Arg};
+
+use ffv1::decoder::Decoder;
+use ffv1::error::Error;
+use ffv1::threads::{default_threads, ThreadLimit};
+
+use ffv1_util::demux::{next_packet, open_input};
+
+// The outcome of decoding a file.
+struct Report {
+    path: PathBuf,
+    frames: usize,
+    crc_failures: usize,
+    errors: usize,
+    bytes: usize,
+    elapsed: Duration,
+    // Why the file could not be read to its end
+    failure: Option<String>,
+}
+
+impl Report {
+    // Returns whether the file is intact.
+    fn is_ok(&self) -> bool {
+        self.crc_failures == 0 && self.errors == 0 && self.failure.is_none()
+    }
+}
+
+// Collects the matroska files of the directory tree 'dir'.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mkv"))
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Decodes every frame of the ffv1 tracks of a file.
+fn decode_file(path: PathBuf, limit: &Arc<ThreadLimit>) -> Report {
+    let mut report = Report {
+        path,
+        frames: 0,
+        crc_failures: 0,
+        errors: 0,
+        bytes: 0,
+        elapsed: Duration::default(),
+        failure: None,
+    };
+    let start = Instant::now();
+
+    let (mut demuxer, track_params) = match open_input(&report.path) {
+        Ok(input) => input,
+        Err(err) => {
+            report.failure = Some(err.to_string());
+            return report;
+        }
+    };
+    let mut tracks = Vec::new();
+    for params in track_params {
+        match Decoder::new(&params.extradata, params.width, params.height) {
+            Ok(mut decoder) => {
+                decoder.set_thread_limit(limit.clone());
+                tracks.push((params.stream_index, decoder));
+            }
+            Err(err) => {
+                report.failure =
+                    Some(format!("track {}: {}", params.stream_index, err));
+                return report;
+            }
+        }
+    }
+
+    loop {
+        let pkt = match next_packet(&mut demuxer) {
+            Ok(Some(pkt)) => pkt,
+            Ok(None) => break,
+            Err(err) => {
+                report.failure = Some(err);
+                break;
+            }
+        };
+        let decoder = match tracks
+            .iter_mut()
+            .find(|(stream_index, _)| *stream_index == pkt.stream_index)
+        {
+            Some((_, decoder)) => decoder,
+            None => continue,
+        };
+        report.frames += 1;
+        report.bytes += pkt.data.len();
+        match decoder.decode_frame(&pkt.data) {
+            Ok(_) => {}
+            Err(Error::CrcMismatch(_)) => report.crc_failures += 1,
+            Err(_) => report.errors += 1,
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    report
+}
+
+// Prints the summary table of the reports.
+fn print_summary(reports: &[Report]) {
+    let width = reports
+        .iter()
+        .map(|report| report.path.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("FILE".len());
+    println!(
+        "{:<width$}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  STATUS",
+        "FILE",
+        "FRAMES",
+        "CRC",
+        "ERRORS",
+        "FPS",
+        "MB/S",
+        width = width
+    );
+    for report in reports {
+        let seconds = report.elapsed.as_secs_f64().max(f64::EPSILON);
+        let status = match report.failure {
+            Some(ref failure) => failure.as_str(),
+            None if report.is_ok() => "ok",
+            None => "damaged",
+        };
+        println!(
+            "{:<width$}  {:>8}  {:>8}  {:>8}  {:>8.1}  {:>8.1}  {}",
+            report.path.display(),
+            report.frames,
+            report.crc_failures,
+            report.errors,
+            report.frames as f64 / seconds,
+            report.bytes as f64 / seconds / 1e6,
+            status,
+            width = width
+        );
+    }
+}
+
+fn main() -> io::Result<()> {
+    // Set up CLI configuration and input parameters
+    let matches = App::new("ffv1-batch")
+        .about("Decodes every ffv1 matroska file of a directory tree")
+        .arg(
+            Arg::new("input-dir")
+                .help("Directory searched for matroska files")
+                .short('i')
+                .long("input")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("jobs")
+                .help("Number of files decoded at the same time")
+                .short('j')
+                .long("jobs")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let input_dir = matches.value_of("input-dir").map(Path::new).unwrap();
+    let jobs = match matches.value_of("jobs") {
+        Some(jobs) => jobs
+            .parse::<usize>()
+            .ok()
+            .filter(|&jobs| jobs > 0)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid number of jobs: {}", jobs),
+                )
+            })?,
+        None => default_threads(),
+    };
+
+    let mut files = Vec::new();
+    collect_files(input_dir, &mut files)?;
+    files.sort();
+    eprintln!("{} matroska files found", files.len());
+
+    // Workers take the next file from the queue, and all the slice threads
+    // of their decoders share a single limit
+    let queue: Mutex<VecDeque<_>> =
+        Mutex::new(files.into_iter().enumerate().collect());
+    let reports = Mutex::new(Vec::new());
+    let limit = Arc::new(ThreadLimit::new(default_threads()));
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let next = queue.lock().unwrap().pop_front();
+                let (index, path) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                eprintln!("Decoding {}", path.display());
+                let report = decode_file(path, &limit);
+                reports.lock().unwrap().push((index, report));
+            });
+        }
+    });
+
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort_by_key(|(index, _)| *index);
+    let reports: Vec<_> =
+        reports.into_iter().map(|(_, report)| report).collect();
+    print_summary(&reports);
+
+    if reports.iter().any(|report| !report.is_ok()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
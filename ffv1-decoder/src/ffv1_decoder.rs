@@ -1,7 +1,8 @@
 //! This example decodes a ffv1 codec contained in a matroska file.
 
-// ffv1 crate
+// ffv1 crates
 extern crate ffv1;
+extern crate ffv1_util;
 
 // rust-av crates
 extern crate av_data as data;
@@ -16,90 +17,443 @@ extern crate clap;
 // Byteorder crate
 extern crate byteorder;
 
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::Path;
+// PNG encoder
+extern crate png;
 
-use data::params::MediaKind;
-use format::buffer::AccReader;
-use format::demuxer::{Context, Event};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use matroska::demuxer::MkvDemuxer;
+use ffv1::decoder::Decoder;
+use ffv1::raw::{Endianness, Layout, RawWriter};
+use ffv1::record::ConfigRecord;
+use ffv1::slice::is_keyframe;
+use ffv1::{nut, y4m};
 
-use ffv1::decoder::{Decoder, Frame};
+use ffv1_util::demux::{next_packet, open_input, prefetch_packets, DecParams};
+use ffv1_util::output::create_output;
+use ffv1_util::seek::KeyframeSeeker;
 
-use byteorder::{LittleEndian, WriteBytesExt};
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 
-// ffv1 decoder parameters
-#[derive(Default)]
-struct DecParams {
-    width: u32,
-    height: u32,
-    extradata: Vec<u8>,
+mod bench;
+mod coding;
+mod conformance;
+mod diff;
+mod image;
+mod repair;
+mod scan;
+mod sequence;
+mod usage;
+
+use bench::{bench, parallelism, parse_threads};
+use coding::coding;
+use conformance::conformance;
+use diff::diff;
+use image::write_png;
+use repair::repair;
+use scan::scan;
+use sequence::{write_dpx, write_tiff};
+use usage::usage;
+
+// Where the frames of a track are written.
+enum TrackOutput {
+    // Raw stream
+    Raw(RawWriter<BufWriter<Box<dyn Write>>>),
+    // Y4M stream
+    Y4m(y4m::Writer<BufWriter<Box<dyn Write>>>),
+    // NUT stream of raw video
+    Nut(nut::Writer<BufWriter<Box<dyn Write>>>),
+    // Directory of images, one per frame
+    Sequence { dir: PathBuf, index: usize },
+}
+
+// A decoded track.
+struct Track {
+    params: DecParams,
+    decoder: Decoder,
+    output: TrackOutput,
 }
 
-// Writes a u16 buffer as little endian on a file.
-#[inline(always)]
-fn write_u16_le<W: Write>(
-    file: &mut BufWriter<W>,
-    buf16: &[u16],
-) -> std::io::Result<()> {
-    for &v in buf16 {
-        file.write_u16::<LittleEndian>(v)?
+// Returns the output path of a track, when several tracks are decoded:
+// 'OUTPUT.trackN.EXT' for files and 'OUTPUT/trackN' for directories.
+fn track_path(
+    output_path: &Path,
+    stream_index: isize,
+    sequence: bool,
+) -> PathBuf {
+    let track = format!("track{}", stream_index);
+    if sequence {
+        return output_path.join(track);
     }
-    Ok(())
+    let mut name = output_path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{}", track));
+    if let Some(extension) = output_path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    output_path.with_file_name(name)
+}
+
+// Parses a framerate given as 'NUM:DEN' or 'NUM'.
+fn parse_framerate(framerate: &str) -> std::io::Result<(u32, u32)> {
+    let mut ratio = framerate.splitn(2, ':');
+    let num = ratio.next().and_then(|num| num.trim().parse().ok());
+    let den = match ratio.next() {
+        Some(den) => den.trim().parse().ok(),
+        None => Some(1),
+    };
+    match (num, den) {
+        (Some(num), Some(den)) if num > 0 && den > 0 => Ok((num, den)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid framerate: {}", framerate),
+        )),
+    }
+}
+
+// Creates the raw, Y4M or NUT output of a track.
+fn stream_output(
+    matches: &ArgMatches,
+    output_path: &Path,
+    format: &str,
+) -> std::io::Result<TrackOutput> {
+    // Open the output file, "-" means stdout
+    let output = create_output(output_path)?;
+
+    // Y4M and NUT always store little-endian planar samples
+    let (num, den) = parse_framerate(matches.value_of("framerate").unwrap())?;
+    if format == "y4m" {
+        let mut writer = y4m::Writer::new(BufWriter::new(output));
+        writer.set_framerate(num, den);
+        return Ok(TrackOutput::Y4m(writer));
+    }
+    if format == "nut" {
+        let mut writer = nut::Writer::new(BufWriter::new(output));
+        writer.set_framerate(num, den);
+        return Ok(TrackOutput::Nut(writer));
+    }
+
+    let mut writer = RawWriter::new(BufWriter::new(output));
+    if matches.value_of("endianness") == Some("be") {
+        writer.set_endianness(Endianness::Big);
+    }
+    if matches.value_of("layout") == Some("interleaved") {
+        writer.set_layout(Layout::Interleaved);
+    }
+    if let Some(order) = matches.value_of("plane-order") {
+        let order = order
+            .split(',')
+            .map(|plane| plane.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid plane order: {}", err),
+                )
+            })?;
+        writer.set_plane_order(&order);
+    }
+    Ok(TrackOutput::Raw(writer))
 }
 
-// Decodes a single ffv1 frame
-fn decode_single_frame(
-    demuxer: &mut Context,
-    decoder: &mut Decoder,
-    extradata: &[u8],
-) -> Result<Frame, String> {
-    // The demuxer reads which event has occurred
-    match demuxer.read_event() {
-        // If a new packet has been found, decode it
-        Ok(event) => match event {
-            Event::NewPacket(pkt) => {
-                println!(
-                    "extradata = {} packet = {} track = {}\n",
-                    extradata.len(),
-                    pkt.data.len(),
-                    pkt.pos.unwrap_or(0)
-                );
-                // Reads a ffv1 frame
-                let frame = decoder.decode_frame(&pkt.data).unwrap();
-                println!(
-                    "Frame decoded at {}x{}\n",
-                    frame.width, frame.height
-                );
-                Ok(frame)
+// Decodes every frame of the first ffv1 track of the input file, or of all
+// of them, into raw, Y4M or NUT outputs or directories of images.
+fn decode(matches: &ArgMatches) -> std::io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    // Get the path to the output file
+    let output_path = matches.value_of("output-path").map(Path::new).unwrap();
+
+    // Get the output format
+    let format = matches.value_of("format").unwrap();
+    // Image sequences are written one file per frame
+    let sequence = format == "dpx" || format == "tiff";
+
+    let (_, mut track_params) = open_input(input_path)?;
+    if !matches.is_present("all-tracks") {
+        track_params.truncate(1);
+    }
+    let multiple = track_params.len() > 1;
+    // Damaged frames are recorded and skipped instead of aborting
+    let damage_map = matches.value_of("damage-map").map(Path::new);
+    let threads = parse_threads(matches)?;
+    if multiple && output_path == Path::new("-") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "several tracks cannot be written to stdout",
+        ));
+    }
+
+    // Create a new ffv1 decoder and output for every track
+    let mut tracks = Vec::new();
+    for params in track_params {
+        let path = if multiple {
+            track_path(output_path, params.stream_index, sequence)
+        } else {
+            output_path.to_owned()
+        };
+        let output = if sequence {
+            std::fs::create_dir_all(&path)?;
+            TrackOutput::Sequence {
+                dir: path,
+                index: 0,
             }
-            // When the EOF is reached, the decoding process is stopped
-            Event::Eof => {
-                println!("EOF reached.");
-                Err("EOF reached".to_owned())
+        } else {
+            stream_output(matches, &path, format)?
+        };
+        let mut decoder = params.decoder();
+        if let Some(threads) = threads {
+            decoder.set_threads(threads);
+        }
+        eprintln!(
+            "Track {} parallelism: {}",
+            params.stream_index,
+            parallelism(&decoder)
+        );
+        decoder.set_damage_map_enabled(damage_map.is_some());
+        decoder.set_pixel_stats_enabled(matches.is_present("pixel-stats"));
+        tracks.push(Track {
+            decoder,
+            params,
+            output,
+        });
+    }
+
+    // Iterate over the packets, read ahead on another thread, decoding
+    // them with the decoder of their track
+    for pkt in prefetch_packets(input_path) {
+        let pkt = pkt?;
+        let track = match tracks
+            .iter_mut()
+            .find(|track| track.params.stream_index == pkt.stream_index)
+        {
+            Some(track) => track,
+            None => continue,
+        };
+
+        // Reads a ffv1 frame
+        let frame = match track.decoder.decode_frame(&pkt.data) {
+            Err(err) if damage_map.is_some() => {
+                eprintln!("Frame skipped: {}\n", err);
+                continue;
             }
-            _ => {
-                // If an unsupported event occurs,
-                // the decoding process is stopped
-                println!("Unsupported event {:?}", event);
-                Err("Unsupported event".to_owned())
+            result => result.unwrap(),
+        };
+        eprintln!("Frame decoded at {}x{}\n", frame.width(), frame.height());
+        for diagnostic in frame.diagnostics() {
+            eprintln!("Warning: {}", diagnostic);
+        }
+        let pixel_stats = track.decoder.pixel_stats().unwrap_or_default();
+        for (plane, stats) in pixel_stats.iter().enumerate() {
+            let (black, white) = stats.clipped();
+            eprintln!(
+                "Plane {}: min {} max {} mean {:.2}, {} samples at 0, {} at \
+                 the maximum, {} unused low bits",
+                plane,
+                stats.min,
+                stats.max,
+                stats.mean(),
+                black,
+                white,
+                stats.unused_low_bits()
+            );
+        }
+
+        match track.output {
+            TrackOutput::Raw(ref mut writer) => writer.write_frame(&frame)?,
+            TrackOutput::Y4m(ref mut writer) => writer.write_frame(&frame)?,
+            TrackOutput::Nut(ref mut writer) => writer.write_frame(&frame)?,
+            TrackOutput::Sequence {
+                ref dir,
+                ref mut index,
+            } => {
+                let path = dir.join(format!("{:08}.{}", index, format));
+                if format == "dpx" {
+                    write_dpx(&frame, &path)?;
+                } else {
+                    write_tiff(&frame, &path)?;
+                }
+                *index += 1;
             }
-        },
-        Err(err) => {
-            // If there are no more events, the decoding process is stopped
-            println!("No more events {:?}", err);
-            Err("No more events".to_owned())
         }
     }
+
+    for track in &mut tracks {
+        match track.output {
+            TrackOutput::Raw(ref mut writer) => writer.flush()?,
+            TrackOutput::Y4m(ref mut writer) => writer.flush()?,
+            TrackOutput::Nut(ref mut writer) => writer.flush()?,
+            TrackOutput::Sequence { .. } => {}
+        }
+    }
+
+    // Write the damaged regions of every track as a JSON array
+    if let Some(path) = damage_map {
+        let maps: Vec<_> = tracks
+            .iter()
+            .filter_map(|track| {
+                let map = track.decoder.damage_map()?;
+                Some(format!(
+                    "{{\"track\":{},\"damage_map\":{}}}",
+                    track.params.stream_index,
+                    map.to_json()
+                ))
+            })
+            .collect();
+        std::fs::write(path, format!("[{}]\n", maps.join(",")))?;
+        eprintln!("Damage map written to {}", path.display());
+    }
+    eprintln!("Done.");
+    Ok(())
+}
+
+// Demuxes the packets of the first ffv1 track of the input file from its
+// start up to 'frame_index', returning those from the last keyframe.
+fn demux_keyframe_packets(
+    input_path: &Path,
+    frame_index: usize,
+) -> std::io::Result<Vec<Vec<u8>>> {
+    let (mut demuxer, tracks) = open_input(input_path)?;
+
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+    let mut index = 0;
+    while index <= frame_index {
+        let pkt = next_packet(&mut demuxer)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("The input file contains only {} frames", index),
+                )
+            })?;
+        if pkt.stream_index != tracks[0].stream_index {
+            continue;
+        }
+        if is_keyframe(&pkt.data) {
+            packets.clear();
+        }
+        packets.push(pkt.data);
+        index += 1;
+    }
+    Ok(packets)
+}
+
+// Decodes a single frame of the input file and writes it as an image.
+fn extract(matches: &ArgMatches) -> std::io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    // Get the path to the output image
+    let output_path = matches.value_of("out").map(Path::new).unwrap();
+
+    // Get the index of the frame to extract
+    let frame_index: usize =
+        matches.value_of("frame").unwrap().parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The frame index must be a non-negative integer",
+            )
+        })?;
+
+    let (_, tracks) = open_input(input_path)?;
+
+    // Create a new ffv1 decoder for the first track
+    let mut ffv1_decoder = tracks[0].decoder();
+
+    // Only the packets starting from the last keyframe before the requested
+    // frame are needed, since keyframes reset all the decoder states. The
+    // Cues locate that keyframe, otherwise the file is demuxed from its
+    // start.
+    let packets = match KeyframeSeeker::open(input_path)? {
+        Some(mut seeker) => seeker.keyframe_packets(frame_index)?,
+        None => demux_keyframe_packets(input_path, frame_index)?,
+    };
+
+    let mut frame = None;
+    for packet in &packets {
+        frame = Some(ffv1_decoder.decode_frame(packet).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cannot decode frame {}: {}", frame_index, err),
+            )
+        })?);
+    }
+
+    // At least the requested packet has been read
+    write_png(&frame.unwrap(), output_path)?;
+    eprintln!("Frame {} written to {}", frame_index, output_path.display());
+    Ok(())
+}
+
+// Prints the configuration record of every ffv1 track of the input file
+// without decoding any frame.
+fn info(matches: &ArgMatches) -> std::io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    let (_, tracks) = open_input(input_path)?;
+
+    for decoder_params in &tracks {
+        if tracks.len() > 1 {
+            println!("track {}:", decoder_params.stream_index);
+        }
+        let record = ConfigRecord::parse_config_record(
+            &decoder_params.extradata,
+            decoder_params.width,
+            decoder_params.height,
+        )
+        .map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid configuration record: {}", err),
+            )
+        })?;
+
+        let coder = match record.coder_type {
+            0 => "Golomb-Rice",
+            1 => "range coder (default state transition table)",
+            _ => "range coder (custom state transition table)",
+        };
+        println!("{}", record);
+        println!("version: {}.{}", record.version, record.micro_version);
+        println!("coder: {}", coder);
+        println!("dimensions: {}x{}", record.width, record.height);
+        println!("bit depth: {}", record.bits_per_raw_sample);
+        println!("colorspace: {}", record.colorspace_type);
+        println!(
+            "ffmpeg pixel format: {}",
+            record.ffmpeg_pix_fmt().as_deref().unwrap_or("none")
+        );
+        println!("pixel format: {}", record.pixel_format());
+        println!("planes: {}", record.num_planes());
+        println!("chroma planes: {}", record.chroma_planes);
+        // Grayscale streams may code any subsampling, which is unused
+        if record.chroma_planes {
+            println!(
+                "chroma subsampling: log2 h {} / log2 v {}",
+                record.log2_h_chroma_subsample, record.log2_v_chroma_subsample
+            );
+        }
+        println!("extra plane: {}", record.extra_plane);
+        println!(
+            "slices: {}x{}",
+            record.num_h_slices_minus1 as u32 + 1,
+            record.num_v_slices_minus1 as u32 + 1
+        );
+        println!("quant table sets: {}", record.quant_table_set_count);
+        println!("initial states coded: {:?}", record.states_coded);
+        println!("ec: {}", record.ec);
+        println!("intra: {}", record.intra);
+    }
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
     // Set up CLI configuration and input parameters
     let matches = App::new("ffv1-decode")
         .about("Decodes a ffv1 codec contained in a matroska file")
+        .subcommand_negates_reqs(true)
         .arg(
             Arg::new("input-path")
                 .help("Matroska file to analyze")
@@ -110,82 +464,340 @@ fn main() -> std::io::Result<()> {
         )
         .arg(
             Arg::new("output-path")
-                .help("Output file")
+                .help(
+                    "Output file, - for stdout, or a directory for image \
+                     sequences",
+                )
                 .short('o')
                 .long("output")
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::new("format")
+                .help("Output format")
+                .short('f')
+                .long("format")
+                .takes_value(true)
+                .possible_values(["raw", "y4m", "nut", "dpx", "tiff"])
+                .default_value("raw"),
+        )
+        .arg(
+            Arg::new("all-tracks")
+                .help(
+                    "Decodes every ffv1 track into its own output, named \
+                     OUTPUT.trackN.EXT, or OUTPUT/trackN for image sequences",
+                )
+                .long("all-tracks"),
+        )
+        .arg(
+            Arg::new("endianness")
+                .help("Byte order of raw samples wider than 8 bits")
+                .long("endianness")
+                .takes_value(true)
+                .possible_values(["le", "be"])
+                .default_value("le"),
+        )
+        .arg(
+            Arg::new("layout")
+                .help("Arrangement of the raw planes")
+                .long("layout")
+                .takes_value(true)
+                .possible_values(["planar", "interleaved"])
+                .default_value("planar"),
+        )
+        .arg(
+            Arg::new("plane-order")
+                .help(
+                    "Comma-separated raw planes to write, e.g. 2,0,1 for \
+                     R, G, B",
+                )
+                .long("plane-order")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("damage-map")
+                .help(
+                    "Skips the frames which cannot be decoded, and writes the \
+                     damaged slices of every frame to a JSON file",
+                )
+                .long("damage-map")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("pixel-stats")
+                .help(
+                    "Prints the minimum, maximum and mean sample of every \
+                     plane of every frame, and how many samples are clipped",
+                )
+                .long("pixel-stats"),
+        )
+        .arg(
+            Arg::new("framerate")
+                .help("Framerate written in the Y4M or NUT header")
+                .long("framerate")
+                .takes_value(true)
+                .default_value("25:1"),
+        )
+        .arg(
+            Arg::new("threads")
+                .help(
+                    "Number of threads decoding the slices of a frame, the \
+                     available parallelism by default",
+                )
+                .long("threads")
+                .takes_value(true),
+        )
+        .subcommand(
+            App::new("extract")
+                .about("Extracts a single frame into a PNG image")
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to analyze")
+                        .short('i')
+                        .long("input")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("frame")
+                        .help("Index of the frame to extract, starting at 0")
+                        .long("frame")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out")
+                        .help("Output PNG file")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("repair")
+                .about(
+                    "Copies a matroska file, validating every ffv1 slice and \
+                     optionally rewriting the slice footers",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to repair")
+                        .short('i')
+                        .long("input")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("out")
+                        .help("Repaired matroska file")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("clear-error-status")
+                        .help("Sets the error_status of every slice to 0")
+                        .long("clear-error-status"),
+                )
+                .arg(
+                    Arg::new("recompute-crc")
+                        .help("Recomputes the CRC of every slice")
+                        .long("recompute-crc"),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about(
+                    "Decodes two matroska files, or a matroska file and a raw \
+                     or Y4M reference, and reports the first mismatching \
+                     sample, or checks the frames against a framemd5 file",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to check")
+                        .short('i')
+                        .long("input")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("reference")
+                        .help(
+                            "Matroska file, raw video, Y4M stream or \
+                             framemd5 file to compare against",
+                        )
+                        .long("reference")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("raw")
+                        .help(
+                            "Reads the reference as raw planar video, in the \
+                             same plane order as the decoded frames",
+                        )
+                        .long("raw"),
+                )
+                .arg(
+                    Arg::new("y4m")
+                        .help(
+                            "Reads the reference as a Y4M stream, whose \
+                             frames have no alpha plane",
+                        )
+                        .long("y4m")
+                        .conflicts_with("raw"),
+                )
+                .arg(
+                    Arg::new("framemd5")
+                        .help(
+                            "Reads the reference as the framemd5 or framecrc \
+                             checksums written by FFmpeg, e.g. with -f \
+                             framemd5, and reports every mismatching frame",
+                        )
+                        .long("framemd5")
+                        .conflicts_with_all(&["raw", "y4m", "diff-image"]),
+                )
+                .arg(
+                    Arg::new("endianness")
+                        .help("Byte order of samples of the raw reference")
+                        .long("endianness")
+                        .takes_value(true)
+                        .possible_values(["le", "be"])
+                        .default_value("le")
+                        .requires("raw"),
+                )
+                .arg(
+                    Arg::new("diff-image")
+                        .help(
+                            "PNG file receiving a map of the differing \
+                             samples of the first mismatching frame",
+                        )
+                        .long("diff-image")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("info")
+                .about("Prints the configuration record of a matroska file")
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to analyze")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("scan")
+                .about(
+                    "Checks the slice headers and footers of every packet \
+                     of a matroska file without decoding it",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to scan")
+                        .index(1)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            App::new("bench")
+                .about(
+                    "Decodes every ffv1 track of a matroska file without \
+                     writing it, and reports the decoding speed and the \
+                     utilization of every slice thread",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to decode")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .help(
+                            "Number of threads decoding the slices of a \
+                             frame, the available parallelism by default",
+                        )
+                        .long("threads")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("coding")
+                .about(
+                    "Decodes every ffv1 track of a matroska file and reports \
+                     the contexts, the runs and the magnitudes of the \
+                     symbols of every frame",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to analyze")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("slices")
+                        .help("Reports every slice of a frame too")
+                        .long("slices"),
+                )
+                .arg(
+                    Arg::new("contexts")
+                        .help(
+                            "Prints how many symbols every context of a \
+                             track decoded",
+                        )
+                        .long("contexts"),
+                ),
+        )
+        .subcommand(
+            App::new("conformance")
+                .about(
+                    "Checks every ffv1 track of a matroska file against the \
+                     requirements of the specification",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to check")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("strict")
+                        .help("Fails on SHOULD requirements too")
+                        .long("strict"),
+                ),
+        )
+        .subcommand(
+            App::new("usage")
+                .about(
+                    "Reports the features of the specification used by \
+                     every ffv1 track of a matroska file",
+                )
+                .arg(
+                    Arg::new("input-path")
+                        .help("Matroska file to profile")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("json")
+                        .help("Prints one JSON object per track")
+                        .long("json"),
+                ),
+        )
         .get_matches();
 
-    // Get the path to the matroska file
-    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
-
-    // Get the path to the output file
-    let output_path = matches.value_of("output-path").map(Path::new).unwrap();
-
-    // Open the matroska file
-    let reader = File::open(input_path).unwrap();
-
-    // Create a buffer of size 4096MB to contain matroska data
-    let ar = AccReader::with_capacity(4 * 1024, reader);
-
-    // Set the type of demuxer, in this case, a matroska demuxer
-    let mut demuxer = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
-
-    // Read matroska headers
-    demuxer
-        .read_headers()
-        .expect("Cannot parse the format headers");
-
-    // Save decoder params for ffv1 decoder
-    let mut decoder_params: DecParams = Default::default();
-
-    // Iterate over the streams contained in a matroska file
-    for stream in &demuxer.info.streams {
-        // Considers only video streams and analyze the type of codec inside.
-        if let Some(MediaKind::Video(info)) = &stream.params.kind {
-            let extradata =
-                stream.params.extradata.as_ref().unwrap_or_else(|| {
-                    eprintln!("No extradata detected. Aborting");
-                    std::process::exit(1);
-                });
-            if String::from_utf8_lossy(extradata).contains("FFV1") {
-                decoder_params.width = info.width as u32;
-                decoder_params.height = info.height as u32;
-                // As per Matroska spec for VFW CodecPrivate
-                decoder_params.extradata = extradata[40..].to_owned();
-            }
-        }
+    match matches.subcommand() {
+        Some(("bench", sub_matches)) => bench(sub_matches),
+        Some(("coding", sub_matches)) => coding(sub_matches),
+        Some(("conformance", sub_matches)) => conformance(sub_matches),
+        Some(("diff", sub_matches)) => diff(sub_matches),
+        Some(("extract", sub_matches)) => extract(sub_matches),
+        Some(("info", sub_matches)) => info(sub_matches),
+        Some(("repair", sub_matches)) => repair(sub_matches),
+        Some(("scan", sub_matches)) => scan(sub_matches),
+        Some(("usage", sub_matches)) => usage(sub_matches),
+        _ => decode(&matches),
     }
-
-    // Create a new ffv1 decoder
-    let mut ffv1_decoder = Decoder::new(
-        &decoder_params.extradata,
-        decoder_params.width,
-        decoder_params.height,
-    )
-    .unwrap();
-
-    // Open raw file
-    let mut output_file = BufWriter::new(File::create(output_path).unwrap());
-
-    // Iterate over the decoded frames
-    while let Ok(frame) = decode_single_frame(
-        &mut demuxer,
-        &mut ffv1_decoder,
-        &decoder_params.extradata,
-    ) {
-        if frame.bit_depth == 8 {
-            output_file.write_all(&frame.buf[0])?;
-            output_file.write_all(&frame.buf[1])?;
-            output_file.write_all(&frame.buf[2])?;
-        } else {
-            write_u16_le(&mut output_file, &frame.buf16[0])?;
-            write_u16_le(&mut output_file, &frame.buf16[1])?;
-            write_u16_le(&mut output_file, &frame.buf16[2])?;
-        }
-    }
-    println!("Done.");
-    Ok(())
 }
@@ -0,0 +1,189 @@
+//! This tool repairs the damaged slices of the ffv1 tracks of a matroska
+//! file.
+//!
+//! Slices whose CRC fails are either marked as damaged, through their
+//! error_status, or replaced with the same slice of the previous frame.
+//! Their footers are rewritten to be consistent again. Packets keep their
+//! size, so the file is patched in place and the matroska structure stays
+//! valid.
+
+use std::io;
+use std::path::Path;
+
+use clap::{App, Arg};
+
+use ffv1::decoder::Decoder;
+use ffv1::repair::seal_slice;
+use ffv1::slice::is_keyframe;
+
+use ffv1_util::demux::{open_input, read_packet, DecParams, PacketPatcher};
+
+// error_status written into the footer of damaged slices.
+//
+// See: 4.8.2. error_status
+const SLICE_DAMAGED: u8 = 1;
+
+// A repaired track.
+struct Track {
+    params: DecParams,
+    decoder: Decoder,
+    // Previous packet, once repaired, if it is a keyframe
+    previous: Option<Vec<u8>>,
+}
+
+// Replaces the content of slice 'index' of 'packet' with the same slice of
+// the previous packet, if it fits, padding it with zeros.
+//
+// Only slices of keyframes are self-contained, and slice headers carry
+// their position, so a slice cannot be copied from a neighbor slice.
+fn replace_slice(
+    track: &Track,
+    packet: &mut [u8],
+    index: usize,
+) -> Option<()> {
+    let previous = track.previous.as_ref()?;
+    let layout = track.decoder.slice_layout(packet).ok()?;
+    let previous_layout = track.decoder.slice_layout(previous).ok()?;
+    if layout.len() != previous_layout.len() {
+        return None;
+    }
+    // Slices marked as damaged are not copied further
+    if previous_layout[index].error_status != 0 {
+        return None;
+    }
+    let range = layout[index].range.clone();
+    let source = &previous[previous_layout[index].range.clone()];
+    if source.len() > range.len() {
+        return None;
+    }
+    packet[range.start..range.start + source.len()].copy_from_slice(source);
+    for byte in &mut packet[range.start + source.len()..range.end] {
+        *byte = 0;
+    }
+    seal_slice(&mut packet[range.start..layout[index].footer.end], 0).ok()
+}
+
+fn main() -> io::Result<()> {
+    // Set up CLI configuration and input parameters
+    let matches = App::new("ffv1-fix")
+        .about("Repairs the damaged slices of a ffv1 matroska file")
+        .arg(
+            Arg::new("input-path")
+                .help("Damaged matroska file")
+                .short('i')
+                .long("input")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("output-path")
+                .help("Repaired matroska file")
+                .short('o')
+                .long("output")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("replace")
+                .help(
+                    "Replaces damaged slices of keyframes with the same \
+                     slice of the previous keyframe when it fits, instead of \
+                     only marking them",
+                )
+                .long("replace"),
+        )
+        .get_matches();
+
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    let output_path = matches.value_of("output-path").map(Path::new).unwrap();
+    let replace = matches.is_present("replace");
+
+    let (mut demuxer, track_params) = open_input(input_path)?;
+    let mut tracks = Vec::new();
+    for params in track_params {
+        let decoder = params.decoder();
+        if decoder.config_record().ec == 0 {
+            eprintln!(
+                "Track {} has no slice CRCs, damaged slices cannot be found",
+                params.stream_index
+            );
+            std::process::exit(1);
+        }
+        tracks.push(Track {
+            params,
+            decoder,
+            previous: None,
+        });
+    }
+
+    let mut patcher = PacketPatcher::create(input_path, output_path)?;
+    let (mut packets, mut marked, mut replaced) = (0, 0, 0);
+    while let Some(mut pkt) = read_packet(&mut demuxer) {
+        let track = match tracks
+            .iter_mut()
+            .find(|track| track.params.stream_index == pkt.stream_index)
+        {
+            Some(track) => track,
+            None => continue,
+        };
+
+        if !patcher.find_packet(&pkt.data)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet {} not found in the input file", packets),
+            ));
+        }
+        packets += 1;
+
+        let layout = match track.decoder.slice_layout(&pkt.data) {
+            Ok(layout) => layout,
+            Err(err) => {
+                eprintln!(
+                    "packet {}: unreadable slices: {}",
+                    packets - 1,
+                    err
+                );
+                track.previous = None;
+                patcher.patch_packet(&pkt.data)?;
+                continue;
+            }
+        };
+
+        let keyframe = is_keyframe(&pkt.data);
+        for (index, slice) in layout.iter().enumerate() {
+            if slice.crc_valid != Some(false) {
+                continue;
+            }
+            let mut data = pkt.data.clone();
+            if replace
+                && keyframe
+                && replace_slice(track, &mut data, index).is_some()
+                && is_keyframe(&data)
+            {
+                eprintln!("packet {} slice {}: replaced", packets - 1, index);
+                pkt.data = data;
+                replaced += 1;
+                continue;
+            }
+            let slice_data =
+                &mut pkt.data[slice.range.start..slice.footer.end];
+            if seal_slice(slice_data, SLICE_DAMAGED).is_ok() {
+                eprintln!("packet {} slice {}: marked", packets - 1, index);
+                marked += 1;
+            }
+        }
+
+        patcher.patch_packet(&pkt.data)?;
+        track.previous = if keyframe { Some(pkt.data) } else { None };
+    }
+
+    patcher.finish()?;
+    eprintln!(
+        "{} packets, {} slices marked, {} slices replaced, written to {}",
+        packets,
+        marked,
+        replaced,
+        output_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! This example shows how a ffv1 track goes through a complete rust-av
+//! pipeline: a matroska file is demuxed, the packets of its ffv1 tracks are
+//! decoded, and every packet is muxed back into a new matroska file.
+//!
+//! The crate has no encoder, so packets are passed through untouched. The
+//! decoding validates them, and is where a filter and an encoder would be
+//! plugged in.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use av_format::muxer::Context;
+
+use matroska::muxer::MkvMuxer;
+
+use clap::{App, Arg};
+
+use ffv1::decoder::Decoder;
+
+use ffv1_util::demux::{open_input, read_packet, DecParams};
+use ffv1_util::extradata::codec_private;
+
+// A decoded track.
+struct Track {
+    params: DecParams,
+    decoder: Decoder,
+    frames: usize,
+    errors: usize,
+}
+
+// Converts a muxing error into an I/O error.
+fn mux_error<E: std::fmt::Debug>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("muxing failed: {:?}", err))
+}
+
+fn main() -> io::Result<()> {
+    // Set up CLI configuration and input parameters
+    let matches = App::new("ffv1-remux")
+        .about("Decodes the ffv1 tracks of a matroska file and remuxes it")
+        .arg(
+            Arg::new("input-path")
+                .help("Input matroska file")
+                .short('i')
+                .long("input")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("output-path")
+                .help("Output matroska file")
+                .short('o')
+                .long("output")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::new("drop-invalid")
+                .help(
+                    "Drops the ffv1 packets which cannot be decoded instead \
+                     of passing them through",
+                )
+                .long("drop-invalid"),
+        )
+        .get_matches();
+
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    let output_path = matches.value_of("output-path").map(Path::new).unwrap();
+    let drop_invalid = matches.is_present("drop-invalid");
+
+    // Demux the input file, and create a ffv1 decoder for every ffv1 track
+    let (mut demuxer, track_params) = open_input(input_path)?;
+    let mut tracks: Vec<_> = track_params
+        .into_iter()
+        .map(|params| Track {
+            decoder: params.decoder(),
+            params,
+            frames: 0,
+            errors: 0,
+        })
+        .collect();
+
+    // Create a matroska muxer with the same streams as the input file, the
+    // CodecPrivate of the ffv1 tracks being written again from their
+    // configuration record, as FFmpeg writes it
+    let mut info = demuxer.info.clone();
+    for stream in &mut info.streams {
+        if let Some(track) = tracks
+            .iter()
+            .find(|track| track.params.stream_index == stream.index as isize)
+        {
+            let params = &track.params;
+            stream.params.extradata = Some(codec_private(
+                &params.extradata,
+                params.width,
+                params.height,
+            ));
+        }
+    }
+    let output = File::create(output_path)?;
+    let mut muxer =
+        Context::new(Box::new(MkvMuxer::matroska()), Box::new(output));
+    muxer.set_global_info(info).map_err(mux_error)?;
+    muxer.configure().map_err(mux_error)?;
+    muxer.write_header().map_err(mux_error)?;
+
+    // Decode the packets of the ffv1 tracks, and mux all of them back, in
+    // their original order
+    let mut packets = 0;
+    while let Some(pkt) = read_packet(&mut demuxer) {
+        if let Some(track) = tracks
+            .iter_mut()
+            .find(|track| track.params.stream_index == pkt.stream_index)
+        {
+            match track.decoder.decode_frame(&pkt.data) {
+                Ok(frame) => {
+                    // A filter and an encoder would process the frame here
+                    eprintln!(
+                        "track {} frame {}: {}x{}",
+                        pkt.stream_index,
+                        track.frames,
+                        frame.width(),
+                        frame.height()
+                    );
+                    track.frames += 1;
+                }
+                Err(err) => {
+                    eprintln!(
+                        "track {} frame {}: {}",
+                        pkt.stream_index, track.frames, err
+                    );
+                    track.frames += 1;
+                    track.errors += 1;
+                    if drop_invalid {
+                        continue;
+                    }
+                }
+            }
+        }
+        muxer.write_packet(Arc::new(pkt)).map_err(mux_error)?;
+        packets += 1;
+    }
+    muxer.write_trailer().map_err(mux_error)?;
+
+    for track in &tracks {
+        eprintln!(
+            "track {}: {} frames decoded, {} errors",
+            track.params.stream_index, track.frames, track.errors
+        );
+    }
+    eprintln!("{} packets written to {}", packets, output_path.display());
+
+    if tracks.iter().any(|track| track.errors > 0) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Conversion of decoded ffv1 frames into PNG images.
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use ffv1::constants::ColorSpace;
+use ffv1::decoder::Frame;
+
+// Reads the sample at the given luma coordinates from a plane,
+// taking chroma subsampling into account.
+pub fn sample(frame: &Frame, plane: usize, x: u32, y: u32) -> i32 {
+    let (x, y, width) = if frame.color_space() == ColorSpace::YCbCr
+        && (plane == 1 || plane == 2)
+    {
+        let h = frame.chroma_subsample_h();
+        let v = frame.chroma_subsample_v();
+        (x >> h, y >> v, (frame.width() + (1 << h) - 1) >> h)
+    } else {
+        (x, y, frame.width())
+    };
+    let pos = (y * width + x) as usize;
+    if frame.bit_depth() == 8 {
+        frame.buf()[plane][pos] as i32
+    } else {
+        frame.buf16()[plane][pos] as i32
+    }
+}
+
+// Converts a limited range BT.601 YCbCr pixel to RGB.
+fn ycbcr_to_rgb(y: i32, cb: i32, cr: i32, bit_depth: u8) -> [i32; 3] {
+    let shift = bit_depth - 8;
+    let max = (1 << bit_depth) - 1;
+    let y = (y - (16 << shift)) as f32 * 1.164;
+    let cb = (cb - (128 << shift)) as f32;
+    let cr = (cr - (128 << shift)) as f32;
+    let clip = |v: f32| (v.round() as i32).max(0).min(max);
+    [
+        clip(y + 1.596 * cr),
+        clip(y - 0.813 * cr - 0.391 * cb),
+        clip(y + 2.018 * cb),
+    ]
+}
+
+/// Writes a decoded frame as a PNG image.
+///
+/// RGB and YCbCr frames are written as RGB images, frames without chroma
+/// planes as grayscale images. Frames with a bit depth greater than 8 are
+/// scaled to 16 bits.
+pub fn write_png(frame: &Frame, path: &Path) -> io::Result<()> {
+    let channels = if frame.has_chroma() { 3 } else { 1 };
+    let max = (1u32 << frame.bit_depth()) - 1;
+
+    let mut pixels = Vec::with_capacity(
+        (frame.width() * frame.height()) as usize * channels,
+    );
+    for y in 0..frame.height() {
+        for x in 0..frame.width() {
+            if !frame.has_chroma() {
+                pixels.push(sample(frame, 0, x, y));
+            } else if frame.color_space() == ColorSpace::YCbCr {
+                pixels.extend_from_slice(&ycbcr_to_rgb(
+                    sample(frame, 0, x, y),
+                    sample(frame, 1, x, y),
+                    sample(frame, 2, x, y),
+                    frame.bit_depth(),
+                ));
+            } else {
+                // Planes are stored as GBR.
+                pixels.push(sample(frame, 2, x, y));
+                pixels.push(sample(frame, 0, x, y));
+                pixels.push(sample(frame, 1, x, y));
+            }
+        }
+    }
+
+    let (depth, data): (png::BitDepth, Vec<u8>) = if frame.bit_depth() == 8 {
+        let data = pixels.iter().map(|&v| v as u8).collect();
+        (png::BitDepth::Eight, data)
+    } else {
+        // PNG stores 16-bit samples as big endian.
+        let data = pixels
+            .iter()
+            .flat_map(|&v| ((v as u32 * 0xFFFF / max) as u16).to_be_bytes())
+            .collect();
+        (png::BitDepth::Sixteen, data)
+    };
+
+    let color = if frame.has_chroma() {
+        png::ColorType::Rgb
+    } else {
+        png::ColorType::Grayscale
+    };
+    encode(frame.width(), frame.height(), color, depth, &data, path)
+}
+
+/// Writes 8-bit grayscale pixels as a PNG image.
+pub fn write_gray_png(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    path: &Path,
+) -> io::Result<()> {
+    encode(
+        width,
+        height,
+        png::ColorType::Grayscale,
+        png::BitDepth::Eight,
+        pixels,
+        path,
+    )
+}
+
+// Encodes packed pixels into a PNG file.
+fn encode(
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    depth: png::BitDepth,
+    data: &[u8],
+    path: &Path,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    writer
+        .write_image_data(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
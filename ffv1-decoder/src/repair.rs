@@ -0,0 +1,106 @@
+//! Stream-copy repair of the ffv1 packets of a matroska file.
+//!
+//! The input file is copied as is, except for the slice footers of the
+//! ffv1 packets, which are validated and optionally rewritten in place.
+//! Footers keep their size, so the matroska structure stays valid.
+
+use std::io;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use ffv1::record::ConfigRecord;
+use ffv1::repair::{repair_packet, RepairOptions};
+
+use ffv1_util::demux::{open_input, read_packet, PacketPatcher};
+
+// Copies the input file into the output file, validating every slice of
+// the ffv1 packets and rewriting their footers as requested.
+pub fn repair(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    // Get the path to the repaired file
+    let output_path = matches.value_of("out").map(Path::new).unwrap();
+
+    let options = RepairOptions {
+        clear_error_status: matches.is_present("clear-error-status"),
+        recompute_crc: matches.is_present("recompute-crc"),
+    };
+
+    let (mut demuxer, tracks) = open_input(input_path)?;
+    for track in &tracks {
+        let record = ConfigRecord::parse_config_record(
+            &track.extradata,
+            track.width,
+            track.height,
+        )
+        .unwrap_or_else(|err| {
+            eprintln!("Invalid configuration record: {}", err);
+            std::process::exit(1);
+        });
+        if record.ec == 0 {
+            eprintln!(
+                "Track {} has no slice CRCs, nothing to repair",
+                track.stream_index
+            );
+            std::process::exit(1);
+        }
+    }
+
+    let mut patcher = PacketPatcher::create(input_path, output_path)?;
+    let (mut packets, mut slices, mut crc_errors, mut error_statuses) =
+        (0, 0, 0, 0);
+    while let Some(mut pkt) = read_packet(&mut demuxer) {
+        if !tracks
+            .iter()
+            .any(|track| track.stream_index == pkt.stream_index)
+        {
+            continue;
+        }
+
+        if !patcher.find_packet(&pkt.data)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("packet {} not found in the input file", packets),
+            ));
+        }
+
+        let status =
+            repair_packet(&mut pkt.data, &options).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("packet {}: {}", packets, err),
+                )
+            })?;
+        for (i, slice) in status.iter().enumerate() {
+            if !slice.crc_valid {
+                eprintln!("packet {} slice {}: CRC mismatch", packets, i);
+                crc_errors += 1;
+            }
+            if slice.error_status != 0 {
+                eprintln!(
+                    "packet {} slice {}: error_status {}",
+                    packets, i, slice.error_status
+                );
+                error_statuses += 1;
+            }
+        }
+        slices += status.len();
+
+        patcher.patch_packet(&pkt.data)?;
+        packets += 1;
+    }
+
+    patcher.finish()?;
+    eprintln!(
+        "{} packets, {} slices, {} CRC mismatches, {} slices with an \
+         error_status, written to {}",
+        packets,
+        slices,
+        crc_errors,
+        error_statuses,
+        output_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Structural audit of the ffv1 packets of a matroska file.
+//!
+//! Only the slice headers and footers are read, no sample is decoded, so
+//! whole files are audited quickly.
+
+use std::io;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use ffv1_util::demux::{open_input, read_packet};
+
+// Prints the structure of every ffv1 packet of the input file, and the
+// slices whose headers or footers are inconsistent.
+pub fn scan(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+
+    let (mut demuxer, tracks) = open_input(input_path)?;
+    let decoders: Vec<_> = tracks
+        .iter()
+        .map(|track| (track.stream_index, track.decoder()))
+        .collect();
+
+    let (mut packets, mut keyframes, mut slices, mut issues) = (0, 0, 0, 0);
+    while let Some(pkt) = read_packet(&mut demuxer) {
+        let decoder = match decoders
+            .iter()
+            .find(|(index, _)| *index == pkt.stream_index)
+        {
+            Some((_, decoder)) => decoder,
+            None => continue,
+        };
+
+        let scan = match decoder.scan_frame(&pkt.data) {
+            Ok(scan) => scan,
+            Err(err) => {
+                println!("packet {}: {}", packets, err);
+                issues += 1;
+                packets += 1;
+                continue;
+            }
+        };
+
+        // Every slice is expected to agree with the first one
+        let first = &scan.slices[0].header;
+        println!(
+            "packet {}: track {} {} {} slices, picture structure {}, \
+             sar {}:{}",
+            packets,
+            pkt.stream_index,
+            if scan.keyframe { "keyframe" } else { "inter" },
+            scan.slices.len(),
+            first.picture_structure(),
+            first.sar().0,
+            first.sar().1,
+        );
+        for (i, slice) in scan.slices.iter().enumerate() {
+            let (x, y, width, height) = slice.rect;
+            let mut problems = Vec::new();
+            if slice.crc_valid == Some(false) {
+                problems.push("CRC mismatch".to_owned());
+            }
+            if slice.error_status != 0 {
+                problems.push(format!("error_status {}", slice.error_status));
+            }
+            if slice.header.picture_structure() != first.picture_structure() {
+                problems.push("picture structure differs".to_owned());
+            }
+            if slice.header.sar() != first.sar() {
+                problems.push("sar differs".to_owned());
+            }
+            if !problems.is_empty() {
+                println!(
+                    "  slice {} at {},{} {}x{}, quant table sets {:?}: {}",
+                    i,
+                    x,
+                    y,
+                    width,
+                    height,
+                    slice.header.quant_table_set_index(),
+                    problems.join(", ")
+                );
+                issues += 1;
+            }
+        }
+
+        keyframes += scan.keyframe as usize;
+        slices += scan.slices.len();
+        packets += 1;
+    }
+
+    println!(
+        "{} packets, {} keyframes, {} slices, {} issues",
+        packets, keyframes, slices, issues
+    );
+    Ok(())
+}
@@ -0,0 +1,362 @@
+//! Export of decoded ffv1 frames as DPX or TIFF image sequences.
+//!
+//! Every frame is written into its own file, keeping the bit depth of the
+//! stream as close as the image format allows and tagging samples as either
+//! RGB or YCbCr.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use ffv1::constants::ColorSpace;
+use ffv1::decoder::Frame;
+
+use crate::image::sample;
+
+// Size of the DPX file header, image data starts right after it.
+const DPX_HEADER_SIZE: usize = 2048;
+
+// DPX image element descriptors.
+const DPX_LUMA: u8 = 6;
+const DPX_RGB: u8 = 50;
+const DPX_CBYCR: u8 = 102;
+
+// Bit depths supported by DPX readers.
+fn dpx_bit_depth(bit_depth: u8) -> u8 {
+    match bit_depth {
+        8 => 8,
+        9 | 10 => 10,
+        11 | 12 => 12,
+        _ => 16,
+    }
+}
+
+// Appends the components of a pixel in DPX order, shifted to the
+// output bit depth. Chroma planes are upsampled to 4:4:4.
+fn dpx_pixel(frame: &Frame, x: u32, y: u32, shift: u8, line: &mut Vec<u32>) {
+    let s = |plane| (sample(frame, plane, x, y) as u32) << shift;
+    if !frame.has_chroma() {
+        line.push(s(0));
+    } else if frame.color_space() == ColorSpace::YCbCr {
+        line.extend_from_slice(&[s(1), s(0), s(2)]);
+    } else {
+        // Planes are stored as GBR.
+        line.extend_from_slice(&[s(2), s(0), s(1)]);
+    }
+}
+
+// Packs a line of samples according to the DPX bit depth.
+//
+// 10-bit samples are packed three per 32-bit word (method A),
+// 12-bit samples are left-justified in 16-bit words, and every
+// line is padded to a 32-bit boundary.
+fn dpx_pack_line(samples: &[u32], bit_size: u8, out: &mut Vec<u8>) {
+    let start = out.len();
+    match bit_size {
+        8 => out.extend(samples.iter().map(|&v| v as u8)),
+        10 => {
+            for chunk in samples.chunks(3) {
+                let mut word = 0u32;
+                for (i, &v) in chunk.iter().enumerate() {
+                    word |= v << (22 - 10 * i);
+                }
+                out.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        12 => {
+            for &v in samples {
+                out.extend_from_slice(&((v as u16) << 4).to_be_bytes());
+            }
+        }
+        _ => {
+            for &v in samples {
+                out.extend_from_slice(&(v as u16).to_be_bytes());
+            }
+        }
+    }
+    while (out.len() - start) % 4 != 0 {
+        out.push(0);
+    }
+}
+
+// Writes a fixed-size, zero-padded string into a header field.
+fn write_str(header: &mut [u8], offset: usize, len: usize, value: &str) {
+    let bytes = value.as_bytes();
+    let n = bytes.len().min(len - 1);
+    header[offset..offset + n].copy_from_slice(&bytes[..n]);
+}
+
+/// Writes a decoded frame as a DPX image.
+///
+/// RGB frames are written with the RGB descriptor, YCbCr frames with the
+/// CbYCr 4:4:4 descriptor after upsampling the chroma planes, and frames
+/// without chroma planes with the luma descriptor.
+pub fn write_dpx(frame: &Frame, path: &Path) -> io::Result<()> {
+    let bit_size = dpx_bit_depth(frame.bit_depth());
+    let shift = bit_size - frame.bit_depth();
+    let ycbcr = frame.color_space() == ColorSpace::YCbCr;
+
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    for y in 0..frame.height() {
+        line.clear();
+        for x in 0..frame.width() {
+            dpx_pixel(frame, x, y, shift, &mut line);
+        }
+        dpx_pack_line(&line, bit_size, &mut data);
+    }
+
+    let (descriptor, ref_low, ref_high) = if !frame.has_chroma() {
+        (DPX_LUMA, 16u32 << (bit_size - 8), 235u32 << (bit_size - 8))
+    } else if ycbcr {
+        (DPX_CBYCR, 16 << (bit_size - 8), 240 << (bit_size - 8))
+    } else {
+        (DPX_RGB, 0, (1 << bit_size) - 1)
+    };
+
+    let mut header = vec![0u8; DPX_HEADER_SIZE];
+    {
+        let mut h = &mut header[..];
+        // File information header
+        h.write_u32::<BigEndian>(0x5344_5058)?; // "SDPX"
+        h.write_u32::<BigEndian>(DPX_HEADER_SIZE as u32)?;
+    }
+    write_str(&mut header, 8, 8, "V2.0");
+    {
+        let mut h = &mut header[16..36];
+        h.write_u32::<BigEndian>((DPX_HEADER_SIZE + data.len()) as u32)?;
+        h.write_u32::<BigEndian>(1)?; // New frame
+        h.write_u32::<BigEndian>(1664)?; // Generic header size
+        h.write_u32::<BigEndian>(384)?; // Industry header size
+        h.write_u32::<BigEndian>(0)?; // User data size
+    }
+    if let Some(name) = path.file_name() {
+        write_str(&mut header, 36, 100, &name.to_string_lossy());
+    }
+    write_str(&mut header, 160, 100, "ffv1-decoder");
+    (&mut header[660..664]).write_u32::<BigEndian>(0xFFFF_FFFF)?;
+
+    // Image information header
+    {
+        let mut h = &mut header[768..780];
+        h.write_u16::<BigEndian>(0)?; // Left to right, top to bottom
+        h.write_u16::<BigEndian>(1)?; // Number of image elements
+        h.write_u32::<BigEndian>(frame.width())?;
+        h.write_u32::<BigEndian>(frame.height())?;
+    }
+    {
+        let mut h = &mut header[780..808];
+        h.write_u32::<BigEndian>(0)?; // Unsigned samples
+        h.write_u32::<BigEndian>(ref_low)?;
+        h.write_f32::<BigEndian>(0.0)?;
+        h.write_u32::<BigEndian>(ref_high)?;
+        h.write_f32::<BigEndian>(0.0)?;
+        h.write_u8(descriptor)?;
+        h.write_u8(0)?; // User-defined transfer characteristic
+        h.write_u8(0)?; // User-defined colorimetric specification
+        h.write_u8(bit_size)?;
+    }
+    {
+        let mut h = &mut header[804..820];
+        let packing = if bit_size == 10 || bit_size == 12 {
+            1
+        } else {
+            0
+        };
+        h.write_u16::<BigEndian>(packing)?;
+        h.write_u16::<BigEndian>(0)?; // No encoding
+        h.write_u32::<BigEndian>(DPX_HEADER_SIZE as u32)?;
+        h.write_u32::<BigEndian>(0)?; // End-of-line padding
+        h.write_u32::<BigEndian>(0)?; // End-of-image padding
+    }
+    // Unused image elements are undefined
+    for b in &mut header[852..1356] {
+        *b = 0xFF;
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&header)?;
+    file.write_all(&data)?;
+    file.flush()
+}
+
+// TIFF field types.
+const TIFF_SHORT: u16 = 3;
+const TIFF_LONG: u16 = 4;
+const TIFF_RATIONAL: u16 = 5;
+
+// A TIFF directory entry whose values are stored out of line when
+// they do not fit into four bytes.
+struct TiffEntry {
+    tag: u16,
+    kind: u16,
+    values: Vec<u32>,
+}
+
+impl TiffEntry {
+    fn new(tag: u16, kind: u16, values: Vec<u32>) -> Self {
+        Self { tag, kind, values }
+    }
+
+    fn size(&self) -> usize {
+        if self.kind == TIFF_SHORT {
+            2 * self.values.len()
+        } else {
+            4 * self.values.len()
+        }
+    }
+
+    fn write_values<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for &v in &self.values {
+            if self.kind == TIFF_SHORT {
+                w.write_u16::<LittleEndian>(v as u16)?;
+            } else {
+                w.write_u32::<LittleEndian>(v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a decoded frame as a planar TIFF image.
+///
+/// RGB frames are tagged as RGB, YCbCr frames as YCbCr keeping their
+/// chroma subsampling, and frames without chroma planes as grayscale.
+/// Frames with a bit depth greater than 8 are stored in 16-bit samples,
+/// shifted so that the most significant bits are aligned.
+pub fn write_tiff(frame: &Frame, path: &Path) -> io::Result<()> {
+    let bit_size = if frame.bit_depth() == 8 { 8 } else { 16 };
+    let shift = bit_size - frame.bit_depth();
+    let ycbcr = frame.color_space() == ColorSpace::YCbCr;
+    let num_planes = if frame.has_chroma() { 3 } else { 1 };
+
+    // Planes are stored as GBR, TIFF wants RGB.
+    let order: &[usize] = if !frame.has_chroma() {
+        &[0]
+    } else if ycbcr {
+        &[0, 1, 2]
+    } else {
+        &[2, 0, 1]
+    };
+
+    let mut strips = Vec::with_capacity(num_planes);
+    for &plane in order {
+        let mut strip = Vec::new();
+        if frame.bit_depth() == 8 {
+            strip.extend_from_slice(&frame.buf()[plane]);
+        } else {
+            for &v in &frame.buf16()[plane] {
+                strip.write_u16::<LittleEndian>(v << shift)?;
+            }
+        }
+        strips.push(strip);
+    }
+
+    let photometric = if !frame.has_chroma() {
+        1 // BlackIsZero
+    } else if ycbcr {
+        6 // YCbCr
+    } else {
+        2 // RGB
+    };
+
+    // Image data follows the 8 bytes header
+    let mut offsets = Vec::with_capacity(num_planes);
+    let mut offset = 8;
+    for strip in &strips {
+        offsets.push(offset as u32);
+        offset += strip.len();
+    }
+
+    let mut entries = vec![
+        TiffEntry::new(256, TIFF_LONG, vec![frame.width()]),
+        TiffEntry::new(257, TIFF_LONG, vec![frame.height()]),
+        TiffEntry::new(258, TIFF_SHORT, vec![bit_size as u32; num_planes]),
+        TiffEntry::new(259, TIFF_SHORT, vec![1]),
+        TiffEntry::new(262, TIFF_SHORT, vec![photometric]),
+        TiffEntry::new(273, TIFF_LONG, offsets),
+        TiffEntry::new(277, TIFF_SHORT, vec![num_planes as u32]),
+        TiffEntry::new(278, TIFF_LONG, vec![frame.height()]),
+        TiffEntry::new(
+            279,
+            TIFF_LONG,
+            strips.iter().map(|s| s.len() as u32).collect(),
+        ),
+        TiffEntry::new(284, TIFF_SHORT, vec![2]), // Planar
+    ];
+    if frame.has_chroma() && ycbcr {
+        let max = (1u32 << bit_size) - 1;
+        let scale = 1 << (bit_size - 8);
+        entries.push(TiffEntry::new(
+            530,
+            TIFF_SHORT,
+            vec![
+                1 << frame.chroma_subsample_h(),
+                1 << frame.chroma_subsample_v(),
+            ],
+        ));
+        entries.push(TiffEntry::new(
+            532,
+            TIFF_RATIONAL,
+            vec![
+                16 * scale,
+                1,
+                235 * scale,
+                1,
+                128 * scale,
+                1,
+                max.min(240 * scale),
+                1,
+                128 * scale,
+                1,
+                max.min(240 * scale),
+                1,
+            ],
+        ));
+    }
+
+    // The directory comes right after the image data, followed by the
+    // values which do not fit into the entries.
+    let ifd_offset = offset + offset % 2;
+    let mut extra_offset = ifd_offset + 2 + 12 * entries.len() + 4;
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(b"II")?;
+    file.write_u16::<LittleEndian>(42)?;
+    file.write_u32::<LittleEndian>(ifd_offset as u32)?;
+    for strip in &strips {
+        file.write_all(strip)?;
+    }
+    if offset % 2 != 0 {
+        file.write_u8(0)?;
+    }
+
+    file.write_u16::<LittleEndian>(entries.len() as u16)?;
+    for entry in &entries {
+        file.write_u16::<LittleEndian>(entry.tag)?;
+        file.write_u16::<LittleEndian>(entry.kind)?;
+        let count = if entry.kind == TIFF_RATIONAL {
+            entry.values.len() / 2
+        } else {
+            entry.values.len()
+        };
+        file.write_u32::<LittleEndian>(count as u32)?;
+        if entry.size() <= 4 {
+            let mut value = Vec::with_capacity(4);
+            entry.write_values(&mut value)?;
+            value.resize(4, 0);
+            file.write_all(&value)?;
+        } else {
+            file.write_u32::<LittleEndian>(extra_offset as u32)?;
+            extra_offset += entry.size();
+        }
+    }
+    file.write_u32::<LittleEndian>(0)?; // No further directories
+
+    for entry in entries.iter().filter(|e| e.size() > 4) {
+        entry.write_values(&mut file)?;
+    }
+    file.flush()
+}
@@ -0,0 +1,52 @@
+//! Feature usage report of the ffv1 tracks of a matroska file.
+//!
+//! The features of the specification used by every track are printed, e.g.
+//! for archives to profile their holdings and spot outliers.
+
+use std::io;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use ffv1::usage::FeatureScanner;
+
+use ffv1_util::demux::{open_input, read_packet};
+
+// Prints the features used by every ffv1 track of the input file.
+pub fn usage(matches: &ArgMatches) -> io::Result<()> {
+    // Get the path to the matroska file
+    let input_path = matches.value_of("input-path").map(Path::new).unwrap();
+    // One JSON object per track instead of plain text
+    let json = matches.is_present("json");
+
+    let (mut demuxer, tracks) = open_input(input_path)?;
+    let mut scanners = Vec::new();
+    for track in &tracks {
+        match FeatureScanner::new(&track.extradata, track.width, track.height)
+        {
+            Ok(scanner) => scanners.push((track.stream_index, scanner)),
+            Err(err) => {
+                eprintln!("track {}: {}", track.stream_index, err);
+            }
+        }
+    }
+
+    while let Some(pkt) = read_packet(&mut demuxer) {
+        if let Some((_, scanner)) = scanners
+            .iter_mut()
+            .find(|(index, _)| *index == pkt.stream_index)
+        {
+            scanner.scan_packet(&pkt.data);
+        }
+    }
+
+    for (index, scanner) in scanners {
+        let usage = scanner.finish();
+        if json {
+            println!("{}", usage.to_json());
+        } else {
+            println!("track {}:\n{}", index, usage);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Reading of the ffv1 tracks of a matroska file.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::iter;
+use std::path::Path;
+
+use av_data::packet::Packet;
+use av_data::params::MediaKind;
+use av_format::buffer::AccReader;
+use av_format::demuxer::{Context, Event};
+
+use matroska::demuxer::MkvDemuxer;
+
+use ffv1::decoder::Decoder;
+use ffv1::pipeline::PacketQueue;
+
+use crate::extradata::config_record;
+
+// Number of packets read ahead of the decoders
+const PREFETCH_PACKETS: usize = 16;
+
+// Size of the reads used to locate the packets in the file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// ffv1 decoder parameters of a track.
+pub struct DecParams {
+    /// Index of the stream of the track.
+    pub stream_index: isize,
+    /// Width of the frames.
+    pub width: u32,
+    /// Height of the frames.
+    pub height: u32,
+    /// Configuration record of the track.
+    pub extradata: Vec<u8>,
+}
+
+impl DecParams {
+    /// Creates a new ffv1 decoder for the track.
+    pub fn decoder(&self) -> Decoder {
+        Decoder::new(&self.extradata, self.width, self.height).unwrap()
+    }
+}
+
+/// Reads the next packet of the input file, logging it to the standard
+/// error, or None at the end of the file.
+pub fn read_packet(demuxer: &mut Context) -> Option<Packet> {
+    // The demuxer reads which event has occurred
+    match demuxer.read_event() {
+        // If a new packet has been found, return it
+        Ok(event) => match event {
+            Event::NewPacket(pkt) => {
+                eprintln!(
+                    "packet = {} track = {}\n",
+                    pkt.data.len(),
+                    pkt.stream_index
+                );
+                Some(pkt)
+            }
+            // When the EOF is reached, the decoding process is stopped
+            Event::Eof => {
+                eprintln!("EOF reached.");
+                None
+            }
+            _ => {
+                // If an unsupported event occurs,
+                // the decoding process is stopped
+                eprintln!("Unsupported event {:?}", event);
+                None
+            }
+        },
+        Err(err) => {
+            // If there are no more events, the decoding process is stopped
+            eprintln!("No more events {:?}", err);
+            None
+        }
+    }
+}
+
+/// Reads the next packet of the input file without logging it, or None at
+/// the end of the file.
+pub fn next_packet(demuxer: &mut Context) -> Result<Option<Packet>, String> {
+    match demuxer.read_event() {
+        Ok(Event::NewPacket(pkt)) => Ok(Some(pkt)),
+        Ok(Event::Eof) => Ok(None),
+        Ok(event) => Err(format!("Unsupported event {:?}", event)),
+        // The demuxer runs out of events at the end of the file
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads the packets of the input file on a reader thread, ahead of their
+/// decoding, so that the decoders don't wait for the disk.
+///
+/// When the file cannot be opened again on the reader thread, its error is
+/// the only item of the queue.
+pub fn prefetch_packets(input_path: &Path) -> PacketQueue<io::Result<Packet>> {
+    let input_path = input_path.to_owned();
+    PacketQueue::new(PREFETCH_PACKETS, move || {
+        // The demuxer is opened again on the reader thread
+        let (mut demuxer, mut error) = match open_input(&input_path) {
+            Ok((demuxer, _)) => (Some(demuxer), None),
+            Err(err) => (None, Some(err)),
+        };
+        iter::from_fn(move || match &mut demuxer {
+            Some(demuxer) => read_packet(demuxer).map(Ok),
+            None => error.take().map(Err),
+        })
+    })
+}
+
+/// Returns an error about an invalid input file.
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Opens a matroska file and reads the ffv1 decoder parameters of every
+/// ffv1 track.
+pub fn open_input(input_path: &Path) -> io::Result<(Context, Vec<DecParams>)> {
+    // Open the matroska file
+    let reader = File::open(input_path).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("Cannot open the input file: {}", err),
+        )
+    })?;
+
+    // Create a buffer of size 4096MB to contain matroska data
+    let ar = AccReader::with_capacity(4 * 1024, reader);
+
+    // Set the type of demuxer, in this case, a matroska demuxer
+    let mut demuxer = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
+
+    // Read matroska headers
+    demuxer.read_headers().map_err(|err| {
+        invalid_data(format!("Cannot parse the format headers: {:?}", err))
+    })?;
+
+    // Save decoder params for every ffv1 decoder
+    let mut tracks = Vec::new();
+
+    // Iterate over the streams contained in a matroska file
+    for stream in &demuxer.info.streams {
+        // Considers only video streams and analyze the type of codec inside.
+        if let Some(MediaKind::Video(info)) = &stream.params.kind {
+            let extradata =
+                stream.params.extradata.as_ref().ok_or_else(|| {
+                    invalid_data("No extradata detected".to_owned())
+                })?;
+            if let Some(record) = config_record(extradata) {
+                tracks.push(DecParams {
+                    stream_index: stream.index as isize,
+                    width: info.width as u32,
+                    height: info.height as u32,
+                    extradata: record.to_owned(),
+                });
+            }
+        }
+    }
+
+    if tracks.is_empty() {
+        return Err(invalid_data("No ffv1 track detected".to_owned()));
+    }
+
+    Ok((demuxer, tracks))
+}
+
+/// Opens a matroska file and reads the ffv1 decoder parameters of its
+/// first ffv1 track.
+pub fn open_first_track(
+    input_path: &Path,
+) -> io::Result<(Context, DecParams)> {
+    let (demuxer, mut tracks) = open_input(input_path)?;
+    Ok((demuxer, tracks.remove(0)))
+}
+
+/// Copies a matroska file into an output file, patching its packets in
+/// place as they are demuxed.
+///
+/// Packets are stored in order and unmodified in the file, so each one is
+/// searched for right after the previous one, streaming the file once
+/// instead of holding it in memory.
+pub struct PacketPatcher {
+    input: File,
+    output: BufWriter<File>,
+    // Bytes of the input read but not yet copied
+    window: Vec<u8>,
+}
+
+impl PacketPatcher {
+    /// Opens the input file and creates the output file, which must differ
+    /// since it is written while the input is read.
+    pub fn create(input_path: &Path, output_path: &Path) -> io::Result<Self> {
+        let input = File::open(input_path)?;
+        if fs::canonicalize(output_path).ok()
+            == Some(fs::canonicalize(input_path)?)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The output file must differ from the input file",
+            ));
+        }
+        Ok(PacketPatcher {
+            input,
+            output: BufWriter::new(File::create(output_path)?),
+            window: Vec::new(),
+        })
+    }
+
+    /// Copies the input up to the next occurrence of 'packet', returning
+    /// false when the input holds no more occurrence of it.
+    pub fn find_packet(&mut self, packet: &[u8]) -> io::Result<bool> {
+        if packet.is_empty() {
+            return Ok(false);
+        }
+        loop {
+            if let Some(pos) = self
+                .window
+                .windows(packet.len())
+                .position(|window| window == packet)
+            {
+                self.output.write_all(&self.window[..pos])?;
+                self.window.drain(..pos);
+                return Ok(true);
+            }
+
+            // Keep only the bytes which may start the packet
+            let keep = self.window.len().min(packet.len() - 1);
+            let skip = self.window.len() - keep;
+            self.output.write_all(&self.window[..skip])?;
+            self.window.drain(..skip);
+
+            let len = self.window.len();
+            self.window.resize(len + CHUNK_SIZE.max(packet.len()), 0);
+            let read = self.input.read(&mut self.window[len..])?;
+            self.window.truncate(len + read);
+            if read == 0 {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Writes 'packet' in place of the packet found by 'find_packet', which
+    /// has the same size.
+    pub fn patch_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        assert!(packet.len() <= self.window.len());
+        self.output.write_all(packet)?;
+        self.window.drain(..packet.len());
+        Ok(())
+    }
+
+    /// Copies the rest of the input and flushes the output.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.output.write_all(&self.window)?;
+        io::copy(&mut self.input, &mut self.output)?;
+        self.output.flush()
+    }
+}
@@ -0,0 +1,50 @@
+//! The ffv1 configuration record in a matroska CodecPrivate.
+//!
+//! Matroska has no codec ID of its own for ffv1: FFmpeg and VLC store it
+//! as V_MS/VFW/FOURCC, whose CodecPrivate is a BITMAPINFOHEADER followed
+//! by the configuration record.
+
+/// Matroska codec ID of ffv1 tracks.
+pub const CODEC_ID: &str = "V_MS/VFW/FOURCC";
+
+/// Size of the BITMAPINFOHEADER preceding the codec data of a
+/// V_MS/VFW/FOURCC CodecPrivate.
+pub const BITMAPINFOHEADER_SIZE: usize = 40;
+
+/// Returns whether 'codec_private' is the CodecPrivate of an ffv1 track.
+pub fn is_ffv1(codec_private: &[u8]) -> bool {
+    codec_private.len() > BITMAPINFOHEADER_SIZE
+        && String::from_utf8_lossy(codec_private).contains("FFV1")
+}
+
+/// Returns the configuration record stored in 'codec_private', or None
+/// when it is not the CodecPrivate of an ffv1 track.
+pub fn config_record(codec_private: &[u8]) -> Option<&[u8]> {
+    if is_ffv1(codec_private) {
+        // As per Matroska spec for VFW CodecPrivate
+        Some(&codec_private[BITMAPINFOHEADER_SIZE..])
+    } else {
+        None
+    }
+}
+
+/// Returns the CodecPrivate of an ffv1 track of 'width'x'height' frames
+/// coded with the configuration record 'record', as FFmpeg writes it.
+pub fn codec_private(record: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let size = (BITMAPINFOHEADER_SIZE + record.len()) as u32;
+    // FFmpeg always writes a bit count of 24
+    let image_size = width.wrapping_mul(height).wrapping_mul(3);
+
+    let mut codec_private = Vec::with_capacity(size as usize);
+    codec_private.extend_from_slice(&size.to_le_bytes());
+    codec_private.extend_from_slice(&width.to_le_bytes());
+    codec_private.extend_from_slice(&height.to_le_bytes());
+    codec_private.extend_from_slice(&1u16.to_le_bytes()); // planes
+    codec_private.extend_from_slice(&24u16.to_le_bytes()); // bit count
+    codec_private.extend_from_slice(b"FFV1"); // compression
+    codec_private.extend_from_slice(&image_size.to_le_bytes());
+    // Pixels per meter and palette colors are left unset
+    codec_private.extend_from_slice(&[0; 16]);
+    codec_private.extend_from_slice(record);
+    codec_private
+}
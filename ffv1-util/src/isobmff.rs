@@ -0,0 +1,87 @@
+//! ISOBMFF sample entries of ffv1 tracks, e.g. for muxers to write MP4 and
+//! MOV files which FFmpeg opens like those it writes.
+//!
+//! ISOBMFF has no codec configuration box for ffv1: FFmpeg stores the
+//! configuration record in a 'glbl' box, its box for the global header of
+//! codecs the specification does not cover, inside the 'FFV1' sample entry.
+
+use std::convert::TryFrom;
+
+use ffv1::record::ConfigRecord;
+
+/// Sample entry type of ffv1 tracks.
+pub const SAMPLE_ENTRY_TYPE: [u8; 4] = *b"FFV1";
+
+/// Type of the box holding the configuration record.
+pub const GLBL: [u8; 4] = *b"glbl";
+
+// Size and type of a box
+const BOX_HEADER_SIZE: usize = 8;
+
+// Resolution of 72 dpi, in 16.16 fixed point
+const RESOLUTION: u32 = 0x0048_0000;
+
+// Colour images with no alpha
+const DEPTH: u16 = 0x18;
+
+/// Writes the box 'kind' holding 'payload'.
+fn put_box(out: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    let size = (BOX_HEADER_SIZE + payload.len()) as u32;
+    out.extend_from_slice(&size.to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(payload);
+}
+
+/// Returns the 'glbl' box holding the configuration record 'record'.
+pub fn glbl_box(record: &ConfigRecord) -> Vec<u8> {
+    let record = record.to_bytes();
+    let mut glbl = Vec::with_capacity(BOX_HEADER_SIZE + record.len());
+    put_box(&mut glbl, &GLBL, &record);
+    glbl
+}
+
+/// Returns the configuration record held by the 'glbl' box 'glbl', e.g.
+/// to be passed to 'Decoder::new', or None when it is not a whole 'glbl'
+/// box.
+pub fn glbl_record(glbl: &[u8]) -> Option<&[u8]> {
+    if glbl.len() < BOX_HEADER_SIZE || glbl[4..8] != GLBL {
+        return None;
+    }
+    let size = u32::from_be_bytes([glbl[0], glbl[1], glbl[2], glbl[3]]);
+    if size as usize != glbl.len() {
+        return None;
+    }
+    Some(&glbl[BOX_HEADER_SIZE..])
+}
+
+/// Returns the 'FFV1' sample entry of a track coded with the
+/// configuration record 'record', holding its 'glbl' box, with the fields
+/// FFmpeg writes in MP4 files. The colour and aspect ratio boxes are left
+/// to the muxer.
+///
+/// Returns None when the frames are too large for the 16-bit width and
+/// height of the entry.
+pub fn sample_entry(record: &ConfigRecord) -> Option<Vec<u8>> {
+    let width = u16::try_from(record.width).ok()?;
+    let height = u16::try_from(record.height).ok()?;
+
+    // 8.5.2. VisualSampleEntry of ISO/IEC 14496-12
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&[0; 6]); // reserved
+    payload.extend_from_slice(&1u16.to_be_bytes()); // data_reference_index
+    payload.extend_from_slice(&[0; 16]); // pre_defined and reserved
+    payload.extend_from_slice(&width.to_be_bytes());
+    payload.extend_from_slice(&height.to_be_bytes());
+    payload.extend_from_slice(&RESOLUTION.to_be_bytes()); // horizontal
+    payload.extend_from_slice(&RESOLUTION.to_be_bytes()); // vertical
+    payload.extend_from_slice(&[0; 4]); // reserved
+    payload.extend_from_slice(&1u16.to_be_bytes()); // frame_count
+    payload.extend_from_slice(&[0; 32]); // empty compressorname
+    payload.extend_from_slice(&DEPTH.to_be_bytes());
+    payload.extend_from_slice(&(-1i16).to_be_bytes()); // pre_defined
+    payload.extend_from_slice(&glbl_box(record));
+
+    let mut entry = Vec::with_capacity(BOX_HEADER_SIZE + payload.len());
+    put_box(&mut entry, &SAMPLE_ENTRY_TYPE, &payload);
+    Some(entry)
+}
@@ -0,0 +1,11 @@
+//! Glue shared by the ffv1 tools, benchmarks and plugins: demuxing of the
+//! ffv1 tracks of matroska files, the configuration record in their
+//! CodecPrivate, their track entries, seeking to their keyframes, the ISOBMFF
+//! sample entries of ffv1 tracks and the creation of the raw outputs.
+
+pub mod demux;
+pub mod extradata;
+pub mod isobmff;
+pub mod output;
+pub mod seek;
+pub mod track;
@@ -0,0 +1,14 @@
+//! Creation of the outputs the raw, Y4M and NUT writers write to.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Opens 'path' for writing, "-" meaning the standard output.
+pub fn create_output(path: &Path) -> io::Result<Box<dyn Write>> {
+    if path == Path::new("-") {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path)?))
+    }
+}
@@ -0,0 +1,538 @@
+//! Seeking to the keyframes of the first ffv1 track of a matroska file.
+//!
+//! A frame is located from its timestamp, given by the default duration of
+//! the track: the Cues give the cluster of the last keyframe preceding it,
+//! from which the packets are read up to the frame, without demuxing the
+//! file from its start.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use ffv1::slice::is_keyframe;
+
+use crate::extradata::config_record;
+
+// EBML IDs of the elements read
+const EBML: u32 = 0x1A45_DFA3;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const DEFAULT_DURATION: u32 = 0x23_E383;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+// TimestampScale of the files which do not code it, in nanoseconds
+const DEFAULT_TIMESTAMP_SCALE: u64 = 1_000_000;
+
+// Lacing bits of the flags of a block
+const LACING: u8 = 0x06;
+
+// Returns an error about an invalid input file.
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Reads the EBML elements of a file.
+struct Reader {
+    file: BufReader<File>,
+    // Position in the file
+    pos: u64,
+}
+
+impl Reader {
+    fn seek(&mut self, pos: u64) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn skip(&mut self, size: u64) -> io::Result<()> {
+        let offset = i64::try_from(size)
+            .map_err(|_| invalid_data("Element size overflow"))?;
+        self.file.seek_relative(offset)?;
+        self.pos += size;
+        Ok(())
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.file.read_exact(&mut byte)?;
+        self.pos += 1;
+        Ok(byte[0])
+    }
+
+    // Reads a variable size integer whose first byte is 'first', returning
+    // it with its length marker and its length.
+    fn vint(&mut self, first: u8) -> io::Result<(u64, u32)> {
+        let length = first.leading_zeros() + 1;
+        if length > 8 {
+            return Err(invalid_data("Invalid EBML variable size integer"));
+        }
+        let mut value = first as u64;
+        for _ in 1..length {
+            value = (value << 8) | self.byte()? as u64;
+        }
+        Ok((value, length))
+    }
+
+    // Reads the ID and the size of the next element, the size being None
+    // when it is unknown, or None at the end of the file.
+    fn header(&mut self) -> io::Result<Option<(u32, Option<u64>)>> {
+        let mut first = [0];
+        if self.file.read(&mut first)? == 0 {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let (id, length) = self.vint(first[0])?;
+        if length > 4 {
+            return Err(invalid_data("Invalid EBML element ID"));
+        }
+        let first = self.byte()?;
+        let (size, length) = self.vint(first)?;
+        let marker = 1 << (7 * length);
+        let size = size & (marker - 1);
+        // A size of all ones is unknown
+        let size = if size == marker - 1 { None } else { Some(size) };
+        Ok(Some((id as u32, size)))
+    }
+
+    // Reads the ID and the size of the next element of a container ending
+    // at 'end', or None at its end.
+    fn child(&mut self, end: u64) -> io::Result<Option<(u32, u64)>> {
+        if self.pos >= end {
+            return Ok(None);
+        }
+        match self.header()? {
+            Some((id, Some(size))) => Ok(Some((id, size))),
+            Some((_, None)) => Err(invalid_data("Unexpected unknown size")),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn uint(&mut self, size: u64) -> io::Result<u64> {
+        if size > 8 {
+            return Err(invalid_data("Invalid EBML unsigned integer"));
+        }
+        let mut value = 0;
+        for _ in 0..size {
+            value = (value << 8) | self.byte()? as u64;
+        }
+        Ok(value)
+    }
+
+    fn bytes(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.file).take(size).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.pos += size;
+        Ok(bytes)
+    }
+}
+
+// Fields of a track entry used to seek.
+#[derive(Default)]
+struct TrackEntry {
+    number: u64,
+    default_duration: Option<u64>,
+    ffv1: bool,
+}
+
+fn track_entry(reader: &mut Reader, end: u64) -> io::Result<TrackEntry> {
+    let mut entry = TrackEntry::default();
+    while let Some((id, size)) = reader.child(end)? {
+        match id {
+            TRACK_NUMBER => entry.number = reader.uint(size)?,
+            DEFAULT_DURATION => {
+                entry.default_duration = Some(reader.uint(size)?)
+            }
+            CODEC_PRIVATE => {
+                entry.ffv1 = config_record(&reader.bytes(size)?).is_some()
+            }
+            _ => reader.skip(size)?,
+        }
+    }
+    Ok(entry)
+}
+
+// Returns the tracks, the timestamps and the cluster positions, relative
+// to the segment data, of the cue points of the Cues ending at 'end'.
+fn cue_points(
+    reader: &mut Reader,
+    end: u64,
+) -> io::Result<Vec<(u64, u64, u64)>> {
+    let mut cues = Vec::new();
+    while let Some((id, size)) = reader.child(end)? {
+        if id != CUE_POINT {
+            reader.skip(size)?;
+            continue;
+        }
+        let point_end = reader.pos + size;
+        let mut time = None;
+        let mut positions = Vec::new();
+        while let Some((id, size)) = reader.child(point_end)? {
+            if id != CUE_TRACK_POSITIONS {
+                match id {
+                    CUE_TIME => time = Some(reader.uint(size)?),
+                    _ => reader.skip(size)?,
+                }
+                continue;
+            }
+            let positions_end = reader.pos + size;
+            let (mut track, mut position) = (None, None);
+            while let Some((id, size)) = reader.child(positions_end)? {
+                match id {
+                    CUE_TRACK => track = Some(reader.uint(size)?),
+                    CUE_CLUSTER_POSITION => {
+                        position = Some(reader.uint(size)?)
+                    }
+                    _ => reader.skip(size)?,
+                }
+            }
+            if let (Some(track), Some(position)) = (track, position) {
+                positions.push((track, position));
+            }
+        }
+        if let Some(time) = time {
+            cues.extend(
+                positions
+                    .into_iter()
+                    .map(|(track, position)| (track, time, position)),
+            );
+        }
+    }
+    Ok(cues)
+}
+
+/// Reads the packets of the first ffv1 track of a matroska file from the
+/// keyframe preceding a frame.
+///
+/// The frames are counted from the first packet of the track, which is
+/// expected to be followed by one packet every default duration.
+pub struct KeyframeSeeker {
+    reader: Reader,
+    // End of the segment in the file, u64::MAX when its size is unknown
+    segment_end: u64,
+    // TimestampScale of the segment, in nanoseconds
+    timestamp_scale: u64,
+    track: u64,
+    // DefaultDuration of the track, in nanoseconds
+    default_duration: i64,
+    // Timestamps of the cue points of the track, in nanoseconds, and the
+    // positions of their clusters in the file, in increasing order of
+    // timestamp; the first one is the first cluster of the segment
+    cues: Vec<(i64, u64)>,
+    // Timestamp of the cluster being read, in TimestampScale units
+    cluster_timestamp: u64,
+}
+
+impl KeyframeSeeker {
+    /// Opens a matroska file and reads the Cues of its first ffv1 track.
+    ///
+    /// Returns None when the track has no default duration, the timestamps
+    /// of its frames being unknown, or no packet.
+    pub fn open(input_path: &Path) -> io::Result<Option<Self>> {
+        let mut reader = Reader {
+            file: BufReader::new(File::open(input_path)?),
+            pos: 0,
+        };
+
+        match reader.header()? {
+            Some((EBML, Some(size))) => reader.skip(size)?,
+            _ => return Err(invalid_data("Not an EBML file")),
+        }
+        let segment_size = match reader.header()? {
+            Some((SEGMENT, size)) => size,
+            _ => return Err(invalid_data("No matroska segment")),
+        };
+        let segment = reader.pos;
+        let segment_end = segment_size.map_or(u64::MAX, |size| segment + size);
+
+        // Read the elements of the segment preceding its first cluster
+        let mut timestamp_scale = DEFAULT_TIMESTAMP_SCALE;
+        let mut entry = None;
+        let mut cues_position = None;
+        let mut cues = None;
+        let mut first_cluster = None;
+        while reader.pos < segment_end {
+            let start = reader.pos;
+            let (id, size) = match reader.header()? {
+                Some((CLUSTER, _)) => {
+                    first_cluster = Some(start);
+                    break;
+                }
+                Some((id, Some(size))) => (id, size),
+                Some((_, None)) => {
+                    return Err(invalid_data("Unexpected unknown size"))
+                }
+                None => break,
+            };
+            let end = reader.pos + size;
+            match id {
+                SEEK_HEAD => {
+                    while let Some((id, size)) = reader.child(end)? {
+                        if id != SEEK {
+                            reader.skip(size)?;
+                            continue;
+                        }
+                        let seek_end = reader.pos + size;
+                        let (mut seek_id, mut position) = (None, None);
+                        while let Some((id, size)) = reader.child(seek_end)? {
+                            match id {
+                                SEEK_ID => seek_id = Some(reader.bytes(size)?),
+                                SEEK_POSITION => {
+                                    position = Some(reader.uint(size)?)
+                                }
+                                _ => reader.skip(size)?,
+                            }
+                        }
+                        if seek_id.as_deref() == Some(&CUES.to_be_bytes()[..])
+                        {
+                            cues_position = position;
+                        }
+                    }
+                }
+                INFO => {
+                    while let Some((id, size)) = reader.child(end)? {
+                        match id {
+                            TIMESTAMP_SCALE => {
+                                timestamp_scale = reader.uint(size)?
+                            }
+                            _ => reader.skip(size)?,
+                        }
+                    }
+                }
+                TRACKS => {
+                    while let Some((id, size)) = reader.child(end)? {
+                        let end = reader.pos + size;
+                        match id {
+                            TRACK_ENTRY if entry.is_none() => {
+                                let track = track_entry(&mut reader, end)?;
+                                if track.ffv1 {
+                                    entry = Some(track);
+                                }
+                            }
+                            _ => reader.skip(size)?,
+                        }
+                    }
+                }
+                CUES => cues = Some(cue_points(&mut reader, end)?),
+                _ => reader.skip(size)?,
+            }
+        }
+
+        let entry =
+            entry.ok_or_else(|| invalid_data("No ffv1 track detected"))?;
+        let (default_duration, first_cluster) =
+            match (entry.default_duration, first_cluster) {
+                (Some(duration), Some(cluster)) if duration > 0 => {
+                    let duration = i64::try_from(duration)
+                        .map_err(|_| invalid_data("Timestamp overflow"))?;
+                    (duration, cluster)
+                }
+                _ => return Ok(None),
+            };
+
+        // The Cues usually follow the clusters
+        let cues = match (cues, cues_position) {
+            (Some(cues), _) => cues,
+            (None, Some(position)) => {
+                reader.seek(segment + position)?;
+                match reader.header()? {
+                    Some((CUES, Some(size))) => {
+                        let end = reader.pos + size;
+                        cue_points(&mut reader, end)?
+                    }
+                    // Files whose SeekHead is wrong are read from the start
+                    _ => Vec::new(),
+                }
+            }
+            (None, None) => Vec::new(),
+        };
+
+        let mut seeker = KeyframeSeeker {
+            reader,
+            segment_end,
+            timestamp_scale,
+            track: entry.number,
+            default_duration,
+            cues: Vec::new(),
+            cluster_timestamp: 0,
+        };
+
+        // Frames are timed from the first packet of the track
+        seeker.reader.seek(first_cluster)?;
+        let origin = match seeker.next_block()? {
+            Some((time, _)) => time,
+            None => return Ok(None),
+        };
+        seeker.cues.push((origin, first_cluster));
+        for (track, time, position) in cues {
+            let time = seeker.timestamp(time, 0)?;
+            if track == seeker.track && time > origin {
+                seeker.cues.push((time, segment + position));
+            }
+        }
+        seeker.cues.sort_unstable();
+        Ok(Some(seeker))
+    }
+
+    // Returns the timestamp, in nanoseconds, of 'relative' TimestampScale
+    // units from 'timestamp'.
+    fn timestamp(&self, timestamp: u64, relative: i16) -> io::Result<i64> {
+        let overflow = || invalid_data("Timestamp overflow");
+        let timestamp = i64::try_from(timestamp).map_err(|_| overflow())?;
+        let scale =
+            i64::try_from(self.timestamp_scale).map_err(|_| overflow())?;
+        timestamp
+            .checked_add(relative.into())
+            .and_then(|timestamp| timestamp.checked_mul(scale))
+            .ok_or_else(overflow)
+    }
+
+    // Reads the next block of the track, returning its timestamp, in
+    // nanoseconds, and its data, or None at the end of the segment.
+    fn next_block(&mut self) -> io::Result<Option<(i64, Vec<u8>)>> {
+        while self.reader.pos < self.segment_end {
+            let (id, size) = match self.reader.header()? {
+                Some(header) => header,
+                None => break,
+            };
+            // The blocks are read from the clusters and the block groups
+            let size = match (id, size) {
+                (CLUSTER, _) | (BLOCK_GROUP, _) => continue,
+                (_, Some(size)) => size,
+                (_, None) => {
+                    return Err(invalid_data("Unexpected unknown size"))
+                }
+            };
+            match id {
+                TIMESTAMP => {
+                    self.cluster_timestamp = self.reader.uint(size)?
+                }
+                SIMPLE_BLOCK | BLOCK => {
+                    if let Some(block) = self.block(size)? {
+                        return Ok(Some(block));
+                    }
+                }
+                _ => self.reader.skip(size)?,
+            }
+        }
+        Ok(None)
+    }
+
+    // Reads the block of 'size' bytes starting at the current position,
+    // returning its timestamp and its data, or None when it belongs to
+    // another track.
+    fn block(&mut self, size: u64) -> io::Result<Option<(i64, Vec<u8>)>> {
+        let end = self.reader.pos + size;
+        let first = self.reader.byte()?;
+        let (track, length) = self.reader.vint(first)?;
+        let track = track & ((1 << (7 * length)) - 1);
+        let header_end = self.reader.pos + 3;
+        if header_end > end {
+            return Err(invalid_data("Invalid block"));
+        }
+        if track != self.track {
+            self.reader.skip(end - self.reader.pos)?;
+            return Ok(None);
+        }
+        let relative =
+            i16::from_be_bytes([self.reader.byte()?, self.reader.byte()?]);
+        if self.reader.byte()? & LACING != 0 {
+            return Err(invalid_data("Laced blocks are unsupported"));
+        }
+        let time = self.timestamp(self.cluster_timestamp, relative)?;
+        let data = self.reader.bytes(end - header_end)?;
+        Ok(Some((time, data)))
+    }
+
+    // Reads the packets from the first one of the cue point 'cue' up to
+    // the one timed within 'target', or None when the cue point is not on
+    // a keyframe.
+    fn packets_from(
+        &mut self,
+        cue: usize,
+        target: &Range<i64>,
+    ) -> io::Result<Option<Vec<Vec<u8>>>> {
+        let (cue_time, position) = self.cues[cue];
+        self.reader.seek(position)?;
+        let mut packets = Vec::new();
+        while let Some((time, data)) = self.next_block()? {
+            // The cluster may start before the cue point
+            if time < cue_time {
+                continue;
+            }
+            if packets.is_empty() && !is_keyframe(&data) {
+                return Ok(None);
+            }
+            if time >= target.end {
+                return Err(invalid_data("Frame missing from the input file"));
+            }
+            packets.push(data);
+            if time >= target.start {
+                return Ok(Some(packets));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Frame past the end of the input file",
+        ))
+    }
+
+    /// Returns the packets of the track from the last keyframe at or
+    /// before 'frame' up to 'frame', which decode it since keyframes reset
+    /// all the decoder states.
+    pub fn keyframe_packets(
+        &mut self,
+        frame: usize,
+    ) -> io::Result<Vec<Vec<u8>>> {
+        // Timestamps within half a frame of the one of 'frame' match it
+        let tolerance = self.default_duration / 2;
+        let target = i64::try_from(frame)
+            .ok()
+            .and_then(|frame| frame.checked_mul(self.default_duration))
+            .and_then(|time| time.checked_add(self.cues[0].0))
+            .and_then(|time| {
+                Some(
+                    time.checked_sub(tolerance)?
+                        ..time.checked_add(tolerance)?,
+                )
+            })
+            .ok_or_else(|| invalid_data("Timestamp overflow"))?;
+        let last = self
+            .cues
+            .iter()
+            .rposition(|&(time, _)| time < target.end)
+            .unwrap_or(0);
+        // The cue points are expected on keyframes, otherwise the previous
+        // ones are tried
+        for cue in (0..=last).rev() {
+            if let Some(packets) = self.packets_from(cue, &target)? {
+                return Ok(packets);
+            }
+        }
+        Err(invalid_data("No keyframe precedes the frame"))
+    }
+}
@@ -0,0 +1,127 @@
+//! Matroska track entries of ffv1 tracks, e.g. for muxers to write files
+//! which FFmpeg and VLC open like those written by FFmpeg.
+
+use crate::extradata::{codec_private, CODEC_ID};
+
+// EBML IDs of the elements of a track entry
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const FLAG_LACING: u32 = 0x9C;
+const LANGUAGE: u32 = 0x22B59C;
+const TRACK_TYPE: u32 = 0x83;
+const DEFAULT_DURATION: u32 = 0x23E383;
+const CODEC_ID_ELEMENT: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CODEC_PRIVATE: u32 = 0x63A2;
+
+// TrackType of video tracks
+const VIDEO_TRACK: u64 = 1;
+
+/// Writes the EBML element 'id' holding 'payload'.
+fn put_element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    let id = id.to_be_bytes();
+    let skip = id.iter().take_while(|&&byte| byte == 0).count();
+    out.extend_from_slice(&id[skip..]);
+
+    // The size is coded on as few bytes as possible
+    let size = payload.len() as u64;
+    let length = (1..=8).find(|&n| size < (1 << (7 * n)) - 1).unwrap_or(8);
+    let marked = size | (1 << (7 * length));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+    out.extend_from_slice(payload);
+}
+
+/// Writes the EBML unsigned integer element 'id' of value 'value'.
+fn put_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(7);
+    put_element(out, id, &bytes[skip..]);
+}
+
+/// The TrackEntry element of an ffv1 track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackEntry {
+    number: u64,
+    uid: u64,
+    width: u32,
+    height: u32,
+    default_duration: Option<u64>,
+    codec_private: Vec<u8>,
+}
+
+impl TrackEntry {
+    /// Creates the entry of the track 'number' of 'width'x'height'
+    /// frames coded with the configuration record 'record'. Its UID is
+    /// its number.
+    pub fn new(number: u64, record: &[u8], width: u32, height: u32) -> Self {
+        Self {
+            number,
+            uid: number,
+            width,
+            height,
+            default_duration: None,
+            codec_private: codec_private(record, width, height),
+        }
+    }
+
+    /// Sets the UID of the track, which has to be unique in the file.
+    pub fn set_uid(&mut self, uid: u64) {
+        self.uid = uid;
+    }
+
+    /// Sets the frame rate of the track, as 'num'/'den' frames per
+    /// second. The frame rate is variable by default.
+    pub fn set_framerate(&mut self, num: u32, den: u32) {
+        self.default_duration = if num == 0 || den == 0 {
+            None
+        } else {
+            // Rounded to the nearest nanosecond
+            let (num, den) = (u64::from(num), u64::from(den));
+            Some((1_000_000_000 * den + num / 2) / num)
+        };
+    }
+
+    /// Returns the DefaultDuration of the track, in nanoseconds, or None
+    /// when its frame rate is variable.
+    pub fn default_duration(&self) -> Option<u64> {
+        self.default_duration
+    }
+
+    /// Returns the CodecID of the track.
+    pub fn codec_id(&self) -> &'static str {
+        CODEC_ID
+    }
+
+    /// Returns the CodecPrivate of the track.
+    pub fn codec_private(&self) -> &[u8] {
+        &self.codec_private
+    }
+
+    /// Returns the TrackEntry element, as stored in the Tracks element.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut video = Vec::new();
+        put_uint(&mut video, PIXEL_WIDTH, self.width.into());
+        put_uint(&mut video, PIXEL_HEIGHT, self.height.into());
+
+        let mut entry = Vec::new();
+        put_uint(&mut entry, TRACK_NUMBER, self.number);
+        put_uint(&mut entry, TRACK_UID, self.uid);
+        // Every block holds a single frame
+        put_uint(&mut entry, FLAG_LACING, 0);
+        put_element(&mut entry, LANGUAGE, b"und");
+        put_uint(&mut entry, TRACK_TYPE, VIDEO_TRACK);
+        if let Some(duration) = self.default_duration {
+            put_uint(&mut entry, DEFAULT_DURATION, duration);
+        }
+        put_element(&mut entry, CODEC_ID_ELEMENT, CODEC_ID.as_bytes());
+        put_element(&mut entry, VIDEO, &video);
+        put_element(&mut entry, CODEC_PRIVATE, &self.codec_private);
+
+        let mut out = Vec::with_capacity(entry.len() + 9);
+        put_element(&mut out, TRACK_ENTRY, &entry);
+        out
+    }
+}
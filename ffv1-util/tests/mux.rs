@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use av_format::demuxer::Context;
+
+use ffv1::record::ConfigRecord;
+
+use ffv1_util::demux::{open_first_track, DecParams};
+
+// Opens a fixture of the ffv1 crate and reads its first ffv1 track
+fn open(input: &str) -> (Context, DecParams) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(input);
+    open_first_track(&path).unwrap()
+}
+
+#[test]
+fn test_track_entry() {
+    use ffv1_util::extradata::{codec_private, config_record};
+    use ffv1_util::track::TrackEntry;
+
+    // The CodecPrivate is written as FFmpeg wrote it in the fixture
+    let (demuxer, params) = open("data/ffv1_v3.mkv");
+    let (record, width, height) = (&params.extradata, 640, 360);
+    let original = demuxer.info.streams[0].params.extradata.as_ref();
+    let written = codec_private(record, width, height);
+    assert_eq!(Some(&written), original);
+    assert_eq!(config_record(&written), Some(&record[..]));
+
+    let mut entry = TrackEntry::new(1, record, width, height);
+    assert_eq!(entry.default_duration(), None);
+    entry.set_framerate(25, 1);
+    assert_eq!(entry.default_duration(), Some(40_000_000));
+    assert_eq!(entry.codec_private(), &written[..]);
+
+    // TrackEntry, whose size takes two bytes
+    let mut expected = vec![0xAE, 0x40, 140];
+    expected.extend_from_slice(&[0xD7, 0x81, 1]); // TrackNumber
+    expected.extend_from_slice(&[0x73, 0xC5, 0x81, 1]); // TrackUID
+    expected.extend_from_slice(&[0x9C, 0x81, 0]); // FlagLacing
+    expected.extend_from_slice(&[0x22, 0xB5, 0x9C, 0x83]); // Language
+    expected.extend_from_slice(b"und");
+    expected.extend_from_slice(&[0x83, 0x81, 1]); // TrackType
+    expected.extend_from_slice(&[0x23, 0xE3, 0x83, 0x84]); // DefaultDuration
+    expected.extend_from_slice(&40_000_000u32.to_be_bytes());
+    expected.extend_from_slice(&[0x86, 0x8F]); // CodecID
+    expected.extend_from_slice(b"V_MS/VFW/FOURCC");
+    expected.extend_from_slice(&[0xE0, 0x88]); // Video
+    expected.extend_from_slice(&[0xB0, 0x82, 0x02, 0x80]); // PixelWidth
+    expected.extend_from_slice(&[0xBA, 0x82, 0x01, 0x68]); // PixelHeight
+    expected.extend_from_slice(&[0x63, 0xA2, 0x80 | 82]); // CodecPrivate
+    expected.extend_from_slice(&written);
+    assert_eq!(entry.to_bytes(), expected);
+}
+
+#[test]
+fn test_sample_entry() {
+    use ffv1_util::isobmff::{glbl_box, glbl_record, sample_entry};
+
+    let record = open("data/ffv1_v3.mkv").1.extradata;
+    let parsed = ConfigRecord::parse_config_record(&record, 640, 360).unwrap();
+
+    let glbl = glbl_box(&parsed);
+    assert_eq!(glbl.len(), 8 + record.len());
+    assert_eq!(glbl_record(&glbl), Some(&record[..]));
+    assert_eq!(glbl_record(&glbl[..glbl.len() - 1]), None);
+
+    // VisualSampleEntry, followed by the glbl box
+    let mut expected = (8 + 78 + glbl.len() as u32).to_be_bytes().to_vec();
+    expected.extend_from_slice(b"FFV1");
+    expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    expected.extend_from_slice(&[0; 16]);
+    expected.extend_from_slice(&[0x02, 0x80, 0x01, 0x68]); // 640x360
+    expected.extend_from_slice(&[0, 0x48, 0, 0, 0, 0x48, 0, 0]); // 72 dpi
+    expected.extend_from_slice(&[0, 0, 0, 0, 0, 1]); // frame_count
+    expected.extend_from_slice(&[0; 32]);
+    expected.extend_from_slice(&[0, 0x18, 0xFF, 0xFF]);
+    expected.extend_from_slice(&glbl);
+    assert_eq!(sample_entry(&parsed), Some(expected));
+
+    let large =
+        ConfigRecord::parse_config_record(&record, 65536, 360).unwrap();
+    assert_eq!(sample_entry(&large), None);
+}
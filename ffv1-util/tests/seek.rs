@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+
+use ffv1_util::demux::{next_packet, open_first_track};
+use ffv1_util::seek::KeyframeSeeker;
+
+// Returns the path of a fixture of the ffv1 crate
+fn fixture(input: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(input)
+}
+
+#[test]
+fn test_keyframe_packets() {
+    let path = fixture("data/ffv1_v3.mkv");
+
+    // Demux the whole track from its start
+    let (mut demuxer, params) = open_first_track(&path).unwrap();
+    let mut packets = Vec::new();
+    while let Some(pkt) = next_packet(&mut demuxer).unwrap() {
+        if pkt.stream_index == params.stream_index {
+            packets.push(pkt.data);
+        }
+    }
+
+    // Keyframes start every cluster of the fixture, every 12 frames
+    let mut seeker = KeyframeSeeker::open(&path).unwrap().unwrap();
+    assert_eq!(seeker.keyframe_packets(0).unwrap(), &packets[..1]);
+    assert_eq!(seeker.keyframe_packets(13).unwrap(), &packets[12..14]);
+    let last = packets.len() - 1;
+    assert_eq!(
+        seeker.keyframe_packets(last).unwrap(),
+        &packets[last / 12 * 12..]
+    );
+    assert!(seeker.keyframe_packets(packets.len()).is_err());
+
+    // The frames of tracks with no default duration have no timestamp
+    let path = fixture("data/ffv1_v3_gray.mkv");
+    assert!(KeyframeSeeker::open(&path).unwrap().is_none());
+}
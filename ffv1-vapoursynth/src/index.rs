@@ -0,0 +1,186 @@
+//! Packet index of the first ffv1 track of a matroska file.
+//!
+//! The file is demuxed once, when the clip is created, recording where
+//! every packet is stored, so that any frame is later read back without
+//! demuxing the file again.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use av_data::params::MediaKind;
+use av_format::buffer::AccReader;
+use av_format::demuxer::{Context, Event};
+use failure::{format_err, Error};
+
+use matroska::demuxer::MkvDemuxer;
+
+use ffv1::scan_keyframes;
+
+use ffv1_util::extradata::config_record;
+
+// Size of the reads used to locate the packets in the file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Packets of an ffv1 track and their keyframes
+pub struct Track {
+    pub width: u32,
+    pub height: u32,
+    // Configuration record, without the VFW header
+    pub extradata: Vec<u8>,
+    // Position and size of every packet in the file
+    packets: Vec<(u64, usize)>,
+    // Indices of the keyframes, in increasing order
+    keyframes: Vec<usize>,
+}
+
+impl Track {
+    // Returns the number of frames of the track
+    pub fn num_frames(&self) -> usize {
+        self.packets.len()
+    }
+
+    // Returns the last keyframe at or before 'frame'
+    pub fn keyframe_before(&self, frame: usize) -> Result<usize, Error> {
+        match self.keyframes.binary_search(&frame) {
+            Ok(i) => Ok(self.keyframes[i]),
+            Err(0) => Err(format_err!("No keyframe precedes frame {}", frame)),
+            Err(i) => Ok(self.keyframes[i - 1]),
+        }
+    }
+
+    // Reads the packet of 'frame' from the file
+    pub fn read_packet(
+        &self,
+        file: &mut File,
+        frame: usize,
+    ) -> io::Result<Vec<u8>> {
+        let (pos, size) = self.packets[frame];
+        let mut packet = vec![0; size];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut packet)?;
+        Ok(packet)
+    }
+}
+
+// Finds the packets in the file as they are demuxed.
+//
+// Packets are stored in order and unmodified in the file, so each one is
+// searched for right after the previous one, reading the file only once.
+struct Locator {
+    file: File,
+    // Bytes of the file not yet searched
+    window: Vec<u8>,
+    // Position of 'window' in the file
+    start: u64,
+}
+
+impl Locator {
+    fn locate(&mut self, packet: &[u8]) -> Result<u64, Error> {
+        if packet.is_empty() {
+            return Err(format_err!("Empty packet"));
+        }
+        loop {
+            if let Some(pos) = self
+                .window
+                .windows(packet.len())
+                .position(|window| window == packet)
+            {
+                let offset = self.start + pos as u64;
+                let end = pos + packet.len();
+                self.window.drain(..end);
+                self.start += end as u64;
+                return Ok(offset);
+            }
+
+            // Keep only the bytes which may start the packet
+            let keep = self.window.len().min(packet.len() - 1);
+            let skip = self.window.len() - keep;
+            self.window.drain(..skip);
+            self.start += skip as u64;
+
+            let len = self.window.len();
+            self.window.resize(len + CHUNK_SIZE, 0);
+            let read = self.file.read(&mut self.window[len..])?;
+            self.window.truncate(len + read);
+            if read == 0 {
+                return Err(format_err!("Packet not found in the file"));
+            }
+        }
+    }
+}
+
+// Demuxes the first ffv1 track of a matroska file into a packet index.
+pub fn index(path: &Path) -> Result<Track, Error> {
+    let reader = File::open(path)?;
+    let ar = AccReader::with_capacity(4 * 1024, reader);
+    let mut demuxer = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
+    demuxer
+        .read_headers()
+        .map_err(|err| format_err!("Cannot parse the headers: {:?}", err))?;
+
+    // Look for the first ffv1 track
+    let (stream_index, width, height, extradata) = demuxer
+        .info
+        .streams
+        .iter()
+        .find_map(|stream| match &stream.params.kind {
+            Some(MediaKind::Video(info)) => {
+                let extradata = stream.params.extradata.as_ref()?;
+                config_record(extradata).map(|record| {
+                    (
+                        stream.index as isize,
+                        info.width as u32,
+                        info.height as u32,
+                        record.to_owned(),
+                    )
+                })
+            }
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("No ffv1 track found"))?;
+
+    let mut locator = Locator {
+        file: File::open(path)?,
+        window: Vec::new(),
+        start: 0,
+    };
+    let mut packets = Vec::new();
+    let mut error = None;
+
+    // Every packet is located while the keyframes are scanned
+    let keyframes = scan_keyframes(std::iter::from_fn(|| loop {
+        match demuxer.read_event() {
+            Ok(Event::NewPacket(pkt)) => {
+                if pkt.stream_index != stream_index {
+                    continue;
+                }
+                match locator.locate(&pkt.data) {
+                    Ok(pos) => packets.push((pos, pkt.data.len())),
+                    Err(err) => {
+                        error = Some(err);
+                        return None;
+                    }
+                }
+                return Some(pkt.data);
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            Ok(_) => continue,
+        }
+    }));
+
+    if let Some(err) = error {
+        return Err(err);
+    }
+    if packets.is_empty() {
+        return Err(format_err!("The ffv1 track has no packets"));
+    }
+
+    Ok(Track {
+        width,
+        height,
+        extradata,
+        packets,
+        keyframes,
+    })
+}
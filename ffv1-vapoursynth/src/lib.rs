@@ -0,0 +1,252 @@
+//! VapourSynth source filter decoding the first ffv1 track of a matroska
+//! file.
+//!
+//! ```python
+//! clip = core.ffv1.Source("input.mkv")
+//! ```
+//!
+//! Frames can be requested in any order: the packets are indexed when the
+//! clip is created, and each frame is decoded starting from the last
+//! keyframe preceding it, or right after the last decoded frame when it
+//! belongs to the same group of frames.
+
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+
+use failure::{format_err, Error};
+use vapoursynth::api::API;
+use vapoursynth::core::CoreRef;
+use vapoursynth::format::{ColorFamily, Format, SampleType};
+use vapoursynth::frame::{FrameRef, FrameRefMut};
+use vapoursynth::plugins::{Filter, FrameContext, Metadata};
+use vapoursynth::video_info::{
+    Flags, Framerate, Property, Resolution, VideoInfo,
+};
+use vapoursynth::{export_vapoursynth_plugin, make_filter_function};
+
+use ffv1::constants::ColorSpace;
+use ffv1::decoder::{Decoder, Frame};
+
+mod index;
+
+use crate::index::{index, Track};
+
+// Decoder of the track and the next frame it can decode
+struct Cursor {
+    file: File,
+    decoder: Decoder,
+    next: usize,
+}
+
+struct Source<'core> {
+    track: Track,
+    format: Format<'core>,
+    framerate: Property<Framerate>,
+    cursor: Mutex<Cursor>,
+}
+
+impl<'core> Source<'core> {
+    // Decodes 'n', starting from its keyframe unless the decoder has just
+    // decoded a frame of the same group of frames preceding it.
+    fn decode(&self, n: usize) -> Result<Frame, Error> {
+        let mut guard = self
+            .cursor
+            .lock()
+            .map_err(|_| format_err!("A previous decoding panicked"))?;
+        let cursor = &mut *guard;
+
+        let keyframe = self.track.keyframe_before(n)?;
+        if cursor.next > n || cursor.next < keyframe {
+            cursor.next = keyframe;
+        }
+
+        loop {
+            // After an error, decoding restarts from a keyframe
+            let frame = match self.decode_next(cursor) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    cursor.next = 0;
+                    return Err(err);
+                }
+            };
+            cursor.next += 1;
+            if cursor.next > n {
+                return Ok(frame);
+            }
+        }
+    }
+
+    // Decodes the next frame of the cursor
+    fn decode_next(&self, cursor: &mut Cursor) -> Result<Frame, Error> {
+        let packet = self.track.read_packet(&mut cursor.file, cursor.next)?;
+        Ok(cursor.decoder.decode_frame(&packet)?)
+    }
+}
+
+// Copies a plane of 'frame' into a plane of 'output'.
+//
+// FFV1 stores RGB planes as green, blue and red, while VapourSynth expects
+// red, green and blue.
+fn copy_plane(frame: &Frame, output: &mut FrameRefMut, plane: usize) {
+    let source = if frame.color_space() == ColorSpace::Rgb {
+        [2, 0, 1][plane]
+    } else {
+        plane
+    };
+    let (width, height) = frame.plane_dimensions(source);
+    let offset = frame.offsets()[source];
+    let stride = frame.strides()[source];
+    for y in 0..height {
+        let start = offset + y * stride;
+        if frame.bit_depth() == 8 {
+            output
+                .plane_row_mut::<u8>(plane, y)
+                .copy_from_slice(&frame.buf()[source][start..start + width]);
+        } else {
+            output
+                .plane_row_mut::<u16>(plane, y)
+                .copy_from_slice(&frame.buf16()[source][start..start + width]);
+        }
+    }
+}
+
+impl<'core> Filter<'core> for Source<'core> {
+    fn video_info(
+        &self,
+        _api: API,
+        _core: CoreRef<'core>,
+    ) -> Vec<VideoInfo<'core>> {
+        vec![VideoInfo {
+            format: Property::Constant(self.format),
+            framerate: self.framerate,
+            resolution: Property::Constant(Resolution {
+                width: self.track.width as usize,
+                height: self.track.height as usize,
+            }),
+            num_frames: self.track.num_frames(),
+            flags: Flags::empty(),
+        }]
+    }
+
+    fn get_frame_initial(
+        &self,
+        _api: API,
+        core: CoreRef<'core>,
+        _context: FrameContext,
+        n: usize,
+    ) -> Result<Option<FrameRef<'core>>, Error> {
+        let frame = self.decode(n)?;
+
+        let resolution = Resolution {
+            width: frame.width() as usize,
+            height: frame.height() as usize,
+        };
+        let mut output = unsafe {
+            FrameRefMut::new_uninitialized(core, None, self.format, resolution)
+        };
+        // The alpha plane, if any, is not exported
+        for plane in 0..self.format.plane_count() {
+            copy_plane(&frame, &mut output, plane);
+        }
+
+        Ok(Some(output.into()))
+    }
+
+    fn get_frame(
+        &self,
+        _api: API,
+        _core: CoreRef<'core>,
+        _context: FrameContext,
+        _n: usize,
+    ) -> Result<FrameRef<'core>, Error> {
+        unreachable!()
+    }
+}
+
+make_filter_function! {
+    SourceFunction, "Source"
+
+    fn create_source<'core>(
+        _api: API,
+        core: CoreRef<'core>,
+        source: &[u8],
+        fpsnum: Option<i64>,
+        fpsden: Option<i64>,
+        threads: Option<i64>,
+    ) -> Result<Option<Box<dyn Filter<'core> + 'core>>, Error> {
+        let path = std::str::from_utf8(source)?;
+        let track = index(Path::new(path))?;
+
+        let mut decoder =
+            Decoder::new(&track.extradata, track.width, track.height)?;
+        if let Some(threads) = threads {
+            if threads < 1 {
+                return Err(format_err!("threads must be at least 1"));
+            }
+            decoder.set_threads(threads as usize);
+        }
+
+        let record = decoder.config_record();
+        let (family, sub_w, sub_h) = match record.colorspace_type {
+            ColorSpace::Rgb => (ColorFamily::RGB, 0, 0),
+            ColorSpace::YCbCr if !record.chroma_planes => {
+                (ColorFamily::Gray, 0, 0)
+            }
+            ColorSpace::YCbCr => (
+                ColorFamily::YUV,
+                record.log2_h_chroma_subsample,
+                record.log2_v_chroma_subsample,
+            ),
+        };
+        let format = core
+            .register_format(
+                family,
+                SampleType::Integer,
+                record.bits_per_raw_sample,
+                sub_w,
+                sub_h,
+            )
+            .ok_or_else(|| format_err!("Unsupported pixel format"))?;
+
+        let framerate = match (fpsnum, fpsden) {
+            (None, None) => Property::Variable,
+            (Some(numerator), denominator) => {
+                let denominator = denominator.unwrap_or(1);
+                if numerator < 1 || denominator < 1 {
+                    return Err(format_err!("Invalid frame rate"));
+                }
+                Property::Constant(Framerate {
+                    numerator: numerator as u64,
+                    denominator: denominator as u64,
+                })
+            }
+            (None, Some(_)) => {
+                return Err(format_err!("fpsden requires fpsnum"));
+            }
+        };
+
+        let cursor = Cursor {
+            file: File::open(path)?,
+            decoder,
+            next: 0,
+        };
+
+        Ok(Some(Box::new(Source {
+            track,
+            format,
+            framerate,
+            cursor: Mutex::new(cursor),
+        })))
+    }
+}
+
+export_vapoursynth_plugin! {
+    Metadata {
+        identifier: "com.github.rust-av.ffv1",
+        namespace: "ffv1",
+        name: "FFV1 source filter",
+        read_only: true,
+    },
+    [SourceFunction::new()]
+}
@@ -0,0 +1,161 @@
+//! Allocation of the planes of decoded frames.
+//!
+//! Memory that cannot be owned by a Vec, e.g. a shared memory segment, can
+//! be decoded into directly with 'Decoder::decode_frame_into' instead, and
+//! decoders given a 'Capacity' allocate everything up front.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::decoder::Frame;
+use crate::error::{Error, Result};
+use crate::record::ConfigRecord;
+
+/// Allocates the planes of the frames returned by 'Decoder::decode_frame'.
+///
+/// Planes must be exactly 'len' samples long. Their contents do not need
+/// to be initialized to any value, since every sample is overwritten but
+/// for the padding added by 'Decoder::set_alignment'.
+pub trait FrameAllocator: fmt::Debug + Send + Sync {
+    /// Allocates an 8-bit plane of 'len' samples.
+    fn alloc_u8(&self, len: usize) -> Vec<u8> {
+        vec![0; len]
+    }
+
+    /// Allocates a 16-bit plane of 'len' samples.
+    fn alloc_u16(&self, len: usize) -> Vec<u16> {
+        vec![0; len]
+    }
+}
+
+/// Allocator using the global allocator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalAllocator;
+
+impl FrameAllocator for GlobalAllocator {}
+
+/// Allocator reusing the planes of recycled frames, avoiding an
+/// allocation per plane and frame once the pool is warm.
+#[derive(Debug, Default)]
+pub struct FramePool {
+    planes: Mutex<Vec<Vec<u8>>>,
+    planes16: Mutex<Vec<Vec<u16>>>,
+}
+
+/// Takes a plane of at least 'len' samples from 'pool', if any.
+fn take<T: Clone + Default>(pool: &Mutex<Vec<Vec<T>>>, len: usize) -> Vec<T> {
+    let mut pool = pool.lock().unwrap_or_else(|err| err.into_inner());
+    match pool.iter().position(|plane| plane.capacity() >= len) {
+        Some(i) => {
+            let mut plane = pool.swap_remove(i);
+            plane.resize(len, T::default());
+            plane
+        }
+        None => vec![T::default(); len],
+    }
+}
+
+impl FramePool {
+    /// Creates an empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives the planes of a frame back to the pool.
+    pub fn recycle(&self, frame: Frame) {
+        let Frame { buf, buf16, .. } = frame;
+        self.planes
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(buf);
+        self.planes16
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(buf16);
+    }
+}
+
+impl FrameAllocator for FramePool {
+    fn alloc_u8(&self, len: usize) -> Vec<u8> {
+        take(&self.planes, len)
+    }
+
+    fn alloc_u16(&self, len: usize) -> Vec<u16> {
+        take(&self.planes16, len)
+    }
+}
+
+/// Samples whose planes can be allocated by a FrameAllocator.
+pub(crate) trait Allocate: Sized {
+    fn allocate(allocator: &dyn FrameAllocator, len: usize) -> Vec<Self>;
+}
+
+impl Allocate for u8 {
+    fn allocate(allocator: &dyn FrameAllocator, len: usize) -> Vec<Self> {
+        allocator.alloc_u8(len)
+    }
+}
+
+impl Allocate for u16 {
+    fn allocate(allocator: &dyn FrameAllocator, len: usize) -> Vec<Self> {
+        allocator.alloc_u16(len)
+    }
+}
+
+/// Largest streams a fixed-capacity decoder accepts, see
+/// 'Decoder::set_capacity'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    /// Largest frame width, in pixels.
+    pub max_width: u32,
+    /// Largest frame height, in pixels.
+    pub max_height: u32,
+    /// Largest bit depth of the samples.
+    pub max_bit_depth: u8,
+    /// Largest number of slices of a frame.
+    pub max_slices: usize,
+}
+
+impl Capacity {
+    /// Creates a capacity for streams of up to 'max_width' by 'max_height'
+    /// frames of 'max_bit_depth' samples, coded in up to 'max_slices'
+    /// slices.
+    pub fn new(
+        max_width: u32,
+        max_height: u32,
+        max_bit_depth: u8,
+        max_slices: usize,
+    ) -> Self {
+        Self {
+            max_width,
+            max_height,
+            max_bit_depth,
+            max_slices,
+        }
+    }
+
+    /// Checks that the streams of 'record' fit, including their slice
+    /// grid.
+    pub(crate) fn check(&self, record: &ConfigRecord) -> Result<()> {
+        let grid = (record.num_h_slices_minus1 as usize + 1)
+            * (record.num_v_slices_minus1 as usize + 1);
+        if record.width > self.max_width || record.height > self.max_height {
+            Err(Error::ResourceError(format!(
+                "{}x{} frames exceed the {}x{} capacity",
+                record.width, record.height, self.max_width, self.max_height
+            )))
+        } else if record.bits_per_raw_sample > self.max_bit_depth {
+            Err(Error::ResourceError(format!(
+                "{}-bit samples exceed the {}-bit capacity",
+                record.bits_per_raw_sample, self.max_bit_depth
+            )))
+        } else if grid > self.max_slices {
+            Err(Error::ResourceError(format!(
+                "{} slices exceed the capacity of {} slices",
+                grid, self.max_slices
+            )))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Incremental assembly of packets from a byte stream.
+//!
+//! FFV1 frames are not self-delimiting: slices are located from the end of
+//! the frame, so the size of a frame must be known before it can be
+//! decoded. That size comes either from the container, e.g. a Matroska
+//! block size, or from a framing layer which prefixes every packet with its
+//! size.
+
+use std::collections::VecDeque;
+
+use crate::decoder::{Decoder, Frame};
+use crate::error::{Error, Result};
+
+/// Size of the prefix of length-prefixed packets.
+const PREFIX_SIZE: usize = 4;
+
+/// Default maximum size of a packet, see
+/// 'PacketAssembler::set_max_packet_size'.
+const DEFAULT_MAX_PACKET_SIZE: usize = 1 << 30;
+
+/// How the packet boundaries are found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// The sizes of the packets are provided by the container, see
+    /// 'PacketAssembler::push_size'.
+    Sized,
+    /// Every packet is preceded by its size, as a 32-bit big-endian
+    /// integer.
+    LengthPrefixed,
+}
+
+/// Assembles packets from chunks of arbitrary sizes, e.g. as read from a
+/// socket, and feeds them to a decoder once they are complete.
+#[derive(Debug)]
+pub struct PacketAssembler {
+    framing: Framing,
+    buf: Vec<u8>,
+    pos: usize,
+    sizes: VecDeque<usize>,
+    max_packet_size: usize,
+}
+
+impl PacketAssembler {
+    /// Creates an assembler finding the packet boundaries with 'framing'.
+    pub fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            buf: Vec::new(),
+            pos: 0,
+            sizes: VecDeque::new(),
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+        }
+    }
+
+    /// Sets the size above which packets are rejected as invalid, so that
+    /// a corrupted size does not buffer the whole stream.
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.max_packet_size = max_packet_size;
+    }
+
+    /// Appends a chunk of the stream.
+    pub fn push(&mut self, chunk: &[u8]) {
+        // Drop the bytes of the returned packets before growing the buffer.
+        if self.pos > 0 && self.pos >= self.buf.len() / 2 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Queues the size of an upcoming packet, as provided by the
+    /// container. Only used with 'Framing::Sized'.
+    pub fn push_size(&mut self, size: usize) -> Result<()> {
+        if self.framing != Framing::Sized {
+            return Err(Error::InvalidConfiguration(
+                "packet sizes are read from the stream".to_owned(),
+            ));
+        }
+        self.check_size(size)?;
+        self.sizes.push_back(size);
+        Ok(())
+    }
+
+    /// Returns the number of buffered bytes not returned as packets yet.
+    pub fn pending(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// Returns the next complete packet, if any.
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>> {
+        let available = &self.buf[self.pos..];
+        let (start, size) = match self.framing {
+            Framing::Sized => match self.sizes.front() {
+                Some(&size) => (0, size),
+                None => return Ok(None),
+            },
+            Framing::LengthPrefixed => {
+                if available.len() < PREFIX_SIZE {
+                    return Ok(None);
+                }
+                let mut prefix = [0; PREFIX_SIZE];
+                prefix.copy_from_slice(&available[..PREFIX_SIZE]);
+                let size = u32::from_be_bytes(prefix) as usize;
+                self.check_size(size)?;
+                (PREFIX_SIZE, size)
+            }
+        };
+        if available.len() < start + size {
+            return Ok(None);
+        }
+
+        let packet = available[start..start + size].to_vec();
+        self.pos += start + size;
+        if self.framing == Framing::Sized {
+            self.sizes.pop_front();
+        }
+        Ok(Some(packet))
+    }
+
+    /// Decodes the next complete packet, if any.
+    pub fn decode_next(
+        &mut self,
+        decoder: &mut Decoder,
+    ) -> Result<Option<Frame>> {
+        match self.next_packet()? {
+            Some(packet) => decoder.decode_frame(&packet).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Discards the buffered bytes and sizes, e.g. to resume at another
+    /// position of the stream.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.pos = 0;
+        self.sizes.clear();
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size == 0 || size > self.max_packet_size {
+            return Err(Error::InvalidInputData(format!(
+                "invalid packet size {}",
+                size
+            )));
+        }
+        Ok(())
+    }
+}
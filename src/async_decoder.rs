@@ -0,0 +1,138 @@
+//! Asynchronous decoding on top of the tokio blocking thread pool.
+//!
+//! Decoding a frame is CPU-bound, so it is moved off the async executor
+//! with 'tokio::task::spawn_blocking'. Frames still depend on the states of
+//! the previous ones, so at most one frame is decoded at a time.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::task::{spawn_blocking, JoinError, JoinHandle};
+
+use crate::decoder::{Decoder, Frame};
+use crate::error::{Error, Result};
+
+type DecodeTask = JoinHandle<(Decoder, Result<Frame>)>;
+
+/// Moves 'decoder' to the blocking thread pool to decode 'packet'.
+fn spawn_decode(mut decoder: Decoder, packet: Vec<u8>) -> DecodeTask {
+    spawn_blocking(move || {
+        let frame = decoder.decode_frame(&packet);
+        (decoder, frame)
+    })
+}
+
+/// Turns a failed decoding task, i.e. a panic, into an error.
+fn task_error(err: JoinError) -> Error {
+    Error::ResourceError(format!("decoding task failed: {}", err))
+}
+
+/// Returns the error for a decoder lost in a failed decoding task.
+fn lost_decoder() -> Error {
+    Error::ResourceError("decoder lost in a failed decoding task".to_owned())
+}
+
+/// Asynchronous wrapper around a Decoder.
+///
+/// If a decoding task panics, the decoder is lost and every following
+/// call fails.
+pub struct AsyncDecoder {
+    decoder: Option<Decoder>,
+}
+
+impl AsyncDecoder {
+    /// Wraps a decoder.
+    pub fn new(decoder: Decoder) -> Self {
+        Self {
+            decoder: Some(decoder),
+        }
+    }
+
+    /// Returns the wrapped decoder, if it has not been lost.
+    pub fn into_inner(self) -> Option<Decoder> {
+        self.decoder
+    }
+
+    /// Decodes a packet on the blocking thread pool.
+    pub async fn decode_frame(&mut self, packet: Vec<u8>) -> Result<Frame> {
+        let decoder = self.decoder.take().ok_or_else(lost_decoder)?;
+        let (decoder, frame) =
+            spawn_decode(decoder, packet).await.map_err(task_error)?;
+        self.decoder = Some(decoder);
+        frame
+    }
+
+    /// Returns a stream of the frames decoded from a stream of packets,
+    /// e.g. coming from an asynchronous demuxer.
+    ///
+    /// The stream ends when the packets end, or after a decoding task
+    /// failed.
+    pub fn into_stream<S>(self, packets: S) -> FrameStream<S>
+    where
+        S: Stream<Item = Vec<u8>> + Unpin,
+    {
+        FrameStream {
+            packets,
+            decoder: self.decoder,
+            task: None,
+        }
+    }
+}
+
+/// Stream of decoded frames, see 'AsyncDecoder::into_stream'.
+pub struct FrameStream<S> {
+    packets: S,
+    decoder: Option<Decoder>,
+    task: Option<DecodeTask>,
+}
+
+impl<S> Stream for FrameStream<S>
+where
+    S: Stream<Item = Vec<u8>> + Unpin,
+{
+    type Item = Result<Frame>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if let Some(task) = this.task.as_mut() {
+            let result = match Pin::new(task).poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            };
+            this.task = None;
+            return Poll::Ready(Some(match result {
+                Ok((decoder, frame)) => {
+                    this.decoder = Some(decoder);
+                    frame
+                }
+                Err(err) => Err(task_error(err)),
+            }));
+        }
+
+        let decoder = match this.decoder.take() {
+            Some(decoder) => decoder,
+            None => return Poll::Ready(None),
+        };
+        match Pin::new(&mut this.packets).poll_next(cx) {
+            Poll::Ready(Some(packet)) => {
+                this.task = Some(spawn_decode(decoder, packet));
+                // Poll the task right away, so it registers the waker.
+                Pin::new(this).poll_next(cx)
+            }
+            Poll::Ready(None) => {
+                this.decoder = Some(decoder);
+                Poll::Ready(None)
+            }
+            Poll::Pending => {
+                this.decoder = Some(decoder);
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Limits on the work done decoding a slice, so that crafted slices can't
+//! keep a decoder busy, see 'Decoder::set_slice_budget'.
+
+use crate::error::{Error, Result};
+
+/// Range coders may read a couple of bytes past the end of a slice.
+const LOOKAHEAD_BITS: u64 = 16;
+
+/// Limits on the entropy decoding of every slice, unlimited by default.
+///
+/// Slices exceeding them fail with a 'ResourceLimitExceeded' error, checked
+/// after every line of samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SliceBudget {
+    /// Maximum number of sample differences decoded from a slice.
+    pub max_symbols: Option<u64>,
+    /// Maximum number of bits read from a slice per byte of the slice,
+    /// 'SliceInfo::size'. 8 stops slices whose coder runs past their end.
+    pub max_bits_per_byte: Option<u32>,
+}
+
+impl SliceBudget {
+    /// Creates a budget of 'max_symbols' sample differences and
+    /// 'max_bits_per_byte' bits read per byte of every slice.
+    pub fn new(max_symbols: u64, max_bits_per_byte: u32) -> Self {
+        SliceBudget {
+            max_symbols: Some(max_symbols),
+            max_bits_per_byte: Some(max_bits_per_byte),
+        }
+    }
+}
+
+/// What is left of the budget of a slice being decoded.
+#[derive(Debug)]
+pub(crate) struct Spending {
+    symbols: u64,
+    max_symbols: u64,
+    max_bits: u64,
+}
+
+impl Spending {
+    /// Starts spending 'budget' on a slice of 'size' bytes.
+    pub(crate) fn new(budget: &SliceBudget, size: usize) -> Self {
+        let max_bits = budget.max_bits_per_byte.map_or(u64::MAX, |bits| {
+            (size as u64 * u64::from(bits)).saturating_add(LOOKAHEAD_BITS)
+        });
+        Spending {
+            symbols: 0,
+            max_symbols: budget.max_symbols.unwrap_or(u64::MAX),
+            max_bits,
+        }
+    }
+
+    /// Accounts for the 'bytes' bytes of the slice read by another coder
+    /// before the current one.
+    #[cfg(feature = "golomb")]
+    pub(crate) fn skip(&mut self, bytes: usize) {
+        self.max_bits = self.max_bits.saturating_sub(bytes as u64 * 8);
+    }
+
+    /// Accounts for 'symbols' more sample differences, 'bits' bits having
+    /// been read from the slice so far.
+    pub(crate) fn spend(&mut self, symbols: usize, bits: u64) -> Result<()> {
+        self.symbols = self.symbols.saturating_add(symbols as u64);
+        if self.symbols > self.max_symbols {
+            return Err(Error::ResourceLimitExceeded(format!(
+                "more than {} symbols in a slice",
+                self.max_symbols
+            )));
+        }
+        if bits > self.max_bits {
+            return Err(Error::ResourceLimitExceeded(format!(
+                "more than {} bits read from a slice",
+                self.max_bits
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Objective comparison of decoded frames.
+//!
+//! Samples are normalized by the maximum value of their bit depth before
+//! being compared, so frames with different bit depths can be compared
+//! too, e.g. a 10-bit source and its 8-bit rendition.
+
+use crate::decoder::Frame;
+use crate::error::{Error, Result};
+
+/// Size of the SSIM windows.
+const SSIM_WINDOW: usize = 8;
+/// Distance between two SSIM windows.
+const SSIM_STEP: usize = 4;
+/// SSIM stabilization constants, for normalized samples.
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+/// Comparison of a single plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlaneComparison {
+    /// Mean squared error, computed over normalized samples.
+    pub mse: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical planes.
+    pub psnr: f64,
+    /// Mean structural similarity index.
+    pub ssim: f64,
+    /// Whether the planes contain exactly the same samples.
+    pub identical: bool,
+}
+
+/// Comparison of two frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Per-plane comparisons, in the same order as the planes of a Frame.
+    pub planes: Vec<PlaneComparison>,
+}
+
+impl Comparison {
+    /// Whether all the planes contain exactly the same samples.
+    pub fn identical(&self) -> bool {
+        self.planes.iter().all(|p| p.identical)
+    }
+
+    /// Peak signal-to-noise ratio of the whole frame in dB, computed from
+    /// the mean squared error of all the planes.
+    pub fn psnr(&self) -> f64 {
+        let mse = self.planes.iter().map(|p| p.mse).sum::<f64>()
+            / self.planes.len() as f64;
+        psnr(mse)
+    }
+
+    /// Mean structural similarity index of all the planes.
+    pub fn ssim(&self) -> f64 {
+        self.planes.iter().map(|p| p.ssim).sum::<f64>()
+            / self.planes.len() as f64
+    }
+}
+
+/// A plane of normalized samples.
+struct Plane {
+    samples: Vec<f64>,
+    raw: Vec<u16>,
+    width: usize,
+    height: usize,
+}
+
+impl Plane {
+    fn new(frame: &Frame, plane: usize) -> Self {
+        let (width, height) = frame.plane_dimensions(plane);
+        let max = ((1u32 << frame.bit_depth) - 1) as f64;
+        let offset = frame.offsets[plane];
+        let stride = frame.strides[plane];
+        let mut raw = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let start = offset + y * stride;
+            if frame.bit_depth == 8 {
+                raw.extend(
+                    frame.buf[plane][start..start + width]
+                        .iter()
+                        .map(|&v| v as u16),
+                );
+            } else {
+                raw.extend_from_slice(
+                    &frame.buf16[plane][start..start + width],
+                );
+            }
+        }
+        let samples = raw.iter().map(|&v| v as f64 / max).collect();
+        Self {
+            samples,
+            raw,
+            width,
+            height,
+        }
+    }
+
+    fn at(&self, x: usize, y: usize) -> f64 {
+        self.samples[y * self.width + x]
+    }
+}
+
+/// Converts a mean squared error of normalized samples to dB.
+fn psnr(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        -10.0 * mse.log10()
+    }
+}
+
+/// Computes the SSIM of a single window.
+fn ssim_window(
+    a: &Plane,
+    b: &Plane,
+    x0: usize,
+    y0: usize,
+    size: (usize, usize),
+) -> f64 {
+    let (w, h) = size;
+    let n = (w * h) as f64;
+    let (mut sa, mut sb, mut saa, mut sbb, mut sab) =
+        (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let va = a.at(x, y);
+            let vb = b.at(x, y);
+            sa += va;
+            sb += vb;
+            saa += va * va;
+            sbb += vb * vb;
+            sab += va * vb;
+        }
+    }
+    let ma = sa / n;
+    let mb = sb / n;
+    let va = saa / n - ma * ma;
+    let vb = sbb / n - mb * mb;
+    let cov = sab / n - ma * mb;
+    ((2.0 * ma * mb + SSIM_C1) * (2.0 * cov + SSIM_C2))
+        / ((ma * ma + mb * mb + SSIM_C1) * (va + vb + SSIM_C2))
+}
+
+/// Compares two planes.
+fn compare_planes(a: &Plane, b: &Plane, same_depth: bool) -> PlaneComparison {
+    let mse = a
+        .samples
+        .iter()
+        .zip(b.samples.iter())
+        .map(|(va, vb)| (va - vb) * (va - vb))
+        .sum::<f64>()
+        / a.samples.len().max(1) as f64;
+
+    // Windows larger than the plane are not possible, so tiny planes
+    // are compared as a single window.
+    let win_w = SSIM_WINDOW.min(a.width);
+    let win_h = SSIM_WINDOW.min(a.height);
+    let mut ssim = 0.0;
+    let mut windows = 0;
+    let mut y = 0;
+    while win_h > 0 && y + win_h <= a.height {
+        let mut x = 0;
+        while win_w > 0 && x + win_w <= a.width {
+            ssim += ssim_window(a, b, x, y, (win_w, win_h));
+            windows += 1;
+            x += SSIM_STEP;
+        }
+        y += SSIM_STEP;
+    }
+
+    PlaneComparison {
+        mse,
+        psnr: psnr(mse),
+        ssim: if windows == 0 {
+            1.0
+        } else {
+            ssim / windows as f64
+        },
+        identical: same_depth && a.raw == b.raw,
+    }
+}
+
+/// Compares two frames plane by plane.
+///
+/// The frames must have the same dimensions, colorspace, planes and
+/// chroma subsampling, but may have different bit depths, in which case
+/// they are never considered identical.
+pub fn compare(a: &Frame, b: &Frame) -> Result<Comparison> {
+    if (a.width, a.height) != (b.width, b.height) {
+        return Err(Error::InvalidInputData(format!(
+            "frame dimensions differ: {}x{} and {}x{}",
+            a.width, a.height, b.width, b.height
+        )));
+    }
+    if a.color_space != b.color_space
+        || a.has_chroma != b.has_chroma
+        || a.has_alpha != b.has_alpha
+        || a.chroma_subsample_h != b.chroma_subsample_h
+        || a.chroma_subsample_v != b.chroma_subsample_v
+    {
+        return Err(Error::InvalidInputData(
+            "frames have a different pixel format".to_owned(),
+        ));
+    }
+
+    let same_depth = a.bit_depth == b.bit_depth;
+    let planes = (0..a.num_planes())
+        .map(|i| {
+            compare_planes(&Plane::new(a, i), &Plane::new(b, i), same_depth)
+        })
+        .collect();
+
+    Ok(Comparison { planes })
+}
@@ -0,0 +1,57 @@
+//! Strictness of the parser towards specification violations.
+
+use log::warn;
+
+use crate::error::{Error, Result};
+
+/// How hard parsing fails on specification violations which do not
+/// prevent decoding, e.g. reserved values or sample aspect ratios with a
+/// single zero term.
+///
+/// Violations preventing decoding, e.g. an unsupported version or a
+/// damaged slice, are always errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Compliance {
+    /// Every violation is an error, e.g. to validate files before they
+    /// enter an archive.
+    Strict,
+    /// Violations are logged as warnings, and the configuration record is
+    /// rejected when its CRC does not match.
+    #[default]
+    Normal,
+    /// Violations are ignored, and configuration records whose CRC does not
+    /// match are decoded anyway, e.g. to recover files mangled by legacy
+    /// software.
+    Permissive,
+}
+
+impl Compliance {
+    /// Reports the violation 'message', as an error built by 'error' in
+    /// strict mode and as a warning in normal mode.
+    ///
+    /// The message of a tolerated violation is returned, e.g. to be
+    /// reported as a diagnostic.
+    pub(crate) fn violation<F>(
+        self,
+        error: F,
+        message: String,
+    ) -> Result<Option<String>>
+    where
+        F: FnOnce(String) -> Error,
+    {
+        match self {
+            Compliance::Strict => Err(error(message)),
+            Compliance::Normal => {
+                warn!("{}", message);
+                Ok(Some(message))
+            }
+            Compliance::Permissive => Ok(Some(message)),
+        }
+    }
+
+    /// Returns whether a configuration record whose CRC does not match is
+    /// rejected.
+    pub(crate) fn checks_record_crc(self) -> bool {
+        self != Compliance::Permissive
+    }
+}
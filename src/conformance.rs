@@ -0,0 +1,470 @@
+//! Checks of streams against the requirements of the specification, e.g.
+//! for the ingest policies of archives.
+//!
+//! Only the configuration record, the slice headers and the slice footers
+//! are read, no sample is decoded. Every rule is reported as passed, failed
+//! or not applicable, along with the first failure found.
+
+use std::fmt;
+
+use crate::compliance::Compliance;
+use crate::crc32mpeg2::crc32_mpeg2;
+use crate::decoder::Decoder;
+use crate::scan::FrameScan;
+
+/// Strength of a requirement of the specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Requirement {
+    /// A MUST requirement, failed by non-conforming streams.
+    Must,
+    /// A SHOULD requirement, whose failures are worth reviewing.
+    Should,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Requirement::Must => "MUST",
+            Requirement::Should => "SHOULD",
+        })
+    }
+}
+
+/// A requirement checked by 'ConformanceChecker'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Rule {
+    /// The configuration record can be parsed and is supported.
+    ///
+    /// See: 4.2. Configuration Record
+    RecordValid,
+    /// The CRC of the configuration record matches.
+    ///
+    /// See: 4.2.2. configuration_record_crc_parity
+    RecordCrc,
+    /// The configuration record uses no reserved value.
+    ///
+    /// See: * 4.1.2. micro_version
+    ///      * 4.1.16. ec
+    ///      * 4.1.17. intra
+    RecordReserved,
+    /// The slice footers and headers of every frame can be parsed.
+    ///
+    /// See: * 4.5. Slice Header
+    ///      * 4.8. Slice Footer
+    FrameStructure,
+    /// The first frame is a keyframe.
+    ///
+    /// See: 4.3. Frame
+    FirstKeyframe,
+    /// Every frame is a keyframe when the configuration record says so.
+    ///
+    /// See: 4.1.17. intra
+    IntraKeyframes,
+    /// The slices of every frame cover the slice grid exactly once.
+    ///
+    /// See: 9.1.1. Multi-threading Support and Independence of Slices
+    SliceTiling,
+    /// The CRC of every slice matches.
+    ///
+    /// See: 4.8.3. slice_crc_parity
+    SliceCrc,
+    /// No slice is marked as damaged by the encoder.
+    ///
+    /// See: 4.8.2. error_status
+    SliceErrorStatus,
+    /// The slice headers use no reserved value.
+    ///
+    /// See: * 4.5.7. picture_structure
+    ///      * 4.5.8. sar_num
+    ///      * 4.5.9. sar_den
+    SliceReserved,
+    /// The slices of every frame share the same sample aspect ratio.
+    ///
+    /// See: * 4.5.8. sar_num
+    ///      * 4.5.9. sar_den
+    SarConsistent,
+}
+
+impl Rule {
+    /// Every rule, in the order of the report.
+    pub const ALL: [Rule; 11] = [
+        Rule::RecordValid,
+        Rule::RecordCrc,
+        Rule::RecordReserved,
+        Rule::FrameStructure,
+        Rule::FirstKeyframe,
+        Rule::IntraKeyframes,
+        Rule::SliceTiling,
+        Rule::SliceCrc,
+        Rule::SliceErrorStatus,
+        Rule::SliceReserved,
+        Rule::SarConsistent,
+    ];
+
+    /// Returns the stable identifier of the rule, e.g. for policies.
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::RecordValid => "record-valid",
+            Rule::RecordCrc => "record-crc",
+            Rule::RecordReserved => "record-reserved",
+            Rule::FrameStructure => "frame-structure",
+            Rule::FirstKeyframe => "first-keyframe",
+            Rule::IntraKeyframes => "intra-keyframes",
+            Rule::SliceTiling => "slice-tiling",
+            Rule::SliceCrc => "slice-crc",
+            Rule::SliceErrorStatus => "slice-error-status",
+            Rule::SliceReserved => "slice-reserved",
+            Rule::SarConsistent => "sar-consistent",
+        }
+    }
+
+    /// Returns the strength of the requirement.
+    pub fn requirement(self) -> Requirement {
+        match self {
+            Rule::SliceErrorStatus | Rule::SarConsistent => {
+                Requirement::Should
+            }
+            _ => Requirement::Must,
+        }
+    }
+
+    /// Returns a short description of the requirement.
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::RecordValid => "configuration record is valid",
+            Rule::RecordCrc => "configuration record CRC matches",
+            Rule::RecordReserved => {
+                "configuration record has no reserved value"
+            }
+            Rule::FrameStructure => "slice headers and footers are valid",
+            Rule::FirstKeyframe => "first frame is a keyframe",
+            Rule::IntraKeyframes => "intra streams only have keyframes",
+            Rule::SliceTiling => "slices cover every frame exactly once",
+            Rule::SliceCrc => "slice CRCs match",
+            Rule::SliceErrorStatus => "no slice is marked as damaged",
+            Rule::SliceReserved => "slice headers have no reserved value",
+            Rule::SarConsistent => "slices share the sample aspect ratio",
+        }
+    }
+}
+
+/// Outcome of a rule, see 'ConformanceReport'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleResult {
+    /// The rule checked.
+    pub rule: Rule,
+    /// Number of times the rule was checked, 0 when it does not apply.
+    pub checked: usize,
+    /// Number of times the rule failed.
+    pub failures: usize,
+    /// Description of the first failure.
+    pub first_failure: Option<String>,
+}
+
+impl RuleResult {
+    /// Returns whether the rule was checked and never failed.
+    pub fn passed(&self) -> bool {
+        self.checked > 0 && self.failures == 0
+    }
+
+    /// Returns whether the rule did not apply to the stream, e.g. slice
+    /// CRCs without error correction.
+    pub fn not_applicable(&self) -> bool {
+        self.checked == 0
+    }
+}
+
+/// Pass or fail outcome of every rule for a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConformanceReport {
+    /// Number of frames checked.
+    pub frames: usize,
+    /// Outcome of every rule, in the order of 'Rule::ALL'.
+    pub results: Vec<RuleResult>,
+}
+
+impl ConformanceReport {
+    /// Returns the outcome of 'rule'.
+    pub fn result(&self, rule: Rule) -> &RuleResult {
+        self.results
+            .iter()
+            .find(|result| result.rule == rule)
+            .expect("every rule has a result")
+    }
+
+    /// Returns whether no MUST requirement failed.
+    pub fn conforms(&self) -> bool {
+        self.results.iter().all(|result| {
+            result.rule.requirement() != Requirement::Must
+                || result.failures == 0
+        })
+    }
+
+    /// Returns whether no requirement at all failed.
+    pub fn is_clean(&self) -> bool {
+        self.results.iter().all(|result| result.failures == 0)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.not_applicable() {
+                "N/A "
+            } else if result.passed() {
+                "PASS"
+            } else {
+                "FAIL"
+            };
+            write!(
+                f,
+                "{} {:<6} {:<18} {}",
+                status,
+                result.rule.requirement(),
+                result.rule.id(),
+                result.rule.description()
+            )?;
+            if let Some(ref failure) = result.first_failure {
+                write!(
+                    f,
+                    " ({} failures, first: {})",
+                    result.failures, failure
+                )?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} frames: {}",
+            self.frames,
+            if self.conforms() {
+                "conforming"
+            } else {
+                "not conforming"
+            }
+        )
+    }
+}
+
+/// Checks a stream against the requirements of the specification, one
+/// packet at a time.
+#[derive(Debug)]
+pub struct ConformanceChecker {
+    decoder: Option<Decoder>,
+    report: ConformanceReport,
+}
+
+impl ConformanceChecker {
+    /// Creates a checker of the stream whose configuration record is
+    /// 'record', with the dimensions stored by the container.
+    ///
+    /// An invalid configuration record fails 'Rule::RecordValid', and no
+    /// packet can be checked.
+    pub fn new(record: &[u8], width: u32, height: u32) -> Self {
+        let results = Rule::ALL
+            .iter()
+            .map(|&rule| RuleResult {
+                rule,
+                checked: 0,
+                failures: 0,
+                first_failure: None,
+            })
+            .collect();
+        let mut checker = Self {
+            decoder: None,
+            report: ConformanceReport { frames: 0, results },
+        };
+
+        // Reserved values are checked below, so they are tolerated
+        let decoder = Decoder::with_compliance(
+            record,
+            width,
+            height,
+            Compliance::Permissive,
+        );
+        checker.check(Rule::RecordCrc, || {
+            (crc32_mpeg2(record) != 0)
+                .then(|| "CRC of the configuration record fails".to_owned())
+        });
+        let decoder = match decoder {
+            Ok(decoder) => decoder,
+            Err(err) => {
+                checker.check(Rule::RecordValid, || Some(err.to_string()));
+                return checker;
+            }
+        };
+        checker.check(Rule::RecordValid, || None);
+
+        let config = decoder.config_record();
+        let mut reserved = Vec::new();
+        if config.micro_version > 4 {
+            reserved.push(format!("micro_version {}", config.micro_version));
+        }
+        if config.ec > 1 {
+            reserved.push(format!("ec {}", config.ec));
+        }
+        if config.intra > 1 {
+            reserved.push(format!("intra {}", config.intra));
+        }
+        checker.check(Rule::RecordReserved, || {
+            (!reserved.is_empty())
+                .then(|| format!("reserved {}", reserved.join(", ")))
+        });
+
+        checker.decoder = Some(decoder);
+        checker
+    }
+
+    /// Checks the next packet of the stream.
+    pub fn check_packet(&mut self, packet: &[u8]) {
+        let decoder = match self.decoder {
+            Some(ref decoder) => decoder,
+            None => return,
+        };
+        let frame = self.report.frames;
+        self.report.frames += 1;
+
+        let scan = decoder.scan_frame(packet);
+        let intra = decoder.config_record().intra != 0;
+        let ec = decoder.config_record().ec == 1;
+        let grid = (
+            decoder.config_record().num_h_slices_minus1 as usize + 1,
+            decoder.config_record().num_v_slices_minus1 as usize + 1,
+        );
+        let scan = match scan {
+            Ok(scan) => scan,
+            Err(err) => {
+                self.check(Rule::FrameStructure, || {
+                    Some(format!("frame {}: {}", frame, err))
+                });
+                return;
+            }
+        };
+        self.check(Rule::FrameStructure, || None);
+
+        if frame == 0 {
+            self.check(Rule::FirstKeyframe, || {
+                (!scan.keyframe)
+                    .then(|| "frame 0 is not a keyframe".to_owned())
+            });
+        }
+        if intra {
+            self.check(Rule::IntraKeyframes, || {
+                (!scan.keyframe)
+                    .then(|| format!("frame {} is not a keyframe", frame))
+            });
+        }
+        self.check(Rule::SliceTiling, || tiling_failure(&scan, grid, frame));
+        self.check_slices(&scan, frame, ec);
+    }
+
+    /// Checks the footers and headers of the slices of a frame.
+    fn check_slices(&mut self, scan: &FrameScan, frame: usize, ec: bool) {
+        let first_sar = match scan.slices.first() {
+            Some(first) => first.header.sar(),
+            None => return,
+        };
+        for (i, slice) in scan.slices.iter().enumerate() {
+            let at = |what: String| {
+                format!("frame {} slice {}: {}", frame, i, what)
+            };
+            if ec {
+                self.check(Rule::SliceCrc, || {
+                    (slice.crc_valid == Some(false))
+                        .then(|| at("CRC mismatch".to_owned()))
+                });
+            }
+            self.check(Rule::SliceErrorStatus, || {
+                (slice.error_status != 0).then(|| {
+                    at(format!("error_status {}", slice.error_status))
+                })
+            });
+
+            let header = &slice.header;
+            let (sar_num, sar_den) = header.sar();
+            self.check(Rule::SliceReserved, || {
+                if header.picture_structure() > 3 {
+                    Some(at(format!(
+                        "reserved picture_structure {}",
+                        header.picture_structure()
+                    )))
+                } else if (sar_num == 0) != (sar_den == 0) {
+                    Some(at(format!("invalid SAR {}:{}", sar_num, sar_den)))
+                } else {
+                    None
+                }
+            });
+            if i > 0 {
+                self.check(Rule::SarConsistent, || {
+                    (header.sar() != first_sar).then(|| {
+                        at(format!(
+                            "SAR {}:{} differs from {}:{}",
+                            sar_num, sar_den, first_sar.0, first_sar.1
+                        ))
+                    })
+                });
+            }
+        }
+    }
+
+    /// Records a check of 'rule', failed when 'failure' returns a
+    /// description.
+    fn check<F>(&mut self, rule: Rule, failure: F)
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        let index = Rule::ALL.iter().position(|&r| r == rule).unwrap();
+        let result = &mut self.report.results[index];
+        result.checked += 1;
+        if let Some(failure) = failure() {
+            result.failures += 1;
+            if result.first_failure.is_none() {
+                result.first_failure = Some(failure);
+            }
+        }
+    }
+
+    /// Returns the report of the packets checked so far.
+    pub fn report(&self) -> &ConformanceReport {
+        &self.report
+    }
+
+    /// Returns the report of the stream.
+    pub fn finish(self) -> ConformanceReport {
+        self.report
+    }
+}
+
+/// Returns why the slices of a frame do not cover its slice grid of
+/// 'grid' columns and rows exactly once, if they do not.
+fn tiling_failure(
+    scan: &FrameScan,
+    grid: (usize, usize),
+    frame: usize,
+) -> Option<String> {
+    let (columns, rows) = grid;
+    let mut covered = vec![0u32; columns * rows];
+    for slice in &scan.slices {
+        let header = &slice.header;
+        let x = header.slice_x() as usize;
+        let y = header.slice_y() as usize;
+        let width = header.slice_width() as usize;
+        let height = header.slice_height() as usize;
+        for row in y..(y + height).min(rows) {
+            let cells = x.min(columns)..(x + width).min(columns);
+            for cell in &mut covered[row * columns..][cells] {
+                *cell += 1;
+            }
+        }
+    }
+    let overlaps = covered.iter().filter(|&&count| count > 1).count();
+    let gaps = covered.iter().filter(|&&count| count == 0).count();
+    if overlaps == 0 && gaps == 0 {
+        return None;
+    }
+    Some(format!(
+        "frame {}: {} grid cells not covered, {} covered several times",
+        frame, gaps, overlaps
+    ))
+}
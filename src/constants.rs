@@ -1,13 +1,122 @@
+use std::fmt;
+
 // Internal constants.
 pub(crate) const MAX_QUANT_TABLES: usize = 8; // Only defined in FFmpeg?
 pub(crate) const MAX_CONTEXT_INPUTS: usize = 5; // 4.9. Quantization Table Set
 pub(crate) const CONTEXT_SIZE: usize = 32; // 4.1. Parameters
+pub(crate) const MAX_QUANT_SCALE: i32 = 32768; // Only defined in FFmpeg?
 
 // API constants.
 
-// FIXME: Use enum
-
 /// Colorspaces.
 /// From 4.1.5. colorspace_type
-pub const YCBCR: usize = 0;
-pub const RGB: usize = 1;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// YCbCr, with optional chroma and alpha planes.
+    YCbCr = 0,
+    /// RGB, coded as JPEG2000-RCT.
+    Rgb = 1,
+}
+
+impl ColorSpace {
+    /// Returns the colorspace for a colorspace_type value, if valid.
+    pub fn from_u8(colorspace_type: u8) -> Option<Self> {
+        match colorspace_type {
+            0 => Some(ColorSpace::YCbCr),
+            1 => Some(ColorSpace::Rgb),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorSpace::YCbCr => f.write_str("YCbCr"),
+            ColorSpace::Rgb => f.write_str("RGB"),
+        }
+    }
+}
+
+/// Planes of decoded frames, see Frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// A single luma plane, for YCbCr streams without chroma planes,
+    /// e.g. scanned documents or mattes.
+    Gray,
+    /// Luma and alpha planes.
+    GrayAlpha,
+    /// Luma and two chroma planes.
+    YCbCr,
+    /// Luma, two chroma and alpha planes.
+    YCbCrAlpha,
+    /// Green, blue and red planes.
+    Rgb,
+    /// Green, blue, red and alpha planes.
+    Rgba,
+}
+
+impl PixelFormat {
+    /// Returns the pixel format of a colorspace with or without chroma and
+    /// alpha planes. RGB always has chroma planes.
+    ///
+    /// See: * 4.1.6. chroma_planes
+    ///      * 4.1.10. extra_plane
+    pub fn new(
+        color_space: ColorSpace,
+        has_chroma: bool,
+        has_alpha: bool,
+    ) -> Self {
+        match (color_space, has_chroma, has_alpha) {
+            (ColorSpace::Rgb, _, false) => PixelFormat::Rgb,
+            (ColorSpace::Rgb, _, true) => PixelFormat::Rgba,
+            (ColorSpace::YCbCr, false, false) => PixelFormat::Gray,
+            (ColorSpace::YCbCr, false, true) => PixelFormat::GrayAlpha,
+            (ColorSpace::YCbCr, true, false) => PixelFormat::YCbCr,
+            (ColorSpace::YCbCr, true, true) => PixelFormat::YCbCrAlpha,
+        }
+    }
+
+    /// Returns the number of planes.
+    pub fn num_planes(self) -> usize {
+        match self {
+            PixelFormat::Gray => 1,
+            PixelFormat::GrayAlpha => 2,
+            PixelFormat::YCbCr | PixelFormat::Rgb => 3,
+            PixelFormat::YCbCrAlpha | PixelFormat::Rgba => 4,
+        }
+    }
+
+    /// Whether the format is grayscale, i.e. has no chroma planes.
+    pub fn is_gray(self) -> bool {
+        matches!(self, PixelFormat::Gray | PixelFormat::GrayAlpha)
+    }
+
+    /// Whether the last plane is an alpha plane.
+    pub fn has_alpha(self) -> bool {
+        matches!(
+            self,
+            PixelFormat::GrayAlpha
+                | PixelFormat::YCbCrAlpha
+                | PixelFormat::Rgba
+        )
+    }
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PixelFormat::Gray => "gray",
+            PixelFormat::GrayAlpha => "gray with alpha",
+            PixelFormat::YCbCr => "YCbCr",
+            PixelFormat::YCbCrAlpha => "YCbCr with alpha",
+            PixelFormat::Rgb => "RGB",
+            PixelFormat::Rgba => "RGB with alpha",
+        })
+    }
+}
+
+#[deprecated(note = "use ColorSpace::YCbCr")]
+pub const YCBCR: usize = ColorSpace::YCbCr as usize;
+#[deprecated(note = "use ColorSpace::Rgb")]
+pub const RGB: usize = ColorSpace::Rgb as usize;
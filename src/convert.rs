@@ -0,0 +1,511 @@
+//! Conversions of decoded frames into layouts commonly needed by
+//! analysis and display code.
+
+use crate::constants::ColorSpace;
+use crate::decoder::Frame;
+use crate::geometry::Crop;
+use crate::sample::Sample;
+
+/// Filter used to upsample chroma planes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChromaFilter {
+    /// Every chroma sample is repeated over the luma samples it covers.
+    Nearest,
+    /// Chroma samples are interpolated, assuming they are centered
+    /// between the luma samples they cover.
+    Bilinear,
+}
+
+/// Upsamples a chroma plane by 2^'log2_h' horizontally and by 2^'log2_v'
+/// vertically, returning a packed plane of 'width'x'height' samples.
+///
+/// 'src' is a plane of 'src_width'x'src_height' samples, whose rows are
+/// 'src_stride' samples apart.
+#[allow(clippy::too_many_arguments)]
+pub fn upsample_plane<T>(
+    src: &[T],
+    src_width: usize,
+    src_height: usize,
+    src_stride: usize,
+    log2_h: u8,
+    log2_v: u8,
+    width: usize,
+    height: usize,
+    filter: ChromaFilter,
+) -> Vec<T>
+where
+    T: Sample,
+{
+    let mut dst = vec![T::default(); width * height];
+    if src_width == 0 || src_height == 0 {
+        return dst;
+    }
+
+    let at = |x: usize, y: usize| -> u32 {
+        src[y.min(src_height - 1) * src_stride + x.min(src_width - 1)].to_u32()
+    };
+
+    match filter {
+        ChromaFilter::Nearest => {
+            for y in 0..height {
+                for x in 0..width {
+                    dst[y * width + x] =
+                        T::from_u32(at(x >> log2_h, y >> log2_v));
+                }
+            }
+        }
+        ChromaFilter::Bilinear => {
+            // Positions are computed in units of 1/(2 * factor) of a
+            // chroma sample, where the center of chroma sample 'i' is
+            // at '(2 * i + 1) * factor'.
+            let fh = 1usize << log2_h;
+            let fv = 1usize << log2_v;
+            let weight_h = 2 * fh;
+            let weight_v = 2 * fv;
+            let position = |i: usize, f: usize| -> (usize, usize) {
+                let pos = (2 * i + 1).saturating_sub(f);
+                (pos / (2 * f), pos % (2 * f))
+            };
+            for y in 0..height {
+                let (sy, fy) = position(y, fv);
+                for x in 0..width {
+                    let (sx, fx) = position(x, fh);
+                    let (fx, fy) = (fx as u32, fy as u32);
+                    let (wh, wv) = (weight_h as u32, weight_v as u32);
+                    let sum = at(sx, sy) * (wh - fx) * (wv - fy)
+                        + at(sx + 1, sy) * fx * (wv - fy)
+                        + at(sx, sy + 1) * (wh - fx) * fy
+                        + at(sx + 1, sy + 1) * fx * fy;
+                    let total = wh * wv;
+                    dst[y * width + x] =
+                        T::from_u32((sum + total / 2) / total);
+                }
+            }
+        }
+    }
+
+    dst
+}
+
+/// Copies a plane into a packed buffer.
+fn pack_plane<T: Copy>(
+    src: &[T],
+    width: usize,
+    height: usize,
+    stride: usize,
+) -> Vec<T> {
+    let mut dst = Vec::with_capacity(width * height);
+    for y in 0..height {
+        dst.extend_from_slice(&src[y * stride..y * stride + width]);
+    }
+    dst
+}
+
+/// Converts all the planes of a frame to 4:4:4.
+fn upsample_planes<T>(
+    frame: &Frame,
+    planes: &[Vec<T>],
+    filter: ChromaFilter,
+) -> Vec<Vec<T>>
+where
+    T: Sample,
+{
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    planes
+        .iter()
+        .enumerate()
+        .map(|(i, plane)| {
+            let (plane_width, plane_height) = frame.plane_dimensions(i);
+            let src = &plane[frame.offsets[i]..];
+            if frame.has_chroma && (i == 1 || i == 2) {
+                upsample_plane(
+                    src,
+                    plane_width,
+                    plane_height,
+                    frame.strides[i],
+                    frame.chroma_subsample_h,
+                    frame.chroma_subsample_v,
+                    width,
+                    height,
+                    filter,
+                )
+            } else {
+                pack_plane(src, plane_width, plane_height, frame.strides[i])
+            }
+        })
+        .collect()
+}
+
+/// Returns a frame with the same properties as 'frame' and no planes,
+/// whose strides are set for packed planes.
+fn empty_frame(frame: &Frame) -> Frame {
+    let num_planes = frame.num_planes();
+    Frame {
+        buf: Vec::new(),
+        buf16: Vec::new(),
+        strides: (0..num_planes)
+            .map(|i| frame.plane_dimensions(i).0)
+            .collect(),
+        offsets: vec![0; num_planes],
+        width: frame.width,
+        height: frame.height,
+        bit_depth: frame.bit_depth,
+        color_space: frame.color_space,
+        has_chroma: frame.has_chroma,
+        has_alpha: frame.has_alpha,
+        chroma_subsample_v: frame.chroma_subsample_v,
+        chroma_subsample_h: frame.chroma_subsample_h,
+        crop: frame.crop,
+        metadata: frame.metadata.clone(),
+        diagnostics: frame.diagnostics.clone(),
+    }
+}
+
+/// Returns a copy of a frame whose chroma planes have been upsampled
+/// to full resolution, i.e. a 4:4:4 frame with packed planes.
+pub fn upsample_chroma(frame: &Frame, filter: ChromaFilter) -> Frame {
+    let mut upsampled = empty_frame(frame);
+    if !frame.buf.is_empty() {
+        upsampled.buf = upsample_planes(frame, &frame.buf, filter);
+    }
+    if !frame.buf16.is_empty() {
+        upsampled.buf16 = upsample_planes(frame, &frame.buf16, filter);
+    }
+    upsampled.strides = vec![frame.width as usize; frame.num_planes()];
+    upsampled.chroma_subsample_v = 0;
+    upsampled.chroma_subsample_h = 0;
+    upsampled
+}
+
+/// Shifts samples of the given bit depth in place, so that their most
+/// significant bit becomes bit 15.
+///
+/// The low bits are filled by replicating the high bits, so that the
+/// maximum value of the source bit depth maps to 65535.
+pub fn msb_align(samples: &mut [u16], bit_depth: u8) {
+    if bit_depth >= 16 {
+        return;
+    }
+    let shift = 16 - bit_depth as u32;
+    for sample in samples.iter_mut() {
+        let v = *sample as u32;
+        let mut aligned = v << shift;
+        let mut bits = bit_depth as u32;
+        while bits < 16 {
+            aligned |= aligned >> bits;
+            bits *= 2;
+        }
+        *sample = aligned as u16;
+    }
+}
+
+/// 4x4 ordered dithering matrix.
+const BAYER: [[u32; 4]; 4] =
+    [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduces a plane of samples of the given bit depth to 8 bits, using
+/// ordered dithering, and returns it packed.
+///
+/// 'src' is a plane of 'width'x'height' samples, whose rows are 'stride'
+/// samples apart.
+pub fn dither_to_8bit(
+    src: &[u16],
+    width: usize,
+    height: usize,
+    stride: usize,
+    bit_depth: u8,
+) -> Vec<u8> {
+    let shift = bit_depth.saturating_sub(8) as u32;
+    let mut dst = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let v = src[y * stride + x] as u32;
+            let dither = (BAYER[y & 3][x & 3] << shift) >> 4;
+            dst.push(((v + dither) >> shift).min(255) as u8);
+        }
+    }
+    dst
+}
+
+/// Returns a copy of a frame with packed 16-bit planes whose samples are
+/// MSB-aligned, see 'msb_align'. The bit depth of the copy is 16.
+pub fn to_msb_aligned(frame: &Frame) -> Frame {
+    let mut aligned = empty_frame(frame);
+    aligned.buf16 = (0..frame.num_planes())
+        .map(|i| {
+            let (width, height) = frame.plane_dimensions(i);
+            let mut plane: Vec<u16> = if frame.bit_depth == 8 {
+                pack_plane(
+                    &frame.buf[i][frame.offsets[i]..],
+                    width,
+                    height,
+                    frame.strides[i],
+                )
+                .iter()
+                .map(|&v| v as u16)
+                .collect()
+            } else {
+                pack_plane(
+                    &frame.buf16[i][frame.offsets[i]..],
+                    width,
+                    height,
+                    frame.strides[i],
+                )
+            };
+            msb_align(&mut plane, frame.bit_depth);
+            plane
+        })
+        .collect();
+    aligned.bit_depth = 16;
+    aligned
+}
+
+/// Returns a copy of a frame with packed 8-bit planes, dithering samples
+/// down when the bit depth is greater than 8.
+pub fn to_8bit(frame: &Frame) -> Frame {
+    let mut reduced = empty_frame(frame);
+    reduced.buf = (0..frame.num_planes())
+        .map(|i| {
+            let (width, height) = frame.plane_dimensions(i);
+            if frame.bit_depth == 8 {
+                pack_plane(
+                    &frame.buf[i][frame.offsets[i]..],
+                    width,
+                    height,
+                    frame.strides[i],
+                )
+            } else {
+                dither_to_8bit(
+                    &frame.buf16[i][frame.offsets[i]..],
+                    width,
+                    height,
+                    frame.strides[i],
+                    frame.bit_depth,
+                )
+            }
+        })
+        .collect();
+    reduced.bit_depth = 8;
+    reduced
+}
+
+/// Returns a copy of a frame with packed planes, keeping its bit depth.
+pub fn to_packed(frame: &Frame) -> Frame {
+    let mut packed = empty_frame(frame);
+    for i in 0..frame.num_planes() {
+        let (width, height) = frame.plane_dimensions(i);
+        let (offset, stride) = (frame.offsets[i], frame.strides[i]);
+        if frame.bit_depth == 8 {
+            packed.buf.push(pack_plane(
+                &frame.buf[i][offset..],
+                width,
+                height,
+                stride,
+            ));
+        } else {
+            packed.buf16.push(pack_plane(
+                &frame.buf16[i][offset..],
+                width,
+                height,
+                stride,
+            ));
+        }
+    }
+    packed
+}
+
+/// Returns a frame with the same properties as 'frame' holding packed
+/// planes, 'buf' when the bit depth is 8 and 'buf16' otherwise, e.g. to
+/// compare a decoded frame with raw reference planes.
+pub fn with_packed_planes(
+    frame: &Frame,
+    buf: Vec<Vec<u8>>,
+    buf16: Vec<Vec<u16>>,
+) -> Frame {
+    let mut packed = empty_frame(frame);
+    packed.buf = buf;
+    packed.buf16 = buf16;
+    packed
+}
+
+/// Returns a frame as packed 8-bit RGBA pixels, e.g. for display.
+///
+/// Chroma is upsampled with the nearest filter and YCbCr is converted
+/// from limited range BT.601. Frames without chroma planes are gray, and
+/// frames without an alpha plane are opaque.
+pub fn to_rgba8(frame: &Frame) -> Vec<u8> {
+    let frame = to_8bit(&upsample_chroma(frame, ChromaFilter::Nearest));
+    let ycbcr = frame.color_space == ColorSpace::YCbCr;
+    let clip = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    let pixels = frame.width as usize * frame.height as usize;
+    let mut rgba = Vec::with_capacity(pixels * 4);
+    for i in 0..pixels {
+        let p = |plane: usize| frame.buf[plane][i];
+        if !frame.has_chroma {
+            rgba.extend_from_slice(&[p(0), p(0), p(0)]);
+        } else if ycbcr {
+            let y = (p(0) as f32 - 16.0) * 1.164;
+            let cb = p(1) as f32 - 128.0;
+            let cr = p(2) as f32 - 128.0;
+            rgba.push(clip(y + 1.596 * cr));
+            rgba.push(clip(y - 0.813 * cr - 0.391 * cb));
+            rgba.push(clip(y + 2.018 * cb));
+        } else {
+            // Planes are stored as GBR.
+            rgba.extend_from_slice(&[p(2), p(0), p(1)]);
+        }
+        rgba.push(if frame.has_alpha {
+            p(frame.num_planes() - 1)
+        } else {
+            255
+        });
+    }
+    rgba
+}
+
+/// Downscales a plane of 'width'x'height' samples, whose rows are 'stride'
+/// samples apart, to 'dst_width'x'dst_height' by averaging the samples
+/// covered by every destination sample.
+fn box_downscale<T>(
+    src: &[T],
+    width: usize,
+    height: usize,
+    stride: usize,
+    dst_width: usize,
+    dst_height: usize,
+) -> Vec<T>
+where
+    T: Sample,
+{
+    let mut dst = Vec::with_capacity(dst_width * dst_height);
+    for y in 0..dst_height {
+        let y0 = y * height / dst_height;
+        let y1 = ((y + 1) * height / dst_height).max(y0 + 1);
+        for x in 0..dst_width {
+            let x0 = x * width / dst_width;
+            let x1 = ((x + 1) * width / dst_width).max(x0 + 1);
+            let mut sum = 0u64;
+            for row in src[y0 * stride..].chunks(stride).take(y1 - y0) {
+                sum += row[x0..x1]
+                    .iter()
+                    .map(|&v| v.to_u32() as u64)
+                    .sum::<u64>();
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as u64;
+            dst.push(T::from_u32(((sum + count / 2) / count) as u32));
+        }
+    }
+    dst
+}
+
+impl Frame {
+    /// Returns a thumbnail of the frame fitting into 'max_width'x'max_height',
+    /// keeping its aspect ratio, e.g. for previews. Frames are never
+    /// upscaled.
+    ///
+    /// The thumbnail keeps the bit depth of the frame, and only its luma
+    /// plane for YCbCr frames or its color planes for RGB frames, packed.
+    pub fn thumbnail(&self, max_width: u32, max_height: u32) -> Frame {
+        let (width, height) = (self.width as u64, self.height as u64);
+        let (max_width, max_height) =
+            (max_width.max(1) as u64, max_height.max(1) as u64);
+        let (dst_width, dst_height) =
+            if width <= max_width && height <= max_height {
+                (width, height)
+            } else if width * max_height >= height * max_width {
+                (max_width, (height * max_width / width).max(1))
+            } else {
+                ((width * max_height / height).max(1), max_height)
+            };
+
+        let mut thumbnail = empty_frame(self);
+        thumbnail.width = dst_width as u32;
+        thumbnail.height = dst_height as u32;
+        thumbnail.crop = Crop::display_size(thumbnail.width, thumbnail.height);
+        thumbnail.has_alpha = false;
+        thumbnail.chroma_subsample_h = 0;
+        thumbnail.chroma_subsample_v = 0;
+        if self.color_space == ColorSpace::YCbCr {
+            thumbnail.has_chroma = false;
+        }
+        let num_planes = thumbnail.num_planes();
+        thumbnail.strides = vec![dst_width as usize; num_planes];
+        thumbnail.offsets = vec![0; num_planes];
+
+        let downscale = |i: usize| {
+            let (width, height) = self.plane_dimensions(i);
+            (width, height, self.offsets[i], self.strides[i])
+        };
+        let (dst_width, dst_height) =
+            (dst_width as usize, dst_height as usize);
+        if self.bit_depth == 8 {
+            thumbnail.buf = (0..num_planes)
+                .map(|i| {
+                    let (width, height, offset, stride) = downscale(i);
+                    box_downscale(
+                        &self.buf[i][offset..],
+                        width,
+                        height,
+                        stride,
+                        dst_width,
+                        dst_height,
+                    )
+                })
+                .collect();
+        } else {
+            thumbnail.buf16 = (0..num_planes)
+                .map(|i| {
+                    let (width, height, offset, stride) = downscale(i);
+                    box_downscale(
+                        &self.buf16[i][offset..],
+                        width,
+                        height,
+                        stride,
+                        dst_width,
+                        dst_height,
+                    )
+                })
+                .collect();
+        }
+        thumbnail
+    }
+}
+
+#[cfg(feature = "float")]
+impl Frame {
+    /// Returns packed copies of the planes of the frame, with samples
+    /// normalized to 0.0..=1.0 according to the bit depth, e.g. for
+    /// machine learning tools consuming float tensors.
+    ///
+    /// Planes keep their own dimensions, see 'plane_dimensions'.
+    pub fn to_f32_planes(&self) -> Vec<Vec<f32>> {
+        let max = ((1u32 << self.bit_depth) - 1) as f32;
+        (0..self.num_planes())
+            .map(|i| {
+                let (width, height) = self.plane_dimensions(i);
+                if self.bit_depth == 8 {
+                    pack_plane(
+                        &self.buf[i][self.offsets[i]..],
+                        width,
+                        height,
+                        self.strides[i],
+                    )
+                    .iter()
+                    .map(|&v| v as f32 / max)
+                    .collect()
+                } else {
+                    pack_plane(
+                        &self.buf16[i][self.offsets[i]..],
+                        width,
+                        height,
+                        self.strides[i],
+                    )
+                    .iter()
+                    .map(|&v| v as f32 / max)
+                    .collect()
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,141 @@
+//! Conversion of decoded frames into OpenCV matrices.
+
+use ::opencv::core::{self, Mat, Scalar};
+use ::opencv::prelude::*;
+
+use crate::constants::ColorSpace;
+use crate::convert::{msb_align, to_msb_aligned, to_rgba8};
+use crate::decoder::Frame;
+
+/// Allocates a 'width'x'height' matrix of 'typ', whose samples are set row
+/// by row by 'fill' from their bytes.
+fn new_mat<F>(
+    width: usize,
+    height: usize,
+    typ: i32,
+    mut fill: F,
+) -> ::opencv::Result<Mat>
+where
+    F: FnMut(usize, &mut [u8]),
+{
+    let mut mat = Mat::new_rows_cols_with_default(
+        height as i32,
+        width as i32,
+        typ,
+        Scalar::all(0.0),
+    )?;
+    // Matrices allocated by OpenCV are continuous, with rows of 'step'
+    // bytes.
+    let step = mat.mat_step().get(0);
+    if step > 0 {
+        let data = mat.data_bytes_mut()?;
+        for (y, row) in data.chunks_exact_mut(step).enumerate() {
+            fill(y, row);
+        }
+    }
+    Ok(mat)
+}
+
+/// Returns a plane of a frame as a single channel matrix, with the
+/// dimensions of the plane.
+///
+/// 8-bit planes are CV_8UC1 matrices. Deeper planes are MSB-aligned into
+/// CV_16UC1 matrices, as OpenCV expects 16-bit samples to use the whole
+/// range.
+pub fn plane_to_mat(frame: &Frame, plane: usize) -> ::opencv::Result<Mat> {
+    let (width, height) = frame.plane_dimensions(plane);
+    if frame.bit_depth == 8 {
+        let (offset, stride) = (frame.offsets[plane], frame.strides[plane]);
+        new_mat(width, height, core::CV_8UC1, |y, row| {
+            let start = offset + y * stride;
+            row[..width].copy_from_slice(&frame.buf[plane][start..][..width]);
+        })
+    } else {
+        let (offset, stride) = (frame.offsets[plane], frame.strides[plane]);
+        let mut samples = Vec::with_capacity(width);
+        new_mat(width, height, core::CV_16UC1, |y, row| {
+            let start = offset + y * stride;
+            samples.clear();
+            samples.extend_from_slice(&frame.buf16[plane][start..][..width]);
+            msb_align(&mut samples, frame.bit_depth);
+            for (dst, &v) in row.chunks_exact_mut(2).zip(&samples) {
+                dst.copy_from_slice(&v.to_ne_bytes());
+            }
+        })
+    }
+}
+
+/// Returns every plane of a frame as a single channel matrix, see
+/// 'plane_to_mat'.
+pub fn to_mats(frame: &Frame) -> ::opencv::Result<Vec<Mat>> {
+    (0..frame.num_planes())
+        .map(|plane| plane_to_mat(frame, plane))
+        .collect()
+}
+
+/// Returns a frame as a packed BGR matrix, or BGRA when it has an alpha
+/// plane, as OpenCV images are laid out.
+///
+/// RGB frames keep their depth: 8-bit frames are CV_8UC3 or CV_8UC4
+/// matrices, and deeper ones are MSB-aligned into CV_16UC3 or CV_16UC4
+/// matrices. YCbCr and gray frames are converted to 8-bit RGB with
+/// 'convert::to_rgba8' first.
+pub fn to_bgr_mat(frame: &Frame) -> ::opencv::Result<Mat> {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let channels = if frame.has_alpha { 4 } else { 3 };
+
+    if frame.color_space != ColorSpace::Rgb {
+        let rgba = to_rgba8(frame);
+        let typ = if frame.has_alpha {
+            core::CV_8UC4
+        } else {
+            core::CV_8UC3
+        };
+        return new_mat(width, height, typ, |y, row| {
+            let src = &rgba[y * width * 4..][..width * 4];
+            for (dst, src) in row.chunks_exact_mut(channels).zip(src.chunks(4))
+            {
+                dst[..3].copy_from_slice(&[src[2], src[1], src[0]]);
+                if channels == 4 {
+                    dst[3] = src[3];
+                }
+            }
+        });
+    }
+
+    // Planes are stored as GBR, followed by alpha
+    let order = [1, 0, 2, 3];
+    if frame.bit_depth == 8 {
+        let typ = if frame.has_alpha {
+            core::CV_8UC4
+        } else {
+            core::CV_8UC3
+        };
+        new_mat(width, height, typ, |y, row| {
+            for (c, &plane) in order[..channels].iter().enumerate() {
+                let start = frame.offsets[plane] + y * frame.strides[plane];
+                let src = &frame.buf[plane][start..start + width];
+                for (x, &v) in src.iter().enumerate() {
+                    row[x * channels + c] = v;
+                }
+            }
+        })
+    } else {
+        let aligned = to_msb_aligned(frame);
+        let typ = if frame.has_alpha {
+            core::CV_16UC4
+        } else {
+            core::CV_16UC3
+        };
+        new_mat(width, height, typ, |y, row| {
+            for (c, &plane) in order[..channels].iter().enumerate() {
+                let src = &aligned.buf16[plane][y * width..][..width];
+                for (x, &v) in src.iter().enumerate() {
+                    let at = (x * channels + c) * 2;
+                    row[at..at + 2].copy_from_slice(&v.to_ne_bytes());
+                }
+            }
+        })
+    }
+}
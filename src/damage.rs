@@ -0,0 +1,118 @@
+//! Localization of damaged slices, see 'Decoder::set_damage_map_enabled'.
+
+use std::fmt::{self, Write};
+use std::ops::Range;
+
+/// Why a region of a frame is damaged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DamageCause {
+    /// The CRC of the slice does not match.
+    ///
+    /// See: 4.8.3. slice_crc_parity
+    CrcMismatch,
+    /// The encoder marked the slice as damaged.
+    ///
+    /// See: 4.8.2. error_status
+    ErrorStatus(u8),
+    /// The frame could not be decoded, without a slice to blame, e.g.
+    /// because of damaged slice footers, or a slice without CRC could not
+    /// be decoded and was abandoned.
+    Undecodable(String),
+}
+
+impl fmt::Display for DamageCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DamageCause::CrcMismatch => f.write_str("CRC mismatch"),
+            DamageCause::ErrorStatus(status) => {
+                write!(f, "error_status {}", status)
+            }
+            DamageCause::Undecodable(message) => f.write_str(message),
+        }
+    }
+}
+
+/// A damaged region of a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    /// Number of the frame, counting every packet handed to the decoder
+    /// since the damage map was enabled, from 0.
+    pub frame: u64,
+    /// Index of the damaged slice, or None when the whole frame is.
+    pub slice: Option<usize>,
+    /// Position and size of the damaged region, in pixels, as x, y, width
+    /// and height.
+    pub rect: (u32, u32, u32, u32),
+    /// Byte range of the damaged data within the packet.
+    pub bytes: Range<usize>,
+    /// Why the region is damaged.
+    pub cause: DamageCause,
+}
+
+/// The damaged regions of the frames decoded by a decoder, e.g. to show
+/// conservators which parts of which frames are affected by corruption.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DamageMap {
+    /// Number of frames handed to the decoder.
+    pub frames: u64,
+    /// Damaged regions, in decoding order.
+    pub damages: Vec<Damage>,
+}
+
+impl DamageMap {
+    /// Returns the numbers of the damaged frames, in decoding order.
+    pub fn damaged_frames(&self) -> Vec<u64> {
+        let mut frames: Vec<_> =
+            self.damages.iter().map(|damage| damage.frame).collect();
+        frames.dedup();
+        frames
+    }
+
+    /// Returns the map as a JSON object, e.g. for reports.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let _ = write!(json, "{{\"frames\":{},\"damages\":[", self.frames);
+        for (i, damage) in self.damages.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let slice = damage
+                .slice
+                .map_or_else(|| "null".to_owned(), |slice| slice.to_string());
+            let (x, y, width, height) = damage.rect;
+            let _ = write!(
+                json,
+                "{{\"frame\":{},\"slice\":{},\"x\":{},\"y\":{},\
+                 \"width\":{},\"height\":{},\"offset\":{},\"size\":{},\
+                 \"cause\":\"{}\"}}",
+                damage.frame,
+                slice,
+                x,
+                y,
+                width,
+                height,
+                damage.bytes.start,
+                damage.bytes.len(),
+                escape_json(&damage.cause.to_string())
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+/// Escapes 'text' to be written within a JSON string.
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
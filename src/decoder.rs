@@ -1,30 +1,72 @@
-use num_traits::AsPrimitive;
+use std::any::Any;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
-use crate::constants::CONTEXT_SIZE;
+use log::{info, warn};
+
+use crate::allocator::{Allocate, Capacity, FrameAllocator, GlobalAllocator};
+use crate::budget::{SliceBudget, Spending};
+use crate::compliance::Compliance;
+use crate::constants::{ColorSpace, PixelFormat, CONTEXT_SIZE};
 use crate::crc32mpeg2::crc32_mpeg2;
+use crate::damage::{Damage, DamageCause, DamageMap};
+use crate::diagnostics::Diagnostic;
 use crate::error::{Error, Result};
-use crate::golomb::Coder as GolombCoder;
-use crate::golomb::State;
-use crate::jpeg2000rct::Rct;
-use crate::pred::{derive_borders, get_context, get_median};
-use crate::range::RangeCoder;
+use crate::geometry::{self, chroma_position, chroma_size, Crop};
+#[cfg(feature = "golomb")]
+use crate::golombcoder::golomb::GolombCoder;
+use crate::golombcoder::golomb::State;
+use crate::hash::{adler32_update, Md5};
+use crate::jpeg2000rct::rct_rows;
+use crate::metrics::{Metrics, MetricsHook};
+use crate::plane::PlaneView;
+use crate::pred::{
+    derive_borders, derive_row, get_context, get_median, Neighbours,
+    PlaneSlice,
+};
+use crate::rangecoder::range::RangeCoder;
 use crate::rangecoder::tables::DEFAULT_STATE_TRANSITION;
 use crate::record::ConfigRecord;
+use crate::sample::Sample;
+use crate::scan::FrameScan;
+use crate::sink::{OutputSink, RowAssembler, StatsSink};
 use crate::slice::{
-    count_slices, is_keyframe, InternalFrame, Slice, SliceHeader, SlicePlane,
+    count_slices, is_keyframe, read_footers, ContextStates, FrontEnd,
+    InternalFrame, Slice, SliceHeader, SliceInfo, SliceLayout, SlicePlane,
+    SliceStates,
 };
+use crate::stats::{
+    CodingStats, DecodeStats, PlaneStats, Stage, ThreadStats, Timer,
+};
+use crate::threads::{default_threads, ThreadLimit};
+use crate::trace::Trace;
 
 #[allow(clippy::large_enum_variant)]
 enum Coder<'a> {
+    #[cfg(feature = "golomb")]
     Golomb(GolombCoder<'a>),
     Range(RangeCoder<'a>),
 }
 
+impl Coder<'_> {
+    /// Returns the number of bits read so far.
+    fn bits_read(&self) -> u64 {
+        match self {
+            #[cfg(feature = "golomb")]
+            Coder::Golomb(golomb_coder) => golomb_coder.bits_read(),
+            Coder::Range(range_coder) => range_coder.pos() as u64 * 8,
+        }
+    }
+}
+
 /// Frame contains a decoded FFV1 frame and relevant
 /// data about the frame.
 ///
 /// If BitDepth is 8, image data is in Buf. If it is anything else,
-/// image data is in Buf16.
+/// image data is in Buf16, where samples are LSB-aligned, i.e. their
+/// maximum value is 2^BitDepth - 1. See the convert module to normalize
+/// them.
 ///
 /// Image data consists of up to four contiguous planes, as follows:
 ///   - If ColorSpace is YCbCr:
@@ -37,46 +79,696 @@ enum Coder<'a> {
 ///    - Plane 1 is Blue
 ///    - Plane 2 is Red
 ///    - If HasAlpha is true, plane 4 is alpha.
-#[derive(Debug)]
+///
+/// Row 'y' of plane 'i' starts at 'Offsets[i] + y * Strides[i]'. Unless
+/// an alignment has been requested with 'Decoder::set_alignment', offsets
+/// are zero and strides are equal to the plane widths.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Frame {
     /// Image data. Valid only when BitDepth is 8.
-    pub buf: Vec<Vec<u8>>,
+    pub(crate) buf: Vec<Vec<u8>>,
     /// Image data. Valid only when BitDepth is greater than 8.
-    pub buf16: Vec<Vec<u16>>,
-    /// Unexported 32-bit scratch buffer for 16-bit JPEG2000-RCT RGB
-    pub buf32: Vec<Vec<u32>>,
+    pub(crate) buf16: Vec<Vec<u16>>,
+    /// Distance between the start of two consecutive rows of each plane,
+    /// in samples.
+    pub(crate) strides: Vec<usize>,
+    /// Position of the first sample of each plane, in samples.
+    pub(crate) offsets: Vec<usize>,
     /// Width of the frame, in pixels.
-    #[allow(dead_code)]
-    pub width: u32,
+    pub(crate) width: u32,
     /// Height of the frame, in pixels.
-    #[allow(dead_code)]
-    pub height: u32,
+    pub(crate) height: u32,
     /// Bitdepth of the frame (8-16).
-    #[allow(dead_code)]
-    pub bit_depth: u8,
-    /// Colorspace of the frame. See the colorspace constants.
-    #[allow(dead_code)]
-    pub color_space: isize,
+    pub(crate) bit_depth: u8,
+    /// Colorspace of the frame.
+    pub(crate) color_space: ColorSpace,
     /// Whether or not chroma planes are present.
-    #[allow(dead_code)]
-    pub has_chroma: bool,
+    pub(crate) has_chroma: bool,
     /// Whether or not an alpha plane is present.
-    #[allow(dead_code)]
-    pub has_alpha: bool,
+    pub(crate) has_alpha: bool,
     /// The log2 vertical chroma subampling value.
-    #[allow(dead_code)]
-    pub chroma_subsample_v: u8,
+    pub(crate) chroma_subsample_v: u8,
     /// The log2 horizontal chroma subsampling value.
-    #[allow(dead_code)]
-    pub chroma_subsample_h: u8,
+    pub(crate) chroma_subsample_h: u8,
+    /// Displayed area of the frame.
+    pub(crate) crop: Crop,
+    /// Caller metadata passed along with the packet.
+    pub(crate) metadata: FrameMetadata,
+    /// Non-fatal findings of the decoding.
+    pub(crate) diagnostics: Vec<Diagnostic>,
 }
 
-/// Decoder is a FFV1 decoder instance.
+/// Caller metadata attached to a packet and returned on the decoded frame,
+/// e.g. to keep frames associated with container timing when they are
+/// decoded on other threads.
+#[derive(Debug, Clone, Default)]
+pub struct FrameMetadata {
+    /// Presentation timestamp, in the time base of the caller.
+    pub pts: Option<i64>,
+    /// Opaque user data.
+    pub opaque: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Frame {
+    /// Returns the image data. Valid only when the bit depth is 8.
+    pub fn buf(&self) -> &[Vec<u8>] {
+        &self.buf
+    }
+
+    /// Returns the image data. Valid only when the bit depth is greater
+    /// than 8.
+    pub fn buf16(&self) -> &[Vec<u16>] {
+        &self.buf16
+    }
+
+    /// Returns the distance between the start of two consecutive rows of
+    /// each plane, in samples.
+    pub fn strides(&self) -> &[usize] {
+        &self.strides
+    }
+
+    /// Returns the position of the first sample of each plane, in samples.
+    pub fn offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Returns the width of the frame, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the frame, in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the bitdepth of the frame (8-16).
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    /// Returns the colorspace of the frame.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Returns whether or not chroma planes are present.
+    pub fn has_chroma(&self) -> bool {
+        self.has_chroma
+    }
+
+    /// Returns whether or not an alpha plane is present.
+    pub fn has_alpha(&self) -> bool {
+        self.has_alpha
+    }
+
+    /// Returns the log2 vertical chroma subsampling value.
+    pub fn chroma_subsample_v(&self) -> u8 {
+        self.chroma_subsample_v
+    }
+
+    /// Returns the log2 horizontal chroma subsampling value.
+    pub fn chroma_subsample_h(&self) -> u8 {
+        self.chroma_subsample_h
+    }
+
+    /// Returns the displayed area of the frame, see 'Decoder::set_crop'.
+    pub fn crop(&self) -> Crop {
+        self.crop
+    }
+
+    /// Returns the displayed width of the frame, in pixels.
+    pub fn display_width(&self) -> u32 {
+        self.crop.width
+    }
+
+    /// Returns the displayed height of the frame, in pixels.
+    pub fn display_height(&self) -> u32 {
+        self.crop.height
+    }
+
+    /// Returns the caller metadata passed along with the packet.
+    pub fn metadata(&self) -> &FrameMetadata {
+        &self.metadata
+    }
+
+    /// Returns what was unusual about the frame without preventing its
+    /// decoding, in slice order, e.g. skipped padding or tolerated
+    /// specification violations.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns whether some slices of the frame were concealed instead of
+    /// being decoded, see 'Decoder::decode_frame_with_deadline'.
+    pub fn is_partial(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| {
+            matches!(diagnostic, Diagnostic::SliceConcealed { .. })
+        })
+    }
+
+    /// Returns the planes of the frame.
+    pub fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::new(self.color_space, self.has_chroma, self.has_alpha)
+    }
+
+    /// Returns the number of planes of the frame, e.g. 1 for grayscale
+    /// frames.
+    pub fn num_planes(&self) -> usize {
+        self.pixel_format().num_planes()
+    }
+
+    /// Returns the width and height of a plane, in samples.
+    pub fn plane_dimensions(&self, plane: usize) -> (usize, usize) {
+        geometry::plane_dimensions(
+            self.width,
+            self.height,
+            self.has_chroma,
+            self.chroma_subsample_h,
+            self.chroma_subsample_v,
+            plane,
+        )
+    }
+
+    /// Returns a view of a plane of an 8-bit frame, or None when the bit
+    /// depth is greater than 8.
+    pub fn plane(&self, plane: usize) -> Option<PlaneView<'_, u8>> {
+        self.view(&self.buf, plane)
+    }
+
+    /// Returns a view of a plane of a frame whose bit depth is greater
+    /// than 8, or None when it is 8.
+    pub fn plane16(&self, plane: usize) -> Option<PlaneView<'_, u16>> {
+        self.view(&self.buf16, plane)
+    }
+
+    /// Returns a view of the displayed area of a plane of an 8-bit frame,
+    /// see 'Crop::plane_area'.
+    pub fn display_plane(&self, plane: usize) -> Option<PlaneView<'_, u8>> {
+        self.display_view(self.plane(plane)?, plane)
+    }
+
+    /// Returns a view of the displayed area of a plane of a frame whose
+    /// bit depth is greater than 8, see 'Crop::plane_area'.
+    pub fn display_plane16(&self, plane: usize) -> Option<PlaneView<'_, u16>> {
+        self.display_view(self.plane16(plane)?, plane)
+    }
+
+    /// Returns a view of a plane of 'planes'.
+    fn view<'a, T: Sample>(
+        &self,
+        planes: &'a [Vec<T>],
+        plane: usize,
+    ) -> Option<PlaneView<'a, T>> {
+        let (width, height) = self.plane_dimensions(plane);
+        PlaneView::new(
+            planes.get(plane)?.get(*self.offsets.get(plane)?..)?,
+            width,
+            height,
+            self.strides[plane],
+        )
+    }
+
+    /// Crops the view of a plane to its displayed area.
+    fn display_view<'a, T: Sample>(
+        &self,
+        view: PlaneView<'a, T>,
+        plane: usize,
+    ) -> Option<PlaneView<'a, T>> {
+        let (x, y, width, height) = self.crop.plane_area(
+            self.has_chroma,
+            self.chroma_subsample_h,
+            self.chroma_subsample_v,
+            plane,
+        );
+        view.crop(x, y, width, height)
+    }
+
+    /// Calls 'f' with every row of a plane, as packed little-endian bytes.
+    fn for_each_plane_row<F: FnMut(&[u8])>(&self, plane: usize, mut f: F) {
+        let (width, height) = self.plane_dimensions(plane);
+        let offset = self.offsets[plane];
+        let stride = self.strides[plane];
+        let mut row = Vec::with_capacity(width * 2);
+        for y in 0..height {
+            let start = offset + y * stride;
+            if self.bit_depth == 8 {
+                f(&self.buf[plane][start..start + width]);
+            } else {
+                row.clear();
+                for &v in &self.buf16[plane][start..start + width] {
+                    row.extend_from_slice(&v.to_le_bytes());
+                }
+                f(&row);
+            }
+        }
+    }
+
+    /// Calls 'f' with every row of the frame, laid out as in the FFmpeg
+    /// rawvideo output for the pixel format it decodes the stream to.
+    ///
+    /// This is planar data for every format but 8-bit RGB, which FFmpeg
+    /// outputs as packed BGR0, or BGRA when an alpha plane is present.
+    fn for_each_raw_row<F: FnMut(&[u8])>(&self, mut f: F) {
+        if self.color_space == ColorSpace::Rgb && self.bit_depth == 8 {
+            let width = self.width as usize;
+            let mut row = vec![0; width * 4];
+            for y in 0..self.height as usize {
+                let pos = |plane: usize| {
+                    self.offsets[plane] + y * self.strides[plane]
+                };
+                let (g, b, r) = (pos(0), pos(1), pos(2));
+                for x in 0..width {
+                    row[4 * x] = self.buf[1][b + x];
+                    row[4 * x + 1] = self.buf[0][g + x];
+                    row[4 * x + 2] = self.buf[2][r + x];
+                    row[4 * x + 3] = if self.has_alpha {
+                        self.buf[3][pos(3) + x]
+                    } else {
+                        0
+                    };
+                }
+                f(&row);
+            }
+        } else {
+            for plane in 0..self.num_planes() {
+                self.for_each_plane_row(plane, &mut f);
+            }
+        }
+    }
+
+    /// Returns the MD5 digest of a plane, computed over its packed samples,
+    /// with 16-bit samples stored as little-endian.
+    pub fn plane_md5(&self, plane: usize) -> [u8; 16] {
+        let mut hasher = Md5::new();
+        self.for_each_plane_row(plane, |row| hasher.update(row));
+        hasher.finalize()
+    }
+
+    /// Returns the MD5 digest of the frame, as written by the FFmpeg
+    /// framemd5 muxer for the same stream.
+    pub fn frame_md5(&self) -> [u8; 16] {
+        let mut hasher = Md5::new();
+        self.for_each_raw_row(|row| hasher.update(row));
+        hasher.finalize()
+    }
+
+    /// Returns the checksum of the frame, as written by the FFmpeg framecrc
+    /// muxer for the same stream, which uses Adler-32.
+    pub fn frame_crc(&self) -> u32 {
+        let mut adler = 1;
+        self.for_each_raw_row(|row| adler = adler32_update(adler, row));
+        adler
+    }
+}
+
+/// PlaneBuffer is a caller-provided destination for a decoded plane.
+///
+/// Row 'y' of the plane starts at 'data[y * stride]', so the stride can be
+/// larger than the plane width, e.g. when pointing into a padded surface.
+#[derive(Debug)]
+pub struct PlaneBuffer<'a, T> {
+    /// Samples of the plane.
+    pub data: &'a mut [T],
+    /// Distance between the start of two consecutive rows, in samples.
+    pub stride: usize,
+}
+
+/// FrameBuffers contains the destinations of all the planes of a frame,
+/// in the same order as the planes of a Frame.
 #[derive(Debug)]
+pub enum FrameBuffers<'a> {
+    /// Destinations for frames with a bit depth of 8.
+    U8(Vec<PlaneBuffer<'a, u8>>),
+    /// Destinations for frames with a bit depth greater than 8.
+    U16(Vec<PlaneBuffer<'a, u16>>),
+}
+
+impl<'a> FrameBuffers<'a> {
+    /// Borrows the destinations of the planes.
+    fn planes(&mut self) -> OutputPlanes<'_, 'a> {
+        match self {
+            FrameBuffers::U8(planes) => OutputPlanes::U8(planes),
+            FrameBuffers::U16(planes) => OutputPlanes::U16(planes),
+        }
+    }
+}
+
+/// Destinations of the planes of a frame, borrowed from 'FrameBuffers' or
+/// from the planes of a preallocated frame.
+enum OutputPlanes<'b, 'a> {
+    U8(&'b mut [PlaneBuffer<'a, u8>]),
+    U16(&'b mut [PlaneBuffer<'a, u16>]),
+}
+
+/// Gathers the destinations of up to four planes without allocating,
+/// padding them with empty planes.
+fn plane_array<'a, T, I>(planes: I) -> [PlaneBuffer<'a, T>; 4]
+where
+    I: IntoIterator<Item = PlaneBuffer<'a, T>>,
+{
+    let mut planes = planes.into_iter();
+    let mut next = || {
+        planes.next().unwrap_or(PlaneBuffer {
+            data: &mut [],
+            stride: 0,
+        })
+    };
+    [next(), next(), next(), next()]
+}
+
+/// Buffers of a fixed-capacity decoder, allocated once by
+/// 'Decoder::set_capacity'.
+#[derive(Debug, Clone)]
+struct Preallocated {
+    frame: Frame,
+    scratch: Scratch,
+}
+
+/// Scratch buffers used by the JPEG2000-RCT paths, since samples are
+/// coded there with one more bit than the output samples.
+#[derive(Debug, Clone, Default)]
+struct Scratch {
+    buf16: Vec<Vec<u16>>,
+    buf32: Vec<Vec<u32>>,
+}
+
+impl Scratch {
+    /// Allocates the scratch space of a 'width'x'height' area, only needed
+    /// by the JPEG2000-RCT paths.
+    fn new(record: &ConfigRecord, width: u32, height: u32) -> Self {
+        let mut scratch = Scratch::default();
+        if record.colorspace_type != ColorSpace::Rgb {
+            return scratch;
+        }
+
+        // JPEG2000-RCT is very annoyingly coded as n+1 bits, so 8-bit RGB
+        // is decoded into a 16-bit scratch space to keep the implementation
+        // straightforward... RIP.
+        //
+        // For 16-bit RGB we need a 32-bit scratch space beause we need to
+        // predict based on 17-bit values in the JPEG2000-RCT space, so just
+        // allocate a whole frame, because I am lazy. Is it slow? Yes.
+        let full_size = width as usize * height as usize;
+        let bits = record.bits_per_raw_sample;
+        if bits == 8 {
+            scratch.buf16 = vec![vec![0; full_size]; record.num_planes()];
+        } else if bits == 16 || record.extra_plane {
+            scratch.buf32 = vec![vec![0; full_size]; record.num_planes()];
+        }
+
+        scratch
+    }
+}
+
+/// Planes holding the samples of a single slice, decoded on its own
+/// thread before being copied into the frame.
+pub(crate) enum SlicePlanes {
+    U8(Vec<Vec<u8>>),
+    U16(Vec<Vec<u16>>),
+}
+
+impl SlicePlanes {
+    /// Allocates the planes of a slice, in the order of 'planes'.
+    fn new(record: &ConfigRecord, planes: &[SlicePlane]) -> Self {
+        let sizes = planes
+            .iter()
+            .map(|plane| plane.width as usize * plane.height as usize);
+        if record.bits_per_raw_sample == 8 {
+            SlicePlanes::U8(sizes.map(|size| vec![0; size]).collect())
+        } else {
+            SlicePlanes::U16(sizes.map(|size| vec![0; size]).collect())
+        }
+    }
+
+    /// Allocates the planes of a slice, in the order of 'planes', holding
+    /// concealed samples.
+    fn concealed(record: &ConfigRecord, planes: &[SlicePlane]) -> Self {
+        let planes = planes.iter().enumerate().map(|(i, plane)| {
+            let size = plane.width as usize * plane.height as usize;
+            (size, concealed_sample(record, i, planes.len()))
+        });
+        if record.bits_per_raw_sample == 8 {
+            SlicePlanes::U8(
+                planes
+                    .map(|(size, sample)| vec![sample as u8; size])
+                    .collect(),
+            )
+        } else {
+            SlicePlanes::U16(
+                planes.map(|(size, sample)| vec![sample; size]).collect(),
+            )
+        }
+    }
+
+    /// Returns the planes as destinations, rows being contiguous.
+    fn buffers(&mut self, planes: &[SlicePlane]) -> FrameBuffers<'_> {
+        fn wrap<'a, T>(
+            bufs: &'a mut [Vec<T>],
+            planes: &[SlicePlane],
+        ) -> Vec<PlaneBuffer<'a, T>> {
+            bufs.iter_mut()
+                .zip(planes)
+                .map(|(data, plane)| PlaneBuffer {
+                    data,
+                    stride: plane.width as usize,
+                })
+                .collect()
+        }
+        match self {
+            SlicePlanes::U8(bufs) => FrameBuffers::U8(wrap(bufs, planes)),
+            SlicePlanes::U16(bufs) => FrameBuffers::U16(wrap(bufs, planes)),
+        }
+    }
+
+    /// Copies the planes to their position in the frame buffers.
+    fn copy_to(&self, planes: &[SlicePlane], buffers: &mut OutputPlanes) {
+        fn copy<T: Copy>(
+            bufs: &[Vec<T>],
+            planes: &[SlicePlane],
+            dst: &mut [PlaneBuffer<T>],
+        ) {
+            for ((src, plane), dst) in bufs.iter().zip(planes).zip(dst) {
+                let width = plane.width as usize;
+                if width == 0 {
+                    continue;
+                }
+                for (y, row) in src.chunks_exact(width).enumerate() {
+                    let start = (plane.start_y as usize + y) * dst.stride
+                        + plane.start_x as usize;
+                    dst.data[start..start + width].copy_from_slice(row);
+                }
+            }
+        }
+        match (self, buffers) {
+            (SlicePlanes::U8(bufs), OutputPlanes::U8(dst)) => {
+                copy(bufs, planes, dst)
+            }
+            (SlicePlanes::U16(bufs), OutputPlanes::U16(dst)) => {
+                copy(bufs, planes, dst)
+            }
+            _ => unreachable!("buffers are checked against the bit depth"),
+        }
+    }
+}
+
+/// What a slice needs from the Decoder, shared by the threads decoding
+/// the slices of a frame.
+struct SliceContext<'a> {
+    record: &'a ConfigRecord,
+    state_transition: &'a [u8; 256],
+    keyframe: bool,
+    metrics: &'a MetricsHook,
+    trace: &'a Trace,
+    compliance: Compliance,
+    /// Whether the integrity of every slice has already been checked.
+    checked: bool,
+    /// Instant after which slices are concealed instead of being decoded.
+    deadline: Option<Instant>,
+    budget: SliceBudget,
+    /// Threads converting the rows of JPEG2000-RCT slices decoded into the
+    /// frame buffers.
+    rct_threads: usize,
+    /// Whether the statistics of the symbols of every slice are gathered.
+    coding_stats: bool,
+}
+
+/// Returns the sample concealing the plane 'plane' of the 'planes' planes
+/// of a slice: mid-grey, or opaque for alpha.
+fn concealed_sample(
+    record: &ConfigRecord,
+    plane: usize,
+    planes: usize,
+) -> u16 {
+    let bits = u32::from(record.bits_per_raw_sample);
+    if record.extra_plane && plane + 1 == planes {
+        ((1u32 << bits) - 1) as u16
+    } else {
+        1 << (bits - 1)
+    }
+}
+
+/// Conceals the slice planes 'planes' in the frame buffers.
+fn conceal_planes(
+    record: &ConfigRecord,
+    planes: &[SlicePlane],
+    buffers: &mut OutputPlanes,
+) {
+    fn fill<T: Copy>(
+        planes: &[SlicePlane],
+        dst: &mut [PlaneBuffer<T>],
+        sample: impl Fn(usize) -> T,
+    ) {
+        for (i, (plane, dst)) in planes.iter().zip(dst).enumerate() {
+            let width = plane.width as usize;
+            for y in 0..plane.height as usize {
+                let start = (plane.start_y as usize + y) * dst.stride
+                    + plane.start_x as usize;
+                dst.data[start..start + width].fill(sample(i));
+            }
+        }
+    }
+    let sample = |i| concealed_sample(record, i, planes.len());
+    match buffers {
+        OutputPlanes::U8(dst) => fill(planes, dst, |i| sample(i) as u8),
+        OutputPlanes::U16(dst) => fill(planes, dst, sample),
+    }
+}
+
+/// Derives the samples of the line 'yy' of the plane 'buf' with 'sample'.
+///
+/// The first two rows of a slice have borders, see pred.go for details.
+/// The following ones are derived from their row slices.
+fn derive_line<T: Sample>(
+    buf: &mut [T],
+    width: usize,
+    stride: usize,
+    yy: usize,
+    mut sample: impl FnMut(&Neighbours<T>) -> T,
+) {
+    if yy < 2 {
+        for x in 0..width {
+            let plane = PlaneSlice::new(buf, width, stride);
+            let n = derive_borders(&plane, x, yy);
+            buf[(yy * stride) + x] = sample(&n);
+        }
+        return;
+    }
+    let (above, below) = buf.split_at_mut(yy * stride);
+    derive_row(
+        &mut below[..width],
+        &above[(yy - 1) * stride..],
+        &above[(yy - 2) * stride..],
+        sample,
+    );
+}
+
+/// Wraps frame-sized buffers into plane destinations.
+fn plane_buffers<T>(
+    bufs: &mut [Vec<T>],
+    stride: usize,
+) -> [PlaneBuffer<'_, T>; 4] {
+    plane_array(bufs.iter_mut().map(|data| PlaneBuffer { data, stride }))
+}
+
+/// Adds the samples of the planes of 'dimensions' written to 'buffers' to
+/// 'stats'.
+fn add_pixel_stats(
+    buffers: &OutputPlanes,
+    dimensions: &[(usize, usize)],
+    stats: &mut [PlaneStats],
+) {
+    fn add<T: Copy + Into<u16>>(
+        planes: &[PlaneBuffer<T>],
+        dimensions: &[(usize, usize)],
+        stats: &mut [PlaneStats],
+    ) {
+        for ((plane, &(width, height)), stats) in
+            planes.iter().zip(dimensions).zip(stats)
+        {
+            for y in 0..height {
+                let start = y * plane.stride;
+                stats.add_row(&plane.data[start..start + width]);
+            }
+        }
+    }
+    match buffers {
+        OutputPlanes::U8(planes) => add(planes, dimensions, stats),
+        OutputPlanes::U16(planes) => add(planes, dimensions, stats),
+    }
+}
+
+/// Returns the error of the slice 'slicenum', keeping CRC mismatches and
+/// resource errors in their category.
+fn slice_failed(slicenum: usize, err: Error) -> Error {
+    let message = format!("slice {} failed: {}", slicenum, err);
+    match err {
+        Error::CrcMismatch(_) => Error::CrcMismatch(message),
+        Error::ResourceError(_) => Error::ResourceError(message),
+        Error::ResourceLimitExceeded(_) => {
+            Error::ResourceLimitExceeded(message)
+        }
+        _ => Error::SliceError(message),
+    }
+}
+
+/// Returns whether the CRC of a slice matched, given the result of its
+/// check or decoding, or None when it was not checked.
+///
+/// See: 4.8.3. slice_crc_parity
+fn crc_status<T>(
+    ec: bool,
+    info: &SliceInfo,
+    result: &Result<T>,
+) -> Option<bool> {
+    if !ec || info.error_status != 0 {
+        return None;
+    }
+    Some(!matches!(result, Err(Error::CrcMismatch(_))))
+}
+
+/// States carried over from a decoded frame to the next one, see
+/// 'Decoder::snapshot'.
+#[derive(Debug, Clone)]
+pub struct DecoderSnapshot {
+    frame: InternalFrame,
+}
+
+/// Decoder is a FFV1 decoder instance.
+///
+/// A clone carries on from the same states, sharing the allocator, the
+/// metrics and the thread limit of the original.
+#[derive(Debug, Clone)]
 pub struct Decoder {
     record: ConfigRecord,
     state_transition: [u8; 256],
     current_frame: InternalFrame,
+    alignment: usize,
+    crop: Crop,
+    allocator: Arc<dyn FrameAllocator>,
+    metrics: MetricsHook,
+    stats: Option<DecodeStats>,
+    thread_stats: Option<ThreadStats>,
+    damage: Option<DamageMap>,
+    pixel_stats: Option<Vec<PlaneStats>>,
+    coding_stats: Option<Vec<CodingStats>>,
+    trace: Trace,
+    threads: usize,
+    thread_limit: Option<Arc<ThreadLimit>>,
+    compliance: Compliance,
+    diagnostics: Vec<Diagnostic>,
+    front_end: Option<FrontEnd>,
+    resync: bool,
+    /// Slices of earlier frames beyond the slices of the current one, kept
+    /// for their allocations.
+    spare_slices: Vec<Slice>,
+    capacity: Option<Capacity>,
+    fixed: Option<Box<Preallocated>>,
+    /// Deadline of the frame being decoded, see
+    /// 'decode_frame_with_deadline'.
+    deadline: Option<Instant>,
+    budget: SliceBudget,
 }
 
 impl Decoder {
@@ -89,6 +781,58 @@ impl Decoder {
     /// 'width' and 'height' are the frame width and height provided by
     /// the container.
     pub fn new(record: &[u8], width: u32, height: u32) -> Result<Self> {
+        Self::new_traced(
+            record,
+            width,
+            height,
+            Trace::default(),
+            Compliance::Normal,
+        )
+    }
+
+    /// Creates a decoder like 'new', failing on specification violations
+    /// as hard as 'compliance' says, both in the configuration record and
+    /// in the frames.
+    pub fn with_compliance(
+        record: &[u8],
+        width: u32,
+        height: u32,
+        compliance: Compliance,
+    ) -> Result<Self> {
+        Self::new_traced(record, width, height, Trace::default(), compliance)
+    }
+
+    /// Creates a decoder like 'new', writing every syntax element it
+    /// decodes to 'writer', one per line, starting with the configuration
+    /// record.
+    ///
+    /// Elements are named after the section of the specification defining
+    /// them, with their indexes, e.g. 4.5.1. slice_x: 0. Slice contents are
+    /// traced as their sample differences, preceded by their plane and
+    /// line.
+    #[cfg(feature = "trace-bitstream")]
+    pub fn with_trace<W: std::io::Write + Send + 'static>(
+        record: &[u8],
+        width: u32,
+        height: u32,
+        writer: W,
+    ) -> Result<Self> {
+        Self::new_traced(
+            record,
+            width,
+            height,
+            Trace::new(writer),
+            Compliance::Normal,
+        )
+    }
+
+    fn new_traced(
+        record: &[u8],
+        width: u32,
+        height: u32,
+        trace: Trace,
+        compliance: Compliance,
+    ) -> Result<Self> {
         if width == 0 || height == 0 {
             return Err(Error::InvalidInputData(format!(
                 "invalid dimensions: {}x{}",
@@ -102,17 +846,63 @@ impl Decoder {
             ));
         }
 
-        let record =
-            match ConfigRecord::parse_config_record(record, width, height) {
-                Ok(record) => record,
-                Err(err) => {
-                    return Err(Error::InvalidInputData(format!(
-                        "invalid v3 configuration record: {}",
-                        err
-                    )))
-                }
-            };
+        let record = match ConfigRecord::parse_traced(
+            record, width, height, &trace, compliance,
+        ) {
+            Ok(record) => record,
+            Err(err) => {
+                let message =
+                    format!("invalid v3 configuration record: {}", err);
+                return Err(match err {
+                    Error::CrcMismatch(_) => Error::CrcMismatch(message),
+                    Error::InvalidConfiguration(_) => {
+                        Error::InvalidConfiguration(message)
+                    }
+                    _ => Error::InvalidInputData(message),
+                });
+            }
+        };
+        Self::from_record(record, trace, compliance)
+    }
+
+    /// Creates a decoder of the streams described by 'record', e.g. built
+    /// by a 'ConfigRecordBuilder', without parsing a coded record.
+    pub fn from_config_record(record: ConfigRecord) -> Result<Self> {
+        if record.width == 0 || record.height == 0 {
+            return Err(Error::InvalidInputData(format!(
+                "invalid dimensions: {}x{}",
+                record.width, record.height
+            )));
+        }
+        Self::from_record(record, Trace::default(), Compliance::Normal)
+    }
+
+    fn from_record(
+        record: ConfigRecord,
+        trace: Trace,
+        compliance: Compliance,
+    ) -> Result<Self> {
+        #[cfg(not(feature = "golomb"))]
+        if record.coder_type == 0 {
+            return Err(Error::InvalidConfiguration(
+                "Golomb-Rice coded streams need the 'golomb' feature"
+                    .to_owned(),
+            ));
+        }
+        #[cfg(not(feature = "rgb16"))]
+        if record.colorspace_type == ColorSpace::Rgb
+            && record.bits_per_raw_sample != 8
+            && (record.bits_per_raw_sample == 16 || record.extra_plane)
+        {
+            return Err(Error::InvalidConfiguration(
+                "RGB streams decoded through 32-bit samples need the 'rgb16' \
+                 feature"
+                    .to_owned(),
+            ));
+        }
 
+        let states = ContextStates::new(&record);
+        let crop = Crop::display_size(record.width, record.height);
         let mut decoder = Decoder {
             record,
             state_transition: [0; 256],
@@ -120,11 +910,37 @@ impl Decoder {
                 keyframe: false,
                 slice_info: Vec::new(),
                 slices: Vec::new(),
+                states,
             },
+            alignment: 1,
+            crop,
+            allocator: Arc::new(GlobalAllocator),
+            metrics: MetricsHook::default(),
+            stats: None,
+            thread_stats: None,
+            damage: None,
+            pixel_stats: None,
+            coding_stats: None,
+            trace,
+            threads: default_threads(),
+            thread_limit: None,
+            compliance,
+            diagnostics: Vec::new(),
+            front_end: None,
+            resync: false,
+            spare_slices: Vec::new(),
+            capacity: None,
+            fixed: None,
+            deadline: None,
+            budget: SliceBudget::default(),
         };
 
         decoder.initialize_states();
 
+        if decoder.record.ec == 0 {
+            info!("stream has no slice CRCs, corruption will go undetected");
+        }
+
         Ok(decoder)
     }
 
@@ -136,134 +952,1356 @@ impl Decoder {
         &self.record
     }
 
-    /// DecodeFrame takes a packet and decodes it to a ffv1.Frame.
-    ///
-    /// Slice threading is used by default, with one goroutine per
-    /// slice.
-    pub fn decode_frame(&mut self, frame_input: &[u8]) -> Result<Frame> {
-        let mut frame = Frame {
-            buf: Vec::new(),
-            buf16: Vec::new(),
-            buf32: Vec::new(),
-            width: self.record.width,
-            height: self.record.height,
-            bit_depth: self.record.bits_per_raw_sample,
-            color_space: self.record.colorspace_type as isize,
-            has_chroma: self.record.chroma_planes,
-            has_alpha: self.record.extra_plane,
-            chroma_subsample_v: if self.record.chroma_planes {
-                self.record.log2_v_chroma_subsample
-            } else {
-                0
-            },
-            chroma_subsample_h: if self.record.chroma_planes {
-                self.record.log2_h_chroma_subsample
-            } else {
-                0
-            },
-        };
+    /// Returns how hard the decoder fails on specification violations,
+    /// see 'with_compliance'.
+    pub fn compliance(&self) -> Compliance {
+        self.compliance
+    }
 
-        let mut num_planes = 1;
-        if self.record.chroma_planes {
-            num_planes += 2;
+    /// Returns the non-fatal findings of the last decoded frame, see
+    /// 'Frame::diagnostics', e.g. after 'decode_frame_into'.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Sets the alignment, in bytes, of the planes of the decoded frames.
+    ///
+    /// Every plane of a Frame returned by 'decode_frame' will start at an
+    /// address that is a multiple of 'alignment', and its stride will be
+    /// padded so that every row starts at such an address too, e.g. 32 or
+    /// 64 for SIMD filters. The alignment must be a power of two, and 1
+    /// disables any padding.
+    pub fn set_alignment(&mut self, alignment: usize) -> Result<()> {
+        if !alignment.is_power_of_two() {
+            return Err(Error::InvalidInputData(format!(
+                "alignment must be a power of two: {}",
+                alignment
+            )));
         }
-        if self.record.extra_plane {
-            num_planes += 1;
+        self.alignment = alignment;
+        if self.fixed.is_some() {
+            self.preallocate();
+        }
+        Ok(())
+    }
+
+    /// Sets the displayed area of the decoded frames, e.g. when the
+    /// container stores display dimensions smaller than the coded ones.
+    ///
+    /// Frames are still decoded whole, their display geometry and the
+    /// views of 'Frame::display_plane' follow 'crop', which must lie
+    /// within the coded frame. The whole frame is displayed by default.
+    pub fn set_crop(&mut self, crop: Crop) -> Result<()> {
+        if !crop.fits(self.record.width, self.record.height) {
+            return Err(Error::InvalidConfiguration(format!(
+                "crop {}x{}+{}+{} does not fit in the {}x{} frame",
+                crop.width,
+                crop.height,
+                crop.left,
+                crop.top,
+                self.record.width,
+                self.record.height
+            )));
         }
+        self.crop = crop;
+        Ok(())
+    }
 
-        let full_size = (self.record.width * self.record.height) as usize;
-        let chroma_width =
-            self.record.width >> self.record.log2_h_chroma_subsample;
-        let chroma_height =
-            self.record.height >> self.record.log2_v_chroma_subsample;
-        let chroma_size = (chroma_width * chroma_height) as usize;
+    /// Sets the display dimensions of the decoded frames, cropping the
+    /// right and bottom of the coded frame, see 'set_crop'.
+    pub fn set_display_size(&mut self, width: u32, height: u32) -> Result<()> {
+        self.set_crop(Crop::display_size(width, height))
+    }
 
-        // Hideous and temporary.
+    /// Returns the displayed area of the decoded frames, see 'set_crop'.
+    pub fn crop(&self) -> Crop {
+        self.crop
+    }
+
+    /// Sets the allocator of the planes of the frames returned by
+    /// 'decode_frame'.
+    pub fn set_allocator(&mut self, allocator: Arc<dyn FrameAllocator>) {
+        self.allocator = allocator;
+    }
+
+    /// Switches to fixed-capacity decoding, e.g. for real-time capture
+    /// appliances, failing when the streams of this decoder do not fit in
+    /// 'capacity'.
+    ///
+    /// A frame, the scratch space and the states of 'max_slices' slices
+    /// are allocated once, for the streams of this decoder, and
+    /// 'decode_frame_fixed' never allocates afterward: it decodes on the
+    /// calling thread into the preallocated frame, and fails with a
+    /// 'ResourceError' on frames of more than 'max_slices' slices. Other
+    /// ways of decoding still allocate what they return, and so do errors,
+    /// diagnostics, statistics, traces and the damage map.
+    pub fn set_capacity(&mut self, capacity: Capacity) -> Result<()> {
+        capacity.check(&self.record)?;
+        self.capacity = Some(capacity);
+        self.preallocate();
+        Ok(())
+    }
+
+    /// Returns the capacity set by 'set_capacity', if any.
+    pub fn capacity(&self) -> Option<Capacity> {
+        self.capacity
+    }
+
+    /// Allocates the frame, the scratch space and the slices of a
+    /// fixed-capacity decoder.
+    fn preallocate(&mut self) {
+        let frame = self.allocate_frame(FrameMetadata::default());
+        let scratch =
+            Scratch::new(&self.record, self.record.width, self.record.height);
+        self.fixed = Some(Box::new(Preallocated { frame, scratch }));
+        self.reserve_slices();
+    }
+
+    /// Makes room for the slices of the frames of a fixed-capacity
+    /// decoder, so that decoding them does not allocate.
+    fn reserve_slices(&mut self) {
+        let max_slices = match self.capacity {
+            Some(capacity) => capacity.max_slices,
+            None => return,
+        };
+        let frame = &mut self.current_frame;
+        let spare = max_slices.saturating_sub(frame.slices.len());
+        frame.slice_info.reserve(max_slices);
+        frame.slices.reserve(max_slices);
+        frame.states.reserve(max_slices);
+        self.spare_slices.reserve(max_slices);
+        while self.spare_slices.len() < spare {
+            self.spare_slices.push(Slice::preallocated(&self.record));
+        }
+    }
+
+    /// Sets the limits on the entropy decoding of every slice, e.g. for
+    /// services decoding untrusted streams, so that crafted slices fail
+    /// with a 'ResourceLimitExceeded' error instead of keeping the decoder
+    /// busy. Slices are unlimited by default.
+    pub fn set_slice_budget(&mut self, budget: SliceBudget) {
+        self.budget = budget;
+    }
+
+    /// Returns the limits set by 'set_slice_budget'.
+    pub fn slice_budget(&self) -> SliceBudget {
+        self.budget
+    }
+
+    /// Sets the hook receiving the metrics of this decoder.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+        self.metrics = MetricsHook::new(metrics);
+    }
+
+    /// Sets the number of threads decoding the slices of a frame, see
+    /// 'threads::default_threads' for the default.
+    ///
+    /// Decoded frames are bit-exact whatever the number of threads, only
+    /// the stage timings of 'stats' add up the time spent by every thread.
+    /// Frames are decoded on a single thread while tracing.
+    ///
+    /// With error correction, the CRCs of all the slices are checked on
+    /// these threads before decoding any of them, so damaged frames are
+    /// rejected early, and every damaged slice is reported to the metrics.
+    ///
+    /// RGB frames made of a single slice are decoded on a single thread,
+    /// but converted from JPEG2000-RCT on bands of rows on these threads.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    /// Returns the number of threads decoding the slices of a frame.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Shares 'limit' with other decoders, bounding the slice threads
+    /// running at the same time across all of them.
+    ///
+    /// Every frame is still decoded on at most 'threads' threads, as many
+    /// of them as the limit has free, or on the calling thread alone.
+    pub fn set_thread_limit(&mut self, limit: Arc<ThreadLimit>) {
+        self.thread_limit = Some(limit);
+    }
+
+    /// Enables or disables the per-stage and per-thread timing
+    /// statistics, resetting them. They are disabled by default.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = if enabled {
+            Some(DecodeStats::default())
+        } else {
+            None
+        };
+        self.thread_stats = if enabled {
+            Some(ThreadStats::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the per-stage timing statistics, if enabled.
+    pub fn stats(&self) -> Option<&DecodeStats> {
+        self.stats.as_ref()
+    }
+
+    /// Returns the timing statistics of the slice threads, if enabled
+    /// with 'set_stats_enabled'.
+    pub fn thread_stats(&self) -> Option<&ThreadStats> {
+        self.thread_stats.as_ref()
+    }
+
+    /// Enables or disables the per-plane sample statistics, e.g. to detect
+    /// clipped scans, dead frames or bit depth misuse during QC. They are
+    /// disabled by default.
+    ///
+    /// They are gathered once the samples of a frame are written, as the
+    /// samples of RGB streams are only known after the JPEG2000-RCT
+    /// conversion.
+    pub fn set_pixel_stats_enabled(&mut self, enabled: bool) {
+        self.pixel_stats = if enabled {
+            let bit_depth = self.record.bits_per_raw_sample;
+            Some(
+                self.plane_dimensions()
+                    .iter()
+                    .map(|_| PlaneStats::new(bit_depth))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+    }
+
+    /// Returns the sample statistics of every plane of the last decoded
+    /// frame, in the order of the frame planes, if enabled.
+    pub fn pixel_stats(&self) -> Option<&[PlaneStats]> {
+        self.pixel_stats.as_deref()
+    }
+
+    /// Enables or disables the statistics of the symbols coding the
+    /// samples: the contexts of every quantization table set they use, the
+    /// length of their runs and their magnitude. They are disabled by
+    /// default, as gathering them slows decoding down.
+    pub fn set_coding_stats_enabled(&mut self, enabled: bool) {
+        self.coding_stats = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the symbol statistics of every slice of the last decoded
+    /// frame, in coding order, if enabled. Those of concealed slices are
+    /// empty, and those of abandoned slices stop where they failed.
+    pub fn coding_stats(&self) -> Option<&[CodingStats]> {
+        self.coding_stats.as_deref()
+    }
+
+    /// Enables or disables the damage map, resetting it. It is disabled by
+    /// default.
+    ///
+    /// Once enabled, the slices of every frame failing their CRC or marked
+    /// as damaged are recorded, along with their position in the frame and
+    /// in the packet, and frames failing to decode without a slice to blame
+    /// are recorded as a whole.
+    pub fn set_damage_map_enabled(&mut self, enabled: bool) {
+        self.damage = if enabled {
+            Some(DamageMap::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the damaged regions of the frames decoded since the damage
+    /// map was enabled, if it is.
+    pub fn damage_map(&self) -> Option<&DamageMap> {
+        self.damage.as_ref()
+    }
+
+    /// Enables or disables resynchronization at slice boundaries. It is
+    /// disabled by default, so that a slice failing to decode fails the
+    /// whole frame.
+    ///
+    /// Once enabled, a slice whose decoding fails, e.g. because it is
+    /// damaged or its coder went out of sync, is abandoned: its samples are
+    /// left as they are, its states are reset for the next frames, and
+    /// decoding resumes at the next slice, found through the slice
+    /// footers. Abandoned slices are reported as
+    /// 'Diagnostic::SliceAbandoned' and recorded in the damage map.
+    pub fn set_resync_enabled(&mut self, enabled: bool) {
+        self.resync = enabled;
+    }
+
+    /// Returns whether resynchronization at slice boundaries is enabled.
+    pub fn resync_enabled(&self) -> bool {
+        self.resync
+    }
+
+    /// Returns the position, size, error_status and CRC status of every
+    /// slice of the last packet handed to the decoder, in slice order, e.g.
+    /// for indexing or repair tools, without parsing its footers again.
+    ///
+    /// The map is empty when the footers of the packet could not be parsed.
+    pub fn slice_map(&self) -> &[SliceInfo] {
+        &self.current_frame.slice_info
+    }
+
+    /// Returns the headers of the slices of the last frame handed to the
+    /// decoder, in slice order, e.g. for analyzers. Their position in
+    /// pixels is given by 'SliceHeader::rect'.
+    ///
+    /// The headers of the slices whose decoding failed before their header
+    /// was read are left with their default values, e.g. an empty
+    /// 'quant_table_set_index'.
+    pub fn slice_headers(&self) -> impl Iterator<Item = &SliceHeader> + '_ {
+        self.current_frame.slices.iter().map(|slice| &slice.header)
+    }
+
+    /// Returns a snapshot of the states carried over from the last decoded
+    /// frame to the next one, as bytes.
+    ///
+    /// Restoring the snapshot with 'restore_state' on a decoder created
+    /// from the same configuration record allows decoding the frames
+    /// following the last decoded one, without decoding the frames before
+    /// them again, e.g. to resume after a seek.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.current_frame.serialize_states()
+    }
+
+    /// Restores a snapshot taken with 'save_state'.
+    ///
+    /// The snapshot is checked against the configuration record of this
+    /// decoder, and the decoder is left untouched if it does not match.
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+        self.current_frame = InternalFrame::deserialize_states(
+            state,
+            &self.record,
+            &self.current_frame.states,
+        )?;
+        self.reserve_slices();
+        Ok(())
+    }
+
+    /// Returns an in-memory snapshot of the states carried over from the
+    /// last decoded frame to the next one.
+    ///
+    /// Unlike 'save_state', nothing is serialized, so it is cheap enough
+    /// to take a snapshot after every frame, e.g. to let a scrubbing UI go
+    /// back a few frames and return without decoding again from the
+    /// previous keyframe.
+    pub fn snapshot(&self) -> DecoderSnapshot {
+        DecoderSnapshot {
+            frame: self.current_frame.clone(),
+        }
+    }
+
+    /// Restores a snapshot taken with 'snapshot', on this decoder or on a
+    /// decoder created from the same configuration record.
+    pub fn rewind(&mut self, snapshot: &DecoderSnapshot) -> Result<()> {
+        if !snapshot
+            .frame
+            .states
+            .same_layout(&self.current_frame.states)
+        {
+            return Err(Error::InvalidInputData(
+                "decoder snapshot does not match the configuration record"
+                    .to_owned(),
+            ));
+        }
+        self.current_frame.clone_from(&snapshot.frame);
+        self.reserve_slices();
+        Ok(())
+    }
+
+    /// Returns the byte ranges, CRC validity and header of every slice of
+    /// a packet, without decoding it, e.g. for repair or forensics tools
+    /// isolating and patching individual slices.
+    pub fn slice_layout(&self, packet: &[u8]) -> Result<Vec<SliceLayout>> {
+        let ec = self.record.ec != 0;
+        let footer_size = if ec { 8 } else { 3 };
+        count_slices(packet, ec)?
+            .iter()
+            .enumerate()
+            .map(|(i, info)| {
+                let end = info.pos + info.size;
+                let crc_valid = if ec {
+                    Some(
+                        crc32_mpeg2(&packet[info.pos..end + footer_size]) == 0,
+                    )
+                } else {
+                    None
+                };
+                let header =
+                    self.read_slice_header(i, &packet[info.pos..end])?;
+                Ok(SliceLayout {
+                    range: info.pos..end,
+                    footer: end..end + footer_size,
+                    error_status: info.error_status,
+                    crc_valid,
+                    rect: header.rect(&self.record),
+                    header,
+                })
+            })
+            .collect()
+    }
+
+    /// Parses the slice headers of a packet without decoding any sample,
+    /// e.g. for fast structural audits of whole files.
+    ///
+    /// Unlike 'decode_frame', it does not depend on the previous packets
+    /// and leaves the decoder untouched.
+    pub fn scan_frame(&self, packet: &[u8]) -> Result<FrameScan> {
+        if packet.len() < 2 {
+            return Err(Error::FrameError(
+                "packet is too short for a frame".to_owned(),
+            ));
+        }
+        Ok(FrameScan {
+            keyframe: is_keyframe(packet),
+            slices: self.slice_layout(packet)?,
+        })
+    }
+
+    /// Reads the header of the slice 'slicenum', whose content is 'buf'.
+    fn read_slice_header(
+        &self,
+        slicenum: usize,
+        buf: &[u8],
+    ) -> Result<SliceHeader> {
+        if buf.len() < 2 {
+            return Err(Error::SliceError(format!(
+                "slice {} is too short for a header",
+                slicenum
+            )));
+        }
+        let mut slice = Slice::default();
+        let mut coder = Self::header_coder(
+            buf,
+            slicenum,
+            &self.record,
+            &self.state_transition,
+        );
+        Self::parse_slice_header(
+            &mut slice,
+            slicenum,
+            &self.record,
+            &mut coder,
+            &Trace::default(),
+            self.compliance,
+        )?;
+        Ok(slice.header)
+    }
+
+    /// DecodeFrame takes a packet and decodes it to a ffv1.Frame.
+    ///
+    /// The packet is any contiguous buffer, e.g. a slice, a Vec or a
+    /// 'bytes::Bytes' from a network stack, and is read without being
+    /// copied. Slices are decoded on the threads set by 'set_threads'.
+    pub fn decode_frame(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+    ) -> Result<Frame> {
+        self.decode_frame_with(frame_input, FrameMetadata::default())
+    }
+
+    /// Decodes a packet like 'decode_frame', returning 'metadata' on the
+    /// decoded frame.
+    pub fn decode_frame_with(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+        metadata: FrameMetadata,
+    ) -> Result<Frame> {
+        let frame_input = frame_input.as_ref();
+        let mut frame = self.allocate_frame(metadata);
+
+        // Hideous and temporary.
+        let mut buffers = if self.record.bits_per_raw_sample == 8 {
+            FrameBuffers::U8(
+                Self::frame_buffers(
+                    &mut frame.buf,
+                    &frame.strides,
+                    &frame.offsets,
+                )
+                .collect(),
+            )
+        } else {
+            FrameBuffers::U16(
+                Self::frame_buffers(
+                    &mut frame.buf16,
+                    &frame.strides,
+                    &frame.offsets,
+                )
+                .collect(),
+            )
+        };
+
+        self.decode_frame_into(frame_input, &mut buffers)?;
+        frame.diagnostics = self.diagnostics.clone();
+
+        Ok(frame)
+    }
+
+    /// Decodes a packet like 'decode_frame', without starting any slice
+    /// once 'deadline' has passed, e.g. for real-time monitors which would
+    /// rather show a degraded frame than fall behind.
+    ///
+    /// Only the headers of the slices left are read: their samples are
+    /// concealed as mid-grey, opaque when there is an alpha plane, and
+    /// their states are reset, as when a slice is abandoned. They are
+    /// reported as 'Diagnostic::SliceConcealed' and the frame as partial,
+    /// see 'Frame::is_partial'.
+    pub fn decode_frame_with_deadline(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+        deadline: Instant,
+    ) -> Result<Frame> {
+        self.deadline = Some(deadline);
+        let frame = self.decode_frame(frame_input);
+        self.deadline = None;
+        frame
+    }
+
+    /// Decodes a packet like 'decode_frame_with', from its keyframe flag
+    /// and slice footers already read by 'front_end'.
+    pub(crate) fn decode_frame_from(
+        &mut self,
+        frame_input: &[u8],
+        metadata: FrameMetadata,
+        front_end: FrontEnd,
+    ) -> Result<Frame> {
+        self.front_end = Some(front_end);
+        let frame = self.decode_frame_with(frame_input, metadata);
+        self.front_end = None;
+        frame
+    }
+
+    /// Decodes a packet directly into caller-provided plane buffers.
+    ///
+    /// The buffers must contain the planes in the same order as a Frame,
+    /// use 'FrameBuffers::U8' when the bit depth is 8 and
+    /// 'FrameBuffers::U16' otherwise, and be large enough to hold a plane
+    /// with the given stride. Nothing is allocated for the output, only
+    /// RGB streams need an internal scratch space.
+    pub fn decode_frame_into(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+        buffers: &mut FrameBuffers,
+    ) -> Result<()> {
+        let frame_input = frame_input.as_ref();
+        self.measure_frame(frame_input, |decoder| {
+            decoder.decode_frame_buffers(frame_input, buffers)
+        })
+    }
+
+    /// Decodes a packet into the frame preallocated by 'set_capacity',
+    /// without allocating, and returns it until the next packet.
+    ///
+    /// Slices are decoded on the calling thread. Fails when no capacity is
+    /// set.
+    pub fn decode_frame_fixed(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+    ) -> Result<&Frame> {
+        let frame_input = frame_input.as_ref();
+        // The preallocated buffers are set aside while the decoder decodes
+        // into them
+        let mut fixed = self.fixed.take().ok_or_else(|| {
+            Error::InvalidConfiguration(
+                "fixed-capacity decoding needs a capacity".to_owned(),
+            )
+        })?;
+        let Preallocated { frame, scratch } = &mut *fixed;
+        let planes = frame.num_planes();
+        let result = self.measure_frame(frame_input, |decoder| {
+            if frame.bit_depth == 8 {
+                let mut buffers = plane_array(Self::frame_buffers(
+                    &mut frame.buf,
+                    &frame.strides,
+                    &frame.offsets,
+                ));
+                decoder.decode_planes(
+                    frame_input,
+                    &mut OutputPlanes::U8(&mut buffers[..planes]),
+                    Some(scratch),
+                )
+            } else {
+                let mut buffers = plane_array(Self::frame_buffers(
+                    &mut frame.buf16,
+                    &frame.strides,
+                    &frame.offsets,
+                ));
+                decoder.decode_planes(
+                    frame_input,
+                    &mut OutputPlanes::U16(&mut buffers[..planes]),
+                    Some(scratch),
+                )
+            }
+        });
+        frame.crop = self.crop;
+        frame.diagnostics.clone_from(&self.diagnostics);
+        let frame = &self.fixed.insert(fixed).frame;
+        result.map(|()| frame)
+    }
+
+    /// Decodes a packet, handing the rows of every plane to 'sink' as
+    /// soon as all the slices covering them are decoded.
+    ///
+    /// Only the slices whose rows are not all complete are held in memory,
+    /// instead of the whole frame, e.g. a band of slices for streams coded
+    /// with several rows of slices. Samples not covered by any slice are
+    /// handed as 0. Slices are decoded on the calling thread.
+    pub fn decode_frame_to_sink(
+        &mut self,
+        frame_input: impl AsRef<[u8]>,
+        sink: &mut dyn OutputSink,
+    ) -> Result<()> {
+        let frame_input = frame_input.as_ref();
+        self.measure_frame(frame_input, |decoder| {
+            decoder.decode_frame_rows(frame_input, sink)
+        })
+    }
+
+    /// Decodes a frame with 'decode', reporting it to the metrics.
+    fn measure_frame<F>(&mut self, frame_input: &[u8], decode: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        span!("decode_frame", size = frame_input.len());
+        let start = Instant::now();
+        let result = decode(self);
+        self.map_damage(frame_input, &result);
+        self.metrics.report(|metrics| match result {
+            Ok(()) => {
+                metrics.frame_decoded(frame_input.len(), start.elapsed())
+            }
+            Err(_) => metrics.frame_failed(frame_input.len()),
+        });
+        result
+    }
+
+    /// Records the damaged regions of the frame 'frame_input', decoded
+    /// with 'result', if the damage map is enabled.
+    fn map_damage(&mut self, frame_input: &[u8], result: &Result<()>) {
+        let frame = match self.damage {
+            Some(ref mut map) => {
+                map.frames += 1;
+                map.frames - 1
+            }
+            None => return,
+        };
+        // Decoded frames have no damaged slice, but abandoned ones
+        let abandoned: Vec<_> = self
+            .current_frame
+            .slices
+            .iter()
+            .map(|slice| slice.abandoned.clone())
+            .collect();
+        let err = match result {
+            Ok(()) if abandoned.iter().all(Option::is_none) => return,
+            Ok(()) => None,
+            Err(err) => Some(err),
+        };
+        let mut damages = Vec::new();
+        // Damaged footers or headers hide where the slices are
+        if let Ok(layout) = self.slice_layout(frame_input) {
+            for (i, slice) in layout.into_iter().enumerate() {
+                let cause = if slice.crc_valid == Some(false) {
+                    DamageCause::CrcMismatch
+                } else if slice.error_status != 0 {
+                    DamageCause::ErrorStatus(slice.error_status)
+                } else if let Some(Some(reason)) = abandoned.get(i) {
+                    DamageCause::Undecodable(reason.clone())
+                } else {
+                    continue;
+                };
+                damages.push(Damage {
+                    frame,
+                    slice: Some(i),
+                    rect: slice.rect,
+                    bytes: slice.range.start..slice.footer.end,
+                    cause,
+                });
+            }
+        }
+        if let (true, Some(err)) = (damages.is_empty(), err) {
+            damages.push(Damage {
+                frame,
+                slice: None,
+                rect: (0, 0, self.record.width, self.record.height),
+                bytes: 0..frame_input.len(),
+                cause: DamageCause::Undecodable(err.to_string()),
+            });
+        }
+
+        if let Some(ref mut map) = self.damage {
+            map.damages.extend(damages);
+        }
+    }
+
+    /// Decodes a frame into 'buffers', see 'decode_frame_into'.
+    fn decode_frame_buffers(
+        &mut self,
+        frame_input: &[u8],
+        buffers: &mut FrameBuffers,
+    ) -> Result<()> {
+        self.check_buffers(buffers)?;
+        self.decode_planes(frame_input, &mut buffers.planes(), None)
+    }
+
+    /// Decodes a frame into 'buffers', which are known to hold it.
+    ///
+    /// With 'fixed_scratch', slices are decoded on the calling thread,
+    /// using that preallocated scratch space.
+    fn decode_planes(
+        &mut self,
+        frame_input: &[u8],
+        buffers: &mut OutputPlanes,
+        fixed_scratch: Option<&mut Scratch>,
+    ) -> Result<()> {
+        self.begin_frame(frame_input)?;
+
+        // Traces are written in coding order, so they are never threaded.
+        // The rows of frames made of a single RGB slice are converted from
+        // JPEG2000-RCT on several threads instead.
+        let slices = self.current_frame.slices.len();
+        let rct_rows =
+            slices == 1 && self.record.colorspace_type == ColorSpace::Rgb;
+        let mut threads = if self.trace.is_set() || fixed_scratch.is_some() {
+            1
+        } else if rct_rows {
+            self.threads
+        } else {
+            self.threads.min(slices)
+        };
+        // Threads shared with other decoders are given back once decoded
+        let limit = self.thread_limit.clone();
+        let taken = limit
+            .as_ref()
+            .filter(|_| threads > 1)
+            .map(|limit| limit.acquire(threads));
+        if let Some(ref taken) = taken {
+            threads = taken.count();
+        }
+        if threads > 1 && !rct_rows {
+            self.decode_slices_threaded(frame_input, buffers, threads)?;
+        } else {
+            let mut frame_scratch;
+            let scratch = match fixed_scratch {
+                Some(scratch) => scratch,
+                None => {
+                    frame_scratch = Scratch::new(
+                        &self.record,
+                        self.record.width,
+                        self.record.height,
+                    );
+                    &mut frame_scratch
+                }
+            };
+            let ctx = SliceContext {
+                record: &self.record,
+                state_transition: &self.state_transition,
+                keyframe: self.current_frame.keyframe,
+                metrics: &self.metrics,
+                trace: &self.trace,
+                compliance: self.compliance,
+                checked: false,
+                deadline: self.deadline,
+                budget: self.budget,
+                rct_threads: threads,
+                coding_stats: self.coding_stats.is_some(),
+            };
+            let frame = &mut self.current_frame;
+            for (i, (slice, mut states)) in frame
+                .slices
+                .iter_mut()
+                .zip(frame.states.slices_mut())
+                .enumerate()
+            {
+                let info = &mut frame.slice_info[i];
+                let result = Self::decode_slice(
+                    &ctx,
+                    frame_input,
+                    i,
+                    *info,
+                    slice,
+                    &mut states,
+                    Some((buffers, &mut *scratch)),
+                    &mut self.stats,
+                );
+                info.crc_valid = crc_status(
+                    ctx.record.ec != 0 && !slice.concealed,
+                    info,
+                    &result,
+                );
+                if let Err(err) = result {
+                    Self::abandon_slice(
+                        self.resync,
+                        i,
+                        err,
+                        slice,
+                        &mut states,
+                        ctx.record,
+                    )?;
+                }
+            }
+        }
+
+        if self.pixel_stats.is_some() {
+            let dimensions = self.plane_dimensions();
+            if let Some(ref mut stats) = self.pixel_stats {
+                add_pixel_stats(buffers, &dimensions, stats);
+            }
+        }
+
+        self.end_frame()
+    }
+
+    /// Decodes a frame into 'sink', see 'decode_frame_to_sink'.
+    fn decode_frame_rows(
+        &mut self,
+        frame_input: &[u8],
+        sink: &mut dyn OutputSink,
+    ) -> Result<()> {
+        self.begin_frame(frame_input)?;
+
+        let wide = self.record.bits_per_raw_sample != 8;
+        let mut rows = RowAssembler::new(self.plane_dimensions(), wide);
+        let ctx = SliceContext {
+            record: &self.record,
+            state_transition: &self.state_transition,
+            keyframe: self.current_frame.keyframe,
+            metrics: &self.metrics,
+            trace: &self.trace,
+            compliance: self.compliance,
+            checked: false,
+            deadline: self.deadline,
+            budget: self.budget,
+            rct_threads: 1,
+            coding_stats: self.coding_stats.is_some(),
+        };
+        // Rows are added to the statistics on their way to the sink
+        let mut stats_sink;
+        let sink = match self.pixel_stats {
+            Some(ref mut stats) => {
+                stats_sink = StatsSink::new(sink, stats);
+                &mut stats_sink as &mut dyn OutputSink
+            }
+            None => sink,
+        };
+        let frame = &mut self.current_frame;
+        for (i, (slice, mut states)) in frame
+            .slices
+            .iter_mut()
+            .zip(frame.states.slices_mut())
+            .enumerate()
+        {
+            let info = &mut frame.slice_info[i];
+            let result = Self::decode_slice(
+                &ctx,
+                frame_input,
+                i,
+                *info,
+                slice,
+                &mut states,
+                None,
+                &mut self.stats,
+            );
+            info.crc_valid = crc_status(
+                ctx.record.ec != 0 && !slice.concealed,
+                info,
+                &result,
+            );
+            let planes = match result {
+                Ok(planes) => planes,
+                Err(err) => {
+                    Self::abandon_slice(
+                        self.resync,
+                        i,
+                        err,
+                        slice,
+                        &mut states,
+                        ctx.record,
+                    )?;
+                    // Rows still have to be handed to the sink
+                    let planes = SlicePlanes::new(ctx.record, &slice.planes);
+                    Some(planes)
+                }
+            };
+            if let Some(planes) = planes {
+                rows.push(&slice.planes, planes, sink)?;
+            }
+        }
+        rows.finish(sink)?;
+
+        self.end_frame()
+    }
+
+    /// Reads the keyframe flag and the slice footers of a frame, before
+    /// decoding its slices.
+    fn begin_frame(&mut self, frame_input: &[u8]) -> Result<()> {
+        self.diagnostics.clear();
+        if let Some(ref mut stats) = self.pixel_stats {
+            stats.iter_mut().for_each(PlaneStats::reset);
+        }
+
+        // We parse the frame's keyframe info outside the slice decoding
+        // loop so we know ahead of time if each slice has to refresh its
+        // states or not. This allows easy slice threading.
+        let front_end = self.front_end.take();
+        self.current_frame.keyframe = match front_end {
+            Some(ref front_end) => front_end.keyframe,
+            None => is_keyframe(frame_input),
+        };
+        event!(keyframe = self.current_frame.keyframe);
+        self.trace
+            .element("4.3. keyframe", self.current_frame.keyframe as u8);
+
+        // We parse all the footers ahead of time too, for the same reason.
+        // It allows us to know all the slice positions and sizes.
+        //
+        // See: 9.1.1. Multi-threading Support and Independence of Slices
+        self.current_frame.slice_info.clear();
+        let timer = Timer::start(&self.stats);
+        let err = self.parse_footers(
+            frame_input,
+            front_end.and_then(|front_end| front_end.slice_info),
+        );
+        timer.stop(&mut self.stats, Stage::Footers);
+        if let Err(err) = err {
+            return Err(Error::FrameError(format!(
+                "invalid frame footer: {}",
+                err
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks and gathers what the slices of a frame reported, once they
+    /// are all decoded.
+    fn end_frame(&mut self) -> Result<()> {
+        // It's really weird for slices within the same frame to code
+        // their own SAR values, they should at least agree.
+        //
+        // See: * 4.5.8. sar_num
+        //      * 4.5.9. sar_den
+        //
+        // The headers of abandoned slices can't be trusted.
+        let slices = &self.current_frame.slices;
+        let first = slices
+            .iter()
+            .find(|slice| slice.abandoned.is_none())
+            .map(|slice| slice.header.sar());
+        for (i, slice) in slices.iter().enumerate() {
+            self.diagnostics.extend(slice.diagnostics.iter().cloned());
+            if slice.abandoned.is_some() {
+                continue;
+            }
+            let (sar, first) = match first {
+                Some(first) if slice.header.sar() != first => {
+                    (slice.header.sar(), first)
+                }
+                _ => continue,
+            };
+            let conflict = Diagnostic::SarConflict {
+                slice: i,
+                sar,
+                first,
+            };
+            if self
+                .compliance
+                .violation(Error::FrameError, conflict.to_string())?
+                .is_some()
+            {
+                self.diagnostics.push(conflict);
+            }
+        }
+
+        if let Some(ref mut coding_stats) = self.coding_stats {
+            coding_stats.clear();
+            coding_stats.extend(
+                self.current_frame.slices.iter().map(|slice| {
+                    slice.coding_stats.clone().unwrap_or_default()
+                }),
+            );
+        }
+
+        if let Some(ref mut stats) = self.stats {
+            stats.frames += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the slices of a frame on up to 'threads' threads.
+    ///
+    /// Every slice is decoded into its own planes, which are then copied
+    /// into 'buffers' in slice order, so the frame is the same whatever the
+    /// number of threads and their scheduling.
+    ///
+    /// See: 9.1.1. Multi-threading Support and Independence of Slices
+    fn decode_slices_threaded(
+        &mut self,
+        frame_input: &[u8],
+        buffers: &mut OutputPlanes,
+        threads: usize,
+    ) -> Result<()> {
+        // Damaged frames are rejected before decoding any slice, unless
+        // damaged slices are abandoned
+        let checked = self.record.ec == 1 && !self.resync;
+        if checked {
+            self.check_slices_threaded(frame_input, threads)?;
+        }
+
+        let ctx = SliceContext {
+            record: &self.record,
+            state_transition: &self.state_transition,
+            keyframe: self.current_frame.keyframe,
+            metrics: &self.metrics,
+            trace: &self.trace,
+            compliance: self.compliance,
+            checked,
+            deadline: self.deadline,
+            budget: self.budget,
+            rct_threads: 1,
+            coding_stats: self.coding_stats.is_some(),
+        };
+        let frame = &mut self.current_frame;
+        let stats = self.stats.map(|_| DecodeStats::default());
+
+        let mut jobs: Vec<_> = frame
+            .slices
+            .iter_mut()
+            .zip(frame.states.slices_mut())
+            .zip(frame.slice_info.iter().copied())
+            .enumerate()
+            .collect();
+        let per_thread = jobs.len().div_ceil(threads);
+        let ctx = &ctx;
+        let start = Instant::now();
+        let decoded = thread::scope(|scope| {
+            let handles: Vec<_> = jobs
+                .chunks_mut(per_thread)
+                .map(|jobs| {
+                    scope.spawn(move || {
+                        let busy = Instant::now();
+                        let mut stats = stats;
+                        let planes: Vec<_> = jobs
+                            .iter_mut()
+                            .map(|(i, ((slice, states), info))| {
+                                Self::decode_slice(
+                                    ctx,
+                                    frame_input,
+                                    *i,
+                                    *info,
+                                    slice,
+                                    states,
+                                    None,
+                                    &mut stats,
+                                )
+                            })
+                            .collect();
+                        (planes, stats, busy.elapsed())
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| match handle.join() {
+                    Ok(decoded) => decoded,
+                    Err(panic) => std::panic::resume_unwind(panic),
+                })
+                .collect::<Vec<_>>()
+        });
+        let wall = start.elapsed();
+
+        // Slices are gathered in coding order, so the first error reported
+        // is the same as when decoding them one after another.
+        let mut planes = Vec::with_capacity(jobs.len());
+        let mut busy = Vec::with_capacity(decoded.len());
+        for (thread_planes, thread_stats, thread_busy) in decoded {
+            if let (Some(stats), Some(thread_stats)) =
+                (self.stats.as_mut(), thread_stats)
+            {
+                stats.add(&thread_stats);
+            }
+            planes.extend(thread_planes);
+            busy.push(thread_busy);
+        }
+        if let Some(ref mut thread_stats) = self.thread_stats {
+            thread_stats.add(wall, &busy);
+        }
+        let ec = self.record.ec != 0;
+        let frame = &mut self.current_frame;
+        for (i, ((slice, mut states), slice_planes)) in frame
+            .slices
+            .iter_mut()
+            .zip(frame.states.slices_mut())
+            .zip(planes)
+            .enumerate()
+        {
+            if !checked {
+                let info = &mut frame.slice_info[i];
+                info.crc_valid =
+                    crc_status(ec && !slice.concealed, info, &slice_planes);
+            }
+            match slice_planes {
+                Ok(Some(slice_planes)) => {
+                    slice_planes.copy_to(&slice.planes, buffers)
+                }
+                Ok(None) => {}
+                Err(err) => Self::abandon_slice(
+                    self.resync,
+                    i,
+                    err,
+                    slice,
+                    &mut states,
+                    &self.record,
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the integrity of every slice of a frame on up to 'threads'
+    /// threads, since slices are independent byte ranges.
+    ///
+    /// The first damaged slice in coding order is reported, as when slices
+    /// are checked while decoding them one after another.
+    fn check_slices_threaded(
+        &mut self,
+        frame_input: &[u8],
+        threads: usize,
+    ) -> Result<()> {
+        let ctx = SliceContext {
+            record: &self.record,
+            state_transition: &self.state_transition,
+            keyframe: self.current_frame.keyframe,
+            metrics: &self.metrics,
+            trace: &self.trace,
+            compliance: self.compliance,
+            checked: false,
+            deadline: self.deadline,
+            budget: self.budget,
+            rct_threads: 1,
+            coding_stats: self.coding_stats.is_some(),
+        };
+        let slice_info = &self.current_frame.slice_info;
+        let per_thread = slice_info.len().div_ceil(threads);
+        let ctx = &ctx;
+
+        let timer = Timer::start(&self.stats);
+        let checked = thread::scope(|scope| {
+            let handles: Vec<_> = slice_info
+                .chunks(per_thread)
+                .enumerate()
+                .map(|(chunk, infos)| {
+                    scope.spawn(move || {
+                        infos
+                            .iter()
+                            .enumerate()
+                            .map(|(i, info)| {
+                                Self::check_slice(
+                                    ctx,
+                                    frame_input,
+                                    chunk * per_thread + i,
+                                    *info,
+                                    &mut None,
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| match handle.join() {
+                    Ok(checked) => checked,
+                    Err(panic) => std::panic::resume_unwind(panic),
+                })
+                .collect::<Vec<_>>()
+        });
+        timer.stop(&mut self.stats, Stage::Crc);
+
+        let ec = self.record.ec != 0;
+        for (info, result) in
+            self.current_frame.slice_info.iter_mut().zip(&checked)
+        {
+            info.crc_valid = crc_status(ec, info, result);
+        }
+        for (i, result) in checked.into_iter().enumerate() {
+            result.map_err(|err| slice_failed(i, err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the dimensions of the chroma planes.
+    fn chroma_dimensions(&self) -> (u32, u32) {
+        (
+            chroma_size(
+                self.record.width,
+                self.record.log2_h_chroma_subsample,
+            ),
+            chroma_size(
+                self.record.height,
+                self.record.log2_v_chroma_subsample,
+            ),
+        )
+    }
+
+    /// Returns the width and height of each plane of a frame.
+    fn plane_dimensions(&self) -> Vec<(usize, usize)> {
+        let full = (self.record.width as usize, self.record.height as usize);
+        let (chroma_width, chroma_height) = self.chroma_dimensions();
+        let chroma = (chroma_width as usize, chroma_height as usize);
+        let mut dimensions = vec![full];
+        if self.record.chroma_planes {
+            dimensions.push(chroma);
+            dimensions.push(chroma);
+        }
+        if self.record.extra_plane {
+            dimensions.push(full);
+        }
+        dimensions
+    }
+
+    /// Allocates a frame carrying 'metadata', see 'allocate_planes'.
+    fn allocate_frame(&self, metadata: FrameMetadata) -> Frame {
+        let mut frame = Frame {
+            buf: Vec::new(),
+            buf16: Vec::new(),
+            strides: Vec::new(),
+            offsets: Vec::new(),
+            width: self.record.width,
+            height: self.record.height,
+            bit_depth: self.record.bits_per_raw_sample,
+            color_space: self.record.colorspace_type,
+            has_chroma: self.record.chroma_planes,
+            has_alpha: self.record.extra_plane,
+            chroma_subsample_v: if self.record.chroma_planes {
+                self.record.log2_v_chroma_subsample
+            } else {
+                0
+            },
+            chroma_subsample_h: if self.record.chroma_planes {
+                self.record.log2_h_chroma_subsample
+            } else {
+                0
+            },
+            crop: self.crop,
+            metadata,
+            diagnostics: Vec::new(),
+        };
         if self.record.bits_per_raw_sample == 8 {
-            frame.buf = vec![Vec::new(); num_planes];
-            frame.buf[0] = vec![0; full_size];
-            if self.record.chroma_planes {
-                frame.buf[1] = vec![0; chroma_size];
-                frame.buf[2] = vec![0; chroma_size];
-            }
-            if self.record.extra_plane {
-                frame.buf[3] = vec![0; full_size];
-            }
+            let (buf, strides, offsets) = self.allocate_planes();
+            frame.buf = buf;
+            frame.strides = strides;
+            frame.offsets = offsets;
+        } else {
+            let (buf16, strides, offsets) = self.allocate_planes();
+            frame.buf16 = buf16;
+            frame.strides = strides;
+            frame.offsets = offsets;
         }
+        frame
+    }
 
-        // We allocate *both* if it's 8bit RGB since I'm a terrible person and
-        // I wanted to use it as a scratch space, since JPEG2000-RCT is very
-        // annoyingly coded as n+1 bits, and I wanted the implementation
-        // to be straightforward... RIP.
-        if self.record.bits_per_raw_sample > 8
-            || self.record.colorspace_type == 1
-        {
-            frame.buf16 = vec![Vec::new(); num_planes];
-            frame.buf16[0] = vec![0; full_size];
-            if self.record.chroma_planes {
-                frame.buf16[1] = vec![0; chroma_size];
-                frame.buf16[2] = vec![0; chroma_size];
-            }
-            if self.record.extra_plane {
-                frame.buf16[3] = vec![0; full_size];
-            }
+    /// Allocates the planes of a frame, honoring the requested alignment,
+    /// and returns them along with their strides and offsets.
+    fn allocate_planes<T: Allocate + Clone + Default>(
+        &self,
+    ) -> (Vec<Vec<T>>, Vec<usize>, Vec<usize>) {
+        let sample_size = std::mem::size_of::<T>();
+        // Alignment in samples, at least one.
+        let align = (self.alignment / sample_size).max(1);
+
+        let mut planes = Vec::new();
+        let mut strides = Vec::new();
+        let mut offsets = Vec::new();
+        for (width, height) in self.plane_dimensions() {
+            let stride = (width + align - 1) & !(align - 1);
+            // Reserve enough room to move the start of the plane
+            // to an aligned address.
+            let len = stride * height + align - 1;
+            let mut plane = T::allocate(self.allocator.as_ref(), len);
+            plane.resize(len, T::default());
+            let misalignment = plane.as_ptr() as usize % (align * sample_size);
+            let offset = if misalignment == 0 {
+                0
+            } else {
+                (align * sample_size - misalignment) / sample_size
+            };
+            planes.push(plane);
+            strides.push(stride);
+            offsets.push(offset);
         }
 
-        // For 16-bit RGB we need a 32-bit scratch space beause we need to predict
-        // based on 17-bit values in the JPEG2000-RCT space, so just allocate a
-        // whole frame, because I am lazy. Is it slow? Yes.
-        if self.record.bits_per_raw_sample == 16
-            && self.record.colorspace_type == 1
-        {
-            frame.buf32 = vec![Vec::new(); num_planes];
-            frame.buf32[0] = vec![0; full_size];
-            frame.buf32[1] = vec![0; full_size];
-            frame.buf32[2] = vec![0; full_size];
-            if self.record.extra_plane {
-                frame.buf32[3] = vec![0; full_size];
-            }
-        }
+        (planes, strides, offsets)
+    }
 
-        // We parse the frame's keyframe info outside the slice decoding
-        // loop so we know ahead of time if each slice has to refresh its
-        // states or not. This allows easy slice threading.
-        self.current_frame.keyframe = is_keyframe(frame_input);
+    /// Wraps the planes of a frame into plane destinations.
+    fn frame_buffers<'a, T>(
+        planes: &'a mut [Vec<T>],
+        strides: &'a [usize],
+        offsets: &'a [usize],
+    ) -> impl Iterator<Item = PlaneBuffer<'a, T>> {
+        planes
+            .iter_mut()
+            .zip(strides.iter().zip(offsets.iter()))
+            .map(|(plane, (&stride, &offset))| PlaneBuffer {
+                data: &mut plane[offset..],
+                stride,
+            })
+    }
 
-        // We parse all the footers ahead of time too, for the same reason.
-        // It allows us to know all the slice positions and sizes.
-        //
-        // See: 9.1.1. Multi-threading Support and Independence of Slices
-        let err = self.parse_footers(frame_input);
-        if let Err(err) = err {
-            return Err(Error::FrameError(format!(
-                "invalid frame footer: {}",
-                err
+    /// Checks that the caller-provided buffers can hold a frame.
+    fn check_buffers(&self, buffers: &FrameBuffers) -> Result<()> {
+        let strides_and_lens: Vec<(usize, usize)> = match buffers {
+            FrameBuffers::U8(planes) => {
+                if self.record.bits_per_raw_sample != 8 {
+                    return Err(Error::InvalidInputData(
+                        "8-bit buffers need a bit depth of 8".to_owned(),
+                    ));
+                }
+                planes.iter().map(|p| (p.stride, p.data.len())).collect()
+            }
+            FrameBuffers::U16(planes) => {
+                if self.record.bits_per_raw_sample == 8 {
+                    return Err(Error::InvalidInputData(
+                        "16-bit buffers need a bit depth greater than 8"
+                            .to_owned(),
+                    ));
+                }
+                planes.iter().map(|p| (p.stride, p.data.len())).collect()
+            }
+        };
+
+        let dimensions = self.plane_dimensions();
+        if strides_and_lens.len() != dimensions.len() {
+            return Err(Error::InvalidInputData(format!(
+                "expected {} plane buffers, got {}",
+                dimensions.len(),
+                strides_and_lens.len()
             )));
         }
 
-        // Slice threading lazymode (not using sync for now, only sequential code,
-        // FIXME there could be errors here)
-        for i in 0..self.current_frame.slices.len() {
-            let err = self.decode_slice(frame_input, i, &mut frame);
-            if let Err(err) = err {
-                return Err(Error::SliceError(format!(
-                    "slice {} failed: {}",
-                    i, err
+        for (i, (&(stride, len), &(width, height))) in
+            strides_and_lens.iter().zip(dimensions.iter()).enumerate()
+        {
+            if stride < width {
+                return Err(Error::InvalidInputData(format!(
+                    "plane {} stride {} is smaller than its width {}",
+                    i, stride, width
+                )));
+            }
+            let needed = if height == 0 {
+                0
+            } else {
+                stride * (height - 1) + width
+            };
+            if len < needed {
+                return Err(Error::InvalidInputData(format!(
+                    "plane {} buffer holds {} samples, {} needed",
+                    i, len, needed
                 )));
             }
         }
 
-        // Delete the scratch buffer, if needed, as per above.
-        if self.record.bits_per_raw_sample == 8
-            && self.record.colorspace_type == 1
-        {
-            frame.buf16 = Vec::new();
-        }
-
-        // We'll never need this again.
-        frame.buf32 = Vec::new();
-
-        Ok(frame)
+        Ok(())
     }
 
     /// Initializes initial state for the range coder.
@@ -281,36 +2319,71 @@ impl Decoder {
 
     /// Parses all footers in a frame and allocates any necessary slice structures.
     ///
+    /// Footers already read by a 'FrontEnd' are passed as 'slice_info'.
+    ///
     /// See: * 9.1.1. Multi-threading Support and Independence of Slices
     ///      * 3.8.1.3. Initial Values for the Context Model
     ///      * 3.8.2.4. Initial Values for the VLC context state
-    fn parse_footers(&mut self, buf: &[u8]) -> Result<()> {
-        let slice_info = count_slices(buf, self.record.ec != 0)?;
-        self.current_frame.slice_info = slice_info;
-
-        let mut slices: Vec<Slice> =
-            vec![Default::default(); self.current_frame.slice_info.len()];
-
-        if !self.current_frame.keyframe {
-            if slices.len() != self.current_frame.slices.len() {
-                return Err(Error::SliceError("inter frames must have the same number of slices as the preceding intra frame".to_owned()));
+    fn parse_footers(
+        &mut self,
+        buf: &[u8],
+        slice_info: Option<Vec<SliceInfo>>,
+    ) -> Result<()> {
+        span!("parse_footers", size = buf.len());
+        let max_slices = self
+            .capacity
+            .map_or(usize::MAX, |capacity| capacity.max_slices);
+        match slice_info {
+            Some(slice_info) if slice_info.len() > max_slices => {
+                return Err(Error::ResourceError(format!(
+                    "more than {} slices",
+                    max_slices
+                )));
             }
-            for (next, current) in
-                slices.iter_mut().zip(self.current_frame.slices.iter())
-            {
-                next.state = current.state.clone();
+            Some(slice_info) => self.current_frame.slice_info = slice_info,
+            None => read_footers(
+                buf,
+                self.record.ec != 0,
+                max_slices,
+                &mut self.current_frame.slice_info,
+            )?,
+        }
+        let slice_info = &self.current_frame.slice_info;
+        event!(slices = slice_info.len());
+        for (i, info) in slice_info.iter().enumerate() {
+            self.trace
+                .element(format_args!("4.8.1. slice_size[{}]", i), info.size);
+            if self.record.ec != 0 {
+                self.trace.element(
+                    format_args!("4.8.2. error_status[{}]", i),
+                    info.error_status,
+                );
             }
+        }
+        let count = slice_info.len();
 
-            if self.record.coder_type == 0 {
-                for (next, current) in
-                    slices.iter_mut().zip(self.current_frame.slices.iter())
-                {
-                    next.golomb_state = current.golomb_state.clone();
-                }
-            }
+        if !self.current_frame.keyframe
+            && count != self.current_frame.slices.len()
+        {
+            return Err(Error::SliceError("inter frames must have the same number of slices as the preceding intra frame".to_owned()));
+        }
+
+        // Slices are reused from frame to frame, keeping the Golomb-Rice
+        // states inter frames carry on from, along with their allocations.
+        let slices = &mut self.current_frame.slices;
+        if slices.len() > count {
+            self.spare_slices.extend(slices.drain(count..));
         }
+        while slices.len() < count {
+            slices.push(self.spare_slices.pop().unwrap_or_default());
+        }
+        slices.iter_mut().for_each(Slice::clear);
 
-        self.current_frame.slices = slices;
+        // Range coder states live in a single buffer for all the slices,
+        // which only needs resizing on keyframes.
+        if self.current_frame.keyframe {
+            self.current_frame.states.resize(count);
+        }
 
         Ok(())
     }
@@ -320,41 +2393,59 @@ impl Decoder {
     /// See: 4.5. Slice Header
     fn parse_slice_header(
         current_slice: &mut Slice,
+        slicenum: usize,
         record: &ConfigRecord,
         coder: &mut RangeCoder,
-    ) {
+        trace: &Trace,
+        compliance: Compliance,
+    ) -> Result<()> {
         // 4. Bitstream
         let mut slice_state: [u8; CONTEXT_SIZE] = [128; CONTEXT_SIZE];
 
         // 4.5.1. slice_x
         current_slice.header.slice_x = coder.ur(&mut slice_state);
+        trace.element("4.5.1. slice_x", current_slice.header.slice_x);
         // 4.5.2. slice_y
         current_slice.header.slice_y = coder.ur(&mut slice_state);
+        trace.element("4.5.2. slice_y", current_slice.header.slice_y);
         // 4.5.3 slice_width
         current_slice.header.slice_width_minus1 = coder.ur(&mut slice_state);
+        trace.element(
+            "4.5.3. slice_width_minus1",
+            current_slice.header.slice_width_minus1,
+        );
         // 4.5.4 slice_height
         current_slice.header.slice_height_minus1 = coder.ur(&mut slice_state);
+        trace.element(
+            "4.5.4. slice_height_minus1",
+            current_slice.header.slice_height_minus1,
+        );
 
         // 4.5.5. quant_table_set_index_count
-        let mut quant_table_set_index_count = 1;
-        if record.chroma_planes {
-            quant_table_set_index_count += 1;
-        }
-        if record.extra_plane {
-            quant_table_set_index_count += 1;
-        }
+        let quant_table_set_index_count = record.quant_table_set_index_count();
 
         // 4.5.6. quant_table_set_index
-        current_slice.header.quant_table_set_index =
-            vec![0; quant_table_set_index_count];
+        current_slice.header.quant_table_set_index.clear();
+        current_slice
+            .header
+            .quant_table_set_index
+            .resize(quant_table_set_index_count, 0);
         for i in 0..quant_table_set_index_count {
             current_slice.header.quant_table_set_index[i] =
                 coder.ur(&mut slice_state) as u8;
+            trace.element(
+                format_args!("4.5.6. quant_table_set_index[{}]", i),
+                current_slice.header.quant_table_set_index[i],
+            );
         }
 
         // 4.5.7. picture_structure
         current_slice.header.picture_structure =
             coder.ur(&mut slice_state) as u8;
+        trace.element(
+            "4.5.7. picture_structure",
+            current_slice.header.picture_structure,
+        );
 
         // It's really weird for slices within the same frame to code
         // their own SAR values...
@@ -362,33 +2453,72 @@ impl Decoder {
         // See: * 4.5.8. sar_num
         //      * 4.5.9. sar_den
         current_slice.header.sar_num = coder.ur(&mut slice_state);
+        trace.element("4.5.8. sar_num", current_slice.header.sar_num);
         current_slice.header.sar_den = coder.ur(&mut slice_state);
+        trace.element("4.5.9. sar_den", current_slice.header.sar_den);
+        if coder.invalid_symbol() {
+            return Err(Error::SliceError(
+                "invalid symbol in slice header".to_owned(),
+            ));
+        }
+
+        // Reserved values, and sample aspect ratios which are neither
+        // known nor unknown
+        let header = &current_slice.header;
+        let mut violations = Vec::new();
+        if header.picture_structure > 3 {
+            violations.push(format!(
+                "reserved picture_structure {}",
+                header.picture_structure
+            ));
+        }
+        if (header.sar_num == 0) != (header.sar_den == 0) {
+            violations.push(format!(
+                "invalid sample aspect ratio {}:{}",
+                header.sar_num, header.sar_den
+            ));
+        }
+        current_slice.diagnostics.clear();
+        for message in violations {
+            if let Some(message) =
+                compliance.violation(Error::SliceError, message)?
+            {
+                current_slice.diagnostics.push(Diagnostic::Violation {
+                    slice: slicenum,
+                    message,
+                });
+            }
+        }
+
+        // Damaged slices may code anything, which must not be used to
+        // index the frame or the quantization table sets.
+        let header = &current_slice.header;
+        let (columns, rows) = (
+            record.num_h_slices_minus1 as u64 + 1,
+            record.num_v_slices_minus1 as u64 + 1,
+        );
+        if header.slice_x as u64 + header.slice_width_minus1 as u64 >= columns
+            || header.slice_y as u64 + header.slice_height_minus1 as u64
+                >= rows
+        {
+            return Err(Error::SliceError(format!(
+                "slice rectangle exceeds the {}x{} slice grid",
+                columns, rows
+            )));
+        }
+        if header
+            .quant_table_set_index
+            .iter()
+            .any(|&index| index as usize >= record.quant_table_set_count)
+        {
+            return Err(Error::SliceError(
+                "invalid quantization table set index".to_owned(),
+            ));
+        }
 
         // Calculate boundaries for easy use elsewhere
-        //
-        // See: * 4.6.3. slice_pixel_height
-        //      * 4.6.4. slice_pixel_y
-        //      * 4.7.2. slice_pixel_width
-        //      * 4.7.3. slice_pixel_x
-        let start_x = current_slice.header.slice_x * record.width
-            / (record.num_h_slices_minus1 as u32 + 1);
-        let start_y = current_slice.header.slice_y * record.height
-            / (record.num_v_slices_minus1 as u32 + 1);
-        let width = ((current_slice.header.slice_x
-            + current_slice.header.slice_width_minus1
-            + 1)
-            * record.width
-            / (record.num_h_slices_minus1 as u32 + 1))
-            - start_x;
-        let height = ((current_slice.header.slice_y
-            + current_slice.header.slice_height_minus1
-            + 1)
-            * record.height
-            / (record.num_v_slices_minus1 as u32 + 1))
-            - start_y;
-
-        let stride = record.width;
-        let offset = start_x + start_y * stride;
+        let (start_x, start_y, width, height) =
+            current_slice.header.rect(record);
 
         // Calculate the plane boundaries
         //
@@ -399,53 +2529,38 @@ impl Decoder {
             start_y,
             width,
             height,
-            stride,
-            offset: offset as usize,
             quant: 0,
         };
 
-        // alpha is an additiona full plane
-        if record.extra_plane {
-            let alpha_plane = SlicePlane {
-                quant: 2,
-                ..full_plane
-            };
-            current_slice.planes.push(alpha_plane);
-        }
-
-        current_slice.planes.push(full_plane);
+        current_slice.planes.push(full_plane.clone());
 
         if record.chroma_planes {
-            // This is, of course, silly, but I want to do it "by the spec".
-            let start_x = (start_x as f64
-                / ((1 << record.log2_v_chroma_subsample) as f64))
-                .ceil() as u32;
-            let start_y = (start_y as f64
-                / ((1 << record.log2_h_chroma_subsample) as f64))
-                .ceil() as u32;
-            let width = (width as f64
-                / (1 << record.log2_h_chroma_subsample) as f64)
-                .ceil() as u32;
-            let height = (height as f64
-                / (1 << record.log2_v_chroma_subsample) as f64)
-                .ceil() as u32;
-            let stride = (record.width as f64
-                / (1 << record.log2_h_chroma_subsample) as f64)
-                .ceil() as u32;
-            let offset = start_x + start_y * stride;
+            let (h, v) = (
+                record.log2_h_chroma_subsample,
+                record.log2_v_chroma_subsample,
+            );
             let chroma_plane = SlicePlane {
-                start_x,
-                start_y,
-                width,
-                height,
-                stride,
-                offset: offset as usize,
+                start_x: chroma_position(start_x, h),
+                start_y: chroma_position(start_y, v),
+                width: chroma_size(width, h),
+                height: chroma_size(height, v),
                 quant: 1,
             };
 
             current_slice.planes.push(chroma_plane.clone());
             current_slice.planes.push(chroma_plane);
         }
+
+        // alpha is an additional full plane, coded after the chroma planes
+        if record.extra_plane {
+            let alpha_plane = SlicePlane {
+                quant: 2,
+                ..full_plane
+            };
+            current_slice.planes.push(alpha_plane);
+        }
+
+        Ok(())
     }
 
     /// Line decoding.
@@ -460,54 +2575,50 @@ impl Decoder {
     ///
     /// See: 4.7. Line
     #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(not(feature = "golomb"), allow(unused_variables))]
     fn decode_line<T>(
         header: &SliceHeader,
         record: &ConfigRecord,
         coder: &mut Coder,
-        state: &mut Vec<Vec<Vec<u8>>>,
-        golomb_state: &mut Vec<Vec<State>>,
+        state: &mut SliceStates,
+        golomb_state: &mut [Vec<State>],
         buf: &mut [T],
         width: usize,
-        height: usize,
         stride: usize,
         yy: usize,
         qt: usize,
+        trace: &Trace,
+        coding_stats: Option<&mut CodingStats>,
     ) where
-        T: AsPrimitive<usize>,
-        u32: AsPrimitive<T>,
+        T: Sample,
     {
         // Runs are horizontal and thus cannot run more than a line.
         //
         // See: 3.8.2.2.1. Run Length Coding
+        #[cfg(feature = "golomb")]
         if let Coder::Golomb(ref mut golomb_coder) = coder {
             golomb_coder.new_line();
         }
 
         // 3.8. Coding of the Sample Difference
-        let shift = if record.colorspace_type == 1 {
+        let shift = if record.colorspace_type == ColorSpace::Rgb {
             record.bits_per_raw_sample + 1
         } else {
             record.bits_per_raw_sample
         };
 
-        let quant_table =
-            &record.quant_tables[header.quant_table_set_index[qt] as usize];
+        let table_set = header.quant_table_set_index[qt] as usize;
+        let quant_table = &record.quant_tables[table_set];
 
-        // 4.7.4. sample_difference
-        for x in 0..width {
-            // Derive neighbours
-            //
-            // See pred.go for details.
-            #[allow(non_snake_case)]
-            #[allow(clippy::many_single_char_names)]
-            let (T, L, t, l, tr, tl) =
-                derive_borders(buf, x, yy, width, height, stride);
+        trace.element("4.7. Line", format_args!("plane {} y {}", qt, yy));
 
+        // 4.7.4. sample_difference
+        let mut symbol = |n: &Neighbours<T>| -> (usize, i32) {
             // See pred.go for details.
             //
             // See also: * 3.4. Context
             //           * 3.6. Quantization Table Set Indexes
-            let mut context = get_context(quant_table, T, L, t, l, tr, tl);
+            let mut context = get_context(quant_table, n);
             let sign = if context < 0 {
                 context = -context;
                 true
@@ -516,50 +2627,75 @@ impl Decoder {
             };
 
             let mut diff = match coder {
+                #[cfg(feature = "golomb")]
                 Coder::Golomb(ref mut golomb_coder) => golomb_coder.sg(
                     context,
                     &mut golomb_state[qt][context as usize],
                     shift as u32,
                 ),
                 Coder::Range(ref mut range_coder) => {
-                    range_coder.sr(&mut state[qt][context as usize])
+                    range_coder.sr(state.context(qt, context as usize))
                 }
             };
 
             // 3.4. Context
             if sign {
-                diff = -diff;
+                diff = diff.wrapping_neg();
             }
+            trace.element("4.7.4. sample_difference", diff);
+            (context as usize, diff)
+        };
 
-            // 3.8. Coding of the Sample Difference
+        // 3.8. Coding of the Sample Difference
+        //
+        // Damaged slices may code any difference, only the low bits are
+        // kept anyway.
+        let reconstruct = |n: &Neighbours<T>, diff: i32| -> T {
             let mut val: i32 = diff;
-            if record.colorspace_type == 0
+            let (l, t, tl) = (
+                n.l.to_u32() as i32,
+                n.t.to_u32() as i32,
+                n.tl.to_u32() as i32,
+            );
+            if record.colorspace_type == ColorSpace::YCbCr
                 && record.bits_per_raw_sample == 16
-                && matches!(coder, Coder::Golomb(_))
+                && record.coder_type == 0
             {
                 // 3.3. Median Predictor
                 let left16s = if l >= 32768 { l - 65536 } else { l };
                 let top16s = if t >= 32768 { t - 65536 } else { t };
                 let diag16s = if tl >= 32768 { tl - 65536 } else { tl };
 
-                val += get_median(
-                    left16s as i32,
-                    top16s as i32,
-                    (left16s + top16s - diag16s) as i32,
-                );
+                val = val.wrapping_add(get_median(
+                    left16s,
+                    top16s,
+                    left16s + top16s - diag16s,
+                ));
             } else {
-                val += get_median(
-                    l as i32,
-                    t as i32,
-                    (l + t).wrapping_sub(tl) as i32,
-                );
+                val = val.wrapping_add(get_median(l, t, l + t - tl));
             }
 
             val &= (1 << shift) - 1;
 
-            let val1 = val as u32;
+            T::from_u32(val as u32)
+        };
 
-            buf[(yy * stride) + x] = val1.as_();
+        // Statistics are only gathered by a line of their own, so that
+        // decoding does not check for them on every sample.
+        match coding_stats {
+            Some(stats) => {
+                derive_line(buf, width, stride, yy, |n| {
+                    let (context, diff) = symbol(n);
+                    stats.add_symbol(table_set, context, diff);
+                    reconstruct(n, diff)
+                });
+                // Runs are horizontal, as in Golomb-Rice mode
+                stats.end_run();
+            }
+            None => derive_line(buf, width, stride, yy, |n| {
+                let (_, diff) = symbol(n);
+                reconstruct(n, diff)
+            }),
         }
     }
 
@@ -568,43 +2704,56 @@ impl Decoder {
     /// Planes are independent.
     ///
     /// See: 3.7.1. YCbCr
-    #[allow(clippy::needless_range_loop)]
+    #[allow(clippy::too_many_arguments)]
     fn decode_slice_content_yuv<T>(
         current_slice: &mut Slice,
+        states: &mut SliceStates,
         record: &ConfigRecord,
         coder: &mut Coder,
-        buf: &mut Vec<Vec<T>>,
-    ) where
-        T: AsPrimitive<usize>,
-        u32: AsPrimitive<T>,
+        buf: &mut [PlaneBuffer<T>],
+        spending: &mut Spending,
+        trace: &Trace,
+        local: bool,
+    ) -> Result<()>
+    where
+        T: Sample,
     {
         let planes = &current_slice.planes;
         let header = &current_slice.header;
-        let state = &mut current_slice.state;
         let golomb_state = &mut current_slice.golomb_state;
+        let mut coding_stats = current_slice.coding_stats.as_mut();
 
         for (plane, buf) in planes.iter().zip(buf.iter_mut()) {
             // 3.8.2.2.1. Run Length Coding
+            #[cfg(feature = "golomb")]
             if let Coder::Golomb(ref mut golomb_coder) = coder {
                 golomb_coder.new_plane(plane.width as u32);
             }
 
+            let offset = if local {
+                0
+            } else {
+                plane.start_x as usize + plane.start_y as usize * buf.stride
+            };
             for y in 0..plane.height as usize {
                 Self::decode_line(
                     header,
                     record,
                     coder,
-                    state,
+                    states,
                     golomb_state,
-                    &mut buf[plane.offset..],
+                    &mut buf.data[offset..],
                     plane.width as usize,
-                    plane.height as usize,
-                    plane.stride as usize,
+                    buf.stride,
                     y,
                     plane.quant.into(),
+                    trace,
+                    coding_stats.as_deref_mut(),
                 );
+                spending.spend(plane.width as usize, coder.bits_read())?;
             }
         }
+        Ok(())
     }
 
     /// RGB (JPEG2000-RCT) Mode
@@ -612,165 +2761,265 @@ impl Decoder {
     /// All planes are coded per line.
     ///
     /// See: 3.7.2. RGB
+    #[allow(clippy::too_many_arguments)]
     fn decode_slice_content_rct<T>(
         current_slice: &mut Slice,
+        states: &mut SliceStates,
         record: &ConfigRecord,
         coder: &mut Coder,
-        buf: &mut Vec<Vec<T>>,
-    ) where
-        T: AsPrimitive<usize>,
-        u32: AsPrimitive<T>,
+        buf: &mut [PlaneBuffer<T>],
+        spending: &mut Spending,
+        trace: &Trace,
+        local: bool,
+    ) -> Result<()>
+    where
+        T: Sample,
     {
         let planes = &current_slice.planes;
         // All the planes have the same dimension
         // Just the quantizer change.
-        let stride = planes[0].stride as usize;
         let width = planes[0].width as usize;
         let height = planes[0].height as usize;
-        let offset = planes[0].offset;
+        let (start_x, start_y) = if local {
+            (0, 0)
+        } else {
+            (planes[0].start_x as usize, planes[0].start_y as usize)
+        };
 
         let header = &current_slice.header;
-        let state = &mut current_slice.state;
         let golomb_state = &mut current_slice.golomb_state;
+        let mut coding_stats = current_slice.coding_stats.as_mut();
 
+        #[cfg(feature = "golomb")]
         if let Coder::Golomb(ref mut golomb_coder) = coder {
             golomb_coder.new_plane(width as u32);
         }
 
         for y in 0..height {
             for (plane, buf) in planes.iter().zip(buf.iter_mut()) {
+                let offset = start_x + start_y * buf.stride;
                 Self::decode_line(
                     header,
                     record,
                     coder,
-                    state,
+                    states,
                     golomb_state,
-                    &mut buf[offset..],
+                    &mut buf.data[offset..],
                     width,
-                    height,
-                    stride,
+                    buf.stride,
                     y,
                     plane.quant.into(),
+                    trace,
+                    coding_stats.as_deref_mut(),
                 );
+                spending.spend(width, coder.bits_read())?;
             }
         }
+        Ok(())
     }
 
     /// Decoding happens here.
     ///
+    /// With 'local', the buffers and the scratch space only hold the slice,
+    /// starting at its first sample, instead of the whole frame.
+    ///
     /// See: * 4.6. Slice Content
+    #[allow(clippy::too_many_arguments)]
     fn decode_slice_content(
         current_slice: &mut Slice,
+        states: &mut SliceStates,
         record: &ConfigRecord,
         coder: &mut Coder,
-        frame: &mut Frame,
-    ) {
-        if record.colorspace_type != 1 {
-            if record.bits_per_raw_sample == 8 {
-                Self::decode_slice_content_yuv(
+        buffers: &mut OutputPlanes,
+        scratch: &mut Scratch,
+        spending: &mut Spending,
+        stats: &mut Option<DecodeStats>,
+        trace: &Trace,
+        local: bool,
+        rct_threads: usize,
+    ) -> Result<()> {
+        let timer = Timer::start(stats);
+        if record.colorspace_type != ColorSpace::Rgb {
+            match buffers {
+                OutputPlanes::U8(buf) => Self::decode_slice_content_yuv(
                     current_slice,
+                    states,
                     record,
                     coder,
-                    &mut frame.buf,
-                );
-            } else if record.bits_per_raw_sample == 16 {
-                Self::decode_slice_content_yuv(
+                    buf,
+                    spending,
+                    trace,
+                    local,
+                )?,
+                OutputPlanes::U16(buf) => Self::decode_slice_content_yuv(
                     current_slice,
+                    states,
                     record,
                     coder,
-                    &mut frame.buf16,
-                );
+                    buf,
+                    spending,
+                    trace,
+                    local,
+                )?,
             }
+            timer.stop(stats, Stage::Samples);
         } else {
-            let stride = current_slice.planes[0].stride as usize;
+            // Scratch buffers are frame-sized, or slice-sized when local.
             let width = current_slice.planes[0].width as usize;
             let height = current_slice.planes[0].height as usize;
-            let offset = current_slice.planes[0].offset;
-            if record.bits_per_raw_sample == 8 {
-                Self::decode_slice_content_rct(
-                    current_slice,
-                    record,
-                    coder,
-                    &mut frame.buf16,
-                );
-                Rct::rct(
-                    &mut frame.buf,
-                    &frame.buf16,
-                    width,
-                    height,
-                    stride,
-                    offset,
-                    record.bits_per_raw_sample.into(),
-                );
-            } else if record.bits_per_raw_sample >= 9
-                && record.bits_per_raw_sample <= 15
-                && !record.extra_plane
-            {
-                Self::decode_slice_content_rct(
-                    current_slice,
-                    record,
-                    coder,
-                    &mut frame.buf16,
-                );
-                // See: 3.7.2. RGB
-                Rct::rct(
-                    &mut frame.buf16,
-                    &frame.buf,
-                    width,
-                    height,
-                    stride,
-                    offset,
-                    record.bits_per_raw_sample.into(),
-                );
+            let (scratch_stride, start_x, start_y) = if local {
+                (width, 0, 0)
             } else {
-                Self::decode_slice_content_rct(
-                    current_slice,
-                    record,
-                    coder,
-                    &mut frame.buf32,
-                );
-                Rct::rct(
-                    &mut frame.buf16,
-                    &frame.buf32,
-                    width,
-                    height,
-                    stride,
-                    offset,
-                    record.bits_per_raw_sample.into(),
-                );
+                (
+                    record.width as usize,
+                    current_slice.planes[0].start_x as usize,
+                    current_slice.planes[0].start_y as usize,
+                )
+            };
+            match buffers {
+                OutputPlanes::U8(buf) => {
+                    Self::decode_slice_content_rct(
+                        current_slice,
+                        states,
+                        record,
+                        coder,
+                        &mut plane_buffers(&mut scratch.buf16, scratch_stride),
+                        spending,
+                        trace,
+                        local,
+                    )?;
+                    timer.stop(stats, Stage::Samples);
+                    let timer = Timer::start(stats);
+                    span!("rct", slice_width = width, slice_height = height);
+                    rct_rows(
+                        buf,
+                        &scratch.buf16,
+                        scratch_stride,
+                        start_x,
+                        start_y,
+                        width,
+                        height,
+                        record.bits_per_raw_sample.into(),
+                        rct_threads,
+                    );
+                    timer.stop(stats, Stage::Rct);
+                }
+                #[cfg(feature = "rgb16")]
+                OutputPlanes::U16(buf) if !scratch.buf32.is_empty() => {
+                    Self::decode_slice_content_rct(
+                        current_slice,
+                        states,
+                        record,
+                        coder,
+                        &mut plane_buffers(&mut scratch.buf32, scratch_stride),
+                        spending,
+                        trace,
+                        local,
+                    )?;
+                    timer.stop(stats, Stage::Samples);
+                    let timer = Timer::start(stats);
+                    span!("rct", slice_width = width, slice_height = height);
+                    rct_rows(
+                        buf,
+                        &scratch.buf32,
+                        scratch_stride,
+                        start_x,
+                        start_y,
+                        width,
+                        height,
+                        record.bits_per_raw_sample.into(),
+                        rct_threads,
+                    );
+                    timer.stop(stats, Stage::Rct);
+                }
+                OutputPlanes::U16(buf) => {
+                    Self::decode_slice_content_rct(
+                        current_slice,
+                        states,
+                        record,
+                        coder,
+                        buf,
+                        spending,
+                        trace,
+                        local,
+                    )?;
+                    timer.stop(stats, Stage::Samples);
+                    let timer = Timer::start(stats);
+                    // See: 3.7.2. RGB
+                    span!("rct", slice_width = width, slice_height = height);
+                    let none: &[Vec<u8>] = &[];
+                    rct_rows(
+                        buf,
+                        none,
+                        scratch_stride,
+                        start_x,
+                        start_y,
+                        width,
+                        height,
+                        record.bits_per_raw_sample.into(),
+                        rct_threads,
+                    );
+                    timer.stop(stats, Stage::Rct);
+                }
             }
         }
+        Ok(())
+    }
+
+    /// Abandons the slice 'slicenum' of a frame after 'err' when 'resync'
+    /// is set, resetting its states so that the next frames do not inherit
+    /// them. Otherwise, the frame fails with 'err'.
+    fn abandon_slice(
+        resync: bool,
+        slicenum: usize,
+        err: Error,
+        current_slice: &mut Slice,
+        states: &mut SliceStates,
+        record: &ConfigRecord,
+    ) -> Result<()> {
+        if !resync {
+            return Err(slice_failed(slicenum, err));
+        }
+        warn!("slice {}: abandoned after {}", slicenum, err);
+        Self::reset_slice_states(current_slice, states, record);
+        let reason = err.to_string();
+        current_slice.diagnostics = vec![Diagnostic::SliceAbandoned {
+            slice: slicenum,
+            reason: reason.clone(),
+        }];
+        current_slice.abandoned = Some(reason);
+        Ok(())
     }
 
-    /// Resets the range coder and Golomb-Rice coder states.
-    fn reset_slice_states(current_slice: &mut Slice, record: &ConfigRecord) {
+    /// Resets the range coder and Golomb-Rice coder states, for the
+    /// quantization table sets of the slice header.
+    fn reset_slice_states(
+        current_slice: &mut Slice,
+        states: &mut SliceStates,
+        record: &ConfigRecord,
+    ) {
         // Range coder states
-        current_slice.state = record.initial_states.clone();
+        states.reset(&current_slice.header.quant_table_set_index);
 
         // Golomb-Rice Code states
         if record.coder_type == 0 {
-            let count = record.quant_table_set_count;
-            current_slice.golomb_state = record.context_count[..count]
-                .iter()
-                .map(|&len| vec![Default::default(); len as usize])
-                .collect();
+            current_slice.reset_golomb_states(record);
         }
     }
 
-    fn decode_slice(
-        &mut self,
+    /// Checks the integrity of the slice 'slicenum' of the frame 'buf'.
+    fn check_slice(
+        ctx: &SliceContext,
         buf: &[u8],
         slicenum: usize,
-        frame: &mut Frame,
+        slice_info: SliceInfo,
+        stats: &mut Option<DecodeStats>,
     ) -> Result<()> {
-        let slice_info = self.current_frame.slice_info[slicenum];
-        let current_slice = &mut self.current_frame.slices[slicenum];
-        let record = &self.record;
         // Before we do anything, let's try and check the integrity
         //
         // See: * 4.8.2. error_status
         //      * 4.8.3. slice_crc_parity
-        if record.ec == 1 {
+        if ctx.record.ec == 1 {
             if slice_info.error_status != 0 {
                 return Err(Error::SliceError(format!(
                     "error_status is non-zero: {}",
@@ -778,24 +3027,34 @@ impl Decoder {
                 )));
             }
 
-            let slice_buf_first = &buf[slice_info.pos..];
-            let slice_buf_end = &slice_buf_first[..slice_info.size + 8]; // 8 bytes for footer size
-            if crc32_mpeg2(slice_buf_end) != 0 {
-                return Err(Error::InvalidInputData(
-                    "CRC mismatch".to_owned(),
-                ));
+            // The CRC may have been checked by a 'FrontEnd' already
+            let crc_valid = slice_info.crc_valid.unwrap_or_else(|| {
+                // 8 bytes for footer size
+                let slice_buf_first = &buf[slice_info.pos..];
+                let slice_buf_end = &slice_buf_first[..slice_info.size + 8];
+                let timer = Timer::start(stats);
+                let crc = crc32_mpeg2(slice_buf_end);
+                timer.stop(stats, Stage::Crc);
+                crc == 0
+            });
+            if !crc_valid {
+                ctx.metrics
+                    .report(|metrics| metrics.slice_crc_failed(slicenum));
+                return Err(Error::CrcMismatch("slice data".to_owned()));
             }
         }
+        Ok(())
+    }
 
-        // If this is a keyframe, refresh states.
-        //
-        // See: * 3.8.1.3. Initial Values for the Context Model
-        //      * 3.8.2.4. Initial Values for the VLC context state
-        if self.current_frame.keyframe {
-            Self::reset_slice_states(current_slice, record);
-        }
-
-        let mut coder = RangeCoder::new(&buf[slice_info.pos..]);
+    /// Returns the range coder of the slice 'slicenum', whose content is
+    /// 'buf', positioned at its header.
+    fn header_coder<'b>(
+        buf: &'b [u8],
+        slicenum: usize,
+        record: &ConfigRecord,
+        state_transition: &[u8; 256],
+    ) -> RangeCoder<'b> {
+        let mut coder = RangeCoder::new(buf);
 
         // 4. Bitstream
         let mut state: [u8; CONTEXT_SIZE] = [128; CONTEXT_SIZE];
@@ -807,26 +3066,249 @@ impl Decoder {
 
         if record.coder_type == 2 {
             // Custom state transition table
-            coder.set_table(&self.state_transition);
+            coder.set_table(state_transition);
+        }
+        coder
+    }
+
+    /// Conceals the slice 'slicenum' of the frame 'buf' instead of
+    /// decoding it, once the deadline of the frame has passed.
+    ///
+    /// Only its header is read, for its position. Its samples are written
+    /// like those of a decoded slice, see 'decode_slice'.
+    fn conceal_slice(
+        ctx: &SliceContext,
+        buf: &[u8],
+        slicenum: usize,
+        slice_info: SliceInfo,
+        current_slice: &mut Slice,
+        states: &mut SliceStates,
+        output: Option<(&mut OutputPlanes, &mut Scratch)>,
+    ) -> Result<Option<SlicePlanes>> {
+        let record = ctx.record;
+        let mut coder = Self::header_coder(
+            &buf[slice_info.pos..],
+            slicenum,
+            record,
+            ctx.state_transition,
+        );
+        ctx.trace.element("4.4. Slice", slicenum);
+        Self::parse_slice_header(
+            current_slice,
+            slicenum,
+            record,
+            &mut coder,
+            ctx.trace,
+            ctx.compliance,
+        )?;
+
+        // The next frames can't carry on from states which were not
+        // updated, as when the slice is abandoned
+        Self::reset_slice_states(current_slice, states, record);
+        current_slice.concealed = true;
+        current_slice
+            .diagnostics
+            .push(Diagnostic::SliceConcealed { slice: slicenum });
+
+        let planes = &current_slice.planes;
+        Ok(match output {
+            Some((buffers, _)) => {
+                conceal_planes(record, planes, buffers);
+                None
+            }
+            None => Some(SlicePlanes::concealed(record, planes)),
+        })
+    }
+
+    /// Decodes the slice 'slicenum' of the frame 'buf'.
+    ///
+    /// With 'output', samples are written to the frame buffers. Without
+    /// it, they are written to planes holding only the slice, which are
+    /// returned, so that slices can be decoded on several threads.
+    #[allow(clippy::too_many_arguments)]
+    fn decode_slice(
+        ctx: &SliceContext,
+        buf: &[u8],
+        slicenum: usize,
+        slice_info: SliceInfo,
+        current_slice: &mut Slice,
+        states: &mut SliceStates,
+        output: Option<(&mut OutputPlanes, &mut Scratch)>,
+        stats: &mut Option<DecodeStats>,
+    ) -> Result<Option<SlicePlanes>> {
+        span!(
+            "decode_slice",
+            slice = slicenum,
+            pos = slice_info.pos,
+            size = slice_info.size
+        );
+        let record = ctx.record;
+        if ctx
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Self::conceal_slice(
+                ctx,
+                buf,
+                slicenum,
+                slice_info,
+                current_slice,
+                states,
+                output,
+            );
+        }
+        if !ctx.checked {
+            Self::check_slice(ctx, buf, slicenum, slice_info, stats)?;
         }
 
-        Self::parse_slice_header(current_slice, record, &mut coder);
+        let timer = Timer::start(stats);
+        let mut spending = Spending::new(&ctx.budget, slice_info.size);
+        let mut coder = Self::header_coder(
+            &buf[slice_info.pos..],
+            slicenum,
+            record,
+            ctx.state_transition,
+        );
+
+        let trace = ctx.trace;
+        trace.element("4.4. Slice", slicenum);
+        Self::parse_slice_header(
+            current_slice,
+            slicenum,
+            record,
+            &mut coder,
+            trace,
+            ctx.compliance,
+        )?;
+
+        // If this is a keyframe, refresh states, for the quantization table
+        // sets of the slice header. Other frames carry on from the states
+        // of the same sets.
+        //
+        // See: * 3.8.1.3. Initial Values for the Context Model
+        //      * 3.8.2.4. Initial Values for the VLC context state
+        if ctx.keyframe {
+            Self::reset_slice_states(current_slice, states, record);
+        } else if !states.matches(&current_slice.header.quant_table_set_index)
+        {
+            return Err(Error::SliceError(
+                "quantization table sets differ from the keyframe".to_owned(),
+            ));
+        }
+        if !ctx.coding_stats {
+            current_slice.coding_stats = None;
+        } else if current_slice.coding_stats.is_none() {
+            current_slice.coding_stats = Some(CodingStats::new(record));
+        }
 
+        // Golomb-Rice coded streams are rejected by 'Decoder::new' without
+        // the 'golomb' feature
+        #[cfg(not(feature = "golomb"))]
+        let mut coder = Coder::Range(coder);
+        #[cfg(feature = "golomb")]
         let mut coder = if record.coder_type == 0 {
             // We're switching to Golomb-Rice mode now so we need the bitstream
             // position.
             //
             // See: 3.8.1.1.1. Termination
             coder.sentinel_end();
-            let offset = coder.get_pos() - 1;
+            let offset = coder.pos() - 1;
+            spending.skip(offset);
             let coder = GolombCoder::new(&buf[slice_info.pos + offset..]);
             Coder::Golomb(coder)
         } else {
             Coder::Range(coder)
         };
+        timer.stop(stats, Stage::SliceHeaders);
 
-        Self::decode_slice_content(current_slice, record, &mut coder, frame);
+        let planes = match output {
+            Some((buffers, scratch)) => {
+                Self::decode_slice_content(
+                    current_slice,
+                    states,
+                    record,
+                    &mut coder,
+                    buffers,
+                    scratch,
+                    &mut spending,
+                    stats,
+                    trace,
+                    false,
+                    ctx.rct_threads,
+                )?;
+                None
+            }
+            None => {
+                let mut planes =
+                    SlicePlanes::new(record, &current_slice.planes);
+                let mut scratch = Scratch::new(
+                    record,
+                    current_slice.planes[0].width,
+                    current_slice.planes[0].height,
+                );
+                Self::decode_slice_content(
+                    current_slice,
+                    states,
+                    record,
+                    &mut coder,
+                    &mut planes.buffers(&current_slice.planes).planes(),
+                    &mut scratch,
+                    &mut spending,
+                    stats,
+                    trace,
+                    true,
+                    1,
+                )?;
+                Some(planes)
+            }
+        };
 
-        Ok(())
+        #[cfg(feature = "golomb")]
+        if let Coder::Golomb(ref coder) = coder {
+            if coder.invalid_run() {
+                return Err(Error::SliceError(format!(
+                    "run past the end of a line in slice {}",
+                    slicenum
+                )));
+            }
+        }
+
+        #[cfg_attr(not(feature = "golomb"), allow(irrefutable_let_patterns))]
+        if let Coder::Range(ref coder) = coder {
+            if coder.invalid_symbol() {
+                return Err(Error::SliceError(format!(
+                    "invalid symbol in slice {}",
+                    slicenum
+                )));
+            }
+            // The range coder may read a couple of bytes ahead, anything
+            // more left in the slice is padding, and reading further means
+            // the coder went out of sync.
+            let end = coder.pos();
+            if end > slice_info.size + 2 {
+                return Err(Error::SliceError(format!(
+                    "slice {} was decoded {} bytes past its end",
+                    slicenum,
+                    end - slice_info.size
+                )));
+            }
+            if end + 2 < slice_info.size {
+                warn!(
+                    "slice {}: tolerating {} bytes of padding",
+                    slicenum,
+                    slice_info.size - end
+                );
+                current_slice.diagnostics.push(Diagnostic::PaddingSkipped {
+                    slice: slicenum,
+                    bytes: slice_info.size - end,
+                });
+            }
+        }
+
+        ctx.metrics.report(|metrics| {
+            metrics.slice_decoded(slicenum, slice_info.size)
+        });
+
+        Ok(planes)
     }
 }
@@ -0,0 +1,80 @@
+//! Non-fatal findings about a decoded frame.
+
+use std::fmt;
+
+/// Something unusual about a frame which did not prevent decoding it, e.g.
+/// for QC reports, see 'Frame::diagnostics'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// Bytes left in a slice after its content, skipped as padding.
+    PaddingSkipped {
+        /// Index of the slice.
+        slice: usize,
+        /// Number of bytes skipped.
+        bytes: usize,
+    },
+    /// A slice coding a sample aspect ratio different from slice 0.
+    ///
+    /// See: * 4.5.8. sar_num
+    ///      * 4.5.9. sar_den
+    SarConflict {
+        /// Index of the slice.
+        slice: usize,
+        /// Sample aspect ratio of the slice.
+        sar: (u32, u32),
+        /// Sample aspect ratio of slice 0.
+        first: (u32, u32),
+    },
+    /// A specification violation of a slice header tolerated by the
+    /// compliance mode, e.g. a reserved value.
+    Violation {
+        /// Index of the slice.
+        slice: usize,
+        /// Description of the violation.
+        message: String,
+    },
+    /// A slice which failed to decode and was skipped, see
+    /// 'Decoder::set_resync_enabled'.
+    SliceAbandoned {
+        /// Index of the slice.
+        slice: usize,
+        /// Why decoding the slice failed.
+        reason: String,
+    },
+    /// A slice which was not decoded because the deadline of the frame
+    /// had passed, and whose samples were concealed, see
+    /// 'Decoder::decode_frame_with_deadline'.
+    SliceConcealed {
+        /// Index of the slice.
+        slice: usize,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::PaddingSkipped { slice, bytes } => {
+                write!(
+                    f,
+                    "slice {}: {} bytes of padding skipped",
+                    slice, bytes
+                )
+            }
+            Diagnostic::SarConflict { slice, sar, first } => write!(
+                f,
+                "slice {} has SAR {}:{}, slice 0 has SAR {}:{}",
+                slice, sar.0, sar.1, first.0, first.1
+            ),
+            Diagnostic::Violation { slice, message } => {
+                write!(f, "slice {}: {}", slice, message)
+            }
+            Diagnostic::SliceAbandoned { slice, reason } => {
+                write!(f, "slice {}: abandoned, {}", slice, reason)
+            }
+            Diagnostic::SliceConcealed { slice } => {
+                write!(f, "slice {}: concealed, deadline passed", slice)
+            }
+        }
+    }
+}
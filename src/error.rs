@@ -15,6 +15,71 @@ pub enum Error {
     /// Slice error.
     #[error("Slice error: {0}")]
     SliceError(String),
+    /// Data whose CRC does not match.
+    #[error("CRC mismatch: {0}")]
+    CrcMismatch(String),
+    /// Failure of a resource used by the decoder, e.g. a decoding thread.
+    #[error("Resource error: {0}")]
+    ResourceError(String),
+    /// A slice exceeding the limits set by 'Decoder::set_slice_budget'.
+    #[error("Resource limit exceeded: {0}")]
+    ResourceLimitExceeded(String),
+}
+
+/// Broad category of an error, e.g. to decide whether an operation is
+/// worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// The configuration record or the decoder settings are invalid.
+    Configuration,
+    /// The bitstream is malformed.
+    Bitstream,
+    /// The data has been damaged, as detected by a CRC.
+    Integrity,
+    /// A resource used by the decoder failed.
+    Resource,
+}
+
+impl Error {
+    /// Returns the numeric code of the error.
+    ///
+    /// Codes are stable across releases, e.g. to be returned through a
+    /// C interface or stored by logging systems:
+    ///
+    /// | Code | Variant                 |
+    /// |------|-------------------------|
+    /// | 1    | 'InvalidInputData'      |
+    /// | 2    | 'InvalidConfiguration'  |
+    /// | 3    | 'FrameError'            |
+    /// | 4    | 'SliceError'            |
+    /// | 5    | 'CrcMismatch'           |
+    /// | 6    | 'ResourceError'         |
+    /// | 7    | 'ResourceLimitExceeded' |
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::InvalidInputData(_) => 1,
+            Error::InvalidConfiguration(_) => 2,
+            Error::FrameError(_) => 3,
+            Error::SliceError(_) => 4,
+            Error::CrcMismatch(_) => 5,
+            Error::ResourceError(_) => 6,
+            Error::ResourceLimitExceeded(_) => 7,
+        }
+    }
+
+    /// Returns the category of the error.
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Error::InvalidConfiguration(_) => ErrorCategory::Configuration,
+            Error::InvalidInputData(_)
+            | Error::FrameError(_)
+            | Error::SliceError(_) => ErrorCategory::Bitstream,
+            Error::CrcMismatch(_) => ErrorCategory::Integrity,
+            Error::ResourceError(_) | Error::ResourceLimitExceeded(_) => {
+                ErrorCategory::Resource
+            }
+        }
+    }
 }
 
 /// A specialised `Result` type for decoding operations.
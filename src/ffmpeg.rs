@@ -0,0 +1,187 @@
+//! Conversions between decoded frames and FFmpeg video frames.
+//!
+//! Frames are exchanged through the planar FFmpeg pixel formats with the
+//! same planes, subsampling and bit depth, e.g. 'YUV422P10LE' or 'GBRAP',
+//! so that projects decoding FFV1 with FFmpeg can switch to this crate
+//! while keeping their filter graphs. Samples wider than 8 bits are
+//! LSB-aligned little-endian words in both.
+
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::frame::Video;
+
+use crate::constants::{ColorSpace, PixelFormat};
+use crate::decoder::{Frame, FrameMetadata};
+use crate::geometry::Crop;
+
+/// FFmpeg pixel formats, with their planes, log2 horizontal and vertical
+/// chroma subsampling values and bit depth.
+const FORMATS: &[(Pixel, PixelFormat, u8, u8, u8)] = &[
+    (Pixel::GRAY8, PixelFormat::Gray, 0, 0, 8),
+    (Pixel::GRAY9LE, PixelFormat::Gray, 0, 0, 9),
+    (Pixel::GRAY10LE, PixelFormat::Gray, 0, 0, 10),
+    (Pixel::GRAY12LE, PixelFormat::Gray, 0, 0, 12),
+    (Pixel::GRAY14LE, PixelFormat::Gray, 0, 0, 14),
+    (Pixel::GRAY16LE, PixelFormat::Gray, 0, 0, 16),
+    (Pixel::YUV420P, PixelFormat::YCbCr, 1, 1, 8),
+    (Pixel::YUV422P, PixelFormat::YCbCr, 1, 0, 8),
+    (Pixel::YUV444P, PixelFormat::YCbCr, 0, 0, 8),
+    (Pixel::YUV411P, PixelFormat::YCbCr, 2, 0, 8),
+    (Pixel::YUV410P, PixelFormat::YCbCr, 2, 2, 8),
+    (Pixel::YUV440P, PixelFormat::YCbCr, 0, 1, 8),
+    (Pixel::YUV420P9LE, PixelFormat::YCbCr, 1, 1, 9),
+    (Pixel::YUV422P9LE, PixelFormat::YCbCr, 1, 0, 9),
+    (Pixel::YUV444P9LE, PixelFormat::YCbCr, 0, 0, 9),
+    (Pixel::YUV420P10LE, PixelFormat::YCbCr, 1, 1, 10),
+    (Pixel::YUV422P10LE, PixelFormat::YCbCr, 1, 0, 10),
+    (Pixel::YUV444P10LE, PixelFormat::YCbCr, 0, 0, 10),
+    (Pixel::YUV440P10LE, PixelFormat::YCbCr, 0, 1, 10),
+    (Pixel::YUV420P12LE, PixelFormat::YCbCr, 1, 1, 12),
+    (Pixel::YUV422P12LE, PixelFormat::YCbCr, 1, 0, 12),
+    (Pixel::YUV444P12LE, PixelFormat::YCbCr, 0, 0, 12),
+    (Pixel::YUV440P12LE, PixelFormat::YCbCr, 0, 1, 12),
+    (Pixel::YUV420P14LE, PixelFormat::YCbCr, 1, 1, 14),
+    (Pixel::YUV422P14LE, PixelFormat::YCbCr, 1, 0, 14),
+    (Pixel::YUV444P14LE, PixelFormat::YCbCr, 0, 0, 14),
+    (Pixel::YUV420P16LE, PixelFormat::YCbCr, 1, 1, 16),
+    (Pixel::YUV422P16LE, PixelFormat::YCbCr, 1, 0, 16),
+    (Pixel::YUV444P16LE, PixelFormat::YCbCr, 0, 0, 16),
+    (Pixel::YUVA420P, PixelFormat::YCbCrAlpha, 1, 1, 8),
+    (Pixel::YUVA422P, PixelFormat::YCbCrAlpha, 1, 0, 8),
+    (Pixel::YUVA444P, PixelFormat::YCbCrAlpha, 0, 0, 8),
+    (Pixel::YUVA420P9LE, PixelFormat::YCbCrAlpha, 1, 1, 9),
+    (Pixel::YUVA422P9LE, PixelFormat::YCbCrAlpha, 1, 0, 9),
+    (Pixel::YUVA444P9LE, PixelFormat::YCbCrAlpha, 0, 0, 9),
+    (Pixel::YUVA420P10LE, PixelFormat::YCbCrAlpha, 1, 1, 10),
+    (Pixel::YUVA422P10LE, PixelFormat::YCbCrAlpha, 1, 0, 10),
+    (Pixel::YUVA444P10LE, PixelFormat::YCbCrAlpha, 0, 0, 10),
+    (Pixel::YUVA422P12LE, PixelFormat::YCbCrAlpha, 1, 0, 12),
+    (Pixel::YUVA444P12LE, PixelFormat::YCbCrAlpha, 0, 0, 12),
+    (Pixel::YUVA420P16LE, PixelFormat::YCbCrAlpha, 1, 1, 16),
+    (Pixel::YUVA422P16LE, PixelFormat::YCbCrAlpha, 1, 0, 16),
+    (Pixel::YUVA444P16LE, PixelFormat::YCbCrAlpha, 0, 0, 16),
+    (Pixel::GBRP, PixelFormat::Rgb, 0, 0, 8),
+    (Pixel::GBRP9LE, PixelFormat::Rgb, 0, 0, 9),
+    (Pixel::GBRP10LE, PixelFormat::Rgb, 0, 0, 10),
+    (Pixel::GBRP12LE, PixelFormat::Rgb, 0, 0, 12),
+    (Pixel::GBRP14LE, PixelFormat::Rgb, 0, 0, 14),
+    (Pixel::GBRP16LE, PixelFormat::Rgb, 0, 0, 16),
+    (Pixel::GBRAP, PixelFormat::Rgba, 0, 0, 8),
+    (Pixel::GBRAP10LE, PixelFormat::Rgba, 0, 0, 10),
+    (Pixel::GBRAP12LE, PixelFormat::Rgba, 0, 0, 12),
+    (Pixel::GBRAP16LE, PixelFormat::Rgba, 0, 0, 16),
+];
+
+/// Returns the FFmpeg pixel format of a frame, or None when FFmpeg has no
+/// planar format for it, e.g. for gray frames with an alpha plane.
+///
+/// The planes of FFmpeg formats are in the same order as the planes of
+/// frames, including the green, blue and red order of RGB.
+pub fn pixel_format(frame: &Frame) -> Option<Pixel> {
+    let planes = frame.pixel_format();
+    let subsampling = (frame.chroma_subsample_h, frame.chroma_subsample_v);
+    FORMATS
+        .iter()
+        .find(|&&(_, format, h, v, bit_depth)| {
+            format == planes
+                && bit_depth == frame.bit_depth
+                && (planes.is_gray() || (h, v) == subsampling)
+        })
+        .map(|&(pixel, ..)| pixel)
+}
+
+/// Returns a copy of a frame as an FFmpeg video frame, with the
+/// presentation timestamp of its metadata, or None when FFmpeg has no
+/// planar format for it, see 'pixel_format'.
+pub fn to_video(frame: &Frame) -> Option<Video> {
+    let format = pixel_format(frame)?;
+    let mut video = Video::new(format, frame.width, frame.height);
+    for plane in 0..frame.num_planes() {
+        let (width, height) = frame.plane_dimensions(plane);
+        let (offset, stride) = (frame.offsets[plane], frame.strides[plane]);
+        let linesize = video.stride(plane);
+        let data = video.data_mut(plane);
+        for y in 0..height {
+            let dst = &mut data[y * linesize..];
+            let start = offset + y * stride;
+            if frame.bit_depth == 8 {
+                dst[..width]
+                    .copy_from_slice(&frame.buf[plane][start..][..width]);
+            } else {
+                let src = &frame.buf16[plane][start..][..width];
+                for (dst, &v) in dst.chunks_exact_mut(2).zip(src) {
+                    dst.copy_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+    }
+    video.set_pts(frame.metadata.pts);
+    Some(video)
+}
+
+/// Returns a copy of an FFmpeg video frame as a frame with packed planes,
+/// with its presentation timestamp as metadata, or None when its pixel
+/// format is not one of the planar formats of 'pixel_format'.
+///
+/// Full range 'YUVJ' formats are read as their limited range equivalents,
+/// as frames do not carry the range of their samples.
+pub fn from_video(video: &Video) -> Option<Frame> {
+    let format = match video.format() {
+        Pixel::YUVJ420P => Pixel::YUV420P,
+        Pixel::YUVJ422P => Pixel::YUV422P,
+        Pixel::YUVJ444P => Pixel::YUV444P,
+        Pixel::YUVJ440P => Pixel::YUV440P,
+        Pixel::YUVJ411P => Pixel::YUV411P,
+        format => format,
+    };
+    let &(_, planes, h, v, bit_depth) =
+        FORMATS.iter().find(|&&(pixel, ..)| pixel == format)?;
+    let color_space = match planes {
+        PixelFormat::Rgb | PixelFormat::Rgba => ColorSpace::Rgb,
+        _ => ColorSpace::YCbCr,
+    };
+
+    let (width, height) = (video.width(), video.height());
+    let mut frame = Frame {
+        buf: Vec::new(),
+        buf16: Vec::new(),
+        strides: Vec::new(),
+        offsets: vec![0; planes.num_planes()],
+        width,
+        height,
+        bit_depth,
+        color_space,
+        has_chroma: !planes.is_gray(),
+        has_alpha: planes.has_alpha(),
+        chroma_subsample_v: v,
+        chroma_subsample_h: h,
+        crop: Crop::display_size(width, height),
+        metadata: FrameMetadata {
+            pts: video.pts(),
+            ..FrameMetadata::default()
+        },
+        diagnostics: Vec::new(),
+    };
+
+    for plane in 0..planes.num_planes() {
+        let (width, height) = frame.plane_dimensions(plane);
+        let linesize = video.stride(plane);
+        let data = video.data(plane);
+        let rows = (0..height).map(|y| &data[y * linesize..]);
+        if bit_depth == 8 {
+            frame.buf.push(
+                rows.flat_map(|row| row[..width].iter().copied()).collect(),
+            );
+        } else {
+            frame.buf16.push(
+                rows.flat_map(|row| {
+                    row[..2 * width]
+                        .chunks_exact(2)
+                        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                })
+                .collect(),
+            );
+        }
+        frame.strides.push(width);
+    }
+    Some(frame)
+}
@@ -0,0 +1,169 @@
+//! Reading of the checksums written by the FFmpeg framemd5 and framecrc
+//! muxers, e.g. to check decoded frames against the hashes an archive
+//! recorded when its collection was decoded with FFmpeg.
+//!
+//! Both formats have '#' header lines followed by a line per frame:
+//!
+//! ```text
+//! #tb 0: 1/25
+//! #media_type 0: video
+//! #stream#, dts,        pts, duration,     size, hash
+//! 0,          0,          0,        1,   921600, 7a5e9d3f1c0b2e4d8f6a1b3c5d7e9f01
+//! ```
+//!
+//! where framecrc writes an Adler-32 checksum, e.g. '0x1f2e3d4c', instead
+//! of the MD5 digest.
+
+use std::fmt;
+use std::io::{self, BufRead};
+
+use crate::decoder::Frame;
+
+/// Returns an error about an invalid checksum file.
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The checksum of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// MD5 digest, as written by framemd5.
+    Md5([u8; 16]),
+    /// Adler-32 checksum, as written by framecrc.
+    Adler32(u32),
+}
+
+impl Checksum {
+    /// Returns the checksum of 'frame', of the same kind as this one.
+    pub fn of_frame(&self, frame: &Frame) -> Self {
+        match self {
+            Checksum::Md5(_) => Checksum::Md5(frame.frame_md5()),
+            Checksum::Adler32(_) => Checksum::Adler32(frame.frame_crc()),
+        }
+    }
+
+    /// Parses a checksum as written by framemd5 or framecrc.
+    fn parse(field: &str) -> Option<Self> {
+        if let Some(hex) = field.strip_prefix("0x") {
+            if hex.len() != 8 {
+                return None;
+            }
+            return u32::from_str_radix(hex, 16).ok().map(Checksum::Adler32);
+        }
+        if field.len() != 32 || !field.is_ascii() {
+            return None;
+        }
+        let mut digest = [0; 16];
+        for (byte, hex) in digest.iter_mut().zip(field.as_bytes().chunks(2)) {
+            let hex = std::str::from_utf8(hex).ok()?;
+            *byte = u8::from_str_radix(hex, 16).ok()?;
+        }
+        Some(Checksum::Md5(digest))
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Checksum::Md5(digest) => {
+                for byte in digest {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Checksum::Adler32(adler) => write!(f, "0x{:08x}", adler),
+        }
+    }
+}
+
+/// The line of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Index of the stream of the frame.
+    pub stream: usize,
+    /// Decoding timestamp, in the time base of the stream.
+    pub dts: i64,
+    /// Presentation timestamp, in the time base of the stream.
+    pub pts: i64,
+    /// Duration, in the time base of the stream.
+    pub duration: i64,
+    /// Size of the raw frame, in bytes.
+    pub size: usize,
+    /// Checksum of the raw frame.
+    pub checksum: Checksum,
+}
+
+impl Entry {
+    /// Parses the line of a frame. Side data, written by framecrc after
+    /// the checksum, is ignored.
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(',').map(str::trim);
+        let mut next = || fields.next();
+        Some(Self {
+            stream: next()?.parse().ok()?,
+            dts: next()?.parse().ok()?,
+            pts: next()?.parse().ok()?,
+            duration: next()?.parse().ok()?,
+            size: next()?.parse().ok()?,
+            checksum: Checksum::parse(next()?)?,
+        })
+    }
+}
+
+/// The checksums of every frame of a framemd5 or framecrc file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameChecksums {
+    /// Indexes of the video streams, when listed by the header.
+    pub video_streams: Vec<usize>,
+    /// Frames of all the streams, in the order of the file.
+    pub entries: Vec<Entry>,
+}
+
+impl FrameChecksums {
+    /// Reads a framemd5 or framecrc file.
+    pub fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut checksums = Self::default();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if let Some(header) = line.strip_prefix('#') {
+                // e.g. '#hash: MD5' or '#media_type 0: video'
+                match header.split_once(':') {
+                    Some(("hash", hash)) if hash.trim() != "MD5" => {
+                        return Err(invalid_data(format!(
+                            "unsupported hash {}",
+                            hash.trim()
+                        )));
+                    }
+                    Some((key, media_type))
+                        if media_type.trim() == "video" =>
+                    {
+                        if let Some(stream) = key
+                            .strip_prefix("media_type ")
+                            .and_then(|stream| stream.parse().ok())
+                        {
+                            checksums.video_streams.push(stream);
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let entry = Entry::parse(line).ok_or_else(|| {
+                invalid_data(format!("invalid frame line {}", number + 1))
+            })?;
+            checksums.entries.push(entry);
+        }
+        Ok(checksums)
+    }
+
+    /// Returns the frames of the stream 'stream'.
+    pub fn stream(&self, stream: usize) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.stream == stream)
+    }
+}
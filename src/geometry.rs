@@ -0,0 +1,109 @@
+//! Plane geometry shared by frames and slices.
+//!
+//! Chroma sizes are rounded up, as in FFmpeg, so the last chroma sample of
+//! a frame or slice with an odd width or height covers the remaining luma
+//! samples, while chroma positions are rounded down.
+
+/// Returns the number of chroma samples covering 'luma' luma samples,
+/// for a log2 chroma subsampling.
+///
+/// See: * 4.6.3. slice_pixel_height
+///      * 4.7.2. slice_pixel_width
+pub fn chroma_size(luma: u32, log2_subsample: u8) -> u32 {
+    ((luma as u64 + (1 << log2_subsample) - 1) >> log2_subsample) as u32
+}
+
+/// Returns the position of the chroma sample covering the luma sample at
+/// 'luma', for a log2 chroma subsampling.
+pub fn chroma_position(luma: u32, log2_subsample: u8) -> u32 {
+    luma >> log2_subsample
+}
+
+/// Returns the width and height of a plane of a 'width'x'height' frame,
+/// in samples. Planes 1 and 2 are subsampled chroma planes when
+/// 'has_chroma' is set.
+pub fn plane_dimensions(
+    width: u32,
+    height: u32,
+    has_chroma: bool,
+    log2_h_subsample: u8,
+    log2_v_subsample: u8,
+    plane: usize,
+) -> (usize, usize) {
+    if has_chroma && (plane == 1 || plane == 2) {
+        (
+            chroma_size(width, log2_h_subsample) as usize,
+            chroma_size(height, log2_v_subsample) as usize,
+        )
+    } else {
+        (width as usize, height as usize)
+    }
+}
+
+/// Displayed area of a frame, in luma pixels, when containers store coded
+/// dimensions larger than the display dimensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    /// Number of columns cropped on the left.
+    pub left: u32,
+    /// Number of rows cropped on the top.
+    pub top: u32,
+    /// Displayed width, in pixels.
+    pub width: u32,
+    /// Displayed height, in pixels.
+    pub height: u32,
+}
+
+impl Crop {
+    /// Returns the area displaying the top-left 'width'x'height' pixels.
+    pub fn display_size(width: u32, height: u32) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Returns whether the area is not empty and lies within a
+    /// 'width'x'height' frame.
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.width > 0
+            && self.height > 0
+            && self.left as u64 + self.width as u64 <= width as u64
+            && self.top as u64 + self.height as u64 <= height as u64
+    }
+
+    /// Returns the position and size of the area in a plane, in samples.
+    ///
+    /// Planes 1 and 2 are subsampled chroma planes when 'has_chroma' is
+    /// set, whose area holds every chroma sample covering a displayed
+    /// luma sample.
+    pub fn plane_area(
+        &self,
+        has_chroma: bool,
+        log2_h_subsample: u8,
+        log2_v_subsample: u8,
+        plane: usize,
+    ) -> (usize, usize, usize, usize) {
+        if has_chroma && (plane == 1 || plane == 2) {
+            let x = chroma_position(self.left, log2_h_subsample);
+            let y = chroma_position(self.top, log2_v_subsample);
+            let right = chroma_size(self.left + self.width, log2_h_subsample);
+            let bottom = chroma_size(self.top + self.height, log2_v_subsample);
+            (
+                x as usize,
+                y as usize,
+                (right - x) as usize,
+                (bottom - y) as usize,
+            )
+        } else {
+            (
+                self.left as usize,
+                self.top as usize,
+                self.width as usize,
+                self.height as usize,
+            )
+        }
+    }
+}
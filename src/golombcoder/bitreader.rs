@@ -18,13 +18,14 @@ impl<'a> BitReader<'a> {
     }
 
     /// Reads 'count' bits, up to 32.
+    ///
+    /// Bits past the end of the buffer are read as 0.
     pub fn u(&mut self, count: u32) -> u32 {
-        if count > 32 {
-            panic!("WTF more than 32 bits");
-        }
+        debug_assert!(count <= 32, "more than 32 bits");
         while count > self.bits_in_buf {
             self.bit_buf <<= 8;
-            self.bit_buf |= self.buf[self.pos] as u32;
+            self.bit_buf |=
+                self.buf.get(self.pos).copied().unwrap_or(0) as u32;
             self.bits_in_buf += 8;
             self.pos += 1;
 
@@ -40,4 +41,9 @@ impl<'a> BitReader<'a> {
         self.bits_in_buf -= count;
         (self.bit_buf >> self.bits_in_buf) & ((1 << count) - 1)
     }
+
+    /// Returns the number of bits read so far.
+    pub fn bits_read(&self) -> u64 {
+        self.pos as u64 * 8 - u64::from(self.bits_in_buf)
+    }
 }
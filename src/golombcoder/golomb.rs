@@ -1,26 +1,42 @@
 //! Package golomb implements a Golomb-Rice coder as per
 //! Section 3.8.2. Golomb Rice Mode of draft-ietf-cellar-ffv1.
 
+#[cfg(feature = "golomb")]
 use crate::golombcoder::bitreader::BitReader;
+#[cfg(feature = "golomb")]
 use crate::golombcoder::tables::LOG2_RUN;
 
-/// Coder is an instance of a Golomb-Rice coder
+/// GolombCoder is an instance of a Golomb-Rice coder
 /// as described in 3.8.2. Golomb Rice Mode.
+#[cfg(feature = "golomb")]
 #[derive(Debug)]
-pub struct Coder<'a> {
+pub struct GolombCoder<'a> {
     r: BitReader<'a>,
     run_mode: usize,
     run_count: isize,
     run_index: usize,
     x: u32,
     w: u32,
+    invalid_run: bool,
 }
 
+#[cfg(feature = "golomb")]
+#[deprecated(note = "use GolombCoder")]
+pub type Coder<'a> = GolombCoder<'a>;
+
 /// State contains a single set of states for the a Golomb-Rice coder as
 /// defined in 3.8.2.4.
 ///
 /// Initial Values for the VLC context state.
-#[derive(Debug, Clone)]
+///
+/// With the 'serde' feature, states are serialized as their four fields,
+/// and checked when deserialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "StateFields", into = "StateFields")
+)]
 pub struct State {
     drift: i32,
     error_sum: i32,
@@ -39,7 +55,120 @@ impl Default for State {
     }
 }
 
+impl State {
+    /// Size of a serialized state, in bytes.
+    pub const SERIALIZED_SIZE: usize = 16;
+
+    /// Creates a state from its fields, e.g. to restore a state saved by
+    /// an external analyzer, or None when they cannot be reached while
+    /// decoding.
+    ///
+    /// 'count' must be between 1 and 128, 'drift' between 1 - 'count' and
+    /// 0, 'bias' between -128 and 127, and 'error_sum' non-negative and
+    /// lower than 2^30.
+    pub fn new(
+        drift: i32,
+        error_sum: i32,
+        bias: i32,
+        count: i32,
+    ) -> Option<Self> {
+        let valid = (1..=128).contains(&count)
+            && (1 - count..=0).contains(&drift)
+            && (-128..=127).contains(&bias)
+            && (0..1 << 30).contains(&error_sum);
+        valid.then_some(Self {
+            drift,
+            error_sum,
+            bias,
+            count,
+        })
+    }
+
+    /// Returns the accumulated prediction error sign, used to correct
+    /// 'bias'.
+    pub fn drift(&self) -> i32 {
+        self.drift
+    }
+
+    /// Returns the accumulated magnitude of the prediction errors, used to
+    /// select the Golomb-Rice parameter.
+    pub fn error_sum(&self) -> i32 {
+        self.error_sum
+    }
+
+    /// Returns the correction added to the decoded residuals.
+    pub fn bias(&self) -> i32 {
+        self.bias
+    }
+
+    /// Returns the number of symbols accumulated into 'drift' and
+    /// 'error_sum', halved every 128 symbols.
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    /// Serializes the state as four little-endian 32-bit integers: drift,
+    /// error_sum, bias and count.
+    pub fn to_le_bytes(&self) -> [u8; Self::SERIALIZED_SIZE] {
+        let mut bytes = [0; Self::SERIALIZED_SIZE];
+        for (dst, v) in bytes
+            .chunks_exact_mut(4)
+            .zip([self.drift, self.error_sum, self.bias, self.count].iter())
+        {
+            dst.copy_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserializes a state written by 'to_le_bytes', or None when it is
+    /// invalid, see 'new'.
+    pub fn from_le_bytes(bytes: &[u8; Self::SERIALIZED_SIZE]) -> Option<Self> {
+        let v = |i: usize| {
+            i32::from_le_bytes([
+                bytes[i * 4],
+                bytes[i * 4 + 1],
+                bytes[i * 4 + 2],
+                bytes[i * 4 + 3],
+            ])
+        };
+        Self::new(v(0), v(1), v(2), v(3))
+    }
+}
+
+/// The fields of a serialized 'State'.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StateFields {
+    drift: i32,
+    error_sum: i32,
+    bias: i32,
+    count: i32,
+}
+
+#[cfg(feature = "serde")]
+impl From<State> for StateFields {
+    fn from(state: State) -> Self {
+        Self {
+            drift: state.drift,
+            error_sum: state.error_sum,
+            bias: state.bias,
+            count: state.count,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<StateFields> for State {
+    type Error = String;
+
+    fn try_from(fields: StateFields) -> Result<Self, Self::Error> {
+        Self::new(fields.drift, fields.error_sum, fields.bias, fields.count)
+            .ok_or_else(|| "invalid Golomb-Rice state".to_owned())
+    }
+}
+
 /// Simple sign extension.
+#[cfg(feature = "golomb")]
 pub fn sign_extend(n: i32, bits: u32) -> i32 {
     if bits == 8 {
         let ret = n as i8;
@@ -52,7 +181,8 @@ pub fn sign_extend(n: i32, bits: u32) -> i32 {
     }
 }
 
-impl<'a> Coder<'a> {
+#[cfg(feature = "golomb")]
+impl<'a> GolombCoder<'a> {
     /// Creates a new Golomb-Rice coder.
     pub fn new(buf: &'a [u8]) -> Self {
         let r = BitReader::new(buf);
@@ -63,9 +193,15 @@ impl<'a> Coder<'a> {
             run_index: 0,
             x: 0,
             w: 0,
+            invalid_run: false,
         }
     }
 
+    /// Returns the number of bits read so far.
+    pub fn bits_read(&self) -> u64 {
+        self.r.bits_read()
+    }
+
     /// newPlane should be called on a given Coder as each new Plane is
     /// processed. It resets the run index and sets the slice width.
     ///
@@ -101,11 +237,17 @@ impl<'a> Coder<'a> {
         // Section 3.8.2.2.1. Run Length Coding
         if self.run_mode != 0 {
             if self.run_count == 0 && self.run_mode == 1 {
+                let left = self.w.saturating_sub(self.x) as isize;
                 if self.r.u(1) == 1 {
                     self.run_count = 1 << LOG2_RUN[self.run_index as usize];
-                    if self.x + self.run_count as u32 <= self.w {
+                    if self.x + self.run_count as u32 <= self.w
+                        && self.run_index + 1 < LOG2_RUN.len()
+                    {
                         self.run_index += 1;
                     }
+                    // The last run of a line may be longer than what is
+                    // left of it
+                    self.run_count = self.run_count.min(left);
                 } else {
                     if LOG2_RUN[self.run_index as usize] != 0 {
                         self.run_count =
@@ -113,6 +255,12 @@ impl<'a> Coder<'a> {
                     } else {
                         self.run_count = 0;
                     }
+                    // A run ended by a level has to fit in the line with it,
+                    // otherwise it is clamped to the line.
+                    if self.run_count >= left {
+                        self.run_count = left;
+                        self.invalid_run = true;
+                    }
                     if self.run_index != 0 {
                         self.run_index -= 1;
                     }
@@ -144,6 +292,14 @@ impl<'a> Coder<'a> {
         }
     }
 
+    /// Returns whether a run going past the end of its line was read, in
+    /// which case it was clamped to the line.
+    ///
+    /// See: 3.8.2.2.1. Run Length Coding
+    pub fn invalid_run(&self) -> bool {
+        self.invalid_run
+    }
+
     /// Gets the next Golomb-Rice coded symbol.
     ///
     /// See: 3.8.2.3. Scalar Mode
@@ -151,7 +307,9 @@ impl<'a> Coder<'a> {
         let mut i = state.count;
         let mut k = 0;
 
-        while i < state.error_sum {
+        // Damaged slices may grow error_sum enough for k to exceed the
+        // sample bits, it is clamped to them.
+        while i < state.error_sum && k < bits {
             k += 1;
             i += i;
         }
@@ -1,3 +1,5 @@
+#[cfg(feature = "golomb")]
 pub mod bitreader;
 pub mod golomb;
+#[cfg(feature = "golomb")]
 pub mod tables;
@@ -0,0 +1,148 @@
+//! Upload of decoded frames into wgpu textures.
+
+use crate::convert::{to_msb_aligned, to_rgba8};
+use crate::decoder::Frame;
+
+/// Creates a 2D texture that can be sampled and written to.
+fn create_texture(
+    device: &wgpu::Device,
+    width: usize,
+    height: usize,
+    format: wgpu::TextureFormat,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("ffv1 frame"),
+        size: extent(width, height),
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn extent(width: usize, height: usize) -> wgpu::Extent3d {
+    wgpu::Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Writes rows of 'bytes_per_row' bytes, starting at 'offset' in 'data',
+/// into a texture.
+fn write_texture(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    data: &[u8],
+    offset: usize,
+    bytes_per_row: usize,
+    width: usize,
+    height: usize,
+) {
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        data,
+        wgpu::ImageDataLayout {
+            offset: offset as u64,
+            bytes_per_row: Some(bytes_per_row as u32),
+            rows_per_image: Some(height as u32),
+        },
+        extent(width, height),
+    );
+}
+
+/// Uploads every plane of a frame into its own single channel texture,
+/// with the dimensions of the plane.
+///
+/// 8-bit planes are uploaded as they are into R8Unorm textures, honoring
+/// their strides. Deeper planes are MSB-aligned first and uploaded into
+/// R16Unorm textures, which requires the TEXTURE_FORMAT_16BIT_NORM feature
+/// of the device.
+pub fn upload_planes(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    frame: &Frame,
+) -> Vec<wgpu::Texture> {
+    if frame.bit_depth == 8 {
+        (0..frame.num_planes())
+            .map(|i| {
+                let (width, height) = frame.plane_dimensions(i);
+                let texture = create_texture(
+                    device,
+                    width,
+                    height,
+                    wgpu::TextureFormat::R8Unorm,
+                );
+                write_texture(
+                    queue,
+                    &texture,
+                    &frame.buf[i],
+                    frame.offsets[i],
+                    frame.strides[i],
+                    width,
+                    height,
+                );
+                texture
+            })
+            .collect()
+    } else {
+        let aligned = to_msb_aligned(frame);
+        aligned
+            .buf16
+            .iter()
+            .enumerate()
+            .map(|(i, plane)| {
+                let (width, height) = aligned.plane_dimensions(i);
+                let texture = create_texture(
+                    device,
+                    width,
+                    height,
+                    wgpu::TextureFormat::R16Unorm,
+                );
+                let bytes: Vec<u8> =
+                    plane.iter().flat_map(|v| v.to_le_bytes()).collect();
+                write_texture(
+                    queue,
+                    &texture,
+                    &bytes,
+                    0,
+                    width * 2,
+                    width,
+                    height,
+                );
+                texture
+            })
+            .collect()
+    }
+}
+
+/// Uploads a frame into an Rgba8Unorm texture, after converting it with
+/// 'convert::to_rgba8'.
+pub fn upload_rgba(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    frame: &Frame,
+) -> wgpu::Texture {
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let texture =
+        create_texture(device, width, height, wgpu::TextureFormat::Rgba8Unorm);
+    write_texture(
+        queue,
+        &texture,
+        &to_rgba8(frame),
+        0,
+        width * 4,
+        width,
+        height,
+    );
+    texture
+}
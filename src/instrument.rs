@@ -0,0 +1,19 @@
+//! Optional instrumentation through the tracing crate.
+//!
+//! Without the `tracing` feature, the macros expand to nothing.
+
+/// Enters a debug span until the end of the enclosing block.
+macro_rules! span {
+    ($name:expr $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+/// Emits a debug event.
+macro_rules! event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}
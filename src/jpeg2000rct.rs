@@ -1,13 +1,22 @@
 #![allow(non_snake_case)]
 
+use std::thread;
+
+use crate::decoder::PlaneBuffer;
+
 pub trait Rct<S>: Sized {
-    fn rct(
-        dst: &mut [Vec<Self>],
-        src: &[Vec<S>],
+    /// Converts the rectangle of size 'width'x'height' starting at
+    /// ('start_x', 'start_y') from 'src', a set of frame-sized planes
+    /// with stride 'src_stride', into 'dst'.
+    #[allow(clippy::too_many_arguments)]
+    fn rct<V: AsRef<[S]>>(
+        dst: &mut [PlaneBuffer<Self>],
+        src: &[V],
+        src_stride: usize,
+        start_x: usize,
+        start_y: usize,
         width: usize,
         height: usize,
-        stride: usize,
-        offset: usize,
         bits: usize,
     );
 }
@@ -16,37 +25,39 @@ pub trait Rct<S>: Sized {
 ///
 /// See: 3.7.2. RGB
 impl Rct<u16> for u8 {
-    fn rct(
-        dst: &mut [Vec<u8>],
-        src: &[Vec<u16>],
+    fn rct<V: AsRef<[u16]>>(
+        dst: &mut [PlaneBuffer<u8>],
+        src: &[V],
+        src_stride: usize,
+        start_x: usize,
+        start_y: usize,
         width: usize,
         height: usize,
-        stride: usize,
-        offset: usize,
         _bits: usize,
     ) {
-        let Y = &src[0][offset..];
-        let Cb = &src[1][offset..];
-        let Cr = &src[2][offset..];
-        for y in 0..height {
-            for x in 0..width {
-                let Cbtmp = Cb[(y * stride) + x].wrapping_sub(1 << 8); // See: 3.7.2.1. RGB
-                let Crtmp = Cr[(y * stride) + x].wrapping_sub(1 << 8); // See: 3.7.2.1. RGB
-                let green = Y[(y * stride) + x]
-                    .wrapping_sub((Cbtmp.wrapping_add(Crtmp)) >> 2); // See: 3.7.2.1. RGB
+        let Y = src[0].as_ref();
+        let Cb = src[1].as_ref();
+        let Cr = src[2].as_ref();
+        for y in start_y..start_y + height {
+            for x in start_x..start_x + width {
+                let s = (y * src_stride) + x;
+                let Cbtmp = Cb[s].wrapping_sub(1 << 8); // See: 3.7.2.1. RGB
+                let Crtmp = Cr[s].wrapping_sub(1 << 8); // See: 3.7.2.1. RGB
+                let green =
+                    Y[s].wrapping_sub((Cbtmp.wrapping_add(Crtmp)) >> 2); // See: 3.7.2.1. RGB
                 let red = Crtmp.wrapping_add(green); // See: 3.7.2.1 RGB
                 let blue = Cbtmp.wrapping_add(green); // See: 3.7.2.1 RGB
-                dst[0][offset + (y * stride) + x] = green as u8;
-                dst[1][offset + (y * stride) + x] = blue as u8;
-                dst[2][offset + (y * stride) + x] = red as u8;
+                dst[0].data[(y * dst[0].stride) + x] = green as u8;
+                dst[1].data[(y * dst[1].stride) + x] = blue as u8;
+                dst[2].data[(y * dst[2].stride) + x] = red as u8;
             }
         }
         if src.len() == 4 {
-            let s = &src[3][offset..];
-            let d = &mut dst[3][offset..];
-            for y in 0..height {
-                for x in 0..width {
-                    d[(y * stride) + x] = s[(y * stride) + x] as u8;
+            let s = src[3].as_ref();
+            let d = &mut dst[3];
+            for y in start_y..start_y + height {
+                for x in start_x..start_x + width {
+                    d.data[(y * d.stride) + x] = s[(y * src_stride) + x] as u8;
                 }
             }
         }
@@ -57,31 +68,32 @@ impl Rct<u16> for u8 {
 ///
 /// See: 3.7.2. RGB
 impl Rct<u8> for u16 {
-    fn rct(
-        dst: &mut [Vec<u16>],
-        _src: &[Vec<u8>],
+    fn rct<V: AsRef<[u8]>>(
+        dst: &mut [PlaneBuffer<u16>],
+        _src: &[V],
+        _src_stride: usize,
+        start_x: usize,
+        start_y: usize,
         width: usize,
         height: usize,
-        stride: usize,
-        offset: usize,
         bits: usize,
     ) {
         let src = dst;
-        for y in 0..height {
-            for x in 0..width {
-                let Cbtmp = (src[1][offset + (y * stride) + x]
-                    .wrapping_sub(1))
-                    << bits; // See: 3.7.2.1. RGB
-                let Crtmp = (src[2][offset + (y * stride) + x]
-                    .wrapping_sub(1))
-                    << bits; // See: 3.7.2.1. RGB
-                let blue = src[0][offset + (y * stride) + x]
-                    .wrapping_sub((Cbtmp + Crtmp) >> 2); // See: 3.7.2.1. RGB
-                let red = Crtmp.wrapping_add(blue);
-                let green = Cbtmp.wrapping_add(blue);
-                src[0][offset + (y * stride) + x] = green as u16;
-                src[1][offset + (y * stride) + x] = blue as u16;
-                src[2][offset + (y * stride) + x] = red as u16;
+        for y in start_y..start_y + height {
+            for x in start_x..start_x + width {
+                let s0 = (y * src[0].stride) + x;
+                let s1 = (y * src[1].stride) + x;
+                let s2 = (y * src[2].stride) + x;
+                // Chroma differences are signed
+                let Cbtmp = i32::from(src[1].data[s1]) - (1 << bits); // See: 3.7.2.1. RGB
+                let Crtmp = i32::from(src[2].data[s2]) - (1 << bits); // See: 3.7.2.1. RGB
+                let green =
+                    i32::from(src[0].data[s0]) - ((Cbtmp + Crtmp) >> 2); // See: 3.7.2.1. RGB
+                let red = Crtmp + green; // See: 3.7.2.1 RGB
+                let blue = Cbtmp + green; // See: 3.7.2.1 RGB
+                src[0].data[s0] = green as u16;
+                src[1].data[s1] = blue as u16;
+                src[2].data[s2] = red as u16;
             }
         }
     }
@@ -90,40 +102,111 @@ impl Rct<u8> for u16 {
 /// Converts one line from 17-bit JPEG2000-RCT to planar GBR, in place.
 ///
 /// See: 3.7.2. RGB
+#[cfg(feature = "rgb16")]
 impl Rct<u32> for u16 {
-    fn rct(
-        dst: &mut [Vec<u16>],
-        src: &[Vec<u32>],
+    fn rct<V: AsRef<[u32]>>(
+        dst: &mut [PlaneBuffer<u16>],
+        src: &[V],
+        src_stride: usize,
+        start_x: usize,
+        start_y: usize,
         width: usize,
         height: usize,
-        stride: usize,
-        offset: usize,
         _bits: usize,
     ) {
-        let Y = &src[0][offset..];
-        let Cb = &src[1][offset..];
-        let Cr = &src[2][offset..];
-        for y in 0..height {
-            for x in 0..width {
-                let Cbtmp = Cb[(y * stride) + x].wrapping_sub(1 << 16); // See: 3.7.2.1. RGB
-                let Crtmp = Cr[(y * stride) + x].wrapping_sub(1 << 16); // See: 3.7.2.1. RGB
-                let green = Y[(y * stride) + x]
-                    .wrapping_sub((Cbtmp.wrapping_add(Crtmp)) >> 2); // See: 3.7.2.1. RGB
+        let Y = src[0].as_ref();
+        let Cb = src[1].as_ref();
+        let Cr = src[2].as_ref();
+        for y in start_y..start_y + height {
+            for x in start_x..start_x + width {
+                let s = (y * src_stride) + x;
+                let Cbtmp = Cb[s].wrapping_sub(1 << 16); // See: 3.7.2.1. RGB
+                let Crtmp = Cr[s].wrapping_sub(1 << 16); // See: 3.7.2.1. RGB
+                let green =
+                    Y[s].wrapping_sub((Cbtmp.wrapping_add(Crtmp)) >> 2); // See: 3.7.2.1. RGB
                 let red = Crtmp.wrapping_add(green); // See: 3.7.2.1. RGB
                 let blue = Cbtmp.wrapping_add(green); // See: 3.7.2.1. RGB
-                dst[0][offset + (y * stride) + x] = green as u16;
-                dst[1][offset + (y * stride) + x] = blue as u16;
-                dst[2][offset + (y * stride) + x] = red as u16;
+                dst[0].data[(y * dst[0].stride) + x] = green as u16;
+                dst[1].data[(y * dst[1].stride) + x] = blue as u16;
+                dst[2].data[(y * dst[2].stride) + x] = red as u16;
             }
         }
         if src.len() == 4 {
-            let s = &src[3][offset..];
-            let d = &mut dst[3][offset..];
-            for y in 0..height {
-                for x in 0..width {
-                    d[(y * stride) + x] = s[(y * stride) + x] as u16;
+            let s = src[3].as_ref();
+            let d = &mut dst[3];
+            for y in start_y..start_y + height {
+                for x in start_x..start_x + width {
+                    d.data[(y * d.stride) + x] =
+                        s[(y * src_stride) + x] as u16;
                 }
             }
         }
     }
 }
+
+/// Converts the rectangle like 'Rct::rct', splitting its rows into bands
+/// converted on up to 'threads' threads, since every pixel is converted on
+/// its own, e.g. for frames made of a single slice.
+#[allow(clippy::too_many_arguments)]
+pub fn rct_rows<D, S>(
+    dst: &mut [PlaneBuffer<D>],
+    src: &[Vec<S>],
+    src_stride: usize,
+    start_x: usize,
+    start_y: usize,
+    width: usize,
+    height: usize,
+    bits: usize,
+    threads: usize,
+) where
+    D: Rct<S> + Send,
+    S: Sync,
+{
+    let rows = height.div_ceil(threads.max(1)).max(1);
+    if rows >= height {
+        D::rct(dst, src, src_stride, start_x, start_y, width, height, bits);
+        return;
+    }
+
+    // Bands start at their first row, in the destination as in 'src'
+    let mut rests: Vec<(&mut [D], usize)> = dst
+        .iter_mut()
+        .map(|plane| {
+            let start = (start_y * plane.stride).min(plane.data.len());
+            (&mut plane.data[start..], plane.stride)
+        })
+        .collect();
+    thread::scope(|scope| {
+        for band_y in (0..height).step_by(rows) {
+            let band_height = rows.min(height - band_y);
+            let mut band: Vec<_> = rests
+                .iter_mut()
+                .map(|(rest, stride)| {
+                    let len = (band_height * *stride).min(rest.len());
+                    let (data, tail) = std::mem::take(rest).split_at_mut(len);
+                    *rest = tail;
+                    PlaneBuffer {
+                        data,
+                        stride: *stride,
+                    }
+                })
+                .collect();
+            let band_src: Vec<&[S]> = src
+                .iter()
+                .map(|plane| &plane[(start_y + band_y) * src_stride..])
+                .collect();
+            scope.spawn(move || {
+                D::rct(
+                    &mut band,
+                    &band_src,
+                    src_stride,
+                    start_x,
+                    0,
+                    width,
+                    band_height,
+                    bits,
+                )
+            });
+        }
+    });
+}
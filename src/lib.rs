@@ -1,14 +1,78 @@
-pub mod golombcoder;
-pub use golombcoder::*;
+#[macro_use]
+mod instrument;
+mod trace;
 
+pub mod golombcoder;
 pub mod rangecoder;
-pub use rangecoder::*;
 
+#[cfg(feature = "golomb")]
+#[deprecated(note = "use golombcoder::bitreader")]
+pub mod bitreader {
+    pub use crate::golombcoder::bitreader::*;
+}
+#[deprecated(note = "use golombcoder::golomb")]
+pub mod golomb {
+    pub use crate::golombcoder::golomb::*;
+}
+#[deprecated(note = "use rangecoder::range")]
+pub mod range {
+    pub use crate::rangecoder::range::*;
+}
+
+pub mod allocator;
+pub mod assembler;
+#[cfg(feature = "async")]
+pub mod async_decoder;
+pub mod budget;
+#[cfg(feature = "tools")]
+pub mod compare;
+pub mod compliance;
+#[cfg(feature = "tools")]
+pub mod conformance;
 pub mod constants;
+pub mod convert;
 pub mod crc32mpeg2;
+#[cfg(feature = "opencv")]
+pub mod cv;
+pub mod damage;
 pub mod decoder;
+pub mod diagnostics;
 pub mod error;
+#[cfg(feature = "ffmpeg-next")]
+pub mod ffmpeg;
+#[cfg(feature = "tools")]
+pub mod framemd5;
+pub mod geometry;
+#[cfg(feature = "wgpu")]
+pub mod gpu;
+pub mod hash;
 pub mod jpeg2000rct;
+pub mod metrics;
+#[cfg(feature = "tools")]
+pub mod nut;
+pub mod pipeline;
+pub mod plane;
 pub mod pred;
+pub mod quant_presets;
+#[cfg(feature = "tools")]
+pub mod raw;
 pub mod record;
+#[cfg(feature = "tools")]
+pub mod repair;
+pub mod sample;
+pub mod scan;
+pub use scan::scan_keyframes;
+pub mod shared;
+pub mod sink;
 pub mod slice;
+pub mod state_transition;
+pub mod stats;
+#[cfg(feature = "test-vectors")]
+pub mod test_vectors;
+pub mod threads;
+#[cfg(feature = "tools")]
+pub mod usage;
+#[cfg(feature = "v_frame")]
+pub mod vframe;
+#[cfg(feature = "tools")]
+pub mod y4m;
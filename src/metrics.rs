@@ -0,0 +1,52 @@
+//! Hooks reporting decoding metrics, e.g. to the monitoring of a service.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Receives decoding events from a Decoder.
+///
+/// Every method does nothing by default, so implementations only need to
+/// override the events they care about. Methods are called on the thread
+/// decoding the frame, so they should be cheap, e.g. update atomics or
+/// forward to a metrics registry.
+pub trait Metrics: Send + Sync {
+    /// Called after a frame of 'bytes' bytes has been decoded in 'latency'.
+    fn frame_decoded(&self, _bytes: usize, _latency: Duration) {}
+
+    /// Called after a frame of 'bytes' bytes failed to decode.
+    fn frame_failed(&self, _bytes: usize) {}
+
+    /// Called after the slice 'slice' of 'bytes' bytes has been decoded.
+    fn slice_decoded(&self, _slice: usize, _bytes: usize) {}
+
+    /// Called when the slice 'slice' fails its CRC check.
+    fn slice_crc_failed(&self, _slice: usize) {}
+}
+
+/// Optional metrics hook of a Decoder.
+#[derive(Clone, Default)]
+pub(crate) struct MetricsHook(Option<Arc<dyn Metrics>>);
+
+impl MetricsHook {
+    pub(crate) fn new(metrics: Arc<dyn Metrics>) -> Self {
+        Self(Some(metrics))
+    }
+
+    /// Calls 'f' with the hook, if any.
+    pub(crate) fn report<F: FnOnce(&dyn Metrics)>(&self, f: F) {
+        if let Some(ref metrics) = self.0 {
+            f(metrics.as_ref());
+        }
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+impl fmt::Debug for MetricsHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MetricsHook").field(&self.is_set()).finish()
+    }
+}
@@ -0,0 +1,356 @@
+//! Writing of NUT streams of raw video, as read by FFmpeg.
+//!
+//! Unlike bare planar samples, a NUT stream carries the dimensions, pixel
+//! format and timestamps of its frames, so that it can be piped into
+//! 'ffmpeg -i -' or 'ffplay -' as is. Frames are stored as little-endian
+//! planar samples under the codec tags FFmpeg uses for its raw pixel
+//! formats, e.g. 'I420' or 'Y3[11][10]' for 'yuv420p10le'.
+//!
+//! See: https://ffmpeg.org/~michael/nut.txt
+
+use std::io::{self, Write};
+
+use crate::constants::ColorSpace;
+use crate::crc32mpeg2::crc32_mpeg2;
+use crate::decoder::Frame;
+use crate::raw::RawWriter;
+
+/// Identifies a NUT stream, at its start.
+const FILE_ID: &[u8] = b"nut/multimedia container\0";
+
+/// Startcodes of the main header, stream header and syncpoint packets.
+const MAIN_STARTCODE: u64 = 0x4E4D_7A56_1F5F_04AD;
+const STREAM_STARTCODE: u64 = 0x4E53_1140_5BF2_F9DB;
+const SYNCPOINT_STARTCODE: u64 = 0x4E4B_E4AD_EECA_4569;
+
+/// Frame flags, see: frame_flags.
+const FLAG_KEY: u64 = 1;
+const FLAG_CODED_PTS: u64 = 8;
+const FLAG_STREAM_ID: u64 = 16;
+const FLAG_SIZE_MSB: u64 = 32;
+const FLAG_CHECKSUM: u64 = 64;
+const FLAG_INVALID: u64 = 8192;
+
+/// Version of the syntax, the one written by FFmpeg.
+const VERSION: u64 = 3;
+
+/// Maximum distance between two startcodes, except within a syncpoint
+/// followed by a single frame.
+const MAX_DISTANCE: u64 = 65536;
+
+/// Number of pts bits sent when they are coded relative to the last ones.
+/// Frames always code their full pts here.
+const MSB_PTS_SHIFT: u32 = 7;
+
+/// Appends a 'v', an unsigned integer, 7 bits per byte.
+fn put_v(out: &mut Vec<u8>, value: u64) {
+    let mut len = 1;
+    while len < 10 && value >> (7 * len) != 0 {
+        len += 1;
+    }
+    for i in (1..len).rev() {
+        out.push(0x80 | ((value >> (7 * i)) & 0x7F) as u8);
+    }
+    out.push((value & 0x7F) as u8);
+}
+
+/// Appends an 's', a signed integer folded into a 'v'.
+fn put_s(out: &mut Vec<u8>, value: i64) {
+    if value <= 0 {
+        put_v(out, value.unsigned_abs() * 2);
+    } else {
+        put_v(out, value as u64 * 2 - 1);
+    }
+}
+
+/// Appends a 'vb', a 'v' length followed by as many bytes.
+fn put_vb(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_v(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Appends the checksum of 'bytes', as FFmpeg stores it.
+fn put_checksum(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&crc32_mpeg2(bytes).to_le_bytes());
+}
+
+/// Returns a packet made of a startcode, its size and a checksummed
+/// payload.
+fn packet(startcode: u64, payload: &[u8]) -> Vec<u8> {
+    let mut packet = startcode.to_be_bytes().to_vec();
+    let forward_ptr = payload.len() as u64 + 4;
+    put_v(&mut packet, forward_ptr);
+    if forward_ptr > 4096 {
+        let header = packet.clone();
+        put_checksum(&mut packet, &header);
+    }
+    packet.extend_from_slice(payload);
+    put_checksum(&mut packet, payload);
+    packet
+}
+
+/// Returns the FFmpeg codec tag of the raw pixel format of 'frame', or
+/// None when FFmpeg has no such planar format.
+fn codec_tag(frame: &Frame) -> Option<[u8; 4]> {
+    let bits = frame.bit_depth;
+    let alpha = frame.has_alpha;
+    let planes = if alpha { b'4' } else { b'3' };
+    match frame.color_space {
+        ColorSpace::Rgb => match (alpha, bits) {
+            (false, 8 | 9 | 10 | 12 | 14 | 16) | (true, 8 | 10 | 12 | 16) => {
+                Some([b'G', planes, 0, bits])
+            }
+            _ => None,
+        },
+        // FFmpeg only has packed gray formats with alpha
+        ColorSpace::YCbCr if !frame.has_chroma => match (alpha, bits) {
+            (false, 8) => Some(*b"Y800"),
+            (false, 9 | 10 | 12 | 14 | 16) => Some([b'Y', b'1', 0, bits]),
+            _ => None,
+        },
+        ColorSpace::YCbCr => {
+            let subsampling =
+                (frame.chroma_subsample_h, frame.chroma_subsample_v);
+            if bits == 8 && !alpha {
+                return match subsampling {
+                    (1, 1) => Some(*b"I420"),
+                    (1, 0) => Some(*b"Y42B"),
+                    (0, 0) => Some(*b"444P"),
+                    (2, 0) => Some(*b"Y41B"),
+                    (2, 2) => Some(*b"YUV9"),
+                    _ => None,
+                };
+            }
+            let subsampling = match subsampling {
+                (1, 1) => 11,
+                (1, 0) => 10,
+                (0, 0) => 0,
+                _ => return None,
+            };
+            match (alpha, bits) {
+                (false, 9 | 10 | 12 | 14 | 16) | (true, 8 | 9 | 10 | 16) => {
+                    Some([b'Y', planes, subsampling, bits])
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Returns the greatest common divisor of 'a' and 'b'.
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// The properties of the video stream of a NUT stream, stored in its
+/// headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Width of the frames, in pixels.
+    pub width: u32,
+    /// Height of the frames, in pixels.
+    pub height: u32,
+    /// Frame rate, as a numerator and a denominator.
+    pub framerate: (u32, u32),
+    /// FFmpeg codec tag of the raw pixel format, e.g. 'I420'.
+    pub codec_tag: [u8; 4],
+}
+
+impl Header {
+    /// Returns the header of a stream of frames like 'frame', or None when
+    /// FFmpeg has no raw pixel format for them.
+    pub fn from_frame(frame: &Frame, framerate: (u32, u32)) -> Option<Self> {
+        Some(Self {
+            width: frame.width,
+            height: frame.height,
+            framerate,
+            codec_tag: codec_tag(frame)?,
+        })
+    }
+
+    /// Returns the time base of the frames, the inverse of the frame rate
+    /// in lowest terms.
+    pub fn time_base(&self) -> (u32, u32) {
+        let (num, den) = self.framerate;
+        let divisor = gcd(num, den).max(1);
+        (den / divisor, num / divisor)
+    }
+
+    /// Returns the file identifier, main header and stream header.
+    fn to_bytes(self) -> Vec<u8> {
+        let (tb_num, tb_den) = self.time_base();
+
+        let mut main = Vec::new();
+        put_v(&mut main, VERSION);
+        put_v(&mut main, 1); // stream_count
+        put_v(&mut main, MAX_DISTANCE);
+        put_v(&mut main, 1); // time_base_count
+        put_v(&mut main, tb_num.into());
+        put_v(&mut main, tb_den.into());
+        // Frame code 0 codes every field of a keyframe, the other ones
+        // are invalid, 'N' being reserved for startcodes
+        for &(flags, count) in &[
+            (
+                FLAG_KEY
+                    | FLAG_STREAM_ID
+                    | FLAG_CODED_PTS
+                    | FLAG_SIZE_MSB
+                    | FLAG_CHECKSUM,
+                1,
+            ),
+            (FLAG_INVALID, 254),
+        ] {
+            put_v(&mut main, flags);
+            put_v(&mut main, 6); // fields
+            put_s(&mut main, 0); // pts_delta
+            put_v(&mut main, 1); // data_size_mul
+            put_v(&mut main, 0); // stream_id
+            put_v(&mut main, 0); // data_size_lsb
+            put_v(&mut main, 0); // reserved_count
+            put_v(&mut main, count);
+        }
+
+        let mut stream = Vec::new();
+        put_v(&mut stream, 0); // stream_id
+        put_v(&mut stream, 0); // stream_class, video
+        put_vb(&mut stream, &self.codec_tag);
+        put_v(&mut stream, 0); // time_base_id
+        put_v(&mut stream, MSB_PTS_SHIFT.into());
+        // About a second
+        put_v(&mut stream, (tb_den / tb_num).max(1).into());
+        put_v(&mut stream, 0); // decode_delay
+        put_v(&mut stream, 0); // stream_flags
+        put_vb(&mut stream, &[]); // codec_specific_data
+        put_v(&mut stream, self.width.into());
+        put_v(&mut stream, self.height.into());
+        put_v(&mut stream, 0); // sample_width, unknown
+        put_v(&mut stream, 0); // sample_height
+        put_v(&mut stream, 0); // colorspace_type, unknown
+
+        let mut bytes = FILE_ID.to_vec();
+        bytes.extend(packet(MAIN_STARTCODE, &main));
+        bytes.extend(packet(STREAM_STARTCODE, &stream));
+        bytes
+    }
+}
+
+/// Writes frames as a NUT stream of raw video.
+///
+/// The headers are written along with the first frame, whose properties
+/// all the following frames must share. Every frame is preceded by a
+/// syncpoint, so that readers may start anywhere, and timestamped with its
+/// index at the frame rate.
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+    framerate: (u32, u32),
+    header: Option<Header>,
+    /// Samples of the frame being written.
+    samples: RawWriter<Vec<u8>>,
+    /// Number of bytes written so far.
+    position: u64,
+    /// Position of the last syncpoint.
+    last_syncpoint: Option<u64>,
+    /// Timestamp of the next frame.
+    pts: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a writer outputting to 'writer', at 25 frames per second.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            framerate: (25, 1),
+            header: None,
+            samples: RawWriter::new(Vec::new()),
+            position: 0,
+            last_syncpoint: None,
+            pts: 0,
+        }
+    }
+
+    /// Sets the frame rate written in the header, as a numerator and a
+    /// denominator.
+    pub fn set_framerate(&mut self, num: u32, den: u32) {
+        self.framerate = (num, den);
+    }
+
+    /// Returns the header of the stream, once the first frame has been
+    /// written.
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Writes a frame, preceded by the headers for the first one.
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let header =
+            Header::from_frame(frame, self.framerate).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "FFmpeg has no raw pixel format for this stream",
+                )
+            })?;
+        match self.header {
+            Some(ref first) if *first != header => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the frame differs from the previous ones",
+                ));
+            }
+            Some(_) => {}
+            None => {
+                self.write_all(&header.to_bytes())?;
+                self.header = Some(header);
+            }
+        }
+
+        self.samples.get_mut().clear();
+        self.samples.write_frame(frame)?;
+
+        // The back pointer leads to the previous syncpoint, which is
+        // followed by a keyframe as every frame is one
+        let mut syncpoint = Vec::new();
+        put_v(&mut syncpoint, self.pts); // global_key_pts
+        let back_ptr = self
+            .last_syncpoint
+            .map_or(0, |last| (self.position - last) / 16);
+        put_v(&mut syncpoint, back_ptr);
+        self.last_syncpoint = Some(self.position);
+        self.write_all(&packet(SYNCPOINT_STARTCODE, &syncpoint))?;
+
+        let mut frame_header = vec![0]; // frame_code
+        put_v(&mut frame_header, 0); // stream_id
+        put_v(&mut frame_header, self.pts + (1 << MSB_PTS_SHIFT));
+        put_v(&mut frame_header, self.samples.get_mut().len() as u64);
+        let checksummed = frame_header.clone();
+        put_checksum(&mut frame_header, &checksummed);
+        self.write_all(&frame_header)?;
+
+        let samples = std::mem::take(self.samples.get_mut());
+        let written = self.write_all(&samples);
+        *self.samples.get_mut() = samples;
+        self.pts += 1;
+        written
+    }
+
+    /// Writes bytes, keeping track of the position in the stream.
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
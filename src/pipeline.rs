@@ -0,0 +1,278 @@
+//! Parallel decoding of independent runs of frames.
+//!
+//! Only keyframes reset the decoder states, so the frames from a keyframe
+//! up to the next one form a run which can be decoded independently of the
+//! other runs. Runs are decoded on clones of a decoder, each on its own
+//! thread, and their frames are returned in the original order. FFV1 is
+//! intra-only, so this is also the presentation order.
+//!
+//! Streams with a single slice per frame and few keyframes can use a
+//! 'PipelinedDecoder' instead, which overlaps the cheap front-end work of a
+//! frame with the decoding of the previous one.
+//!
+//! Both take packets from any iterator, e.g. a 'PacketQueue' reading them
+//! ahead of the decoder on another thread.
+
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+use crate::decoder::{Decoder, Frame, FrameMetadata};
+use crate::error::{Error, Result};
+use crate::slice::FrontEnd;
+
+/// A packet and its keyframe flag, as provided by the container.
+#[derive(Debug, Clone, Default)]
+pub struct Packet {
+    /// Content of the packet.
+    pub data: Vec<u8>,
+    /// Whether the packet is a keyframe.
+    pub keyframe: bool,
+    /// Metadata returned on the decoded frame.
+    pub metadata: FrameMetadata,
+}
+
+/// Bounded queue of packets read ahead of the decoder on a reader thread,
+/// e.g. from a demuxer, so that reading latency doesn't stall decoding.
+///
+/// The reader thread stops once the queue is dropped. If it panics, the
+/// queue ends after the packets it has read.
+#[derive(Debug)]
+pub struct PacketQueue<T = Packet> {
+    packets: Receiver<T>,
+}
+
+impl<T: Send + 'static> PacketQueue<T> {
+    /// Starts reading the packets returned by 'open' on a reader thread,
+    /// holding up to 'capacity' of them ahead of the consumer.
+    ///
+    /// 'open' runs on the reader thread, so that the source of packets,
+    /// e.g. a demuxer, doesn't have to be sent to another thread.
+    pub fn new<F, I>(capacity: usize, open: F) -> Self
+    where
+        F: FnOnce() -> I + Send + 'static,
+        I: IntoIterator<Item = T>,
+    {
+        let (packet_tx, packets) = mpsc::sync_channel(capacity.max(1));
+        thread::spawn(move || {
+            for packet in open() {
+                if packet_tx.send(packet).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { packets }
+    }
+}
+
+impl<T> Iterator for PacketQueue<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.packets.recv().ok()
+    }
+}
+
+/// Decodes runs of frames in parallel.
+#[derive(Debug)]
+pub struct Pipeline {
+    decoder: Decoder,
+    threads: usize,
+}
+
+impl Pipeline {
+    /// Creates a pipeline decoding up to 'threads' runs at the same time,
+    /// on clones of 'decoder'.
+    ///
+    /// Packets before the first keyframe are decoded from the states of
+    /// 'decoder'.
+    pub fn new(decoder: Decoder, threads: usize) -> Self {
+        Self {
+            decoder,
+            threads: threads.max(1),
+        }
+    }
+
+    /// Returns the frames decoded from 'packets', in the same order.
+    pub fn decode<I>(self, packets: I) -> Frames<I::IntoIter>
+    where
+        I: IntoIterator<Item = Packet>,
+    {
+        Frames {
+            packets: packets.into_iter().peekable(),
+            decoder: self.decoder,
+            threads: self.threads,
+            runs: VecDeque::new(),
+            frames: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Iterator over decoded frames, see 'Pipeline::decode'.
+///
+/// If a decoding thread panics, the frames of its run are replaced by a
+/// single error.
+pub struct Frames<I: Iterator<Item = Packet>> {
+    packets: Peekable<I>,
+    decoder: Decoder,
+    threads: usize,
+    runs: VecDeque<JoinHandle<Vec<Result<Frame>>>>,
+    frames: vec::IntoIter<Result<Frame>>,
+}
+
+impl<I: Iterator<Item = Packet>> Frames<I> {
+    /// Returns the packets up to the next keyframe.
+    fn next_run(&mut self) -> Vec<Packet> {
+        let mut run = Vec::new();
+        if let Some(packet) = self.packets.next() {
+            run.push(packet);
+        }
+        while let Some(packet) = self.packets.peek() {
+            if packet.keyframe {
+                break;
+            }
+            run.extend(self.packets.next());
+        }
+        run
+    }
+
+    /// Starts decoding runs until 'threads' of them are in flight.
+    fn fill(&mut self) {
+        while self.runs.len() < self.threads {
+            let run = self.next_run();
+            if run.is_empty() {
+                break;
+            }
+            let mut decoder = self.decoder.clone();
+            self.runs.push_back(thread::spawn(move || {
+                run.into_iter()
+                    .map(|packet| {
+                        decoder
+                            .decode_frame_with(&packet.data, packet.metadata)
+                    })
+                    .collect()
+            }));
+        }
+    }
+}
+
+impl<I: Iterator<Item = Packet>> Iterator for Frames<I> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.frames.next() {
+                return Some(frame);
+            }
+            self.fill();
+            let run = self.runs.pop_front()?;
+            self.frames = run
+                .join()
+                .unwrap_or_else(|_| {
+                    vec![Err(Error::ResourceError(
+                        "decoding thread panicked".to_owned(),
+                    ))]
+                })
+                .into_iter();
+        }
+    }
+}
+
+/// Decodes frames in a two-stage pipeline: while a frame is decoded on the
+/// calling thread, the keyframe flag and the slice footers of the next one
+/// are read and the CRCs of its slices are checked on a second thread.
+///
+/// Frames are decoded in order, on a single decoder, so this helps streams
+/// which can neither use slice threading nor a 'Pipeline' of runs.
+#[derive(Debug)]
+pub struct PipelinedDecoder {
+    decoder: Decoder,
+}
+
+impl PipelinedDecoder {
+    /// Creates a pipelined decoder decoding frames with 'decoder'.
+    pub fn new(decoder: Decoder) -> Self {
+        Self { decoder }
+    }
+
+    /// Returns the frames decoded from 'packets', in the same order.
+    pub fn decode<I>(self, packets: I) -> PipelinedFrames<I::IntoIter>
+    where
+        I: IntoIterator<Item = Packet>,
+    {
+        let ec = self.decoder.config_record().ec != 0;
+        let (packet_tx, packet_rx) = mpsc::channel::<Packet>();
+        let (front_end_tx, front_end_rx) = mpsc::channel();
+        thread::spawn(move || {
+            for packet in packet_rx {
+                let front_end = FrontEnd::read(&packet.data, ec);
+                if front_end_tx.send((packet, front_end)).is_err() {
+                    break;
+                }
+            }
+        });
+        PipelinedFrames {
+            packets: packets.into_iter(),
+            decoder: self.decoder,
+            packet_tx,
+            front_end_rx,
+            in_flight: 0,
+        }
+    }
+}
+
+/// Iterator over decoded frames, see 'PipelinedDecoder::decode'.
+///
+/// If the front-end thread panics, the next frame is replaced by an error
+/// and no frame follows.
+pub struct PipelinedFrames<I: Iterator<Item = Packet>> {
+    packets: I,
+    decoder: Decoder,
+    packet_tx: Sender<Packet>,
+    front_end_rx: Receiver<(Packet, FrontEnd)>,
+    in_flight: usize,
+}
+
+impl<I: Iterator<Item = Packet>> PipelinedFrames<I> {
+    /// Hands the next packet to the front-end thread, if any.
+    fn send_next(&mut self) {
+        if let Some(packet) = self.packets.next() {
+            if self.packet_tx.send(packet).is_ok() {
+                self.in_flight += 1;
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = Packet>> Iterator for PipelinedFrames<I> {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.in_flight == 0 {
+            self.send_next();
+        }
+        if self.in_flight == 0 {
+            return None;
+        }
+        self.in_flight -= 1;
+        let (packet, front_end) = match self.front_end_rx.recv() {
+            Ok(received) => received,
+            // Packets can't be sent to the thread anymore either
+            Err(_) => {
+                return Some(Err(Error::ResourceError(
+                    "front-end thread panicked".to_owned(),
+                )))
+            }
+        };
+
+        // The front-end of the next frame runs while this one is decoded
+        self.send_next();
+        Some(self.decoder.decode_frame_from(
+            &packet.data,
+            packet.metadata,
+            front_end,
+        ))
+    }
+}
@@ -0,0 +1,96 @@
+//! Read-only views of the planes of a decoded frame.
+
+use crate::sample::Sample;
+
+/// A rectangle of samples of a plane, whose rows are 'stride' samples
+/// apart.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaneView<'a, S: Sample> {
+    data: &'a [S],
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+impl<'a, S: Sample> PlaneView<'a, S> {
+    /// Creates a view of 'width'x'height' samples of 'data', starting at
+    /// its first sample, whose rows are 'stride' samples apart.
+    ///
+    /// Returns None when 'data' is too short to hold the view.
+    pub fn new(
+        data: &'a [S],
+        width: usize,
+        height: usize,
+        stride: usize,
+    ) -> Option<Self> {
+        let needed = match height {
+            0 => 0,
+            _ => stride.checked_mul(height - 1)?.checked_add(width)?,
+        };
+        if stride < width || data.len() < needed {
+            return None;
+        }
+        Some(Self {
+            data,
+            width,
+            height,
+            stride,
+        })
+    }
+
+    /// Returns the number of samples of a row.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of rows.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the distance between the start of two consecutive rows, in
+    /// samples.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the samples of row 'y'.
+    pub fn row(&self, y: usize) -> &'a [S] {
+        assert!(y < self.height, "row {} out of {}", y, self.height);
+        let start = y * self.stride;
+        &self.data[start..start + self.width]
+    }
+
+    /// Returns an iterator over the rows of the view.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [S]> {
+        let view = *self;
+        (0..self.height).map(move |y| view.row(y))
+    }
+
+    /// Returns a view of the 'width'x'height' samples starting at column
+    /// 'x' of row 'y', or None when they do not lie within this view.
+    pub fn crop(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<Self> {
+        if x.checked_add(width)? > self.width
+            || y.checked_add(height)? > self.height
+        {
+            return None;
+        }
+        let start = if width == 0 || height == 0 {
+            0
+        } else {
+            y * self.stride + x
+        };
+        Some(Self {
+            data: &self.data[start..],
+            width,
+            height,
+            stride: self.stride,
+        })
+    }
+}
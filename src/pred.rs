@@ -1,5 +1,55 @@
 #![allow(non_snake_case)]
 
+use crate::sample::Sample;
+
+/// A plane of samples, whose rows are 'stride' samples apart.
+#[derive(Debug, Clone, Copy)]
+pub struct PlaneSlice<'a, S: Sample> {
+    data: &'a [S],
+    width: usize,
+    stride: usize,
+}
+
+impl<'a, S: Sample> PlaneSlice<'a, S> {
+    /// Creates a plane of 'width' samples per row from 'data', whose rows
+    /// are 'stride' samples apart.
+    pub fn new(data: &'a [S], width: usize, stride: usize) -> Self {
+        Self {
+            data,
+            width,
+            stride,
+        }
+    }
+
+    /// Returns the number of samples of a row.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the distance between the start of two consecutive rows, in
+    /// samples.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the sample at column 'x' of row 'y'.
+    #[inline(always)]
+    pub fn get(&self, x: usize, y: usize) -> S {
+        self.data[y * self.stride + x]
+    }
+}
+
+/// The samples surrounding the current sample, named as in 3.2. Samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Neighbours<S: Sample> {
+    pub T: S,
+    pub L: S,
+    pub t: S,
+    pub l: S,
+    pub tr: S,
+    pub tl: S,
+}
+
 /// Calculates all the neighbouring pixel values given:
 ///
 /// +---+---+---+---+
@@ -30,87 +80,119 @@
 ///
 /// See: * 3.1. Border
 ///      * 3.2. Samples
-pub fn derive_borders<T: num_traits::AsPrimitive<usize>>(
-    plane: &[T],
+pub fn derive_borders<S: Sample>(
+    plane: &PlaneSlice<S>,
     x: usize,
     y: usize,
-    width: usize,
-    _height: usize,
-    stride: usize,
-) -> (usize, usize, usize, usize, usize, usize) {
-    let pos = y * stride + x;
-
+) -> Neighbours<S> {
     // This is really slow and stupid but matches the spec exactly.
     // Each of the neighbouring values has been left entirely separate,
     // and none skipped, even if they could be.
     //
     // Please never implement an actual decoder this way.
+    let zero = S::default();
 
     // T
-    let T = if y > 1 {
-        plane[pos - (2 * stride)].as_()
-    } else {
-        0
-    };
+    let T = if y > 1 { plane.get(x, y - 2) } else { zero };
 
     // L
     let L = if y > 0 && x == 1 {
-        plane[pos - stride - 1].as_()
+        plane.get(0, y - 1)
     } else if x > 1 {
-        plane[pos - 2].as_()
+        plane.get(x - 2, y)
     } else {
-        0
+        zero
     };
 
     // t
-    let t = if y > 0 { plane[pos - stride].as_() } else { 0 };
+    let t = if y > 0 { plane.get(x, y - 1) } else { zero };
 
     // l
     let l = if x > 0 {
-        plane[pos - 1].as_()
+        plane.get(x - 1, y)
     } else if y > 0 {
-        plane[pos - stride].as_()
+        plane.get(x, y - 1)
     } else {
-        0
+        zero
     };
 
     // tl
     let tl = if y > 1 && x == 0 {
-        plane[pos - (2 * stride)].as_()
+        plane.get(x, y - 2)
     } else if y > 0 && x > 0 {
-        plane[pos - stride - 1].as_()
+        plane.get(x - 1, y - 1)
     } else {
-        0
+        zero
     };
 
     // tr
     let tr = if y > 0 {
-        plane[pos - stride + (width - 1 - x).min(1)].as_()
+        plane.get(x + (plane.width - 1 - x).min(1), y - 1)
     } else {
-        0
+        zero
+    };
+
+    Neighbours { T, L, t, l, tr, tl }
+}
+
+/// Derives the neighbours of every sample of a row below at least two
+/// other rows, 'top' and 'top2' above it, calling 'sample' with them to
+/// get each sample in turn.
+///
+/// This gives the same neighbours as 'derive_borders', but slides them
+/// along the row instead of indexing the plane for every sample, so that
+/// the rows are only bounds checked once.
+#[inline(always)]
+pub fn derive_row<S: Sample>(
+    row: &mut [S],
+    top: &[S],
+    top2: &[S],
+    mut sample: impl FnMut(&Neighbours<S>) -> S,
+) {
+    let width = row.len();
+    if width == 0 {
+        return;
+    }
+    let (top, top2) = (&top[..width], &top2[..width]);
+
+    // 'l' and 'tl' of the first sample are the samples above it
+    let mut n = Neighbours {
+        t: top[0],
+        l: top[0],
+        tl: top2[0],
+        ..Neighbours::default()
     };
+    for x in 0..width {
+        n.T = top2[x];
+        n.tr = top[(x + 1).min(width - 1)];
+        let X = sample(&n);
+        row[x] = X;
+        n.L = n.l;
+        n.l = X;
+        n.tl = n.t;
+        n.t = n.tr;
+    }
+}
 
-    (T, L, t, l, tr, tl)
+/// Returns the index into a quantization table of the difference 'a - b'.
+#[inline(always)]
+fn quant_index<S: Sample>(a: S, b: S) -> usize {
+    (a.to_u32().wrapping_sub(b.to_u32()) & 255) as usize
 }
 
 /// Given the neighbouring pixel values, calculate the context.
 ///
 /// See: * 3.4. Context
 ///      * 3.5. Quantization Table Sets
-pub fn get_context(
+pub fn get_context<S: Sample>(
     quant_tables: &[[i16; 256]; 5],
-    T: usize,
-    L: usize,
-    t: usize,
-    l: usize,
-    tr: usize,
-    tl: usize,
+    n: &Neighbours<S>,
 ) -> i32 {
-    quant_tables[0][l.wrapping_sub(tl) & 255] as i32
-        + quant_tables[1][tl.wrapping_sub(t) & 255] as i32
-        + quant_tables[2][t.wrapping_sub(tr) & 255] as i32
-        + quant_tables[3][L.wrapping_sub(l) & 255] as i32
-        + quant_tables[4][T.wrapping_sub(t) & 255] as i32
+    quant_tables[0][quant_index(n.l, n.tl)] as i32
+        + quant_tables[1][quant_index(n.tl, n.t)] as i32
+        + quant_tables[2][quant_index(n.t, n.tr)] as i32
+        + quant_tables[3][quant_index(n.L, n.l)] as i32
+        + quant_tables[4][quant_index(n.T, n.t)] as i32
 }
 
 /// Calculate the median value of 3 numbers
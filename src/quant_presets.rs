@@ -0,0 +1,144 @@
+//! Quantization table sets commonly found in FFV1 streams.
+//!
+//! FFmpeg codes every stream with two sets: set 0 uses the 3 context
+//! inputs of the small context model and set 1 the 5 inputs of the large
+//! one, selected by its 'context' option. Their tables are built from
+//! break points here, as they are stored in a configuration record.
+//!
+//! See: 4.9. Quantization Table Set
+
+use crate::constants::MAX_CONTEXT_INPUTS;
+
+/// A quantization table, mapping the low 8 bits of a sample difference to
+/// its contribution to the context index.
+pub type QuantTable = [i16; 256];
+
+/// A quantization table set, one table per context input.
+pub type QuantTableSet = [QuantTable; MAX_CONTEXT_INPUTS];
+
+/// Break points of the 11-level table of FFmpeg for 8-bit streams.
+pub const QUANT11_BREAK_POINTS: [usize; 5] = [1, 2, 5, 12, 35];
+
+/// Break points of the 5-level table of FFmpeg for 8-bit streams.
+pub const QUANT5_BREAK_POINTS: [usize; 2] = [1, 4];
+
+/// Break points of the 9-level table of FFmpeg for streams wider than 8
+/// bits.
+pub const QUANT9_10BIT_BREAK_POINTS: [usize; 4] = [5, 13, 27, 56];
+
+/// Break points of the 5-level table of FFmpeg for streams wider than 8
+/// bits.
+pub const QUANT5_10BIT_BREAK_POINTS: [usize; 2] = [11, 50];
+
+/// The 11-level table of FFmpeg for 8-bit streams.
+pub const QUANT11: QuantTable = symmetric_table(&QUANT11_BREAK_POINTS);
+
+/// The 5-level table of FFmpeg for 8-bit streams.
+pub const QUANT5: QuantTable = symmetric_table(&QUANT5_BREAK_POINTS);
+
+/// The 9-level table of FFmpeg for streams wider than 8 bits.
+pub const QUANT9_10BIT: QuantTable =
+    symmetric_table(&QUANT9_10BIT_BREAK_POINTS);
+
+/// The 5-level table of FFmpeg for streams wider than 8 bits.
+pub const QUANT5_10BIT: QuantTable =
+    symmetric_table(&QUANT5_10BIT_BREAK_POINTS);
+
+/// Returns the table quantizing the differences from 'break_points[i]'
+/// up to the next break point to 'i + 1', and the smaller ones to 0.
+///
+/// Break points must be increasing and lie in '1..128'. Negative
+/// differences are quantized to the opposite of the positive ones, with
+/// the mirroring of a configuration record.
+///
+/// See: 4.9.1. quant_tables
+pub const fn symmetric_table(break_points: &[usize]) -> QuantTable {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < break_points.len() {
+        assert!(break_points[i] > 0 && break_points[i] < 128);
+        assert!(i == 0 || break_points[i] > break_points[i - 1]);
+        let mut k = break_points[i];
+        while k < 128 {
+            table[k] = i as i16 + 1;
+            k += 1;
+        }
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 128 {
+        table[256 - k] = -table[k];
+        k += 1;
+    }
+    table[128] = -table[127];
+    table
+}
+
+/// Returns the number of levels of a symmetric table, i.e. the number of
+/// distinct values it quantizes to.
+fn levels(table: &QuantTable) -> i32 {
+    2 * table[127] as i32 + 1
+}
+
+/// Returns the set combining the context inputs quantized by 'tables',
+/// scaling each table by the number of levels of the preceding ones.
+///
+/// The inputs left over are not used. Panics when more than 5 tables are
+/// given.
+pub fn table_set(tables: &[QuantTable]) -> QuantTableSet {
+    assert!(
+        tables.len() <= MAX_CONTEXT_INPUTS,
+        "too many context inputs"
+    );
+    let mut set = [[0; 256]; MAX_CONTEXT_INPUTS];
+    let mut scale = 1;
+    for (scaled, table) in set.iter_mut().zip(tables) {
+        for (scaled, &value) in scaled.iter_mut().zip(table.iter()) {
+            *scaled = (scale * value as i32) as i16;
+        }
+        scale *= levels(table);
+    }
+    set
+}
+
+/// Returns the number of contexts of a set.
+///
+/// See: 4.1.15. context_count
+pub fn context_count(set: &QuantTableSet) -> i32 {
+    // A table scaled by the preceding levels multiplies them by its own
+    let scale = set
+        .iter()
+        .fold(1, |scale, table| scale + 2 * table[127] as i32);
+    (scale + 1) / 2
+}
+
+/// Returns the sets of FFmpeg for 8-bit streams.
+pub fn ffmpeg_8bit() -> [QuantTableSet; 2] {
+    [
+        table_set(&[QUANT11, QUANT11, QUANT11]),
+        table_set(&[QUANT11, QUANT11, QUANT5, QUANT5, QUANT5]),
+    ]
+}
+
+/// Returns the sets of FFmpeg for streams wider than 8 bits.
+pub fn ffmpeg_high_bit_depth() -> [QuantTableSet; 2] {
+    [
+        table_set(&[QUANT9_10BIT, QUANT9_10BIT, QUANT9_10BIT]),
+        table_set(&[
+            QUANT9_10BIT,
+            QUANT9_10BIT,
+            QUANT5_10BIT,
+            QUANT5_10BIT,
+            QUANT5_10BIT,
+        ]),
+    ]
+}
+
+/// Returns the sets of FFmpeg for 'bits_per_raw_sample'.
+pub fn ffmpeg_default(bits_per_raw_sample: u8) -> [QuantTableSet; 2] {
+    if bits_per_raw_sample <= 8 {
+        ffmpeg_8bit()
+    } else {
+        ffmpeg_high_bit_depth()
+    }
+}
@@ -0,0 +1,99 @@
+//! Range encoder mirroring the decoder of 'range', e.g. to code
+//! configuration records and the synthetic test vectors.
+
+use crate::rangecoder::tables::DEFAULT_STATE_TRANSITION;
+use crate::state_transition::zero_states;
+
+/// 3.8.1. Range Coding Mode, mirrored for encoding.
+pub(crate) struct RangeEncoder {
+    out: Vec<u8>,
+    low: u32,
+    range: u32,
+    outstanding_count: usize,
+    outstanding_byte: Option<u8>,
+    one_state: [u8; 256],
+    zero_state: [u8; 256],
+}
+
+impl RangeEncoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            low: 0,
+            range: 0xFF00,
+            outstanding_count: 0,
+            outstanding_byte: None,
+            one_state: DEFAULT_STATE_TRANSITION,
+            zero_state: zero_states(&DEFAULT_STATE_TRANSITION),
+        }
+    }
+
+    fn renorm(&mut self) {
+        while self.range < 0x100 {
+            match self.outstanding_byte {
+                None => self.outstanding_byte = Some((self.low >> 8) as u8),
+                Some(byte) if self.low <= 0xFF00 => {
+                    self.out.push(byte);
+                    let count = self.outstanding_count;
+                    self.out.resize(self.out.len() + count, 0xFF);
+                    self.outstanding_count = 0;
+                    self.outstanding_byte = Some((self.low >> 8) as u8);
+                }
+                Some(byte) if self.low >= 0x10000 => {
+                    self.out.push(byte + 1);
+                    let count = self.outstanding_count;
+                    self.out.resize(self.out.len() + count, 0);
+                    self.outstanding_count = 0;
+                    self.outstanding_byte = Some((self.low >> 8) as u8);
+                }
+                Some(_) => self.outstanding_count += 1,
+            }
+            self.low = (self.low & 0xFF) << 8;
+            self.range <<= 8;
+        }
+    }
+
+    pub(crate) fn put(&mut self, state: &mut u8, bit: bool) {
+        let range1 = (self.range * *state as u32) >> 8;
+        if bit {
+            self.low += self.range - range1;
+            self.range = range1;
+            *state = self.one_state[*state as usize];
+        } else {
+            self.range -= range1;
+            *state = self.zero_state[*state as usize];
+        }
+        self.renorm();
+    }
+
+    /// 3.8.1.2. Range Non Binary Values
+    pub(crate) fn symbol(&mut self, state: &mut [u8], v: i32, signed: bool) {
+        if v == 0 {
+            self.put(&mut state[0], true);
+            return;
+        }
+        let a = v.unsigned_abs();
+        let e = 31 - a.leading_zeros() as usize;
+        self.put(&mut state[0], false);
+        for i in 0..e {
+            self.put(&mut state[1 + i.min(9)], true);
+        }
+        self.put(&mut state[1 + e.min(9)], false);
+        for i in (0..e).rev() {
+            self.put(&mut state[22 + i.min(9)], (a >> i) & 1 == 1);
+        }
+        if signed {
+            self.put(&mut state[11 + e.min(10)], v < 0);
+        }
+    }
+
+    /// 3.8.1.1.1. Termination
+    pub(crate) fn terminate(mut self) -> Vec<u8> {
+        self.range = 0xFF;
+        self.low += 0xFF;
+        self.renorm();
+        self.range = 0xFF;
+        self.renorm();
+        self.out
+    }
+}
@@ -1,2 +1,3 @@
+pub(crate) mod encoder;
 pub mod range;
 pub mod tables;
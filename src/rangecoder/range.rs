@@ -5,6 +5,7 @@
 //! https://tools.ietf.org/id/draft-ietf-cellar-ffv1-17
 
 use crate::rangecoder::tables::DEFAULT_STATE_TRANSITION;
+use crate::state_transition::zero_states;
 
 /// RangeCoder is an instance of a range coder, as defined in:
 ///     Martin, G. Nigel N., "Range encoding: an algorithm for
@@ -19,23 +20,27 @@ pub struct RangeCoder<'a> {
     cur_byte: i32,
     zero_state: [u8; 256],
     one_state: [u8; 256],
+    invalid_symbol: bool,
 }
 
 impl<'a> RangeCoder<'a> {
     /// Creates a new range coder instance.
     ///
     /// See: 3.8.1. Range Coding Mode
+    ///
+    /// Bytes past the end of 'buf' are read as 0.
     pub fn new(buf: &'a [u8]) -> Self {
         // Figure 15.
         let mut pos = 2;
         // Figure 14.
-        let mut low = (buf[0] as u16) << 8 | buf[1] as u16;
+        let byte = |i: usize| buf.get(i).copied().unwrap_or(0) as u16;
+        let mut low = byte(0) << 8 | byte(1);
         // Figure 13.
         let rng = 0xFF00;
 
         if low >= rng {
             low = rng;
-            pos = buf.len() - 1;
+            pos = buf.len().saturating_sub(1);
         }
 
         let mut coder = Self {
@@ -46,6 +51,7 @@ impl<'a> RangeCoder<'a> {
             cur_byte: -1,
             zero_state: [0; 256],
             one_state: [0; 256],
+            invalid_symbol: false,
         };
 
         // 3.8.1.3. Initial Values for the Context Model
@@ -117,11 +123,12 @@ impl<'a> RangeCoder<'a> {
         while self.get(&mut state[1 + e.min(9)]) {
             e += 1;
             if e > 31 {
-                panic!("WTF range coder!");
+                self.invalid_symbol = true;
+                return 0;
             }
         }
 
-        let mut a = 1;
+        let mut a: u32 = 1;
         for i in (0..e).rev() {
             a *= 2;
             if self.get(&mut state[22 + i.min(9)]) {
@@ -130,12 +137,18 @@ impl<'a> RangeCoder<'a> {
         }
 
         if signed && self.get(&mut state[11 + e.min(10)]) {
-            -(a as i32)
+            (a as i32).wrapping_neg()
         } else {
             a as i32
         }
     }
 
+    /// Returns whether a symbol coded on more than 32 bits was read, in
+    /// which case 0 was returned instead.
+    pub fn invalid_symbol(&self) -> bool {
+        self.invalid_symbol
+    }
+
     pub fn set_table(&mut self, table: &[u8; 256]) {
         // 3.8.1.4. State Transition Table
 
@@ -143,9 +156,7 @@ impl<'a> RangeCoder<'a> {
         self.one_state[..256].clone_from_slice(&table[..256]);
 
         // Figure 18.
-        for i in 1..255 {
-            self.zero_state[i] = (256 - self.one_state[256 - i] as u16) as u8;
-        }
+        self.zero_state = zero_states(table);
     }
 
     /// Ends the current range coder.
@@ -157,11 +168,16 @@ impl<'a> RangeCoder<'a> {
         self.get(&mut state);
     }
 
-    /// Gets the current position in the bitstream.
-    pub fn get_pos(&self) -> usize {
+    /// Returns the current position in the bitstream.
+    pub fn pos(&self) -> usize {
         if self.rng < 0x100 {
             return self.pos - 1;
         }
         self.pos
     }
+
+    #[deprecated(note = "use RangeCoder::pos")]
+    pub fn get_pos(&self) -> usize {
+        self.pos()
+    }
 }
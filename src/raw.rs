@@ -0,0 +1,171 @@
+//! Raw video output of decoded frames.
+
+use std::io::{self, Write};
+use std::ops::Range;
+
+use crate::decoder::Frame;
+
+/// Byte order of samples wider than 8 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Arrangement of the planes in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Every plane is written in full, one after the other.
+    Planar,
+    /// The samples of every pixel are written next to each other. All
+    /// the written planes must have the same dimensions.
+    Interleaved,
+}
+
+/// Writes frames as raw video, i.e. their samples without any header.
+///
+/// By default, samples are written as little-endian, planar, and all the
+/// planes are written in the same order as the planes of a Frame, which
+/// is what FFmpeg expects for e.g. yuv420p10le or gbrp16le.
+#[derive(Debug)]
+pub struct RawWriter<W: Write> {
+    writer: W,
+    endianness: Endianness,
+    layout: Layout,
+    plane_order: Option<Vec<usize>>,
+}
+
+impl<W: Write> RawWriter<W> {
+    /// Creates a writer outputting to 'writer'.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            endianness: Endianness::Little,
+            layout: Layout::Planar,
+            plane_order: None,
+        }
+    }
+
+    /// Sets the byte order of samples wider than 8 bits.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+    }
+
+    /// Sets the arrangement of the planes.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// Sets the planes to write, as indices into the planes of a Frame,
+    /// e.g. '[2, 0, 1]' writes RGB frames as R, G, B. Planes may be left
+    /// out, e.g. to drop the alpha plane.
+    pub fn set_plane_order(&mut self, plane_order: &[usize]) {
+        self.plane_order = Some(plane_order.to_vec());
+    }
+
+    /// Returns a mutable reference to the underlying writer, e.g. to
+    /// write headers between frames.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes the samples of a frame.
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let planes = match self.plane_order {
+            Some(ref order) => order.clone(),
+            None => (0..frame.num_planes()).collect(),
+        };
+        if let Some(&plane) =
+            planes.iter().find(|&&plane| plane >= frame.num_planes())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the frame has no plane {}", plane),
+            ));
+        }
+
+        match self.layout {
+            Layout::Planar => {
+                let mut row = Vec::new();
+                for &plane in &planes {
+                    let (width, height) = frame.plane_dimensions(plane);
+                    for y in 0..height {
+                        let start =
+                            frame.offsets[plane] + y * frame.strides[plane];
+                        row.clear();
+                        self.put_samples(
+                            frame,
+                            plane,
+                            start..start + width,
+                            &mut row,
+                        );
+                        self.writer.write_all(&row)?;
+                    }
+                }
+            }
+            Layout::Interleaved => {
+                let (width, height) = match planes.first() {
+                    Some(&plane) => frame.plane_dimensions(plane),
+                    None => return Ok(()),
+                };
+                if planes.iter().any(|&plane| {
+                    frame.plane_dimensions(plane) != (width, height)
+                }) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "interleaved planes must have the same dimensions",
+                    ));
+                }
+                let mut row = Vec::with_capacity(width * planes.len() * 2);
+                for y in 0..height {
+                    row.clear();
+                    for x in 0..width {
+                        for &plane in &planes {
+                            let pos = frame.offsets[plane]
+                                + y * frame.strides[plane]
+                                + x;
+                            self.put_samples(
+                                frame,
+                                plane,
+                                pos..pos + 1,
+                                &mut row,
+                            );
+                        }
+                    }
+                    self.writer.write_all(&row)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Appends samples of a plane to 'out', in the configured byte order.
+    fn put_samples(
+        &self,
+        frame: &Frame,
+        plane: usize,
+        range: Range<usize>,
+        out: &mut Vec<u8>,
+    ) {
+        if frame.bit_depth == 8 {
+            out.extend_from_slice(&frame.buf[plane][range]);
+            return;
+        }
+        for &v in &frame.buf16[plane][range] {
+            out.extend_from_slice(&match self.endianness {
+                Endianness::Little => v.to_le_bytes(),
+                Endianness::Big => v.to_be_bytes(),
+            });
+        }
+    }
+}
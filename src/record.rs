@@ -1,15 +1,88 @@
-use crate::constants::{CONTEXT_SIZE, MAX_CONTEXT_INPUTS, MAX_QUANT_TABLES};
+use std::fmt;
+
+use log::warn;
+
+use crate::compliance::Compliance;
+use crate::constants::{
+    ColorSpace, PixelFormat, CONTEXT_SIZE, MAX_CONTEXT_INPUTS,
+    MAX_QUANT_SCALE, MAX_QUANT_TABLES,
+};
 use crate::crc32mpeg2::crc32_mpeg2;
 use crate::error::{Error, Result};
-use crate::range::RangeCoder;
+use crate::quant_presets::{self, QuantTableSet};
+use crate::rangecoder::encoder::RangeEncoder;
+use crate::rangecoder::range::RangeCoder;
+use crate::rangecoder::tables::DEFAULT_STATE_TRANSITION;
+use crate::state_transition;
+use crate::trace::Trace;
 
-#[derive(Debug, PartialEq, Eq)]
+/// FFmpeg pixel formats a stream can be decoded to.
+const FFMPEG_PIX_FMTS: &[&str] = &[
+    "yuv420p",
+    "yuv420p9le",
+    "yuv420p10le",
+    "yuv420p12le",
+    "yuv420p14le",
+    "yuv420p16le",
+    "yuv422p",
+    "yuv422p9le",
+    "yuv422p10le",
+    "yuv422p12le",
+    "yuv422p14le",
+    "yuv422p16le",
+    "yuv444p",
+    "yuv444p9le",
+    "yuv444p10le",
+    "yuv444p12le",
+    "yuv444p14le",
+    "yuv444p16le",
+    "yuv440p",
+    "yuv440p10le",
+    "yuv440p12le",
+    "yuv411p",
+    "yuv410p",
+    "yuva420p",
+    "yuva420p9le",
+    "yuva420p10le",
+    "yuva420p16le",
+    "yuva422p",
+    "yuva422p9le",
+    "yuva422p10le",
+    "yuva422p12le",
+    "yuva422p16le",
+    "yuva444p",
+    "yuva444p9le",
+    "yuva444p10le",
+    "yuva444p12le",
+    "yuva444p16le",
+    "gray",
+    "gray9le",
+    "gray10le",
+    "gray12le",
+    "gray14le",
+    "gray16le",
+    "ya8",
+    "ya16le",
+    "bgr0",
+    "bgra",
+    "gbrp9le",
+    "gbrp10le",
+    "gbrp12le",
+    "gbrp14le",
+    "gbrp16le",
+    "gbrap10le",
+    "gbrap12le",
+    "gbrap14le",
+    "gbrap16le",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConfigRecord {
     pub version: u8,
     pub micro_version: u8,
     pub coder_type: u8,
     pub state_transition_delta: [i16; 256],
-    pub colorspace_type: u8,
+    pub colorspace_type: ColorSpace,
     pub bits_per_raw_sample: u8,
     pub chroma_planes: bool,
     pub log2_h_chroma_subsample: u8,
@@ -20,7 +93,11 @@ pub struct ConfigRecord {
     pub quant_table_set_count: usize,
     pub context_count: [i32; MAX_QUANT_TABLES],
     pub quant_tables: [[[i16; 256]; MAX_CONTEXT_INPUTS]; MAX_QUANT_TABLES],
-    pub states_coded: bool,
+    /// Whether the initial states of every quantization table set are
+    /// coded, see 4.1.14. states_coded.
+    pub states_coded: Vec<bool>,
+    /// Initial state deltas of every quantization table set, empty for
+    /// the sets whose states are not coded.
     pub initial_state_delta: Vec<Vec<Vec<i16>>>, // FIXME: This is horrible
     pub initial_states: Vec<Vec<Vec<u8>>>,
     pub ec: u8,
@@ -40,13 +117,41 @@ impl ConfigRecord {
         width: u32,
         height: u32,
     ) -> Result<Self> {
+        Self::parse_with_compliance(buf, width, height, Compliance::Normal)
+    }
+
+    /// Parses the configuration record like 'parse_config_record', with
+    /// the strictness 'compliance'.
+    pub fn parse_with_compliance(
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        compliance: Compliance,
+    ) -> Result<Self> {
+        Self::parse_traced(buf, width, height, &Trace::default(), compliance)
+    }
+
+    /// Parses the configuration record like 'parse_with_compliance',
+    /// tracing every syntax element to 'trace'.
+    pub(crate) fn parse_traced(
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        trace: &Trace,
+        compliance: Compliance,
+    ) -> Result<Self> {
+        span!("parse_config_record", size = buf.len());
+
         // Before we do anything, CRC check.
         //
         // See: 4.2.2. configuration_record_crc_parity
         if crc32_mpeg2(buf) != 0 {
-            return Err(Error::InvalidConfiguration(
-                "failed CRC check for configuration record".to_owned(),
-            ));
+            if compliance.checks_record_crc() {
+                return Err(Error::CrcMismatch(
+                    "failed CRC check for configuration record".to_owned(),
+                ));
+            }
+            warn!("failed CRC check for configuration record, ignored");
         }
         let mut coder = RangeCoder::new(buf);
         let mut state_transition_delta: [i16; 256] = [0; 256];
@@ -60,6 +165,7 @@ impl ConfigRecord {
 
         // 4.1.1. version
         let version = coder.ur(&mut state) as u8;
+        trace.element("4.1.1. version", version);
         if version != 3 {
             return Err(Error::InvalidConfiguration(
                 "only FFV1 version 3 is supported".to_owned(),
@@ -68,14 +174,26 @@ impl ConfigRecord {
 
         // 4.1.2. micro_version
         let micro_version = coder.ur(&mut state) as u8;
+        trace.element("4.1.2. micro_version", micro_version);
         if micro_version < 1 {
             return Err(Error::InvalidConfiguration(
                 "only FFV1 micro version >1 supported".to_owned(),
             ));
         }
 
+        if micro_version > 4 {
+            compliance.violation(
+                Error::InvalidConfiguration,
+                format!(
+                    "unknown micro_version {}, decoding as micro_version 4",
+                    micro_version
+                ),
+            )?;
+        }
+
         // 4.1.3. coder_type
         let coder_type = coder.ur(&mut state) as u8;
+        trace.element("4.1.3. coder_type", coder_type);
         if coder_type > 2 {
             return Err(Error::InvalidConfiguration(format!(
                 "invalid coder_type: {}",
@@ -85,24 +203,35 @@ impl ConfigRecord {
 
         // 4.1.4. state_transition_delta
         if coder_type > 1 {
-            for state_transition_delta in
-                state_transition_delta.iter_mut().skip(1)
+            for (i, state_transition_delta) in
+                state_transition_delta.iter_mut().enumerate().skip(1)
             {
-                *state_transition_delta = coder.sr(&mut state) as i16;
+                let delta = coder.sr(&mut state);
+                trace.element(
+                    format_args!("4.1.4. state_transition_delta[{}]", i),
+                    delta,
+                );
+                // Out of range deltas are rejected below
+                *state_transition_delta =
+                    delta.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
             }
+            state_transition::from_delta(&state_transition_delta)?;
         }
 
         // 4.1.5. colorspace_type
         let colorspace_type = coder.ur(&mut state) as u8;
-        if colorspace_type > 1 {
-            return Err(Error::InvalidConfiguration(format!(
-                "invalid colorspace_type: {}",
-                colorspace_type
-            )));
-        }
+        trace.element("4.1.5. colorspace_type", colorspace_type);
+        let colorspace_type = ColorSpace::from_u8(colorspace_type)
+            .ok_or_else(|| {
+                Error::InvalidConfiguration(format!(
+                    "invalid colorspace_type: {}",
+                    colorspace_type
+                ))
+            })?;
 
         // 4.1.7. bits_per_raw_sample
         let mut bits_per_raw_sample = coder.ur(&mut state) as u8;
+        trace.element("4.1.7. bits_per_raw_sample", bits_per_raw_sample);
         if bits_per_raw_sample == 0 {
             bits_per_raw_sample = 8;
         }
@@ -114,7 +243,8 @@ impl ConfigRecord {
 
         // 4.1.6. chroma_planes
         let chroma_planes = coder.br(&mut state);
-        if colorspace_type == 1 && !chroma_planes {
+        trace.element("4.1.6. chroma_planes", chroma_planes as u8);
+        if colorspace_type == ColorSpace::Rgb && !chroma_planes {
             return Err(Error::InvalidConfiguration(
                 "RGB must contain chroma planes".to_owned(),
             ));
@@ -122,7 +252,11 @@ impl ConfigRecord {
 
         // 4.1.8. log2_h_chroma_subsample
         let log2_h_chroma_subsample = coder.ur(&mut state) as u8;
-        if colorspace_type == 1 && log2_h_chroma_subsample != 0 {
+        trace.element(
+            "4.1.8. log2_h_chroma_subsample",
+            log2_h_chroma_subsample,
+        );
+        if colorspace_type == ColorSpace::Rgb && log2_h_chroma_subsample != 0 {
             return Err(Error::InvalidConfiguration(
                 "RGB cannot be subsampled".to_owned(),
             ));
@@ -130,7 +264,11 @@ impl ConfigRecord {
 
         // 4.1.9. log2_v_chroma_subsample
         let log2_v_chroma_subsample = coder.ur(&mut state) as u8;
-        if colorspace_type == 1 && log2_v_chroma_subsample != 0 {
+        trace.element(
+            "4.1.9. log2_v_chroma_subsample",
+            log2_v_chroma_subsample,
+        );
+        if colorspace_type == ColorSpace::Rgb && log2_v_chroma_subsample != 0 {
             return Err(Error::InvalidConfiguration(
                 "RGB cannot be subsampled".to_owned(),
             ));
@@ -138,13 +276,17 @@ impl ConfigRecord {
 
         // 4.1.10. extra_plane
         let extra_plane = coder.br(&mut state);
+        trace.element("4.1.10. extra_plane", extra_plane as u8);
         // 4.1.11. num_h_slices
         let num_h_slices_minus1 = coder.ur(&mut state) as u8;
+        trace.element("4.1.11. num_h_slices_minus1", num_h_slices_minus1);
         // 4.1.12. num_v_slices
         let num_v_slices_minus1 = coder.ur(&mut state) as u8;
+        trace.element("4.1.12. num_v_slices_minus1", num_v_slices_minus1);
 
         // 4.1.13. quant_table_set_count
         let quant_table_set_count = coder.ur(&mut state) as usize;
+        trace.element("4.1.13. quant_table_set_count", quant_table_set_count);
         if quant_table_set_count == 0 {
             return Err(Error::InvalidConfiguration(
                 "quant_table_set_count may not be zero".to_owned(),
@@ -159,71 +301,130 @@ impl ConfigRecord {
         for i in 0..quant_table_set_count {
             // 4.9.  Quantization Table Set
             let mut scale = 1;
-            for j in 0..MAX_CONTEXT_INPUTS {
+            for (j, table) in quant_tables[i].iter_mut().enumerate() {
                 // Each table has its own state table.
                 let mut quant_state: [u8; CONTEXT_SIZE] = [128; CONTEXT_SIZE];
                 let mut v = 0;
                 let mut k = 0;
                 while k < 128 {
                     let len_minus1 = coder.ur(&mut quant_state);
-                    for _ in 0..(len_minus1 + 1) as usize {
-                        quant_tables[i][j][k] = (scale * v) as i16;
+                    trace.element(
+                        format_args!(
+                            "4.9.1. quant_tables[{}][{}] len_minus1",
+                            i, j
+                        ),
+                        len_minus1,
+                    );
+                    if len_minus1 as usize >= 128 - k {
+                        return Err(Error::InvalidConfiguration(format!(
+                            "quant_tables[{}][{}] has more than 128 entries",
+                            i, j
+                        )));
+                    }
+                    for _ in 0..=len_minus1 {
+                        table[k] = (scale * v) as i16;
                         k += 1;
                     }
                     v += 1;
                 }
                 for k in 1..128 {
-                    quant_tables[i][j][256 - k] = -quant_tables[i][j][k];
+                    table[256 - k] = -table[k];
                 }
-                quant_tables[i][j][128] = -quant_tables[i][j][127];
+                table[128] = -table[127];
                 scale *= 2 * v - 1;
+                if scale > MAX_QUANT_SCALE {
+                    return Err(Error::InvalidConfiguration(format!(
+                        "quant_tables[{}] are too large",
+                        i
+                    )));
+                }
             }
             context_count[i] = (scale + 1) as i32 / 2;
         }
 
         // Why on earth did they choose to do a variable length buffer in the
         // *middle and start* of a 3D array?
+        //
+        // The deltas of sets whose states are not coded would all be zero,
+        // so they are not kept.
+        let mut states_coded = vec![false; quant_table_set_count];
         let mut initial_state_delta: Vec<Vec<Vec<i16>>> =
             vec![Vec::new(); quant_table_set_count];
         for i in 0..quant_table_set_count {
-            initial_state_delta[i] =
-                vec![Vec::new(); context_count[i] as usize];
-            for j in 0..context_count[i] as usize {
-                initial_state_delta[i][j] = vec![0; CONTEXT_SIZE as usize];
-            }
-            let states_coded = coder.br(&mut state);
-            if states_coded {
-                for j in 0..context_count[i] as usize {
-                    for k in 0..CONTEXT_SIZE {
-                        initial_state_delta[i][j][k] =
-                            coder.sr(&mut state) as i16;
+            // 4.1.14. states_coded
+            states_coded[i] = coder.br(&mut state);
+            trace.element(
+                format_args!("4.1.14. states_coded[{}]", i),
+                states_coded[i] as u8,
+            );
+            if states_coded[i] {
+                // 4.1.15. initial_state_delta
+                initial_state_delta[i] =
+                    vec![vec![0; CONTEXT_SIZE]; context_count[i] as usize];
+                for (j, context) in
+                    initial_state_delta[i].iter_mut().enumerate()
+                {
+                    for (k, delta) in context.iter_mut().enumerate() {
+                        *delta = coder.sr(&mut state) as i16;
+                        trace.element(
+                            format_args!(
+                                "4.1.15. initial_state_delta[{}][{}][{}]",
+                                i, j, k
+                            ),
+                            delta,
+                        );
                     }
                 }
             }
         }
 
-        let mut initial_states = vec![Vec::new(); initial_state_delta.len()];
-        for i in 0..initial_state_delta.len() {
-            initial_states[i] = vec![Vec::new(); initial_state_delta[i].len()];
-            for j in 0..initial_state_delta[i].len() {
-                initial_states[i][j] =
-                    vec![0; initial_state_delta[i][j].len()];
-                for k in 0..initial_state_delta[i][j].len() {
+        let mut initial_states = vec![Vec::new(); quant_table_set_count];
+        for i in 0..quant_table_set_count {
+            initial_states[i] =
+                vec![vec![0; CONTEXT_SIZE]; context_count[i] as usize];
+            for j in 0..context_count[i] as usize {
+                for k in 0..CONTEXT_SIZE {
                     let pred = if j != 0 {
                         initial_states[i][j - 1][k] as i16
                     } else {
                         128
                     };
+                    let delta = initial_state_delta[i]
+                        .get(j)
+                        .map_or(0, |context| context[k]);
                     initial_states[i][j][k] =
-                        ((pred + initial_state_delta[i][j][k]) & 255) as u8;
+                        (pred.wrapping_add(delta) & 255) as u8;
                 }
             }
         }
 
         // 4.1.16. ec
         let ec = coder.ur(&mut state) as u8;
+        trace.element("4.1.16. ec", ec);
+        if ec > 1 {
+            compliance.violation(
+                Error::InvalidConfiguration,
+                format!(
+                    "reserved ec value {}, slice CRCs will not be checked",
+                    ec
+                ),
+            )?;
+        }
         // 4.1.17. intra
         let intra = coder.ur(&mut state) as u8;
+        trace.element("4.1.17. intra", intra);
+        if intra > 1 {
+            compliance.violation(
+                Error::InvalidConfiguration,
+                format!("reserved intra value {}, treating it as 1", intra),
+            )?;
+        }
+
+        if coder.invalid_symbol() {
+            return Err(Error::InvalidConfiguration(
+                "invalid symbol in configuration record".to_owned(),
+            ));
+        }
 
         let config_record = ConfigRecord {
             version,
@@ -241,7 +442,7 @@ impl ConfigRecord {
             quant_table_set_count,
             context_count,
             quant_tables,
-            states_coded: false,
+            states_coded,
             initial_state_delta,
             initial_states,
             ec,
@@ -252,4 +453,602 @@ impl ConfigRecord {
 
         Ok(config_record)
     }
+
+    /// Codes the record as stored in the codec private data, sealed with
+    /// its CRC parity, e.g. for muxers of records made by
+    /// 'ConfigRecordBuilder'.
+    ///
+    /// See: 4.2. Configuration Record
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut c = RangeEncoder::new();
+        let mut state = [128; CONTEXT_SIZE];
+        c.symbol(&mut state, self.version as i32, false);
+        c.symbol(&mut state, self.micro_version as i32, false);
+        c.symbol(&mut state, self.coder_type as i32, false);
+        if self.coder_type > 1 {
+            for &delta in &self.state_transition_delta[1..] {
+                c.symbol(&mut state, delta as i32, true);
+            }
+        }
+        c.symbol(&mut state, self.colorspace_type as i32, false);
+        c.symbol(&mut state, self.bits_per_raw_sample as i32, false);
+        c.put(&mut state[0], self.chroma_planes);
+        c.symbol(&mut state, self.log2_h_chroma_subsample as i32, false);
+        c.symbol(&mut state, self.log2_v_chroma_subsample as i32, false);
+        c.put(&mut state[0], self.extra_plane);
+        c.symbol(&mut state, self.num_h_slices_minus1 as i32, false);
+        c.symbol(&mut state, self.num_v_slices_minus1 as i32, false);
+        c.symbol(&mut state, self.quant_table_set_count as i32, false);
+        for set in &self.quant_tables[..self.quant_table_set_count] {
+            // 4.9.1. quant_tables, as runs of equal values
+            for table in set {
+                let mut quant_state = [128; CONTEXT_SIZE];
+                let mut run = 1;
+                for k in 1..=128 {
+                    if k < 128 && table[k] == table[k - 1] {
+                        run += 1;
+                    } else {
+                        c.symbol(&mut quant_state, run - 1, false);
+                        run = 1;
+                    }
+                }
+            }
+        }
+        for (i, &coded) in self.states_coded.iter().enumerate() {
+            c.put(&mut state[0], coded);
+            for context in &self.initial_state_delta[i] {
+                for &delta in context {
+                    c.symbol(&mut state, delta as i32, true);
+                }
+            }
+        }
+        c.symbol(&mut state, self.ec as i32, false);
+        c.symbol(&mut state, self.intra as i32, false);
+
+        let mut data = c.terminate();
+        let parity = crc32_mpeg2(&data);
+        data.extend_from_slice(&parity.to_le_bytes());
+        data
+    }
+
+    /// Returns the number of contexts of every quantization table set.
+    ///
+    /// See: 4.1.15. context_count
+    pub fn context_counts(&self) -> &[i32] {
+        &self.context_count[..self.quant_table_set_count]
+    }
+
+    /// Returns the quantized value of the difference 'diff' between two
+    /// neighbouring samples, for the context input 'input' of the
+    /// quantization table set 'set', or None if either does not exist.
+    ///
+    /// The context inputs are, in order, l - tl, tl - t, t - tr, L - l and
+    /// T - t. Only the low 8 bits of the difference are used, as in the
+    /// bitstream.
+    ///
+    /// See: 3.5. Quantization Table Sets
+    pub fn quantize(
+        &self,
+        set: usize,
+        input: usize,
+        diff: i32,
+    ) -> Option<i16> {
+        if set >= self.quant_table_set_count || input >= MAX_CONTEXT_INPUTS {
+            return None;
+        }
+        Some(self.quant_tables[set][input][(diff & 255) as usize])
+    }
+
+    /// Returns the context for the given differences of neighbouring
+    /// samples, in the order used by 'quantize', or None if the
+    /// quantization table set does not exist.
+    ///
+    /// A negative context shares the states of its opposite, with the
+    /// sign of the coded difference flipped.
+    ///
+    /// See: 3.4. Context
+    pub fn context(
+        &self,
+        set: usize,
+        diffs: [i32; MAX_CONTEXT_INPUTS],
+    ) -> Option<i32> {
+        let mut context = 0;
+        for (input, &diff) in diffs.iter().enumerate() {
+            context += self.quantize(set, input, diff)? as i32;
+        }
+        Some(context)
+    }
+
+    /// Returns the planes of the decoded frames.
+    pub fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::new(
+            self.colorspace_type,
+            self.chroma_planes,
+            self.extra_plane,
+        )
+    }
+
+    /// Returns the number of planes of the decoded frames, e.g. 1 for
+    /// grayscale streams.
+    pub fn num_planes(&self) -> usize {
+        self.pixel_format().num_planes()
+    }
+
+    /// Returns the number of quantization table set indexes coded by every
+    /// slice header, one per plane slot: luma, chroma and alpha.
+    ///
+    /// Before version 4, the index of the chroma planes is coded even
+    /// when there are none, e.g. for grayscale.
+    ///
+    /// See: 4.5.5. quant_table_set_index_count
+    pub(crate) fn quant_table_set_index_count(&self) -> usize {
+        1 + (self.chroma_planes || self.version < 4) as usize
+            + self.extra_plane as usize
+    }
+
+    /// Returns a human-readable, one line description of the stream, e.g.
+    /// "FFV1 v3.4, range coder, 10-bit YCbCr 4:2:2, 4x4 slices, slice CRC on".
+    pub fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    /// Returns the name of the FFmpeg pixel format the stream decodes to,
+    /// e.g. "yuv420p", "gbrp16le" or "yuva444p10le", or None if FFmpeg
+    /// has no such format.
+    ///
+    /// 8-bit RGB streams are decoded by FFmpeg to packed "bgr0" or
+    /// "bgra", while this decoder returns planes.
+    pub fn ffmpeg_pix_fmt(&self) -> Option<String> {
+        let bits = self.bits_per_raw_sample;
+        let suffix = |name: &str| {
+            if bits == 8 {
+                name.to_owned()
+            } else {
+                format!("{}{}le", name, bits)
+            }
+        };
+        let name = match self.colorspace_type {
+            ColorSpace::Rgb if bits == 8 => {
+                if self.extra_plane { "bgra" } else { "bgr0" }.to_owned()
+            }
+            ColorSpace::Rgb => {
+                suffix(if self.extra_plane { "gbrap" } else { "gbrp" })
+            }
+            ColorSpace::YCbCr if !self.chroma_planes => {
+                match (self.extra_plane, bits) {
+                    (false, 8) => "gray".to_owned(),
+                    (false, _) => format!("gray{}le", bits),
+                    (true, 8) => "ya8".to_owned(),
+                    (true, _) => format!("ya{}le", bits),
+                }
+            }
+            ColorSpace::YCbCr => {
+                let subsampling = match (
+                    self.log2_h_chroma_subsample,
+                    self.log2_v_chroma_subsample,
+                ) {
+                    (0, 0) => "444",
+                    (1, 0) => "422",
+                    (1, 1) => "420",
+                    (2, 0) => "411",
+                    (2, 2) => "410",
+                    (0, 1) => "440",
+                    _ => return None,
+                };
+                let planes = if self.extra_plane { "yuva" } else { "yuv" };
+                suffix(&format!("{}{}p", planes, subsampling))
+            }
+        };
+        if FFMPEG_PIX_FMTS.contains(&name.as_str()) {
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the conventional J:a:b notation of the chroma subsampling.
+    pub(crate) fn subsampling(&self) -> String {
+        match (self.log2_h_chroma_subsample, self.log2_v_chroma_subsample) {
+            (0, 0) => "4:4:4".to_owned(),
+            (1, 0) => "4:2:2".to_owned(),
+            (1, 1) => "4:2:0".to_owned(),
+            (2, 0) => "4:1:1".to_owned(),
+            (2, 1) => "4:1:0".to_owned(),
+            (0, 1) => "4:4:0".to_owned(),
+            (h, v) => format!("1/{}x1/{}", 1 << h, 1 << v),
+        }
+    }
+}
+
+impl fmt::Display for ConfigRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "FFV1 v{}.{}, ", self.version, self.micro_version)?;
+        match self.coder_type {
+            0 => f.write_str("Golomb-Rice")?,
+            1 => f.write_str("range coder")?,
+            _ => f.write_str("range coder with custom state transitions")?,
+        }
+        write!(f, ", {}-bit ", self.bits_per_raw_sample)?;
+        if self.chroma_planes {
+            write!(f, "{}", self.colorspace_type)?;
+            if self.colorspace_type == ColorSpace::YCbCr {
+                write!(f, " {}", self.subsampling())?;
+            }
+        } else {
+            f.write_str("grayscale")?;
+        }
+        if self.extra_plane {
+            f.write_str(" with alpha")?;
+        }
+        write!(
+            f,
+            ", {}x{} slices, slice CRC {}",
+            self.num_h_slices_minus1 as u32 + 1,
+            self.num_v_slices_minus1 as u32 + 1,
+            if self.ec != 0 { "on" } else { "off" }
+        )?;
+        if self.intra != 0 {
+            f.write_str(", intra only")?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds configuration records in memory, e.g. for synthetic streams in
+/// tests or to configure encoders, without coding and parsing them.
+///
+/// Every parameter defaults to what FFmpeg uses for 8-bit YCbCr 4:2:0
+/// streams: version 3.4, range coder with the default state transition
+/// table, a single slice, the quantization table sets of
+/// 'quant_presets::ffmpeg_default', no coded initial states, slice CRCs
+/// and no intra flag.
+#[derive(Debug, Clone)]
+pub struct ConfigRecordBuilder {
+    width: u32,
+    height: u32,
+    micro_version: u8,
+    coder_type: u8,
+    state_transition: [u8; 256],
+    colorspace_type: ColorSpace,
+    bits_per_raw_sample: u8,
+    chroma_planes: bool,
+    log2_chroma_subsample: (u8, u8),
+    extra_plane: bool,
+    slices: (u32, u32),
+    quant_tables: Option<Vec<QuantTableSet>>,
+    initial_states: Vec<Option<Vec<Vec<u8>>>>,
+    ec: u8,
+    intra: u8,
+}
+
+impl ConfigRecordBuilder {
+    /// Creates a builder of records of 'width' by 'height' frames.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            micro_version: 4,
+            coder_type: 1,
+            state_transition: DEFAULT_STATE_TRANSITION,
+            colorspace_type: ColorSpace::YCbCr,
+            bits_per_raw_sample: 8,
+            chroma_planes: true,
+            log2_chroma_subsample: (1, 1),
+            extra_plane: false,
+            slices: (1, 1),
+            quant_tables: None,
+            initial_states: Vec::new(),
+            ec: 1,
+            intra: 0,
+        }
+    }
+
+    /// Sets the micro version of the bitstream.
+    ///
+    /// See: 4.1.2. micro_version
+    pub fn micro_version(mut self, micro_version: u8) -> Self {
+        self.micro_version = micro_version;
+        self
+    }
+
+    /// Sets the coder, 0 for Golomb-Rice and 1 for the range coder with
+    /// the default state transition table.
+    ///
+    /// See: 4.1.3. coder_type
+    pub fn coder_type(mut self, coder_type: u8) -> Self {
+        self.coder_type = coder_type;
+        self.state_transition = DEFAULT_STATE_TRANSITION;
+        self
+    }
+
+    /// Selects the range coder with the custom state transition table
+    /// 'table', i.e. coder_type 2.
+    ///
+    /// See: 4.1.4. state_transition_delta
+    pub fn state_transition(mut self, table: &[u8; 256]) -> Self {
+        self.coder_type = 2;
+        self.state_transition = *table;
+        self
+    }
+
+    /// Sets the colorspace of the samples.
+    ///
+    /// See: 4.1.5. colorspace_type
+    pub fn colorspace(mut self, colorspace_type: ColorSpace) -> Self {
+        self.colorspace_type = colorspace_type;
+        self
+    }
+
+    /// Sets the bit depth of the samples.
+    ///
+    /// See: 4.1.7. bits_per_raw_sample
+    pub fn bits_per_raw_sample(mut self, bits_per_raw_sample: u8) -> Self {
+        self.bits_per_raw_sample = bits_per_raw_sample;
+        self
+    }
+
+    /// Sets whether chroma planes are coded, i.e. whether the stream is
+    /// not grayscale.
+    ///
+    /// See: 4.1.6. chroma_planes
+    pub fn chroma_planes(mut self, chroma_planes: bool) -> Self {
+        self.chroma_planes = chroma_planes;
+        self
+    }
+
+    /// Sets the log2 of the horizontal and vertical chroma subsampling.
+    ///
+    /// See: * 4.1.8. log2_h_chroma_subsample
+    ///      * 4.1.9. log2_v_chroma_subsample
+    pub fn chroma_subsample(mut self, log2_h: u8, log2_v: u8) -> Self {
+        self.log2_chroma_subsample = (log2_h, log2_v);
+        self
+    }
+
+    /// Sets whether an alpha plane is coded.
+    ///
+    /// See: 4.1.10. extra_plane
+    pub fn extra_plane(mut self, extra_plane: bool) -> Self {
+        self.extra_plane = extra_plane;
+        self
+    }
+
+    /// Sets the number of columns and rows of the slice grid.
+    ///
+    /// See: * 4.1.11. num_h_slices
+    ///      * 4.1.12. num_v_slices
+    pub fn slices(mut self, num_h_slices: u32, num_v_slices: u32) -> Self {
+        self.slices = (num_h_slices, num_v_slices);
+        self
+    }
+
+    /// Sets the quantization table sets, e.g. from 'quant_presets', instead
+    /// of the ones of FFmpeg for the bit depth.
+    ///
+    /// See: 4.9. Quantization Table Set
+    pub fn quant_tables(mut self, sets: Vec<QuantTableSet>) -> Self {
+        self.quant_tables = Some(sets);
+        self
+    }
+
+    /// Codes 'states' as the initial states of the quantization table set
+    /// 'set', one array of 'CONTEXT_SIZE' states per context.
+    ///
+    /// See: * 4.1.14. states_coded
+    ///      * 4.1.15. initial_state_delta
+    pub fn initial_states(mut self, set: usize, states: Vec<Vec<u8>>) -> Self {
+        if self.initial_states.len() <= set {
+            self.initial_states.resize(set + 1, None);
+        }
+        self.initial_states[set] = Some(states);
+        self
+    }
+
+    /// Sets the error detection and correction mode.
+    ///
+    /// See: 4.1.16. ec
+    pub fn ec(mut self, ec: u8) -> Self {
+        self.ec = ec;
+        self
+    }
+
+    /// Sets whether every frame is a keyframe.
+    ///
+    /// See: 4.1.17. intra
+    pub fn intra(mut self, intra: u8) -> Self {
+        self.intra = intra;
+        self
+    }
+
+    /// Returns the record, with its context counts and initial states
+    /// computed, or an error when a parameter would not be accepted in a
+    /// coded record.
+    pub fn build(self) -> Result<ConfigRecord> {
+        let invalid =
+            |message: String| Err(Error::InvalidConfiguration(message));
+        let bits = self.bits_per_raw_sample;
+        let (log2_h, log2_v) = self.log2_chroma_subsample;
+        let (num_h_slices, num_v_slices) = self.slices;
+        if self.micro_version < 1 {
+            return invalid("only FFV1 micro version >1 supported".to_owned());
+        }
+        if self.coder_type > 2 {
+            return invalid(format!(
+                "invalid coder_type: {}",
+                self.coder_type
+            ));
+        }
+        if bits == 0 || bits > 16 {
+            return invalid(format!("invalid bits_per_raw_sample: {}", bits));
+        }
+        if self.coder_type == 0 && bits != 8 {
+            return invalid(
+                "golomb-rice mode cannot have >8bit per sample".to_owned(),
+            );
+        }
+        if self.colorspace_type == ColorSpace::Rgb {
+            if !self.chroma_planes {
+                return invalid("RGB must contain chroma planes".to_owned());
+            }
+            if (log2_h, log2_v) != (0, 0) {
+                return invalid("RGB cannot be subsampled".to_owned());
+            }
+        }
+        if !(1..=256).contains(&num_h_slices)
+            || !(1..=256).contains(&num_v_slices)
+        {
+            return invalid(format!(
+                "invalid slice grid: {}x{}",
+                num_h_slices, num_v_slices
+            ));
+        }
+
+        let sets = self
+            .quant_tables
+            .unwrap_or_else(|| quant_presets::ffmpeg_default(bits).to_vec());
+        if sets.is_empty() {
+            return invalid(
+                "quant_table_set_count may not be zero".to_owned(),
+            );
+        } else if sets.len() > MAX_QUANT_TABLES {
+            return invalid(format!(
+                "too many quant tables: {} > {}",
+                sets.len(),
+                MAX_QUANT_TABLES
+            ));
+        }
+        if self.initial_states.len() > sets.len() {
+            return invalid(format!(
+                "initial states of missing quant table set {}",
+                self.initial_states.len() - 1
+            ));
+        }
+        let mut context_count = [0; MAX_QUANT_TABLES];
+        let mut quant_tables =
+            [[[0; 256]; MAX_CONTEXT_INPUTS]; MAX_QUANT_TABLES];
+        for (i, set) in sets.iter().enumerate() {
+            check_quant_table_set(i, set)?;
+            context_count[i] = quant_presets::context_count(set);
+            quant_tables[i] = *set;
+        }
+
+        let mut states_coded = vec![false; sets.len()];
+        let mut initial_state_delta = vec![Vec::new(); sets.len()];
+        let mut initial_states = vec![Vec::new(); sets.len()];
+        for (i, &count) in context_count[..sets.len()].iter().enumerate() {
+            let coded = self.initial_states.get(i).and_then(Option::as_ref);
+            let states = match coded {
+                Some(states) => {
+                    if states.len() != count as usize
+                        || states.iter().any(|s| s.len() != CONTEXT_SIZE)
+                    {
+                        return invalid(format!(
+                            "initial states of quant table set {} need {} \
+                             contexts of {} states",
+                            i, count, CONTEXT_SIZE
+                        ));
+                    }
+                    states.clone()
+                }
+                None => vec![vec![128; CONTEXT_SIZE]; count as usize],
+            };
+            if coded.is_some() {
+                // Every context is coded as a difference from the previous
+                // one, or from 128 for the first.
+                //
+                // See: 4.1.15. initial_state_delta
+                states_coded[i] = true;
+                initial_state_delta[i] = states
+                    .iter()
+                    .enumerate()
+                    .map(|(j, context)| {
+                        context
+                            .iter()
+                            .enumerate()
+                            .map(|(k, &state)| {
+                                let pred = if j != 0 {
+                                    states[j - 1][k]
+                                } else {
+                                    128
+                                };
+                                state as i16 - pred as i16
+                            })
+                            .collect()
+                    })
+                    .collect();
+            }
+            initial_states[i] = states;
+        }
+
+        let state_transition_delta = if self.coder_type == 2 {
+            state_transition::to_delta(&self.state_transition)
+        } else {
+            [0; 256]
+        };
+
+        Ok(ConfigRecord {
+            version: 3,
+            micro_version: self.micro_version,
+            coder_type: self.coder_type,
+            state_transition_delta,
+            colorspace_type: self.colorspace_type,
+            bits_per_raw_sample: bits,
+            chroma_planes: self.chroma_planes,
+            log2_h_chroma_subsample: log2_h,
+            log2_v_chroma_subsample: log2_v,
+            extra_plane: self.extra_plane,
+            num_h_slices_minus1: (num_h_slices - 1) as u8,
+            num_v_slices_minus1: (num_v_slices - 1) as u8,
+            quant_table_set_count: sets.len(),
+            context_count,
+            quant_tables,
+            states_coded,
+            initial_state_delta,
+            initial_states,
+            ec: self.ec,
+            intra: self.intra,
+            width: self.width,
+            height: self.height,
+        })
+    }
+}
+
+/// Checks that the quantization table set 'set' can be coded in a record,
+/// i.e. that every table steps from 0 by its scale up to index 127 and
+/// mirrors it for the negative differences.
+///
+/// See: 4.9.1. quant_tables
+fn check_quant_table_set(i: usize, set: &QuantTableSet) -> Result<()> {
+    let invalid = |j: usize| {
+        Err(Error::InvalidConfiguration(format!(
+            "quant_tables[{}][{}] cannot be coded",
+            i, j
+        )))
+    };
+    let mut scale = 1;
+    for (j, table) in set.iter().enumerate() {
+        if table[0] != 0 {
+            return invalid(j);
+        }
+        for k in 1..128 {
+            let step = table[k] as i32 - table[k - 1] as i32;
+            if step != 0 && step != scale {
+                return invalid(j);
+            }
+            if table[256 - k] != -table[k] {
+                return invalid(j);
+            }
+        }
+        if table[128] != -table[127] {
+            return invalid(j);
+        }
+        scale += 2 * table[127] as i32;
+        if scale > MAX_QUANT_SCALE {
+            return Err(Error::InvalidConfiguration(format!(
+                "quant_tables[{}] are too large",
+                i
+            )));
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,108 @@
+//! Checking and repair of packets without decoding them, e.g. to copy the
+//! FFV1 packets of a file mangled by legacy software into a new file.
+//!
+//! Only the slice footers are rewritten, so repaired packets keep their
+//! size and the slice contents are left untouched.
+
+use crate::crc32mpeg2::crc32_mpeg2;
+use crate::error::{Error, Result};
+use crate::slice::count_slices;
+
+/// Size of the slice footer when error correction is enabled.
+///
+/// See: 4.8. Slice Footer
+const FOOTER_SIZE: usize = 8;
+
+/// Status of a slice of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SliceStatus {
+    /// Position of the slice within the packet, in bytes.
+    pub pos: usize,
+    /// Size of the slice without its footer, in bytes.
+    pub size: usize,
+    /// error_status of the slice footer.
+    pub error_status: u8,
+    /// Whether slice_crc_parity matches the slice.
+    pub crc_valid: bool,
+}
+
+/// Footer fields rewritten by 'repair_packet'.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepairOptions {
+    /// Sets error_status to 0, i.e. no error.
+    pub clear_error_status: bool,
+    /// Recomputes slice_crc_parity from the slice content.
+    pub recompute_crc: bool,
+}
+
+/// Returns the status of every slice of a packet.
+///
+/// Slices only have an error_status and a CRC when error correction is
+/// enabled, so the packet must come from a stream whose configuration
+/// record has 'ec' set.
+pub fn check_packet(buf: &[u8]) -> Result<Vec<SliceStatus>> {
+    let slice_info = count_slices(buf, true).map_err(|err| {
+        Error::InvalidInputData(format!("invalid frame footer: {}", err))
+    })?;
+    Ok(slice_info
+        .iter()
+        .map(|info| SliceStatus {
+            pos: info.pos,
+            size: info.size,
+            error_status: info.error_status,
+            crc_valid: crc32_mpeg2(
+                &buf[info.pos..info.pos + info.size + FOOTER_SIZE],
+            ) == 0,
+        })
+        .collect())
+}
+
+/// Rewrites the slice footers of a packet according to 'options', and
+/// returns the status of its slices before they were rewritten.
+///
+/// As for 'check_packet', error correction must be enabled.
+pub fn repair_packet(
+    buf: &mut [u8],
+    options: &RepairOptions,
+) -> Result<Vec<SliceStatus>> {
+    let status = check_packet(buf)?;
+    for slice in &status {
+        let end = slice.pos + slice.size + FOOTER_SIZE;
+        if options.clear_error_status {
+            buf[end - 5] = 0;
+        }
+        if options.recompute_crc {
+            write_parity(&mut buf[slice.pos..end]);
+        }
+    }
+    Ok(status)
+}
+
+/// Rewrites the footer of a slice, given as its content followed by its
+/// footer, with 'error_status' and a matching slice_crc_parity, e.g. to
+/// mark a damaged slice or to seal a slice whose content was replaced.
+///
+/// See: 4.8. Slice Footer
+pub fn seal_slice(slice: &mut [u8], error_status: u8) -> Result<()> {
+    if slice.len() < FOOTER_SIZE {
+        return Err(Error::SliceError(format!(
+            "{} bytes are too short for a slice footer",
+            slice.len()
+        )));
+    }
+    let end = slice.len();
+    slice[end - 5] = error_status;
+    write_parity(slice);
+    Ok(())
+}
+
+/// Writes the slice_crc_parity of a slice followed by its footer.
+///
+/// The CRC of a slice followed by its parity, as little-endian, is 0.
+///
+/// See: 4.8.3. slice_crc_parity
+fn write_parity(slice: &mut [u8]) {
+    let end = slice.len();
+    let parity = crc32_mpeg2(&slice[..end - 4]);
+    slice[end - 4..].copy_from_slice(&parity.to_le_bytes());
+}
@@ -0,0 +1,77 @@
+//! Sample types of the decoded planes.
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+}
+
+/// A sample of a plane, stored as u8 for 8-bit streams, as u16 for wider
+/// ones, and as u32 for the JPEG2000-RCT planes of 16-bit RGB streams.
+///
+/// The trait is sealed, so it cannot be implemented outside this crate.
+pub trait Sample:
+    private::Sealed + Copy + Default + Send + Sync + 'static
+{
+    /// Returns the sample as a u32.
+    fn to_u32(self) -> u32;
+
+    /// Returns the sample as a usize, e.g. to index a table.
+    fn to_usize(self) -> usize;
+
+    /// Returns the sample holding the low bits of 'value'.
+    fn from_u32(value: u32) -> Self;
+}
+
+impl Sample for u8 {
+    #[inline(always)]
+    fn to_u32(self) -> u32 {
+        self.into()
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self.into()
+    }
+
+    #[inline(always)]
+    fn from_u32(value: u32) -> Self {
+        value as u8
+    }
+}
+
+impl Sample for u16 {
+    #[inline(always)]
+    fn to_u32(self) -> u32 {
+        self.into()
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self.into()
+    }
+
+    #[inline(always)]
+    fn from_u32(value: u32) -> Self {
+        value as u16
+    }
+}
+
+impl Sample for u32 {
+    #[inline(always)]
+    fn to_u32(self) -> u32 {
+        self
+    }
+
+    #[inline(always)]
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+
+    #[inline(always)]
+    fn from_u32(value: u32) -> Self {
+        value
+    }
+}
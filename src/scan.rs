@@ -0,0 +1,37 @@
+//! Lightweight scans of packets, without decoding them.
+
+use crate::slice::{is_keyframe, SliceLayout};
+
+/// Structure of a frame, see 'Decoder::scan_frame'.
+#[derive(Debug, Clone)]
+pub struct FrameScan {
+    /// Whether the frame is a keyframe.
+    ///
+    /// See: 4.3. Frame
+    pub keyframe: bool,
+    /// Layout and header of every slice, in coding order.
+    pub slices: Vec<SliceLayout>,
+}
+
+/// Returns the positions of the keyframes among 'packets', e.g. to build a
+/// seek index for a container without reliable cue data.
+///
+/// Only the keyframe bit of every packet is read, so nothing is decoded
+/// and packets too short to hold a frame are never keyframes.
+///
+/// See: 4.3. Frame
+pub fn scan_keyframes<I, P>(packets: I) -> Vec<usize>
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<[u8]>,
+{
+    packets
+        .into_iter()
+        .enumerate()
+        .filter(|(_, packet)| {
+            let packet = packet.as_ref();
+            packet.len() >= 2 && is_keyframe(packet)
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
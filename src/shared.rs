@@ -0,0 +1,127 @@
+//! Decoded frames shared between several consumers.
+
+use std::sync::Arc;
+
+use crate::constants::ColorSpace;
+use crate::decoder::{Frame, FrameMetadata};
+use crate::diagnostics::Diagnostic;
+use crate::geometry::{self, Crop};
+
+/// A decoded frame whose planes are reference-counted, so it can be
+/// handed to several consumers, e.g. display, hashing and encoding, by
+/// cloning it without copying its samples.
+///
+/// The fields have the same meaning as in a Frame.
+#[derive(Debug, Clone)]
+pub struct ArcFrame {
+    /// Image data. Valid only when BitDepth is 8.
+    pub buf: Vec<Arc<[u8]>>,
+    /// Image data. Valid only when BitDepth is greater than 8.
+    pub buf16: Vec<Arc<[u16]>>,
+    /// Distance between the start of two consecutive rows of each plane,
+    /// in samples.
+    pub strides: Vec<usize>,
+    /// Position of the first sample of each plane, in samples.
+    pub offsets: Vec<usize>,
+    /// Width of the frame, in pixels.
+    pub width: u32,
+    /// Height of the frame, in pixels.
+    pub height: u32,
+    /// Bitdepth of the frame (8-16).
+    pub bit_depth: u8,
+    /// Colorspace of the frame.
+    pub color_space: ColorSpace,
+    /// Whether or not chroma planes are present.
+    pub has_chroma: bool,
+    /// Whether or not an alpha plane is present.
+    pub has_alpha: bool,
+    /// The log2 vertical chroma subampling value.
+    pub chroma_subsample_v: u8,
+    /// The log2 horizontal chroma subsampling value.
+    pub chroma_subsample_h: u8,
+    /// Displayed area of the frame.
+    pub crop: Crop,
+    /// Caller metadata passed along with the packet.
+    pub metadata: FrameMetadata,
+    /// Non-fatal findings of the decoding.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ArcFrame {
+    /// Returns the number of planes of the frame.
+    pub fn num_planes(&self) -> usize {
+        let mut num_planes = 1;
+        if self.has_chroma {
+            num_planes += 2;
+        }
+        if self.has_alpha {
+            num_planes += 1;
+        }
+        num_planes
+    }
+
+    /// Returns the width and height of a plane, in samples.
+    pub fn plane_dimensions(&self, plane: usize) -> (usize, usize) {
+        geometry::plane_dimensions(
+            self.width,
+            self.height,
+            self.has_chroma,
+            self.chroma_subsample_h,
+            self.chroma_subsample_v,
+            plane,
+        )
+    }
+
+    /// Returns a Frame with copies of the planes, e.g. for the functions
+    /// taking a Frame.
+    pub fn to_frame(&self) -> Frame {
+        Frame {
+            buf: self.buf.iter().map(|plane| plane.to_vec()).collect(),
+            buf16: self.buf16.iter().map(|plane| plane.to_vec()).collect(),
+            strides: self.strides.clone(),
+            offsets: self.offsets.clone(),
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_space: self.color_space,
+            has_chroma: self.has_chroma,
+            has_alpha: self.has_alpha,
+            chroma_subsample_v: self.chroma_subsample_v,
+            chroma_subsample_h: self.chroma_subsample_h,
+            crop: self.crop,
+            metadata: self.metadata.clone(),
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
+}
+
+impl From<Frame> for ArcFrame {
+    fn from(frame: Frame) -> Self {
+        Self {
+            buf: frame.buf.into_iter().map(Arc::from).collect(),
+            buf16: frame.buf16.into_iter().map(Arc::from).collect(),
+            strides: frame.strides,
+            offsets: frame.offsets,
+            width: frame.width,
+            height: frame.height,
+            bit_depth: frame.bit_depth,
+            color_space: frame.color_space,
+            has_chroma: frame.has_chroma,
+            has_alpha: frame.has_alpha,
+            chroma_subsample_v: frame.chroma_subsample_v,
+            chroma_subsample_h: frame.chroma_subsample_h,
+            crop: frame.crop,
+            metadata: frame.metadata,
+            diagnostics: frame.diagnostics,
+        }
+    }
+}
+
+impl Frame {
+    /// Moves the planes of the frame behind reference counts, see
+    /// 'ArcFrame'. This copies every plane once, clones of the result
+    /// never do.
+    pub fn into_shared(self) -> ArcFrame {
+        ArcFrame::from(self)
+    }
+}
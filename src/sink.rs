@@ -0,0 +1,240 @@
+//! Output of decoded rows as soon as they are complete.
+//!
+//! 'Decoder::decode_frame_to_sink' hands the rows of a frame to an
+//! 'OutputSink' once all the slices covering them are decoded, so only the
+//! slices of the rows not yet complete are held in memory instead of the
+//! whole frame, e.g. for 16-bit 8K frames on memory-constrained systems.
+
+use crate::decoder::SlicePlanes;
+use crate::error::Result;
+use crate::slice::SlicePlane;
+use crate::stats::PlaneStats;
+
+/// A destination of the decoded rows of a frame.
+///
+/// The rows of a plane are handed from top to bottom, each exactly once,
+/// while the rows of different planes are interleaved as the slices
+/// covering them are decoded. Rows have the width of their plane, without
+/// any padding.
+///
+/// An error returned by the sink stops decoding the frame and is returned
+/// by the decoder, e.g. a 'ResourceError' for a failed write.
+pub trait OutputSink {
+    /// Receives row 'y' of 'plane' of an 8-bit frame.
+    fn write_row(&mut self, plane: usize, y: usize, row: &[u8]) -> Result<()>;
+
+    /// Receives row 'y' of 'plane' of a frame whose bit depth is greater
+    /// than 8.
+    fn write_row16(
+        &mut self,
+        plane: usize,
+        y: usize,
+        row: &[u16],
+    ) -> Result<()>;
+
+    /// Called once all the rows of 'plane' have been handed.
+    fn plane_done(&mut self, _plane: usize) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Gathers the decoded slices of a frame, handing every row to a sink once
+/// all the slices covering it are decoded.
+pub(crate) struct RowAssembler {
+    /// Dimensions of the planes of the frame.
+    dimensions: Vec<(usize, usize)>,
+    /// Number of samples decoded in every row of every plane.
+    covered: Vec<Vec<usize>>,
+    /// Next row to hand, for every plane.
+    next_row: Vec<usize>,
+    /// Decoded slices whose rows have not all been handed.
+    pending: Vec<(Vec<SlicePlane>, SlicePlanes)>,
+    /// Whether samples are wider than 8 bits.
+    wide: bool,
+    /// Rows being assembled, reused from one row to the next.
+    row: Vec<u8>,
+    row16: Vec<u16>,
+}
+
+impl RowAssembler {
+    /// Creates an assembler of a frame with planes of 'dimensions', whose
+    /// samples are wider than 8 bits when 'wide' is set.
+    pub(crate) fn new(dimensions: Vec<(usize, usize)>, wide: bool) -> Self {
+        Self {
+            covered: dimensions
+                .iter()
+                .map(|&(_, height)| vec![0; height])
+                .collect(),
+            next_row: vec![0; dimensions.len()],
+            dimensions,
+            pending: Vec::new(),
+            wide,
+            row: Vec::new(),
+            row16: Vec::new(),
+        }
+    }
+
+    /// Adds a decoded slice, whose planes are in the order of the frame
+    /// planes, and hands the rows it completes to 'sink'.
+    pub(crate) fn push(
+        &mut self,
+        planes: &[SlicePlane],
+        data: SlicePlanes,
+        sink: &mut dyn OutputSink,
+    ) -> Result<()> {
+        for (plane, area) in planes.iter().enumerate() {
+            if let Some(covered) = self.covered.get_mut(plane) {
+                let start = area.start_y as usize;
+                let end = (start + area.height as usize).min(covered.len());
+                for row in covered.iter_mut().take(end).skip(start) {
+                    *row += area.width as usize;
+                }
+            }
+        }
+        self.pending.push((planes.to_vec(), data));
+
+        for plane in 0..self.dimensions.len() {
+            let (width, height) = self.dimensions[plane];
+            while self.next_row[plane] < height
+                && self.covered[plane][self.next_row[plane]] >= width
+            {
+                self.write_row(plane, sink)?;
+            }
+            if self.next_row[plane] == height && height > 0 {
+                self.plane_done(plane, sink)?;
+            }
+        }
+
+        // Slices whose rows have all been handed are not needed anymore
+        let next_row = &self.next_row;
+        self.pending.retain(|(planes, _)| {
+            planes.iter().zip(next_row).any(|(area, &next)| {
+                (area.start_y + area.height) as usize > next
+            })
+        });
+        Ok(())
+    }
+
+    /// Hands the rows left to 'sink', the samples not covered by any slice
+    /// being 0.
+    pub(crate) fn finish(mut self, sink: &mut dyn OutputSink) -> Result<()> {
+        for plane in 0..self.dimensions.len() {
+            if self.next_row[plane] == usize::MAX {
+                continue;
+            }
+            while self.next_row[plane] < self.dimensions[plane].1 {
+                self.write_row(plane, sink)?;
+            }
+            self.plane_done(plane, sink)?;
+        }
+        Ok(())
+    }
+
+    /// Marks 'plane' as done, telling 'sink' once.
+    fn plane_done(
+        &mut self,
+        plane: usize,
+        sink: &mut dyn OutputSink,
+    ) -> Result<()> {
+        self.next_row[plane] = usize::MAX;
+        sink.plane_done(plane)
+    }
+
+    /// Hands the next row of 'plane' to 'sink', assembled from the pending
+    /// slices covering it.
+    fn write_row(
+        &mut self,
+        plane: usize,
+        sink: &mut dyn OutputSink,
+    ) -> Result<()> {
+        fn assemble<T: Copy + Default>(
+            row: &mut Vec<T>,
+            width: usize,
+            y: usize,
+            slices: &[(&SlicePlane, &Vec<T>)],
+        ) {
+            row.clear();
+            row.resize(width, T::default());
+            for (area, src) in slices {
+                let start_x = area.start_x as usize;
+                let slice_width = area.width as usize;
+                let slice_y = y - area.start_y as usize;
+                let src = &src[slice_y * slice_width..][..slice_width];
+                row[start_x..start_x + slice_width].copy_from_slice(src);
+            }
+        }
+
+        let y = self.next_row[plane];
+        let width = self.dimensions[plane].0;
+        let covering = self.pending.iter().filter_map(|(planes, data)| {
+            let area = planes.get(plane)?;
+            let start = area.start_y as usize;
+            if y < start || y >= start + area.height as usize {
+                return None;
+            }
+            Some((area, data))
+        });
+
+        if self.wide {
+            let slices: Vec<_> = covering
+                .filter_map(|(area, data)| match data {
+                    SlicePlanes::U16(bufs) => Some((area, &bufs[plane])),
+                    SlicePlanes::U8(_) => None,
+                })
+                .collect();
+            assemble(&mut self.row16, width, y, &slices);
+            sink.write_row16(plane, y, &self.row16)?;
+        } else {
+            let slices: Vec<_> = covering
+                .filter_map(|(area, data)| match data {
+                    SlicePlanes::U8(bufs) => Some((area, &bufs[plane])),
+                    SlicePlanes::U16(_) => None,
+                })
+                .collect();
+            assemble(&mut self.row, width, y, &slices);
+            sink.write_row(plane, y, &self.row)?;
+        }
+        self.next_row[plane] += 1;
+        Ok(())
+    }
+}
+
+/// Adds the rows handed to a sink to the statistics of their plane.
+pub(crate) struct StatsSink<'a> {
+    sink: &'a mut dyn OutputSink,
+    stats: &'a mut [PlaneStats],
+}
+
+impl<'a> StatsSink<'a> {
+    pub(crate) fn new(
+        sink: &'a mut dyn OutputSink,
+        stats: &'a mut [PlaneStats],
+    ) -> Self {
+        Self { sink, stats }
+    }
+}
+
+impl OutputSink for StatsSink<'_> {
+    fn write_row(&mut self, plane: usize, y: usize, row: &[u8]) -> Result<()> {
+        if let Some(stats) = self.stats.get_mut(plane) {
+            stats.add_row(row);
+        }
+        self.sink.write_row(plane, y, row)
+    }
+
+    fn write_row16(
+        &mut self,
+        plane: usize,
+        y: usize,
+        row: &[u16],
+    ) -> Result<()> {
+        if let Some(stats) = self.stats.get_mut(plane) {
+            stats.add_row(row);
+        }
+        self.sink.write_row16(plane, y, row)
+    }
+
+    fn plane_done(&mut self, plane: usize) -> Result<()> {
+        self.sink.plane_done(plane)
+    }
+}
@@ -1,28 +1,499 @@
+use std::ops::Range;
+use std::sync::Arc;
+
 use crate::constants::CONTEXT_SIZE;
-use crate::error::Result;
-use crate::golomb::State;
-use crate::range::RangeCoder;
+use crate::crc32mpeg2::crc32_mpeg2;
+use crate::diagnostics::Diagnostic;
+use crate::error::{Error, Result};
+use crate::golombcoder::golomb::State;
+use crate::rangecoder::range::RangeCoder;
+use crate::record::ConfigRecord;
+use crate::stats::CodingStats;
 
 #[derive(Debug, Clone, Default)]
 pub struct InternalFrame {
     pub keyframe: bool,
     pub slice_info: Vec<SliceInfo>,
     pub slices: Vec<Slice>,
+    pub(crate) states: ContextStates,
+}
+
+/// Range coder context states of all the slices of a frame, backed by a
+/// single allocation.
+///
+/// The states of a slice are laid out by plane slot, i.e. luma, chroma and
+/// alpha, then by context, every context taking CONTEXT_SIZE bytes. Every
+/// slot has room for the largest quantization table set, since the set
+/// of a slot is only known from the slice header.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ContextStates {
+    data: Vec<u8>,
+    /// Quantization table set of every slot of every slice, UNSET until
+    /// the states of the slot are reset.
+    sets: Vec<u8>,
+    /// Number of slots of a slice.
+    slots: usize,
+    /// Size of the states of a slot, in bytes.
+    slot_size: usize,
+    /// Offsets of the quantization table sets within the initial states,
+    /// in contexts, followed by the number of contexts of all the sets.
+    set_offsets: Vec<usize>,
+    /// Initial states of every quantization table set, shared by the
+    /// clones and snapshots of the states.
+    initial: Arc<[u8]>,
+}
+
+/// Quantization table set of the slots whose states were never reset, or
+/// were abandoned.
+const UNSET: u8 = u8::MAX;
+
+impl ContextStates {
+    /// Creates an empty set of states, initialized from the
+    /// configuration record on reset.
+    ///
+    /// See: 3.8.1.3. Initial Values for the Context Model
+    pub(crate) fn new(record: &ConfigRecord) -> Self {
+        let mut set_offsets = vec![0];
+        let mut initial = Vec::new();
+        for set in &record.initial_states {
+            set_offsets.push(set_offsets[set_offsets.len() - 1] + set.len());
+            for context in set {
+                initial.extend_from_slice(context);
+            }
+        }
+        let largest = record.initial_states.iter().map(Vec::len).max();
+        Self {
+            data: Vec::new(),
+            sets: Vec::new(),
+            slots: record.quant_table_set_index_count(),
+            slot_size: largest.unwrap_or(0) * CONTEXT_SIZE,
+            set_offsets,
+            initial: initial.into(),
+        }
+    }
+
+    /// Creates an empty set of states with the same initial states.
+    pub(crate) fn empty_like(&self) -> Self {
+        Self {
+            data: Vec::new(),
+            sets: Vec::new(),
+            slots: self.slots,
+            slot_size: self.slot_size,
+            set_offsets: self.set_offsets.clone(),
+            initial: self.initial.clone(),
+        }
+    }
+
+    /// Size of the states of a slice, in bytes.
+    fn slice_size(&self) -> usize {
+        self.slots * self.slot_size
+    }
+
+    /// Returns the number of contexts of every quantization table set.
+    pub(crate) fn context_counts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.set_offsets.windows(2).map(|w| w[1] - w[0])
+    }
+
+    /// Whether both sets of states come from the same initial states.
+    pub(crate) fn same_layout(&self, other: &Self) -> bool {
+        self.slots == other.slots
+            && self.set_offsets == other.set_offsets
+            && (Arc::ptr_eq(&self.initial, &other.initial)
+                || self.initial == other.initial)
+    }
+
+    /// Sets the number of slices, without initializing their states.
+    pub(crate) fn resize(&mut self, slices: usize) {
+        self.data.resize(slices * self.slice_size(), 0);
+        self.sets.resize(slices * self.slots, UNSET);
+    }
+
+    /// Reserves room for the states of 'slices' slices, so that resizing
+    /// to as many does not allocate.
+    pub(crate) fn reserve(&mut self, slices: usize) {
+        let len = slices * self.slice_size();
+        self.data.reserve(len.saturating_sub(self.data.len()));
+        let len = slices * self.slots;
+        self.sets.reserve(len.saturating_sub(self.sets.len()));
+    }
+
+    /// Returns the quantization table set and the states of every slot of
+    /// a slice, as bytes.
+    fn slice(&self, slice: usize) -> impl Iterator<Item = (u8, &[u8])> + '_ {
+        let sets = &self.sets[slice * self.slots..(slice + 1) * self.slots];
+        let size = self.slice_size();
+        let data = &self.data[slice * size..(slice + 1) * size];
+        sets.iter().zip(data.chunks(self.slot_size.max(1))).map(
+            move |(&set, data)| {
+                let count = self.context_count(set);
+                (set, &data[..count * CONTEXT_SIZE])
+            },
+        )
+    }
+
+    /// Returns the number of contexts of a quantization table set, 0 for
+    /// UNSET.
+    fn context_count(&self, set: u8) -> usize {
+        self.context_counts().nth(set as usize).unwrap_or(0)
+    }
+
+    /// Returns the states of every slice, e.g. to decode them on several
+    /// threads.
+    pub(crate) fn slices_mut(
+        &mut self,
+    ) -> impl Iterator<Item = SliceStates<'_>> + '_ {
+        let size = self.slice_size();
+        let slot_size = self.slot_size;
+        let set_offsets = &self.set_offsets;
+        let initial = &self.initial;
+        self.data
+            .chunks_mut(size.max(1))
+            .zip(self.sets.chunks_mut(self.slots.max(1)))
+            .map(move |(data, sets)| SliceStates {
+                data,
+                sets,
+                slot_size,
+                set_offsets,
+                initial,
+            })
+    }
+}
+
+/// Range coder context states of a slice.
+pub(crate) struct SliceStates<'a> {
+    data: &'a mut [u8],
+    sets: &'a mut [u8],
+    slot_size: usize,
+    set_offsets: &'a [usize],
+    initial: &'a [u8],
+}
+
+impl<'a> SliceStates<'a> {
+    /// Returns the state of a context of a plane slot.
+    pub(crate) fn context(
+        &mut self,
+        slot: usize,
+        context: usize,
+    ) -> &mut [u8] {
+        let start = slot * self.slot_size + context * CONTEXT_SIZE;
+        &mut self.data[start..start + CONTEXT_SIZE]
+    }
+
+    /// Resets the states of every plane slot to the initial states of its
+    /// quantization table set in 'quant_table_set_index'.
+    ///
+    /// When the indexes do not fit the slots, e.g. for a slice whose header
+    /// is damaged, the states are left unset instead, and the slice cannot
+    /// be decoded until the next keyframe.
+    pub(crate) fn reset(&mut self, quant_table_set_index: &[u8]) {
+        let set_count = self.set_offsets.len() - 1;
+        if quant_table_set_index.len() != self.sets.len()
+            || quant_table_set_index
+                .iter()
+                .any(|&set| set as usize >= set_count)
+        {
+            self.sets.iter_mut().for_each(|set| *set = UNSET);
+            return;
+        }
+        self.sets.copy_from_slice(quant_table_set_index);
+        for (slot, &set) in quant_table_set_index.iter().enumerate() {
+            let set = set as usize;
+            let initial = &self.initial[self.set_offsets[set] * CONTEXT_SIZE
+                ..self.set_offsets[set + 1] * CONTEXT_SIZE];
+            let start = slot * self.slot_size;
+            self.data[start..start + initial.len()].copy_from_slice(initial);
+        }
+    }
+
+    /// Whether the states were reset for 'quant_table_set_index', so that
+    /// a frame which is not a keyframe carries on from them.
+    pub(crate) fn matches(&self, quant_table_set_index: &[u8]) -> bool {
+        *self.sets == *quant_table_set_index
+    }
+}
+
+/// Identifies serialized decoder states, followed by the format version.
+const STATE_MAGIC: &[u8; 8] = b"FFV1STA\x03";
+
+impl InternalFrame {
+    /// Serializes the states carried over to the next frame: the keyframe
+    /// flag, and the range coder states of every plane slot of every slice,
+    /// after the quantization table set of the slot, followed by the
+    /// Golomb-Rice states of every plane slot of the slice.
+    ///
+    /// All integers are little-endian, and every list is prefixed with
+    /// its length as a 32-bit integer.
+    pub(crate) fn serialize_states(&self) -> Vec<u8> {
+        let mut out = STATE_MAGIC.to_vec();
+        let put_len = |out: &mut Vec<u8>, len: usize| {
+            out.extend_from_slice(&(len as u32).to_le_bytes())
+        };
+        out.push(self.keyframe as u8);
+        put_len(&mut out, self.slices.len());
+        for (i, slice) in self.slices.iter().enumerate() {
+            put_len(&mut out, self.states.slots);
+            for (set, states) in self.states.slice(i) {
+                put_len(&mut out, set as usize);
+                put_len(&mut out, states.len() / CONTEXT_SIZE);
+                out.extend_from_slice(states);
+            }
+            put_len(&mut out, slice.golomb_state.len());
+            for slot in &slice.golomb_state {
+                put_len(&mut out, slot.len());
+                for state in slot {
+                    out.extend_from_slice(&state.to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    /// Deserializes states written by 'serialize_states', checking that
+    /// they match the configuration record, from which 'layout' was
+    /// created.
+    pub(crate) fn deserialize_states(
+        buf: &[u8],
+        record: &ConfigRecord,
+        layout: &ContextStates,
+    ) -> Result<Self> {
+        let mismatch = || {
+            Error::InvalidInputData(
+                "decoder state does not match the configuration record"
+                    .to_owned(),
+            )
+        };
+        let context_counts = record.context_counts();
+        let golomb_slots = if record.coder_type == 0 {
+            layout.slots
+        } else {
+            0
+        };
+
+        let mut reader = StateReader { buf };
+        if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(Error::InvalidInputData(
+                "not a serialized decoder state".to_owned(),
+            ));
+        }
+        let keyframe = reader.take(1)?[0] != 0;
+        let slice_count = reader.len()?;
+        let mut states = layout.empty_like();
+        let mut data = Vec::new();
+        let mut sets = Vec::new();
+        let mut counts = Vec::new();
+        let mut slices = Vec::new();
+        for _ in 0..slice_count {
+            let mut slice = Slice::default();
+            counts.clear();
+            if reader.len()? != states.slots {
+                return Err(mismatch());
+            }
+            for _ in 0..states.slots {
+                // Slots never reset hold no states
+                let set = reader.len()?;
+                let count = match set {
+                    set if set == UNSET as usize => 0,
+                    set if set < context_counts.len() => {
+                        context_counts[set] as usize
+                    }
+                    _ => return Err(mismatch()),
+                };
+                if reader.len()? != count {
+                    return Err(mismatch());
+                }
+                sets.push(set as u8);
+                counts.push(count);
+                let start = data.len();
+                data.extend_from_slice(reader.take(count * CONTEXT_SIZE)?);
+                data.resize(start + states.slot_size, 0);
+            }
+            // The Golomb-Rice states of a slot are reset along with its
+            // range coder states, for the same set
+            if reader.len()? != golomb_slots {
+                return Err(mismatch());
+            }
+            for &count in &counts[..golomb_slots] {
+                if reader.len()? != count {
+                    return Err(mismatch());
+                }
+                let bytes = reader.take(count * State::SERIALIZED_SIZE)?;
+                slice.golomb_state.push(
+                    bytes
+                        .chunks_exact(State::SERIALIZED_SIZE)
+                        .map(|c| {
+                            let mut state = [0; State::SERIALIZED_SIZE];
+                            state.copy_from_slice(c);
+                            State::from_le_bytes(&state).ok_or_else(|| {
+                                Error::InvalidInputData(
+                                    "invalid Golomb-Rice state".to_owned(),
+                                )
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                );
+            }
+            slices.push(slice);
+        }
+        if !reader.buf.is_empty() {
+            return Err(Error::InvalidInputData(
+                "trailing data after decoder state".to_owned(),
+            ));
+        }
+        states.data = data;
+        states.sets = sets;
+
+        Ok(Self {
+            keyframe,
+            slice_info: Vec::new(),
+            slices,
+            states,
+        })
+    }
+}
+
+/// Reads the fields of a serialized decoder state.
+struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.buf.len() {
+            return Err(Error::InvalidInputData(
+                "truncated decoder state".to_owned(),
+            ));
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    }
 }
 
+/// Position and integrity of a slice within a packet, see
+/// 'Decoder::slice_map'.
 #[derive(Debug, Clone, Default, Copy)]
 pub struct SliceInfo {
     pub(crate) pos: usize,
     pub(crate) size: usize,
     pub(crate) error_status: u8,
+    pub(crate) crc_valid: Option<bool>,
+}
+
+impl SliceInfo {
+    /// Position of the first byte of the slice within the packet.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Size of the slice content, without its footer, in bytes.
+    ///
+    /// See: 4.8.1. slice_size
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// error_status of the slice footer, always 0 without error
+    /// correction.
+    ///
+    /// See: 4.8.2. error_status
+    pub fn error_status(&self) -> u8 {
+        self.error_status
+    }
+
+    /// Whether slice_crc_parity matches the slice, None when it was not
+    /// checked, i.e. without error correction, for slices marked as
+    /// damaged, or for the slices not reached once decoding failed.
+    ///
+    /// See: 4.8.3. slice_crc_parity
+    pub fn crc_valid(&self) -> Option<bool> {
+        self.crc_valid
+    }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Slice {
     pub(crate) header: SliceHeader,
-    pub(crate) state: Vec<Vec<Vec<u8>>>,
     pub(crate) golomb_state: Vec<Vec<State>>,
     pub(crate) planes: Vec<SlicePlane>,
+    /// Findings of the last decoding of the slice.
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Why the slice was abandoned, see 'Decoder::set_resync_enabled'.
+    pub(crate) abandoned: Option<String>,
+    /// Whether the slice was concealed instead of being decoded, see
+    /// 'Decoder::decode_frame_with_deadline'.
+    pub(crate) concealed: bool,
+    /// Statistics of the symbols of the last decoding of the slice, see
+    /// 'Decoder::set_coding_stats_enabled'.
+    pub(crate) coding_stats: Option<CodingStats>,
+}
+
+impl Slice {
+    /// Creates a slice holding everything the decoding of a slice of
+    /// 'record' stores, so that decoding it does not allocate.
+    pub(crate) fn preallocated(record: &ConfigRecord) -> Self {
+        let mut slice = Slice {
+            planes: Vec::with_capacity(4),
+            ..Default::default()
+        };
+        slice.header.quant_table_set_index = Vec::with_capacity(3);
+        if record.coder_type == 0 {
+            // Every plane slot has room for the largest set
+            let counts = &record.context_count[..record.quant_table_set_count];
+            let largest = counts.iter().copied().max().unwrap_or(0) as usize;
+            slice.golomb_state = (0..record.quant_table_set_index_count())
+                .map(|_| Vec::with_capacity(largest))
+                .collect();
+        }
+        slice
+    }
+
+    /// Forgets what the last decoding of the slice found, keeping its
+    /// Golomb-Rice states and its allocations.
+    pub(crate) fn clear(&mut self) {
+        let mut quant_table_set_index =
+            std::mem::take(&mut self.header.quant_table_set_index);
+        quant_table_set_index.clear();
+        self.header = SliceHeader {
+            quant_table_set_index,
+            ..Default::default()
+        };
+        self.planes.clear();
+        self.diagnostics.clear();
+        self.abandoned = None;
+        self.concealed = false;
+        if let Some(ref mut stats) = self.coding_stats {
+            stats.clear();
+        }
+    }
+
+    /// Resets the Golomb-Rice states of every plane slot, for the
+    /// quantization table set of the slot in the slice header.
+    ///
+    /// When the indexes do not fit the slots, e.g. for a slice whose header
+    /// is damaged, the states are left empty instead.
+    ///
+    /// See: 3.8.2.4. Initial Values for the VLC context state
+    pub(crate) fn reset_golomb_states(&mut self, record: &ConfigRecord) {
+        let slots = record.quant_table_set_index_count();
+        let index = &self.header.quant_table_set_index;
+        let valid = index.len() == slots
+            && index
+                .iter()
+                .all(|&set| (set as usize) < record.quant_table_set_count);
+        self.golomb_state.resize_with(slots, Vec::new);
+        for (slot, states) in self.golomb_state.iter_mut().enumerate() {
+            states.clear();
+            if valid {
+                let count = record.context_count[index[slot] as usize];
+                states.resize(count as usize, Default::default());
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -37,16 +508,113 @@ pub struct SliceHeader {
     pub(crate) sar_den: u32,
 }
 
+impl SliceHeader {
+    /// Horizontal position of the slice, in slice grid units.
+    ///
+    /// See: 4.5.1. slice_x
+    pub fn slice_x(&self) -> u32 {
+        self.slice_x
+    }
+
+    /// Vertical position of the slice, in slice grid units.
+    ///
+    /// See: 4.5.2. slice_y
+    pub fn slice_y(&self) -> u32 {
+        self.slice_y
+    }
+
+    /// Width of the slice, in slice grid units.
+    ///
+    /// See: 4.5.3. slice_width
+    pub fn slice_width(&self) -> u32 {
+        self.slice_width_minus1 + 1
+    }
+
+    /// Height of the slice, in slice grid units.
+    ///
+    /// See: 4.5.4. slice_height
+    pub fn slice_height(&self) -> u32 {
+        self.slice_height_minus1 + 1
+    }
+
+    /// Quantization table set used by every plane, in the order luma,
+    /// chroma, alpha.
+    ///
+    /// See: 4.5.6. quant_table_set_index
+    pub fn quant_table_set_index(&self) -> &[u8] {
+        &self.quant_table_set_index
+    }
+
+    /// See: 4.5.7. picture_structure
+    pub fn picture_structure(&self) -> u8 {
+        self.picture_structure
+    }
+
+    /// Sample aspect ratio, as numerator and denominator, 0 if unknown.
+    ///
+    /// See: * 4.5.8. sar_num
+    ///      * 4.5.9. sar_den
+    pub fn sar(&self) -> (u32, u32) {
+        (self.sar_num, self.sar_den)
+    }
+
+    /// Returns the position and size of the slice in a frame of the
+    /// configuration 'record', in pixels, as x, y, width and height.
+    ///
+    /// See: * 4.6.3. slice_pixel_height
+    ///      * 4.6.4. slice_pixel_y
+    ///      * 4.7.2. slice_pixel_width
+    ///      * 4.7.3. slice_pixel_x
+    pub fn rect(&self, record: &ConfigRecord) -> (u32, u32, u32, u32) {
+        let h_slices = record.num_h_slices_minus1 as u32 + 1;
+        let v_slices = record.num_v_slices_minus1 as u32 + 1;
+        let start_x = self.slice_x * record.width / h_slices;
+        let start_y = self.slice_y * record.height / v_slices;
+        let width = (self.slice_x + self.slice_width_minus1 + 1)
+            * record.width
+            / h_slices
+            - start_x;
+        let height = (self.slice_y + self.slice_height_minus1 + 1)
+            * record.height
+            / v_slices
+            - start_y;
+        (start_x, start_y, width, height)
+    }
+}
+
+/// Layout of a slice within a packet, see 'Decoder::slice_layout'.
+#[derive(Debug, Clone)]
+pub struct SliceLayout {
+    /// Byte range of the slice content within the packet.
+    pub range: Range<usize>,
+    /// Byte range of the slice footer within the packet.
+    ///
+    /// See: 4.8. Slice Footer
+    pub footer: Range<usize>,
+    /// error_status of the slice footer, always 0 without error
+    /// correction.
+    pub error_status: u8,
+    /// Whether slice_crc_parity matches the slice, None without error
+    /// correction.
+    pub crc_valid: Option<bool>,
+    /// Header of the slice.
+    pub header: SliceHeader,
+    /// Position and size of the slice within the frame, in pixels, as x,
+    /// y, width and height.
+    ///
+    /// See: * 4.6.3. slice_pixel_height
+    ///      * 4.6.4. slice_pixel_y
+    ///      * 4.7.2. slice_pixel_width
+    ///      * 4.7.3. slice_pixel_x
+    pub rect: (u32, u32, u32, u32),
+}
+
 #[derive(Debug, Clone)]
 pub struct SlicePlane {
-    #[allow(dead_code)]
     pub(crate) start_x: u32,
-    #[allow(dead_code)]
     pub(crate) start_y: u32,
     pub(crate) width: u32,
     pub(crate) height: u32,
-    pub(crate) stride: u32,
-    pub(crate) offset: usize,
     pub(crate) quant: u8,
 }
 
@@ -62,11 +630,59 @@ pub fn is_keyframe(buf: &[u8]) -> bool {
     coder.br(&mut state)
 }
 
+/// Keyframe flag and slice footers of a packet, read ahead of decoding its
+/// slices, e.g. on another thread, see 'PipelinedDecoder'.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FrontEnd {
+    pub(crate) keyframe: bool,
+    /// Slice footers, with the CRC of each slice checked when error
+    /// correction is on, or None when they are invalid.
+    pub(crate) slice_info: Option<Vec<SliceInfo>>,
+}
+
+impl FrontEnd {
+    /// Reads the keyframe flag and the slice footers of a packet, and
+    /// checks the CRCs of its slices when 'ec' is set.
+    ///
+    /// See: * 4.3. Frame
+    ///      * 4.8. Slice Footer
+    pub(crate) fn read(buf: &[u8], ec: bool) -> Self {
+        let slice_info = count_slices(buf, ec).ok().map(|mut slice_info| {
+            for info in slice_info.iter_mut() {
+                if ec && info.error_status == 0 {
+                    // 8 bytes for footer size
+                    let slice_buf = &buf[info.pos..][..info.size + 8];
+                    info.crc_valid = Some(crc32_mpeg2(slice_buf) == 0);
+                }
+            }
+            slice_info
+        });
+        Self {
+            keyframe: is_keyframe(buf),
+            slice_info,
+        }
+    }
+}
+
 /// Counts the number of slices in a frame, as described in
 /// 9.1.1. Multi-threading Support and Independence of Slices.
 ///
 /// See: 4.8. Slice Footer
 pub fn count_slices(buf: &[u8], ec: bool) -> Result<Vec<SliceInfo>> {
+    let mut slice_info = Vec::new();
+    read_footers(buf, ec, usize::MAX, &mut slice_info)?;
+    Ok(slice_info)
+}
+
+/// Reads the slice footers of a frame into 'slice_info' like
+/// 'count_slices', reusing its allocation, and failing once more than
+/// 'max_slices' slices are found.
+pub(crate) fn read_footers(
+    buf: &[u8],
+    ec: bool,
+    max_slices: usize,
+    slice_info: &mut Vec<SliceInfo>,
+) -> Result<()> {
     let mut footer_size = 3;
     if ec {
         footer_size += 5;
@@ -76,10 +692,24 @@ pub fn count_slices(buf: &[u8], ec: bool) -> Result<Vec<SliceInfo>> {
     // so we can derive the slice positions within the packet, and
     // allow multithreading.
     let mut end_pos = buf.len();
-    let mut slice_info = Vec::new();
+    slice_info.clear();
     while end_pos > 0 {
         let mut info: SliceInfo = Default::default();
 
+        if slice_info.len() == max_slices {
+            return Err(Error::ResourceError(format!(
+                "more than {} slices",
+                max_slices
+            )));
+        }
+
+        if end_pos < footer_size {
+            return Err(Error::SliceError(format!(
+                "truncated slice footer at byte {}",
+                end_pos
+            )));
+        }
+
         // 4.8.1. slice_size
         let mut size = (buf[end_pos - footer_size] as u32) << 16;
         size |= (buf[end_pos - footer_size + 1] as u32) << 8;
@@ -87,8 +717,17 @@ pub fn count_slices(buf: &[u8], ec: bool) -> Result<Vec<SliceInfo>> {
         info.size = size as usize;
 
         // 4.8.2. error_status
-        info.error_status = buf[end_pos - footer_size + 3] as u8;
+        if ec {
+            info.error_status = buf[end_pos - footer_size + 3] as u8;
+        }
 
+        if info.size > end_pos - footer_size {
+            return Err(Error::SliceError(format!(
+                "slice size {} exceeds the {} bytes before its footer",
+                info.size,
+                end_pos - footer_size
+            )));
+        }
         let pos = end_pos - info.size - footer_size;
         info.pos = pos;
         slice_info.push(info);
@@ -98,5 +737,5 @@ pub fn count_slices(buf: &[u8], ec: bool) -> Result<Vec<SliceInfo>> {
     // Preappend here
     slice_info.reverse();
 
-    Ok(slice_info)
+    Ok(())
 }
@@ -0,0 +1,184 @@
+//! Analysis of the state transition tables of the range coder.
+//!
+//! A table maps the state of a context after coding a 1 to its next
+//! state, the transitions after a 0 being derived from it. Streams with
+//! 'coder_type' 2 store their own table in the configuration record, as
+//! differences from the default one.
+//!
+//! See: * 3.8.1.4. State Transition Table
+//!      * 4.1.4. state_transition_delta
+
+use crate::error::{Error, Result};
+use crate::rangecoder::tables::DEFAULT_STATE_TRANSITION;
+
+/// The table FFmpeg stores in the streams it codes with 'coder_type' 2.
+pub const FFMPEG_STATE_TRANSITION: [u8; 256] = [
+    0, 10, 10, 10, 10, 16, 16, 16, 28, 16, 16, 29, 42, 49, 20, 49, 59, 25, 26,
+    26, 27, 31, 33, 33, 33, 34, 34, 37, 67, 38, 39, 39, 40, 40, 41, 79, 43,
+    44, 45, 45, 48, 48, 64, 50, 51, 52, 88, 52, 53, 74, 55, 57, 58, 58, 74,
+    60, 101, 61, 62, 84, 66, 66, 68, 69, 87, 82, 71, 97, 73, 73, 82, 75, 111,
+    77, 94, 78, 87, 81, 83, 97, 85, 83, 94, 86, 99, 89, 90, 99, 111, 92, 93,
+    134, 95, 98, 105, 98, 105, 110, 102, 108, 102, 118, 103, 106, 106, 113,
+    109, 112, 114, 112, 116, 125, 115, 116, 117, 117, 126, 119, 125, 121, 121,
+    123, 145, 124, 126, 131, 127, 129, 165, 130, 132, 138, 133, 135, 145, 136,
+    137, 139, 146, 141, 143, 142, 144, 148, 147, 155, 151, 149, 151, 150, 152,
+    157, 153, 154, 156, 168, 158, 162, 161, 160, 172, 163, 169, 164, 166, 184,
+    167, 170, 177, 174, 171, 173, 182, 176, 180, 178, 175, 189, 179, 181, 186,
+    183, 192, 185, 200, 187, 191, 188, 190, 197, 193, 196, 197, 194, 195, 196,
+    198, 202, 199, 201, 210, 203, 207, 204, 205, 206, 208, 214, 209, 211, 221,
+    212, 213, 215, 224, 216, 217, 218, 219, 220, 222, 228, 223, 225, 226, 224,
+    227, 229, 240, 230, 231, 232, 233, 234, 235, 236, 238, 239, 237, 242, 241,
+    243, 242, 244, 245, 246, 247, 248, 249, 250, 251, 252, 252, 253, 254, 255,
+];
+
+/// Returns the curated tables, with their names.
+pub fn presets() -> [(&'static str, &'static [u8; 256]); 2] {
+    [
+        ("default", &DEFAULT_STATE_TRANSITION),
+        ("ffmpeg", &FFMPEG_STATE_TRANSITION),
+    ]
+}
+
+/// Returns the table coded by the differences 'delta' from the default
+/// table, or an error when a state falls out of a byte.
+///
+/// See: 4.1.4. state_transition_delta
+pub fn from_delta(delta: &[i16; 256]) -> Result<[u8; 256]> {
+    let mut table = [0; 256];
+    for i in 1..256 {
+        let state = DEFAULT_STATE_TRANSITION[i] as i32 + delta[i] as i32;
+        if !(0..=255).contains(&state) {
+            return Err(Error::InvalidConfiguration(format!(
+                "invalid state transition {}: {}",
+                i, state
+            )));
+        }
+        table[i] = state as u8;
+    }
+    Ok(table)
+}
+
+/// Returns the differences from the default table coding 'table' in a
+/// configuration record.
+pub fn to_delta(table: &[u8; 256]) -> [i16; 256] {
+    let mut delta = [0; 256];
+    for i in 1..256 {
+        delta[i] = table[i] as i16 - DEFAULT_STATE_TRANSITION[i] as i16;
+    }
+    delta
+}
+
+/// Returns the transitions after coding a 0, derived from the transitions
+/// after coding a 1 in 'table'.
+///
+/// See: 3.8.1.4. State Transition Table
+pub fn zero_states(table: &[u8; 256]) -> [u8; 256] {
+    let mut zero_state = [0; 256];
+    for i in 1..255 {
+        zero_state[i] = (256 - table[256 - i] as u16) as u8;
+    }
+    zero_state
+}
+
+/// Returns the number of bits needed to code 'bit' in 'state'.
+///
+/// The range coder gives a 1 a probability of 'state' / 256, so a 1
+/// cannot be coded in state 0, and costs an infinite length.
+pub fn code_length(state: u8, bit: bool) -> f64 {
+    let ones = state as f64 / 256.0;
+    let probability = if bit { ones } else { 1.0 - ones };
+    -probability.log2()
+}
+
+/// Statistics of a state over the coded bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StateStats {
+    /// Number of bits coded in the state.
+    pub visits: u64,
+    /// Number of 1s among them.
+    pub ones: u64,
+    /// Total length of their codes, in bits.
+    pub code_length: f64,
+}
+
+impl StateStats {
+    /// Returns the expected code length of a bit coded in the state, or
+    /// None when no bit has been.
+    pub fn expected_code_length(&self) -> Option<f64> {
+        if self.visits == 0 {
+            None
+        } else {
+            Some(self.code_length / self.visits as f64)
+        }
+    }
+}
+
+/// Evaluates a table against sample bits, e.g. to tune a table for an
+/// encoder or compare it with the presets.
+///
+/// Bits are coded as a range coder would, from the states of the caller,
+/// one per context, while the code length spent in every state is
+/// gathered.
+#[derive(Debug, Clone)]
+pub struct TransitionAnalysis {
+    one_state: [u8; 256],
+    zero_state: [u8; 256],
+    stats: Vec<StateStats>,
+}
+
+impl TransitionAnalysis {
+    /// Creates an analysis of 'table'.
+    pub fn new(table: &[u8; 256]) -> Self {
+        Self {
+            one_state: *table,
+            zero_state: zero_states(table),
+            stats: vec![StateStats::default(); 256],
+        }
+    }
+
+    /// Codes 'bit' in 'state', moving it to its next state.
+    pub fn code(&mut self, state: &mut u8, bit: bool) {
+        let stats = &mut self.stats[*state as usize];
+        stats.visits += 1;
+        stats.ones += bit as u64;
+        stats.code_length += code_length(*state, bit);
+        *state = if bit {
+            self.one_state[*state as usize]
+        } else {
+            self.zero_state[*state as usize]
+        };
+    }
+
+    /// Codes the bits of a context from 'state', returning its final
+    /// state.
+    pub fn code_bits<I>(&mut self, mut state: u8, bits: I) -> u8
+    where
+        I: IntoIterator<Item = bool>,
+    {
+        for bit in bits {
+            self.code(&mut state, bit);
+        }
+        state
+    }
+
+    /// Returns the statistics of a state.
+    pub fn state_stats(&self, state: u8) -> &StateStats {
+        &self.stats[state as usize]
+    }
+
+    /// Returns the expected code length of a bit coded in 'state', see
+    /// 'StateStats::expected_code_length'.
+    pub fn expected_code_length(&self, state: u8) -> Option<f64> {
+        self.state_stats(state).expected_code_length()
+    }
+
+    /// Returns the number of coded bits.
+    pub fn visits(&self) -> u64 {
+        self.stats.iter().map(|stats| stats.visits).sum()
+    }
+
+    /// Returns the total code length of the coded bits, in bits.
+    pub fn code_length(&self) -> f64 {
+        self.stats.iter().map(|stats| stats.code_length).sum()
+    }
+}
@@ -0,0 +1,347 @@
+//! Decoding statistics.
+
+use std::time::{Duration, Instant};
+
+use crate::record::ConfigRecord;
+
+/// Time spent in every stage of decoding, accumulated over all the frames
+/// decoded since the statistics were enabled.
+///
+/// It tells whether a stream is slow to decode because of its CRCs, its
+/// entropy coding or its JPEG2000-RCT conversion.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// Number of frames decoded.
+    pub frames: u64,
+    /// Time spent parsing slice footers.
+    pub footers: Duration,
+    /// Time spent checking slice CRCs.
+    pub crc: Duration,
+    /// Time spent parsing slice headers.
+    pub slice_headers: Duration,
+    /// Time spent decoding samples.
+    pub samples: Duration,
+    /// Time spent converting JPEG2000-RCT to RGB.
+    pub rct: Duration,
+}
+
+impl DecodeStats {
+    /// Adds the stage timings of 'other', e.g. measured on another thread.
+    pub(crate) fn add(&mut self, other: &DecodeStats) {
+        self.footers += other.footers;
+        self.crc += other.crc;
+        self.slice_headers += other.slice_headers;
+        self.samples += other.samples;
+        self.rct += other.rct;
+    }
+}
+
+/// Time spent by the threads decoding the slices of frames, accumulated
+/// over all the frames decoded on several threads since the statistics
+/// were enabled.
+///
+/// It tells how well the slices of a stream keep its threads busy, e.g.
+/// whether a few large slices leave most threads waiting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Number of frames decoded on several threads.
+    pub frames: u64,
+    /// Time from starting the slice threads of a frame to joining them.
+    pub wall: Duration,
+    /// Time spent decoding slices, by thread, the first thread of a frame
+    /// being thread 0.
+    pub busy: Vec<Duration>,
+}
+
+impl ThreadStats {
+    /// Adds the slice threads of a frame, which ran for 'wall' and were
+    /// busy for 'busy'.
+    pub(crate) fn add(&mut self, wall: Duration, busy: &[Duration]) {
+        self.frames += 1;
+        self.wall += wall;
+        if self.busy.len() < busy.len() {
+            self.busy.resize(busy.len(), Duration::ZERO);
+        }
+        for (total, busy) in self.busy.iter_mut().zip(busy) {
+            *total += *busy;
+        }
+    }
+
+    /// Returns the fraction of the time 'thread' spent decoding slices
+    /// while slice threads ran, from 0 to 1.
+    pub fn utilization(&self, thread: usize) -> f64 {
+        let busy = self.busy.get(thread).copied().unwrap_or_default();
+        if self.wall.is_zero() {
+            0.0
+        } else {
+            busy.as_secs_f64() / self.wall.as_secs_f64()
+        }
+    }
+}
+
+/// A stage of decoding.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stage {
+    Footers,
+    Crc,
+    SliceHeaders,
+    Samples,
+    Rct,
+}
+
+/// Measures the time spent in a stage, if statistics are enabled.
+pub(crate) struct Timer(Option<Instant>);
+
+impl Timer {
+    pub(crate) fn start(stats: &Option<DecodeStats>) -> Self {
+        Self(stats.as_ref().map(|_| Instant::now()))
+    }
+
+    /// Adds the time elapsed since the timer started to 'stage'.
+    pub(crate) fn stop(self, stats: &mut Option<DecodeStats>, stage: Stage) {
+        if let (Some(start), Some(stats)) = (self.0, stats.as_mut()) {
+            let elapsed = start.elapsed();
+            match stage {
+                Stage::Footers => stats.footers += elapsed,
+                Stage::Crc => stats.crc += elapsed,
+                Stage::SliceHeaders => stats.slice_headers += elapsed,
+                Stage::Samples => stats.samples += elapsed,
+                Stage::Rct => stats.rct += elapsed,
+            }
+        }
+    }
+}
+
+/// Statistics of the samples of a plane of the last decoded frame, see
+/// 'Decoder::set_pixel_stats_enabled'.
+///
+/// They tell whether a scan is clipped, a frame is dead or samples do not
+/// use the whole bit depth, e.g. 8-bit content stored as 10-bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaneStats {
+    /// Smallest sample.
+    pub min: u16,
+    /// Largest sample.
+    pub max: u16,
+    /// Number of samples.
+    pub samples: u64,
+    /// Number of samples of every value, from 0 to the largest value of the
+    /// bit depth.
+    pub histogram: Vec<u64>,
+    sum: u64,
+    bits: u16,
+}
+
+impl PlaneStats {
+    /// Creates empty statistics of samples of 'bit_depth' bits.
+    pub(crate) fn new(bit_depth: u8) -> Self {
+        Self {
+            min: u16::MAX,
+            max: 0,
+            samples: 0,
+            histogram: vec![0; 1 << bit_depth],
+            sum: 0,
+            bits: 0,
+        }
+    }
+
+    /// Empties the statistics, keeping the histogram allocation.
+    pub(crate) fn reset(&mut self) {
+        self.min = u16::MAX;
+        self.max = 0;
+        self.samples = 0;
+        self.histogram.iter_mut().for_each(|count| *count = 0);
+        self.sum = 0;
+        self.bits = 0;
+    }
+
+    /// Adds the samples of a row.
+    pub(crate) fn add_row<T: Copy + Into<u16>>(&mut self, row: &[T]) {
+        for &sample in row {
+            let sample = sample.into();
+            self.min = self.min.min(sample);
+            self.max = self.max.max(sample);
+            self.sum += sample as u64;
+            self.bits |= sample;
+            if let Some(count) = self.histogram.get_mut(sample as usize) {
+                *count += 1;
+            }
+        }
+        self.samples += row.len() as u64;
+    }
+
+    /// Returns the mean of the samples, 0 without any.
+    pub fn mean(&self) -> f64 {
+        if self.samples == 0 {
+            0.0
+        } else {
+            self.sum as f64 / self.samples as f64
+        }
+    }
+
+    /// Returns the number of samples at the smallest and at the largest
+    /// value of the bit depth, e.g. to detect clipped scans.
+    pub fn clipped(&self) -> (u64, u64) {
+        (
+            self.histogram.first().copied().unwrap_or(0),
+            self.histogram.last().copied().unwrap_or(0),
+        )
+    }
+
+    /// Returns whether every sample has the same value, e.g. for dead
+    /// frames.
+    pub fn is_flat(&self) -> bool {
+        self.min == self.max
+    }
+
+    /// Returns the number of low bits set in no sample, e.g. 2 for 8-bit
+    /// content stored as 10-bit.
+    pub fn unused_low_bits(&self) -> u32 {
+        if self.bits == 0 {
+            0
+        } else {
+            self.bits.trailing_zeros()
+        }
+    }
+}
+
+/// Statistics of the symbols coding the samples of a slice of the last
+/// decoded frame, see 'Decoder::set_coding_stats_enabled'.
+///
+/// They tell how an encoder spreads the samples over the contexts of its
+/// quantization tables, how long its runs are and how large the coded
+/// differences are, e.g. to design better quantization tables.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodingStats {
+    /// Number of sample differences decoded.
+    pub symbols: u64,
+    /// Number of sample differences decoded in every context, by
+    /// quantization table set then context.
+    pub contexts: Vec<Vec<u64>>,
+    /// Number of runs of zero differences in context 0 of every length, by
+    /// length, the first entry being always 0. Golomb-Rice mode codes them
+    /// in run mode.
+    pub runs: Vec<u64>,
+    /// Number of sample differences of every magnitude, the magnitude of a
+    /// difference being the number of significant bits of its absolute
+    /// value. Magnitudes of more than 31 bits, only found in damaged
+    /// slices, are counted in the last entry.
+    pub magnitudes: [u64; 32],
+    run: usize,
+}
+
+impl CodingStats {
+    /// Creates empty statistics for the quantization table sets of
+    /// 'record'.
+    pub(crate) fn new(record: &ConfigRecord) -> Self {
+        let counts = &record.context_count[..record.quant_table_set_count];
+        Self {
+            contexts: counts
+                .iter()
+                .map(|&count| vec![0; count as usize])
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Empties the statistics, keeping their allocations.
+    pub(crate) fn clear(&mut self) {
+        self.symbols = 0;
+        for contexts in &mut self.contexts {
+            contexts.iter_mut().for_each(|count| *count = 0);
+        }
+        self.runs.clear();
+        self.magnitudes = [0; 32];
+        self.run = 0;
+    }
+
+    /// Adds the difference 'diff' decoded in the context 'context' of the
+    /// quantization table set 'table_set'.
+    #[inline]
+    pub(crate) fn add_symbol(
+        &mut self,
+        table_set: usize,
+        context: usize,
+        diff: i32,
+    ) {
+        self.symbols += 1;
+        if let Some(count) = self
+            .contexts
+            .get_mut(table_set)
+            .and_then(|contexts| contexts.get_mut(context))
+        {
+            *count += 1;
+        }
+        let magnitude = 32 - diff.unsigned_abs().leading_zeros();
+        self.magnitudes[magnitude.min(31) as usize] += 1;
+        if context == 0 && diff == 0 {
+            self.run += 1;
+        } else {
+            self.end_run();
+        }
+    }
+
+    /// Ends the current run, at the end of a line or on a difference which
+    /// is not a zero in context 0.
+    pub(crate) fn end_run(&mut self) {
+        if self.run == 0 {
+            return;
+        }
+        if self.runs.len() <= self.run {
+            self.runs.resize(self.run + 1, 0);
+        }
+        self.runs[self.run] += 1;
+        self.run = 0;
+    }
+
+    /// Adds the statistics of 'other', e.g. of another slice.
+    pub fn add(&mut self, other: &CodingStats) {
+        self.symbols += other.symbols;
+        if self.contexts.len() < other.contexts.len() {
+            self.contexts.resize(other.contexts.len(), Vec::new());
+        }
+        for (total, contexts) in self.contexts.iter_mut().zip(&other.contexts)
+        {
+            if total.len() < contexts.len() {
+                total.resize(contexts.len(), 0);
+            }
+            for (total, count) in total.iter_mut().zip(contexts) {
+                *total += count;
+            }
+        }
+        if self.runs.len() < other.runs.len() {
+            self.runs.resize(other.runs.len(), 0);
+        }
+        for (total, count) in self.runs.iter_mut().zip(&other.runs) {
+            *total += count;
+        }
+        for (total, count) in self.magnitudes.iter_mut().zip(&other.magnitudes)
+        {
+            *total += count;
+        }
+    }
+
+    /// Returns the number of contexts of the quantization table set
+    /// 'table_set' in which at least a difference was decoded.
+    pub fn used_contexts(&self, table_set: usize) -> usize {
+        self.contexts.get(table_set).map_or(0, |contexts| {
+            contexts.iter().filter(|&&count| count > 0).count()
+        })
+    }
+
+    /// Returns the number of runs and their mean length, 0 without any.
+    pub fn run_lengths(&self) -> (u64, f64) {
+        let runs: u64 = self.runs.iter().sum();
+        let samples: u64 = self
+            .runs
+            .iter()
+            .enumerate()
+            .map(|(length, &count)| length as u64 * count)
+            .sum();
+        if runs == 0 {
+            (0, 0.0)
+        } else {
+            (runs, samples as f64 / runs as f64)
+        }
+    }
+}
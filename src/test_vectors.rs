@@ -0,0 +1,570 @@
+//! Tiny synthetic FFV1 streams exercising edge cases, e.g. for downstream
+//! projects to test their integration without large binary fixtures.
+//!
+//! Every vector is a single keyframe coded by a minimal encoder written
+//! from the specification, along with the samples it decodes to:
+//!
+//! ```no_run
+//! use ffv1::decoder::Decoder;
+//! use ffv1::test_vectors::test_vectors;
+//!
+//! for vector in test_vectors() {
+//!     let mut decoder =
+//!         Decoder::new(&vector.record, vector.width, vector.height).unwrap();
+//!     let frame = decoder.decode_frame(&vector.packet).unwrap();
+//!     for plane in 0..vector.planes.len() {
+//!         assert_eq!(frame.plane_md5(plane), vector.plane_md5(plane));
+//!     }
+//! }
+//! ```
+
+use crate::constants::{ColorSpace, CONTEXT_SIZE};
+use crate::crc32mpeg2::crc32_mpeg2;
+use crate::geometry;
+use crate::golombcoder::tables::LOG2_RUN;
+use crate::hash::Md5;
+use crate::pred::{derive_borders, get_context, get_median, PlaneSlice};
+use crate::quant_presets;
+use crate::rangecoder::encoder::RangeEncoder;
+use crate::record::{ConfigRecord, ConfigRecordBuilder};
+
+/// A synthetic stream of a single keyframe and its decoded samples.
+#[derive(Debug, Clone)]
+pub struct TestVector {
+    /// Short name of the case, e.g. "one_pixel_wide_slices".
+    pub name: &'static str,
+    /// Width of the frame, in pixels.
+    pub width: u32,
+    /// Height of the frame, in pixels.
+    pub height: u32,
+    /// Configuration record, as stored in the codec private data.
+    ///
+    /// See: 4.2. Configuration Record
+    pub record: Vec<u8>,
+    /// Packet of the keyframe.
+    ///
+    /// See: 4.3. Frame
+    pub packet: Vec<u8>,
+    /// Samples of every plane of the decoded frame, row after row.
+    pub planes: Vec<Vec<u16>>,
+    /// Bit depth of the samples.
+    pub bits_per_raw_sample: u8,
+}
+
+impl TestVector {
+    /// Returns the MD5 digest of a decoded plane, as returned by
+    /// 'Frame::plane_md5'.
+    pub fn plane_md5(&self, plane: usize) -> [u8; 16] {
+        let mut hasher = Md5::new();
+        for &sample in &self.planes[plane] {
+            if self.bits_per_raw_sample <= 8 {
+                hasher.update(&[sample as u8]);
+            } else {
+                hasher.update(&sample.to_le_bytes());
+            }
+        }
+        hasher.finalize()
+    }
+}
+
+/// Returns every test vector.
+pub fn test_vectors() -> Vec<TestVector> {
+    vec![
+        one_pixel_wide_slices(),
+        max_slices(),
+        alpha(),
+        golomb_runs(),
+        context_model_1(),
+    ]
+}
+
+/// Returns a 4x6 YCbCr 4:4:4 frame cut into 4x2 slices one pixel wide, so
+/// that every sample sits on the left and right borders of its slice.
+///
+/// See: 3.1. Border
+pub fn one_pixel_wide_slices() -> TestVector {
+    let record = ConfigRecordBuilder::new(4, 6)
+        .chroma_subsample(0, 0)
+        .slices(4, 2)
+        .intra(1);
+    encode("one_pixel_wide_slices", record, 0, noise)
+}
+
+/// Returns a 32x32 grayscale frame cut into 32x32 slices of a single pixel,
+/// the largest number of slices FFmpeg decodes.
+pub fn max_slices() -> TestVector {
+    let record = ConfigRecordBuilder::new(32, 32)
+        .chroma_planes(false)
+        .slices(32, 32)
+        .intra(1);
+    encode("max_slices", record, 0, noise)
+}
+
+/// Returns a 16x16 YCbCr 4:2:0 frame with an alpha plane, in 2x2 slices.
+///
+/// See: 4.1.10. extra_plane
+pub fn alpha() -> TestVector {
+    let record = ConfigRecordBuilder::new(16, 16)
+        .extra_plane(true)
+        .slices(2, 2)
+        .intra(1);
+    encode("alpha", record, 0, noise)
+}
+
+/// Returns a 64x16 YCbCr 4:2:0 frame of flat areas coded with Golomb-Rice,
+/// in 2x1 slices, so that most samples are coded in run mode, with runs
+/// cut short by isolated samples and runs reaching the end of lines.
+///
+/// See: 3.8.2.2. Run Mode
+pub fn golomb_runs() -> TestVector {
+    let record = ConfigRecordBuilder::new(64, 16)
+        .coder_type(0)
+        .slices(2, 1)
+        .intra(1);
+    encode("golomb_runs", record, 0, |plane, x, y, bits| {
+        let level = (plane as u32 * 40 + 60) << (bits - 8);
+        // A few isolated samples break the runs, and a ramp on some
+        // lines leaves run mode altogether
+        if (x * 7 + y * 13) % 29 == 0 {
+            level + 9
+        } else if y % 5 == 3 && x > 20 {
+            level + x
+        } else {
+            level
+        }
+    })
+}
+
+/// Returns a 16x16 YCbCr 4:2:0 frame in 2x2 slices, with luma and chroma
+/// both coded with the second quantization table set, as FFmpeg does with
+/// '-context 1', so that each plane slot has states of its own for the
+/// same set.
+///
+/// See: 4.5.6. quant_table_set_index
+pub fn context_model_1() -> TestVector {
+    // Initial states differing between contexts, so that the states of a
+    // slot only match the ones of its own set
+    let [_, large] = quant_presets::ffmpeg_8bit();
+    let states = (0..quant_presets::context_count(&large))
+        .map(|context| vec![96 + (context % 64) as u8; CONTEXT_SIZE])
+        .collect();
+    let record = ConfigRecordBuilder::new(16, 16)
+        .initial_states(1, states)
+        .slices(2, 2)
+        .intra(1);
+    encode("context_model_1", record, 1, noise)
+}
+
+/// Returns a keyframe of noise in the YCbCr stream described by 'builder',
+/// with every plane coded with the quantization table set 'context_model',
+/// e.g. to test configurations the other vectors do not cover.
+///
+/// Panics if 'builder' does not build, or has no set 'context_model'.
+///
+/// See: 4.5.6. quant_table_set_index
+pub fn custom(
+    builder: ConfigRecordBuilder,
+    context_model: usize,
+) -> TestVector {
+    encode("custom", builder, context_model, noise)
+}
+
+/// Returns a deterministic mix of gradients and noise.
+fn noise(plane: usize, x: u32, y: u32, bits: u8) -> u32 {
+    let mut rand = (x * 31 + y * 17 + plane as u32 * 7919) ^ 0x5bd1_e995;
+    rand ^= rand << 13;
+    rand ^= rand >> 17;
+    rand ^= rand << 5;
+    let v = x * 9 + y * 5 + (rand >> 8) % 16;
+    v % (1 << bits)
+}
+
+/// Codes a keyframe of the samples returned by 'sample' for the plane,
+/// column and row of every sample, in the stream described by 'builder',
+/// with every plane coded with the quantization table set 'context_model'.
+fn encode<F>(
+    name: &'static str,
+    builder: ConfigRecordBuilder,
+    context_model: usize,
+    sample: F,
+) -> TestVector
+where
+    F: Fn(usize, u32, u32, u8) -> u32,
+{
+    let record = builder.build().expect("valid test vector record");
+    let bits = record.bits_per_raw_sample;
+    let planes: Vec<Vec<u16>> = (0..record.num_planes())
+        .map(|plane| {
+            let (width, height) = plane_dimensions(&record, plane);
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| sample(plane, x as u32, y as u32, bits) as u16)
+                .collect()
+        })
+        .collect();
+    TestVector {
+        name,
+        width: record.width,
+        height: record.height,
+        record: record.to_bytes(),
+        packet: code_frame(&record, context_model, &planes),
+        planes,
+        bits_per_raw_sample: bits,
+    }
+}
+
+/// Returns the width and height of a plane, in samples.
+fn plane_dimensions(record: &ConfigRecord, plane: usize) -> (usize, usize) {
+    geometry::plane_dimensions(
+        record.width,
+        record.height,
+        record.chroma_planes,
+        record.log2_h_chroma_subsample,
+        record.log2_v_chroma_subsample,
+        plane,
+    )
+}
+
+/// Codes a keyframe of 'planes', one slice after the other.
+///
+/// See: 4.3. Frame
+fn code_frame(
+    record: &ConfigRecord,
+    context_model: usize,
+    planes: &[Vec<u16>],
+) -> Vec<u8> {
+    let h_slices = record.num_h_slices_minus1 as u32 + 1;
+    let v_slices = record.num_v_slices_minus1 as u32 + 1;
+    let mut packet = Vec::new();
+    for slice_y in 0..v_slices {
+        for slice_x in 0..h_slices {
+            let mut c = RangeEncoder::new();
+            if packet.is_empty() {
+                // 4.3.1. keyframe
+                c.put(&mut 128, true);
+            }
+            let mut data =
+                code_slice(record, context_model, planes, slice_x, slice_y, c);
+
+            // 4.8. Slice Footer
+            let size = data.len() as u32;
+            data.extend_from_slice(&size.to_be_bytes()[1..]);
+            if record.ec != 0 {
+                data.push(0);
+                let parity = crc32_mpeg2(&data);
+                data.extend_from_slice(&parity.to_le_bytes());
+            }
+            packet.extend_from_slice(&data);
+        }
+    }
+    packet
+}
+
+/// Codes the slice at 'slice_x' and 'slice_y' of the slice grid, after
+/// what 'c' already holds, with every plane coded with the quantization
+/// table set 'context_model'.
+///
+/// See: * 4.4. Slice
+///      * 4.6. Slice Content
+fn code_slice(
+    record: &ConfigRecord,
+    context_model: usize,
+    planes: &[Vec<u16>],
+    slice_x: u32,
+    slice_y: u32,
+    mut c: RangeEncoder,
+) -> Vec<u8> {
+    // 4.5. Slice Header, with luma, chroma and alpha all using the set of
+    // the context model, as FFmpeg does
+    let sets = vec![context_model; 2 + record.extra_plane as usize];
+    let mut state = [128; CONTEXT_SIZE];
+    c.symbol(&mut state, slice_x as i32, false);
+    c.symbol(&mut state, slice_y as i32, false);
+    c.symbol(&mut state, 0, false);
+    c.symbol(&mut state, 0, false);
+    for &set in &sets {
+        c.symbol(&mut state, set as i32, false);
+    }
+    c.symbol(&mut state, 3, false); // picture_structure
+    c.symbol(&mut state, 1, false); // sar_num
+    c.symbol(&mut state, 1, false); // sar_den
+
+    // 4.6.3. slice_pixel_height, 4.6.4. slice_pixel_y,
+    // 4.7.2. slice_pixel_width and 4.7.3. slice_pixel_x
+    let h_slices = record.num_h_slices_minus1 as u32 + 1;
+    let v_slices = record.num_v_slices_minus1 as u32 + 1;
+    let x0 = slice_x * record.width / h_slices;
+    let x1 = (slice_x + 1) * record.width / h_slices;
+    let y0 = slice_y * record.height / v_slices;
+    let y1 = (slice_y + 1) * record.height / v_slices;
+
+    let mut coder = if record.coder_type == 0 {
+        // 3.8.1.1.1. Termination, in sentinel mode
+        c.put(&mut 129, false);
+        SampleEncoder::Golomb(c.terminate(), GolombEncoder::default())
+    } else {
+        SampleEncoder::Range(Box::new(c))
+    };
+    // Every plane slot has states of its own, even when slots share a set
+    let mut range_states: Vec<_> = sets
+        .iter()
+        .map(|&set| record.initial_states[set].clone())
+        .collect();
+    let mut golomb_states: Vec<_> = sets
+        .iter()
+        .map(|&set| {
+            vec![VlcState::default(); record.context_count[set] as usize]
+        })
+        .collect();
+
+    // YCbCr planes are coded one after the other
+    let bits = record.bits_per_raw_sample;
+    debug_assert!(record.colorspace_type == ColorSpace::YCbCr);
+    for (plane, samples) in planes.iter().enumerate() {
+        let (qt, h, v) = if record.chroma_planes && (plane == 1 || plane == 2)
+        {
+            (
+                1,
+                record.log2_h_chroma_subsample,
+                record.log2_v_chroma_subsample,
+            )
+        } else if plane == 0 {
+            (0, 0, 0)
+        } else {
+            (2, 0, 0)
+        };
+        let (stride, _) = plane_dimensions(record, plane);
+        let px0 = geometry::chroma_position(x0, h) as usize;
+        let py0 = geometry::chroma_position(y0, v) as usize;
+        let width = geometry::chroma_size(x1 - x0, h) as usize;
+        let height = geometry::chroma_size(y1 - y0, v) as usize;
+        let slice: Vec<u16> = (0..height)
+            .flat_map(|y| {
+                let start = (py0 + y) * stride + px0;
+                samples[start..start + width].iter().copied()
+            })
+            .collect();
+        let slice = PlaneSlice::new(&slice, width, width);
+
+        if let SampleEncoder::Golomb(_, ref mut golomb) = coder {
+            golomb.run_index = 0;
+        }
+        for y in 0..height {
+            let mask = (1 << bits) - 1;
+            let mut run = Run::default();
+            for x in 0..width {
+                // 3.2. Samples, 3.3. Median Predictor and 3.4. Context
+                let n = derive_borders(&slice, x, y);
+                let quant_tables = &record.quant_tables[sets[qt]];
+                let mut context = get_context(quant_tables, &n);
+                let (l, t, tl) = (n.l as i32, n.t as i32, n.tl as i32);
+                let pred = get_median(l, t, l + t - tl);
+                let mut diff = (slice.get(x, y) as i32 - pred) & mask;
+                if diff > mask >> 1 {
+                    diff -= mask + 1;
+                }
+                if context < 0 {
+                    context = -context;
+                    diff = -diff;
+                }
+                let context = context as usize;
+                match coder {
+                    SampleEncoder::Range(ref mut c) => {
+                        let state = &mut range_states[qt][context];
+                        c.symbol(state, diff, true);
+                    }
+                    SampleEncoder::Golomb(_, ref mut golomb) => golomb
+                        .code_sample(
+                            &mut run,
+                            context,
+                            &mut golomb_states[qt][context],
+                            diff,
+                            bits as u32,
+                        ),
+                }
+            }
+            if let SampleEncoder::Golomb(_, ref mut golomb) = coder {
+                golomb.end_line(&mut run);
+            }
+        }
+    }
+
+    match coder {
+        SampleEncoder::Range(c) => (*c).terminate(),
+        SampleEncoder::Golomb(mut data, golomb) => {
+            data.extend_from_slice(&golomb.finish());
+            data
+        }
+    }
+}
+
+/// Coder of the samples of a slice.
+enum SampleEncoder {
+    Range(Box<RangeEncoder>),
+    /// The terminated slice header, and the samples.
+    Golomb(Vec<u8>, GolombEncoder),
+}
+
+/// 3.8.2.4. Initial Values for the VLC context state, for encoding.
+#[derive(Debug, Clone)]
+struct VlcState {
+    drift: i32,
+    error_sum: i32,
+    bias: i32,
+    count: i32,
+}
+
+impl Default for VlcState {
+    fn default() -> Self {
+        Self {
+            drift: 0,
+            error_sum: 4,
+            bias: 0,
+            count: 1,
+        }
+    }
+}
+
+/// The run of the current line.
+#[derive(Default)]
+struct Run {
+    active: bool,
+    count: u32,
+}
+
+/// 3.8.2. Golomb Rice Mode, mirrored for encoding.
+#[derive(Default)]
+struct GolombEncoder {
+    out: Vec<u8>,
+    bits: u64,
+    bit_count: u32,
+    run_index: usize,
+}
+
+impl GolombEncoder {
+    /// Writes the 'count' low bits of 'value'.
+    fn put_bits(&mut self, count: u32, value: u32) {
+        for i in (0..count).rev() {
+            self.bits = self.bits << 1 | (value >> i) as u64 & 1;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.out.push(self.bits as u8);
+                self.bits = 0;
+                self.bit_count = 0;
+            }
+        }
+    }
+
+    /// Writes the full runs of 'run', the ones reaching 2^LOG2_RUN.
+    ///
+    /// See: 3.8.2.2.1. Run Length Coding
+    fn put_full_runs(&mut self, run: &mut Run) {
+        while run.count >= 1 << LOG2_RUN[self.run_index] {
+            run.count -= 1 << LOG2_RUN[self.run_index];
+            self.run_index += 1;
+            self.put_bits(1, 1);
+        }
+    }
+
+    /// Codes the sample difference 'diff' in 'context'.
+    ///
+    /// See: * 3.8.2.2. Run Mode
+    ///      * 3.8.2.3. Scalar Mode
+    fn code_sample(
+        &mut self,
+        run: &mut Run,
+        context: usize,
+        state: &mut VlcState,
+        mut diff: i32,
+        bits: u32,
+    ) {
+        if context == 0 {
+            run.active = true;
+        }
+        if run.active {
+            if diff == 0 {
+                run.count += 1;
+                return;
+            }
+            // 3.8.2.2.2. Level Coding
+            self.put_full_runs(run);
+            self.put_bits(1, 0);
+            let log2_run = LOG2_RUN[self.run_index] as u32;
+            self.put_bits(log2_run, run.count);
+            self.run_index = self.run_index.saturating_sub(1);
+            *run = Run::default();
+            if diff > 0 {
+                diff -= 1;
+            }
+        }
+        self.put_vlc_symbol(state, diff, bits);
+    }
+
+    /// Ends the run of a line, which may be coded longer than what is left
+    /// of the line.
+    fn end_line(&mut self, run: &mut Run) {
+        if run.active {
+            self.put_full_runs(run);
+            if run.count > 0 {
+                self.put_bits(1, 1);
+            }
+        }
+    }
+
+    /// 3.8.2.3. Scalar Mode
+    fn put_vlc_symbol(&mut self, state: &mut VlcState, diff: i32, bits: u32) {
+        let v = sign_extend(diff - state.bias, bits);
+        let mut i = state.count;
+        let mut k = 0;
+        while i < state.error_sum && k < bits {
+            k += 1;
+            i += i;
+        }
+        let code = if 2 * state.drift < -state.count {
+            -1 - v
+        } else {
+            v
+        };
+
+        // 3.8.2.1. Signed Golomb Rice Codes
+        let code = if code < 0 { -2 * code - 1 } else { 2 * code } as u32;
+        if code >> k < 12 {
+            self.put_bits(code >> k, 0);
+            self.put_bits(1, 1);
+            self.put_bits(k, code);
+        } else {
+            self.put_bits(12, 0);
+            self.put_bits(bits, code - 11);
+        }
+
+        // 3.8.2.4. Initial Values for the VLC context state
+        state.error_sum += v.abs();
+        state.drift += v;
+        if state.count == 128 {
+            state.count >>= 1;
+            state.drift >>= 1;
+            state.error_sum >>= 1;
+        }
+        state.count += 1;
+        if state.drift <= -state.count {
+            state.bias = (state.bias - 1).max(-128);
+            state.drift = (state.drift + state.count).max(-state.count + 1);
+        } else if state.drift > 0 {
+            state.bias = (state.bias + 1).min(127);
+            state.drift = (state.drift - state.count).min(0);
+        }
+    }
+
+    /// Returns the coded bytes, padding the last one with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.put_bits(8 - self.bit_count, 0);
+        }
+        self.out
+    }
+}
+
+/// Returns the 'bits' low bits of 'n' as a signed value.
+fn sign_extend(n: i32, bits: u32) -> i32 {
+    (n << (32 - bits)) >> (32 - bits)
+}
@@ -0,0 +1,89 @@
+//! Selection and sharing of the threads decoding slices.
+//!
+//! Decoders spawn their slice threads for every frame, up to the number set
+//! by 'Decoder::set_threads'. A server running many decoders can bound the
+//! slice threads running at the same time across all of them with a shared
+//! 'ThreadLimit'.
+
+use std::sync::Mutex;
+use std::thread;
+
+/// Environment variable overriding the default number of threads.
+pub const THREADS_ENV: &str = "FFV1_THREADS";
+
+/// Returns the default number of threads decoding the slices of a frame.
+///
+/// This is the value of the 'FFV1_THREADS' environment variable when it is
+/// a positive integer, and the available parallelism otherwise.
+pub fn default_threads() -> usize {
+    std::env::var(THREADS_ENV)
+        .ok()
+        .and_then(|threads| threads.trim().parse().ok())
+        .filter(|&threads| threads > 0)
+        .unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |threads| threads.get())
+        })
+}
+
+/// A number of slice threads shared by several decoders.
+///
+/// A frame is decoded on as many of the threads it asks for as are free,
+/// and on the calling thread alone when none are, so decoding never waits
+/// for another decoder.
+#[derive(Debug)]
+pub struct ThreadLimit {
+    threads: usize,
+    free: Mutex<usize>,
+}
+
+impl ThreadLimit {
+    /// Creates a limit of 'threads' slice threads running at the same time.
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            free: Mutex::new(threads),
+        }
+    }
+
+    /// Returns the number of threads of the limit.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Returns the number of threads not used by any decoder.
+    pub fn free(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Takes up to 'threads' free threads.
+    pub(crate) fn acquire(&self, threads: usize) -> Threads<'_> {
+        let mut free = self.lock();
+        let count = threads.min(*free);
+        *free -= count;
+        Threads { limit: self, count }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, usize> {
+        self.free.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Threads taken from a 'ThreadLimit', given back when dropped.
+#[derive(Debug)]
+pub(crate) struct Threads<'a> {
+    limit: &'a ThreadLimit,
+    count: usize,
+}
+
+impl Threads<'_> {
+    /// Returns the number of threads taken.
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl Drop for Threads<'_> {
+    fn drop(&mut self) {
+        *self.limit.lock() += self.count;
+    }
+}
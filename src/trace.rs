@@ -0,0 +1,62 @@
+//! Optional trace of every decoded syntax element.
+//!
+//! With the `trace-bitstream` feature, a Decoder created with
+//! 'Decoder::with_trace' writes one line per syntax element, named after
+//! the section of the specification defining it, e.g. to diff the symbols
+//! decoded by two implementations. Without the feature, tracing compiles
+//! to nothing.
+
+use std::fmt;
+#[cfg(feature = "trace-bitstream")]
+use std::io::Write;
+#[cfg(feature = "trace-bitstream")]
+use std::sync::{Arc, Mutex};
+
+/// Optional trace writer of a Decoder.
+#[derive(Clone, Default)]
+pub(crate) struct Trace {
+    #[cfg(feature = "trace-bitstream")]
+    writer: Option<Arc<Mutex<dyn Write + Send>>>,
+}
+
+impl Trace {
+    #[cfg(feature = "trace-bitstream")]
+    pub(crate) fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Some(Arc::new(Mutex::new(writer))),
+        }
+    }
+
+    /// Writes the syntax element 'name' with its decoded 'value'.
+    ///
+    /// Write errors are ignored, so that tracing never makes decoding
+    /// fail.
+    #[inline(always)]
+    pub(crate) fn element<N, V>(&self, name: N, value: V)
+    where
+        N: fmt::Display,
+        V: fmt::Display,
+    {
+        #[cfg(feature = "trace-bitstream")]
+        if let Some(ref writer) = self.writer {
+            if let Ok(mut writer) = writer.lock() {
+                let _ = writeln!(writer, "{}: {}", name, value);
+            }
+        }
+        #[cfg(not(feature = "trace-bitstream"))]
+        let _ = (name, value);
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        #[cfg(feature = "trace-bitstream")]
+        return self.writer.is_some();
+        #[cfg(not(feature = "trace-bitstream"))]
+        false
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Trace").field(&self.is_set()).finish()
+    }
+}
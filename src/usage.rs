@@ -0,0 +1,261 @@
+//! Reports of the features of the specification used by streams, e.g. for
+//! archives to profile their holdings and spot outliers.
+//!
+//! Only the configuration record and the slice headers are read, no sample
+//! is decoded.
+
+use std::fmt::{self, Write};
+
+use crate::compliance::Compliance;
+use crate::constants::ColorSpace;
+use crate::decoder::Decoder;
+use crate::error::Result;
+use crate::record::ConfigRecord;
+use crate::scan::FrameScan;
+
+/// Features of the specification used by a stream, see 'FeatureScanner'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureUsage {
+    /// Version and micro version of the bitstream.
+    ///
+    /// See: * 4.1.1. version
+    ///      * 4.1.2. micro_version
+    pub version: (u8, u8),
+    /// Coder of the stream, 0 for Golomb-Rice and 1 or 2 for the range
+    /// coder.
+    ///
+    /// See: 4.1.3. coder_type
+    pub coder_type: u8,
+    /// Whether a state transition table differing from the default one is
+    /// coded.
+    ///
+    /// See: 4.1.4. state_transition_delta
+    pub custom_state_transition: bool,
+    /// Whether the initial states of any quantization table set are coded.
+    ///
+    /// See: 4.1.14. states_coded
+    pub states_coded: bool,
+    /// Bit depth of the samples.
+    ///
+    /// See: 4.1.7. bits_per_raw_sample
+    pub bits_per_raw_sample: u8,
+    /// Colorspace of the samples.
+    ///
+    /// See: 4.1.5. colorspace_type
+    pub colorspace: ColorSpace,
+    /// J:a:b notation of the chroma subsampling of YCbCr streams with
+    /// chroma planes, None otherwise.
+    ///
+    /// See: * 4.1.8. log2_h_chroma_subsample
+    ///      * 4.1.9. log2_v_chroma_subsample
+    pub chroma_subsampling: Option<String>,
+    /// Whether an alpha plane is coded.
+    ///
+    /// See: 4.1.10. extra_plane
+    pub alpha: bool,
+    /// Number of columns and rows of the slice grid.
+    ///
+    /// See: * 4.1.11. num_h_slices
+    ///      * 4.1.12. num_v_slices
+    pub slice_grid: (u32, u32),
+    /// Number of quantization table sets.
+    ///
+    /// See: 4.1.13. quant_table_set_count
+    pub quant_table_sets: usize,
+    /// Error detection and correction mode.
+    ///
+    /// See: 4.1.16. ec
+    pub ec: u8,
+    /// Whether every frame is meant to be a keyframe.
+    ///
+    /// See: 4.1.17. intra
+    pub intra: u8,
+    /// Number of frames scanned.
+    pub frames: usize,
+    /// Number of keyframes among them.
+    pub keyframes: usize,
+    /// Number of frames whose slices could not be parsed.
+    pub invalid_frames: usize,
+    /// Distinct sample aspect ratios coded by the slices, in order of
+    /// appearance.
+    ///
+    /// See: * 4.5.8. sar_num
+    ///      * 4.5.9. sar_den
+    pub sars: Vec<(u32, u32)>,
+}
+
+impl FeatureUsage {
+    /// Creates the report of a stream whose configuration record is
+    /// 'record', before any frame is scanned.
+    pub fn new(record: &ConfigRecord) -> Self {
+        let chroma_subsampling = (record.chroma_planes
+            && record.colorspace_type == ColorSpace::YCbCr)
+            .then(|| record.subsampling());
+        Self {
+            version: (record.version, record.micro_version),
+            coder_type: record.coder_type,
+            custom_state_transition: record.coder_type == 2
+                && record.state_transition_delta.iter().any(|&d| d != 0),
+            states_coded: record.states_coded.iter().any(|&coded| coded),
+            bits_per_raw_sample: record.bits_per_raw_sample,
+            colorspace: record.colorspace_type,
+            chroma_subsampling,
+            alpha: record.extra_plane,
+            slice_grid: (
+                record.num_h_slices_minus1 as u32 + 1,
+                record.num_v_slices_minus1 as u32 + 1,
+            ),
+            quant_table_sets: record.quant_table_set_count,
+            ec: record.ec,
+            intra: record.intra,
+            frames: 0,
+            keyframes: 0,
+            invalid_frames: 0,
+            sars: Vec::new(),
+        }
+    }
+
+    /// Adds a frame to the report, as scanned by 'Decoder::scan_frame', or
+    /// None when it could not be.
+    pub fn add_frame(&mut self, scan: Option<&FrameScan>) {
+        self.frames += 1;
+        let scan = match scan {
+            Some(scan) => scan,
+            None => {
+                self.invalid_frames += 1;
+                return;
+            }
+        };
+        if scan.keyframe {
+            self.keyframes += 1;
+        }
+        for slice in &scan.slices {
+            let sar = slice.header.sar();
+            if !self.sars.contains(&sar) {
+                self.sars.push(sar);
+            }
+        }
+    }
+
+    /// Returns the report as a JSON object, e.g. to aggregate the reports
+    /// of a whole collection.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        let subsampling = self
+            .chroma_subsampling
+            .as_ref()
+            .map_or_else(|| "null".to_owned(), |s| format!("\"{}\"", s));
+        let _ = write!(
+            json,
+            "{{\"version\":\"{}.{}\",\"coder_type\":{},\
+             \"custom_state_transition\":{},\"states_coded\":{},\
+             \"bits_per_raw_sample\":{},\"colorspace\":\"{}\",\
+             \"chroma_subsampling\":{},\"alpha\":{},\
+             \"slice_grid\":[{},{}],\"quant_table_sets\":{},\"ec\":{},\
+             \"intra\":{},\"frames\":{},\"keyframes\":{},\
+             \"invalid_frames\":{},\"sars\":[",
+            self.version.0,
+            self.version.1,
+            self.coder_type,
+            self.custom_state_transition,
+            self.states_coded,
+            self.bits_per_raw_sample,
+            self.colorspace,
+            subsampling,
+            self.alpha,
+            self.slice_grid.0,
+            self.slice_grid.1,
+            self.quant_table_sets,
+            self.ec,
+            self.intra,
+            self.frames,
+            self.keyframes,
+            self.invalid_frames
+        );
+        for (i, (num, den)) in self.sars.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "[{},{}]", num, den);
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+impl fmt::Display for FeatureUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {}.{}", self.version.0, self.version.1)?;
+        writeln!(f, "coder_type: {}", self.coder_type)?;
+        writeln!(
+            f,
+            "custom state transitions: {}",
+            self.custom_state_transition
+        )?;
+        writeln!(f, "states coded: {}", self.states_coded)?;
+        writeln!(f, "bit depth: {}", self.bits_per_raw_sample)?;
+        writeln!(f, "colorspace: {}", self.colorspace)?;
+        writeln!(
+            f,
+            "chroma subsampling: {}",
+            self.chroma_subsampling.as_deref().unwrap_or("none")
+        )?;
+        writeln!(f, "alpha: {}", self.alpha)?;
+        writeln!(f, "slices: {}x{}", self.slice_grid.0, self.slice_grid.1)?;
+        writeln!(f, "quant table sets: {}", self.quant_table_sets)?;
+        writeln!(f, "ec: {}", self.ec)?;
+        writeln!(f, "intra: {}", self.intra)?;
+        let sars: Vec<_> = self
+            .sars
+            .iter()
+            .map(|(num, den)| format!("{}:{}", num, den))
+            .collect();
+        writeln!(f, "sample aspect ratios: {}", sars.join(", "))?;
+        write!(
+            f,
+            "{} frames, {} keyframes, {} invalid",
+            self.frames, self.keyframes, self.invalid_frames
+        )
+    }
+}
+
+/// Collects the features used by a stream, one packet at a time.
+#[derive(Debug)]
+pub struct FeatureScanner {
+    decoder: Decoder,
+    usage: FeatureUsage,
+}
+
+impl FeatureScanner {
+    /// Creates a scanner of the stream whose configuration record is
+    /// 'record', with the dimensions stored by the container.
+    ///
+    /// Reserved values are tolerated, so that outliers are reported
+    /// rather than rejected.
+    pub fn new(record: &[u8], width: u32, height: u32) -> Result<Self> {
+        let decoder = Decoder::with_compliance(
+            record,
+            width,
+            height,
+            Compliance::Permissive,
+        )?;
+        let usage = FeatureUsage::new(decoder.config_record());
+        Ok(Self { decoder, usage })
+    }
+
+    /// Scans the next packet of the stream.
+    pub fn scan_packet(&mut self, packet: &[u8]) {
+        let scan = self.decoder.scan_frame(packet).ok();
+        self.usage.add_frame(scan.as_ref());
+    }
+
+    /// Returns the report of the packets scanned so far.
+    pub fn usage(&self) -> &FeatureUsage {
+        &self.usage
+    }
+
+    /// Returns the report of the stream.
+    pub fn finish(self) -> FeatureUsage {
+        self.usage
+    }
+}
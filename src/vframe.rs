@@ -0,0 +1,132 @@
+//! Conversion of decoded frames into the 'v_frame' planes and frames used
+//! by the rust-av encoders, e.g. rav1e.
+//!
+//! 'v_frame' frames hold a luma and two chroma planes, so alpha planes are
+//! only available as standalone planes, and RGB frames are stored as
+//! 4:4:4 frames with their green, blue and red planes in that order.
+
+use v_frame::frame::Frame as VFrame;
+use v_frame::pixel::{ChromaSampling, Pixel};
+use v_frame::plane::Plane;
+
+use crate::decoder::Frame;
+use crate::plane::PlaneView;
+use crate::sample::Sample;
+
+/// A sample type of 'v_frame' planes: u8 for 8-bit frames, and u16 for
+/// frames whose bit depth is greater than 8.
+pub trait VSample: Sample + Pixel {
+    /// Returns a view of a plane of 'frame', or None when its samples are
+    /// not of this type.
+    fn view(frame: &Frame, plane: usize) -> Option<PlaneView<'_, Self>>;
+}
+
+impl VSample for u8 {
+    fn view(frame: &Frame, plane: usize) -> Option<PlaneView<'_, Self>> {
+        frame.plane(plane)
+    }
+}
+
+impl VSample for u16 {
+    fn view(frame: &Frame, plane: usize) -> Option<PlaneView<'_, Self>> {
+        frame.plane16(plane)
+    }
+}
+
+/// Returns the 'v_frame' chroma sampling of a frame, or None when it has
+/// none, e.g. for 4:1:1 frames.
+pub fn chroma_sampling(frame: &Frame) -> Option<ChromaSampling> {
+    if !frame.has_chroma {
+        return Some(ChromaSampling::Cs400);
+    }
+    match (frame.chroma_subsample_h, frame.chroma_subsample_v) {
+        (1, 1) => Some(ChromaSampling::Cs420),
+        (1, 0) => Some(ChromaSampling::Cs422),
+        (0, 0) => Some(ChromaSampling::Cs444),
+        _ => None,
+    }
+}
+
+/// Copies a plane into 'dst', whose dimensions must be the ones of the
+/// plane, and fills the padding of 'dst' from the edges of the plane of a
+/// 'width'x'height' frame.
+fn copy_plane<T: VSample>(
+    src: PlaneView<'_, T>,
+    dst: &mut Plane<T>,
+    width: usize,
+    height: usize,
+) -> Option<()> {
+    if (dst.cfg.width, dst.cfg.height) != (src.width(), src.height()) {
+        return None;
+    }
+    let stride = dst.cfg.stride;
+    for (src, dst) in src.rows().zip(dst.data_origin_mut().chunks_mut(stride))
+    {
+        dst[..src.len()].copy_from_slice(src);
+    }
+    dst.pad(width, height);
+    Some(())
+}
+
+/// Returns a copy of a plane of a frame as a 'v_frame' plane without
+/// padding, or None when 'T' does not match the bit depth of the frame.
+pub fn to_v_plane<T: VSample>(
+    frame: &Frame,
+    plane: usize,
+) -> Option<Plane<T>> {
+    let src = T::view(frame, plane)?;
+    let (xdec, ydec) = if frame.has_chroma && (plane == 1 || plane == 2) {
+        (frame.chroma_subsample_h, frame.chroma_subsample_v)
+    } else {
+        (0, 0)
+    };
+    let mut dst = Plane::new(
+        src.width(),
+        src.height(),
+        xdec as usize,
+        ydec as usize,
+        0,
+        0,
+    );
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    copy_plane(src, &mut dst, width, height)?;
+    Some(dst)
+}
+
+/// Copies the luma and chroma planes of a frame into 'dst', e.g. a frame
+/// allocated by an encoder, filling its padding.
+///
+/// Returns None when 'T' does not match the bit depth of the frame, or
+/// when the planes of 'dst' do not have the dimensions of the planes of
+/// the frame.
+pub fn copy_to_v_frame<T: VSample>(
+    frame: &Frame,
+    dst: &mut VFrame<T>,
+) -> Option<()> {
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let num_planes = if frame.has_chroma { 3 } else { 1 };
+    for (plane, dst) in dst.planes.iter_mut().enumerate().take(num_planes) {
+        copy_plane(T::view(frame, plane)?, dst, width, height)?;
+    }
+    Some(())
+}
+
+/// Returns a copy of the luma and chroma planes of a frame as a 'v_frame'
+/// frame, with 'luma_padding' samples of padding around the luma plane.
+///
+/// Returns None when 'T' does not match the bit depth of the frame, or
+/// when the frame has no 'v_frame' chroma sampling, see
+/// 'chroma_sampling'.
+pub fn to_v_frame<T: VSample>(
+    frame: &Frame,
+    luma_padding: usize,
+) -> Option<VFrame<T>> {
+    let mut dst = VFrame::new_with_padding(
+        frame.width as usize,
+        frame.height as usize,
+        chroma_sampling(frame)?,
+        luma_padding,
+    );
+    copy_to_v_frame(frame, &mut dst)?;
+    Some(dst)
+}
@@ -0,0 +1,367 @@
+//! Reading and writing of YUV4MPEG2 (Y4M) streams.
+//!
+//! Samples wider than 8 bits are stored as little-endian 16-bit words, as
+//! in the 'C420p10' style colorspaces of FFmpeg. Y4M has no RGB nor alpha
+//! planes, so only YCbCr frames can be written, without their alpha plane.
+
+use std::io::{self, BufRead, Write};
+
+use crate::constants::ColorSpace;
+use crate::decoder::{Frame, FrameMetadata};
+use crate::geometry::Crop;
+use crate::raw::RawWriter;
+
+/// Returns an error about an invalid Y4M stream.
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The properties of a Y4M stream, stored in its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Width of the frames, in pixels.
+    pub width: u32,
+    /// Height of the frames, in pixels.
+    pub height: u32,
+    /// Frame rate, as a numerator and a denominator.
+    pub framerate: (u32, u32),
+    /// Bit depth of the samples (8-16).
+    pub bit_depth: u8,
+    /// Whether or not chroma planes are present.
+    pub has_chroma: bool,
+    /// The log2 horizontal chroma subsampling value.
+    pub chroma_subsample_h: u8,
+    /// The log2 vertical chroma subsampling value.
+    pub chroma_subsample_v: u8,
+}
+
+impl Header {
+    /// Returns the header of a stream of frames like 'frame', or None when
+    /// Y4M cannot represent them.
+    pub fn from_frame(frame: &Frame, framerate: (u32, u32)) -> Option<Self> {
+        if frame.color_space != ColorSpace::YCbCr {
+            return None;
+        }
+        let header = Self {
+            width: frame.width,
+            height: frame.height,
+            framerate,
+            bit_depth: frame.bit_depth,
+            has_chroma: frame.has_chroma,
+            chroma_subsample_h: frame.chroma_subsample_h,
+            chroma_subsample_v: frame.chroma_subsample_v,
+        };
+        header.colorspace().map(|_| header)
+    }
+
+    /// Returns the colorspace tag of the header, e.g. '420p10', or None
+    /// when Y4M cannot represent it.
+    pub fn colorspace(&self) -> Option<String> {
+        if !(8..=16).contains(&self.bit_depth) {
+            return None;
+        }
+        if !self.has_chroma {
+            return Some(if self.bit_depth == 8 {
+                "mono".to_owned()
+            } else {
+                format!("mono{}", self.bit_depth)
+            });
+        }
+        let subsampling =
+            match (self.chroma_subsample_h, self.chroma_subsample_v) {
+                (1, 1) if self.bit_depth == 8 => "420jpeg",
+                (1, 1) => "420",
+                (1, 0) => "422",
+                (0, 0) => "444",
+                (2, 0) => "411",
+                _ => return None,
+            };
+        Some(if self.bit_depth == 8 {
+            subsampling.to_owned()
+        } else {
+            format!("{}p{}", subsampling, self.bit_depth)
+        })
+    }
+
+    /// Sets the bit depth and chroma planes of the header from a
+    /// colorspace tag.
+    fn set_colorspace(&mut self, colorspace: &str) -> io::Result<()> {
+        // e.g. 'mono', 'mono16', '420jpeg', '420paldv' or '420p10'
+        let (subsampling, bit_depth) = match colorspace.strip_prefix("mono") {
+            Some(bit_depth) => ("mono", bit_depth),
+            None => match colorspace.split_once('p') {
+                Some((subsampling, bit_depth))
+                    if bit_depth.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    (subsampling, bit_depth)
+                }
+                _ => (colorspace, ""),
+            },
+        };
+        let (has_chroma, h, v) = match subsampling {
+            "420" | "420jpeg" | "420mpeg2" | "420paldv" => (true, 1, 1),
+            "422" => (true, 1, 0),
+            "444" => (true, 0, 0),
+            "411" => (true, 2, 0),
+            "mono" => (false, 0, 0),
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported colorspace {}",
+                    colorspace
+                )))
+            }
+        };
+        self.bit_depth = match bit_depth {
+            "" => 8,
+            _ => bit_depth
+                .parse()
+                .ok()
+                .filter(|bits| (8..=16).contains(bits))
+                .ok_or_else(|| {
+                    invalid_data(format!(
+                        "unsupported colorspace {}",
+                        colorspace
+                    ))
+                })?,
+        };
+        self.has_chroma = has_chroma;
+        self.chroma_subsample_h = h;
+        self.chroma_subsample_v = v;
+        Ok(())
+    }
+
+    /// Parses the header line of a stream, without its final newline.
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let mut tokens = line.split(' ');
+        if tokens.next() != Some("YUV4MPEG2") {
+            return Err(invalid_data("not a Y4M stream".to_owned()));
+        }
+        let mut header = Self {
+            width: 0,
+            height: 0,
+            framerate: (25, 1),
+            bit_depth: 8,
+            has_chroma: true,
+            chroma_subsample_h: 1,
+            chroma_subsample_v: 1,
+        };
+        for token in tokens.filter(|token| !token.is_empty()) {
+            let (tag, value) = token.split_at(1);
+            let invalid =
+                || invalid_data(format!("invalid header parameter {}", token));
+            match tag {
+                "W" => header.width = value.parse().map_err(|_| invalid())?,
+                "H" => header.height = value.parse().map_err(|_| invalid())?,
+                "F" => {
+                    let mut ratio = value.splitn(2, ':');
+                    let num = ratio.next().and_then(|n| n.parse().ok());
+                    let den = ratio.next().and_then(|d| d.parse().ok());
+                    header.framerate = num.zip(den).ok_or_else(invalid)?;
+                }
+                "C" => header.set_colorspace(value)?,
+                // Interlacing, aspect ratio and extensions are ignored
+                _ => {}
+            }
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(invalid_data("missing frame dimensions".to_owned()));
+        }
+        Ok(header)
+    }
+
+    /// Returns an empty frame with the properties of the header.
+    fn empty_frame(&self) -> Frame {
+        let num_planes = if self.has_chroma { 3 } else { 1 };
+        let mut frame = Frame {
+            buf: Vec::new(),
+            buf16: Vec::new(),
+            strides: Vec::new(),
+            offsets: vec![0; num_planes],
+            width: self.width,
+            height: self.height,
+            bit_depth: self.bit_depth,
+            color_space: ColorSpace::YCbCr,
+            has_chroma: self.has_chroma,
+            has_alpha: false,
+            chroma_subsample_v: self.chroma_subsample_v,
+            chroma_subsample_h: self.chroma_subsample_h,
+            crop: Crop::display_size(self.width, self.height),
+            metadata: FrameMetadata::default(),
+            diagnostics: Vec::new(),
+        };
+        frame.strides = (0..num_planes)
+            .map(|plane| frame.plane_dimensions(plane).0)
+            .collect();
+        frame
+    }
+}
+
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1",
+            self.width, self.height, self.framerate.0, self.framerate.1
+        )?;
+        match self.colorspace() {
+            Some(colorspace) => write!(f, " C{}", colorspace),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the frames of a Y4M stream.
+#[derive(Debug)]
+pub struct Reader<R: BufRead> {
+    reader: R,
+    header: Header,
+}
+
+impl<R: BufRead> Reader<R> {
+    /// Creates a reader of the stream of 'reader', reading its header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let line = read_line(&mut reader)?
+            .ok_or_else(|| invalid_data("empty Y4M stream".to_owned()))?;
+        let header = Header::parse(&line)?;
+        Ok(Self { reader, header })
+    }
+
+    /// Returns the header of the stream.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Reads the next frame, with packed planes, or None at the end of the
+    /// stream.
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        let line = match read_line(&mut self.reader)? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if line.split(' ').next() != Some("FRAME") {
+            return Err(invalid_data("missing frame header".to_owned()));
+        }
+
+        let mut frame = self.header.empty_frame();
+        for plane in 0..frame.num_planes() {
+            let (width, height) = frame.plane_dimensions(plane);
+            if frame.bit_depth == 8 {
+                let mut samples = vec![0; width * height];
+                self.reader.read_exact(&mut samples)?;
+                frame.buf.push(samples);
+            } else {
+                let mut bytes = vec![0; 2 * width * height];
+                self.reader.read_exact(&mut bytes)?;
+                frame.buf16.push(
+                    bytes
+                        .chunks_exact(2)
+                        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                        .collect(),
+                );
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Reads a line, without its final newline, or None at the end of the
+/// stream.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "truncated Y4M header",
+        ));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| invalid_data("invalid Y4M header".to_owned()))
+}
+
+/// Writes frames as a Y4M stream.
+///
+/// The header is written along with the first frame, whose properties all
+/// the following frames must share.
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: RawWriter<W>,
+    framerate: (u32, u32),
+    header: Option<Header>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a writer outputting to 'writer', at 25 frames per second.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: RawWriter::new(writer),
+            framerate: (25, 1),
+            header: None,
+        }
+    }
+
+    /// Sets the frame rate written in the header, as a numerator and a
+    /// denominator.
+    pub fn set_framerate(&mut self, num: u32, den: u32) {
+        self.framerate = (num, den);
+    }
+
+    /// Returns the header of the stream, once the first frame has been
+    /// written.
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    /// Writes a frame, preceded by the header for the first one.
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let header =
+            Header::from_frame(frame, self.framerate).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Y4M cannot represent the colorspace of this stream",
+                )
+            })?;
+        match self.header {
+            Some(ref first) if *first != header => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the frame differs from the previous ones",
+                ));
+            }
+            Some(_) => {}
+            None => {
+                writeln!(self.writer.get_mut(), "{}", header)?;
+                // Y4M has no alpha plane
+                let color_planes = if header.has_chroma { 3 } else { 1 };
+                self.writer
+                    .set_plane_order(&(0..color_planes).collect::<Vec<_>>());
+                self.header = Some(header);
+            }
+        }
+        self.writer.get_mut().write_all(b"FRAME\n")?;
+        self.writer.write_frame(frame)
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Returns a mutable reference to the underlying writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.get_mut()
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
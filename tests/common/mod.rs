@@ -0,0 +1,90 @@
+//! Matroska demuxing shared by the integration tests.
+
+use std::fs::File;
+
+use av_data::params::MediaKind;
+use av_format::buffer::AccReader;
+use av_format::demuxer::{Context, Event};
+
+use matroska::demuxer::MkvDemuxer;
+
+use ffv1::decoder::Decoder;
+
+// ffv1 decoder parameters of the first ffv1 track
+pub struct DecParams {
+    pub width: u32,
+    pub height: u32,
+    pub extradata: Vec<u8>,
+}
+
+impl DecParams {
+    // Creates a new ffv1 decoder for the track
+    pub fn decoder(&self) -> Decoder {
+        Decoder::new(&self.extradata, self.width, self.height).unwrap()
+    }
+}
+
+// Opens a matroska file and reads the ffv1 decoder parameters
+pub fn open_params(input: &str) -> (Context, DecParams) {
+    let reader = File::open(input).unwrap();
+
+    // Create a buffer of size 4096KiB to contain matroska data
+    let ar = AccReader::with_capacity(4 * 1024, reader);
+
+    // Set the type of demuxer, in this case, a matroska demuxer
+    let mut demuxer = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
+
+    // Read matroska headers
+    demuxer
+        .read_headers()
+        .expect("Cannot parse the format headers");
+
+    // Save decoder params for the first ffv1 track
+    let mut decoder_params = None;
+
+    // Iterate over the streams contained in a matroska file
+    for stream in &demuxer.info.streams {
+        // Considers only video streams and analyze the type of codec inside.
+        if let Some(MediaKind::Video(info)) = &stream.params.kind {
+            let extradata = stream
+                .params
+                .extradata
+                .as_ref()
+                .expect("No extradata detected");
+            if decoder_params.is_none()
+                && String::from_utf8_lossy(extradata).contains("FFV1")
+            {
+                decoder_params = Some(DecParams {
+                    width: info.width as u32,
+                    height: info.height as u32,
+                    // As per Matroska spec for VFW CodecPrivate
+                    extradata: extradata[40..].to_owned(),
+                });
+            }
+        }
+    }
+
+    (demuxer, decoder_params.expect("No ffv1 track detected"))
+}
+
+pub fn open(input: &str) -> (Context, Decoder) {
+    let (demuxer, decoder_params) = open_params(input);
+
+    (demuxer, decoder_params.decoder())
+}
+
+// Reads the next packet, or None at the end of the file
+pub fn next_packet(demuxer: &mut Context) -> Option<Vec<u8>> {
+    match demuxer.read_event() {
+        Ok(Event::NewPacket(pkt)) => Some(pkt.data),
+        Ok(Event::Eof) => None,
+        Ok(event) => panic!("Unexpected event {:?}", event),
+        // The demuxer runs out of events at the end of the file
+        Err(_) => None,
+    }
+}
+
+// Reads the next packet
+pub fn read_packet(demuxer: &mut Context) -> Vec<u8> {
+    next_packet(demuxer).expect("Unexpected end of file")
+}
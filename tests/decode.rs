@@ -1,101 +1,16 @@
 use std::fs::File;
 use std::io::Read;
 
-use av_data::params::MediaKind;
-use av_format::buffer::AccReader;
-use av_format::demuxer::{Context, Event};
-
-use matroska::demuxer::MkvDemuxer;
-
 use ffv1::decoder::Decoder;
 
-// ffv1 decoder parameters
-#[derive(Default)]
-struct DecParams {
-    width: u32,
-    height: u32,
-    extradata: Vec<u8>,
-}
-
-// Decodes a single ffv1 frame
-fn decode_single_frame(
-    demuxer: &mut Context,
-    decoder: &mut Decoder,
-) -> Result<ffv1::decoder::Frame, String> {
-    // The demuxer reads which event has occurred
-    match demuxer.read_event() {
-        // If a new packet has been found, decode it
-        Ok(event) => match event {
-            Event::NewPacket(pkt) => {
-                // Reads a ffv1 frame
-                decoder
-                    .decode_frame(&pkt.data)
-                    .map_err(|_e| "Decoding failure".to_owned())
-            }
-            // When the EOF is reached, the decoding process is stopped
-            Event::Eof => {
-                println!("EOF reached.");
-                Err("EOF reached".to_owned())
-            }
-            _ => {
-                // If an unsupported event occurs,
-                // the decoding process is stopped
-                println!("Unsupported event {:?}", event);
-                Err("Unsupported event".to_owned())
-            }
-        },
-        Err(err) => {
-            // If there are no more events, the decoding process is stopped
-            println!("No more events {:?}", err);
-            Err("No more events".to_owned())
-        }
-    }
-}
+mod common;
 
-fn decode(input: &str) -> ffv1::decoder::Frame {
-    let reader = File::open(input).unwrap();
-
-    // Create a buffer of size 4096KiB to contain matroska data
-    let ar = AccReader::with_capacity(4 * 1024, reader);
-
-    // Set the type of demuxer, in this case, a matroska demuxer
-    let mut demuxer = Context::new(Box::new(MkvDemuxer::new()), Box::new(ar));
-
-    // Read matroska headers
-    demuxer
-        .read_headers()
-        .expect("Cannot parse the format headers");
-
-    // Save decoder params for ffv1 decoder
-    let mut decoder_params: DecParams = Default::default();
-
-    // Iterate over the streams contained in a matroska file
-    for stream in &demuxer.info.streams {
-        // Considers only video streams and analyze the type of codec inside.
-        if let Some(MediaKind::Video(info)) = &stream.params.kind {
-            let extradata =
-                stream.params.extradata.as_ref().unwrap_or_else(|| {
-                    eprintln!("No extradata detected. Aborting");
-                    std::process::exit(1);
-                });
-            if String::from_utf8_lossy(&extradata).contains("FFV1") {
-                decoder_params.width = info.width as u32;
-                decoder_params.height = info.height as u32;
-                // As per Matroska spec for VFW CodecPrivate
-                decoder_params.extradata = extradata[40..].to_owned();
-            }
-        }
-    }
+use common::{next_packet, open, open_params, read_packet};
 
-    // Create a new ffv1 decoder
-    let mut ffv1_decoder = Decoder::new(
-        &decoder_params.extradata,
-        decoder_params.width,
-        decoder_params.height,
-    )
-    .unwrap();
+fn decode(input: &str) -> ffv1::decoder::Frame {
+    let (mut demuxer, mut ffv1_decoder) = open(input);
 
-    decode_single_frame(&mut demuxer, &mut ffv1_decoder).unwrap()
+    ffv1_decoder.decode_frame(read_packet(&mut demuxer)).unwrap()
 }
 
 #[test]
@@ -105,9 +20,9 @@ fn test_yuv420() {
     let f = File::open(reference).unwrap();
     let frame = decode(input);
 
-    let yplane = frame.buf[0].iter();
-    let uplane = frame.buf[1].iter();
-    let vplane = frame.buf[2].iter();
+    let yplane = frame.buf()[0].iter();
+    let uplane = frame.buf()[1].iter();
+    let vplane = frame.buf()[2].iter();
 
     let pixels = yplane.chain(uplane).chain(vplane);
 
@@ -124,9 +39,9 @@ fn test_bgr0() {
     let mut f = File::open(reference).unwrap();
     let frame = decode(input);
 
-    let gplane = frame.buf[0].iter();
-    let bplane = frame.buf[1].iter();
-    let rplane = frame.buf[2].iter();
+    let gplane = frame.buf()[0].iter();
+    let bplane = frame.buf()[1].iter();
+    let rplane = frame.buf()[2].iter();
 
     let pixels = gplane.zip(bplane).zip(rplane);
 
@@ -150,9 +65,9 @@ fn test_gbrp16le() {
     let mut f = File::open(reference).unwrap();
     let frame = decode(input);
 
-    let gplane = frame.buf16[0].iter();
-    let bplane = frame.buf16[1].iter();
-    let rplane = frame.buf16[2].iter();
+    let gplane = frame.buf16()[0].iter();
+    let bplane = frame.buf16()[1].iter();
+    let rplane = frame.buf16()[2].iter();
 
     let pixels = gplane.chain(bplane).chain(rplane);
 
@@ -161,3 +76,2171 @@ fn test_gbrp16le() {
         assert_eq!(p, r, "pixel {}", i);
     }
 }
+
+#[test]
+fn test_yuv420_into_padded_buffers() {
+    use ffv1::decoder::{FrameBuffers, PlaneBuffer};
+    let input = "data/ffv1_v3_yuv420p.mkv";
+    let reference = "data/ffv1_v3_yuv420p.ref";
+
+    let reference = std::fs::read(reference).unwrap();
+    let (mut demuxer, mut decoder) = open(input);
+    let packet = read_packet(&mut demuxer);
+
+    let width = decoder.config_record().width as usize;
+    let height = decoder.config_record().height as usize;
+    let dimensions = [
+        (width, height),
+        (width / 2, height / 2),
+        (width / 2, height / 2),
+    ];
+
+    // Pad every row to 64 samples
+    let strides: Vec<usize> =
+        dimensions.iter().map(|&(w, _)| (w + 63) & !63).collect();
+    let mut planes: Vec<Vec<u8>> = dimensions
+        .iter()
+        .zip(strides.iter())
+        .map(|(&(_, h), &stride)| vec![0; stride * h])
+        .collect();
+
+    let mut buffers = FrameBuffers::U8(
+        planes
+            .iter_mut()
+            .zip(strides.iter())
+            .map(|(data, &stride)| PlaneBuffer { data, stride })
+            .collect(),
+    );
+    decoder.decode_frame_into(&packet, &mut buffers).unwrap();
+
+    let mut reference = reference.iter();
+    for ((plane, &stride), &(w, h)) in
+        planes.iter().zip(strides.iter()).zip(dimensions.iter())
+    {
+        for y in 0..h {
+            for x in 0..w {
+                let r = *reference.next().unwrap();
+                assert_eq!(plane[y * stride + x], r, "pixel {}x{}", x, y);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_frame_md5() {
+    use ffv1::hash::md5;
+    for name in &["yuv420p", "bgr0", "gbrp16le"] {
+        let input = format!("data/ffv1_v3_{}.mkv", name);
+        let reference = format!("data/ffv1_v3_{}.ref", name);
+
+        let reference = std::fs::read(reference).unwrap();
+        let frame = decode(&input);
+
+        assert_eq!(frame.frame_md5(), md5(&reference), "{}", name);
+    }
+}
+
+#[test]
+fn test_resume_from_saved_state() {
+    let input = "data/ffv1_v3.mkv";
+    let (mut demuxer, mut ffv1_decoder) = open(input);
+
+    // Frame 0 is a keyframe, the following ones depend on its states
+    for _ in 0..5 {
+        ffv1_decoder.decode_frame(read_packet(&mut demuxer)).unwrap();
+    }
+    let state = ffv1_decoder.save_state();
+
+    let (_, mut resumed_decoder) = open(input);
+    resumed_decoder.restore_state(&state).unwrap();
+
+    for _ in 0..5 {
+        let packet = read_packet(&mut demuxer);
+        let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+        let resumed = resumed_decoder.decode_frame(&packet).unwrap();
+        assert_eq!(frame.frame_md5(), resumed.frame_md5());
+    }
+
+    assert!(resumed_decoder.restore_state(&state[1..]).is_err());
+}
+
+#[test]
+fn test_metrics() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use ffv1::metrics::Metrics;
+
+    #[derive(Default)]
+    struct Counters {
+        frames: AtomicUsize,
+        failed: AtomicUsize,
+        bytes: AtomicUsize,
+        slices: AtomicUsize,
+        crc_failures: AtomicUsize,
+    }
+
+    impl Metrics for Counters {
+        fn frame_decoded(&self, bytes: usize, _latency: Duration) {
+            self.frames.fetch_add(1, Ordering::Relaxed);
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        fn frame_failed(&self, _bytes: usize) {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn slice_decoded(&self, _slice: usize, _bytes: usize) {
+            self.slices.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn slice_crc_failed(&self, _slice: usize) {
+            self.crc_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let counters = Arc::new(Counters::default());
+    ffv1_decoder.set_metrics(counters.clone());
+
+    let mut packet = read_packet(&mut demuxer);
+    ffv1_decoder.decode_frame(&packet).unwrap();
+
+    // Corrupt the first slice
+    packet[10] ^= 0xFF;
+    assert!(ffv1_decoder.decode_frame(&packet).is_err());
+
+    assert_eq!(counters.frames.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.failed.load(Ordering::Relaxed), 1);
+    assert_eq!(counters.bytes.load(Ordering::Relaxed), packet.len());
+    assert_eq!(counters.crc_failures.load(Ordering::Relaxed), 1);
+    assert!(counters.slices.load(Ordering::Relaxed) > 0);
+}
+
+#[test]
+fn test_frame_pool() {
+    use std::sync::Arc;
+
+    use ffv1::allocator::FramePool;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+    let pool = Arc::new(FramePool::new());
+    ffv1_decoder.set_allocator(pool.clone());
+
+    let frame = ffv1_decoder.decode_frame(read_packet(&mut demuxer)).unwrap();
+    let planes: Vec<_> = frame.buf().iter().map(|plane| plane.as_ptr()).collect();
+    pool.recycle(frame);
+
+    let frame = ffv1_decoder.decode_frame(read_packet(&mut demuxer)).unwrap();
+    for plane in frame.buf() {
+        assert!(planes.contains(&plane.as_ptr()));
+    }
+}
+
+#[test]
+fn test_decode_stats() {
+    use std::time::Duration;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_bgr0.mkv");
+    let packet = read_packet(&mut demuxer);
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    assert!(ffv1_decoder.stats().is_none());
+
+    ffv1_decoder.set_stats_enabled(true);
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    ffv1_decoder.decode_frame(&packet).unwrap();
+
+    let stats = ffv1_decoder.stats().unwrap();
+    assert_eq!(stats.frames, 2);
+    assert!(stats.samples > Duration::ZERO);
+    assert!(stats.rct > Duration::ZERO);
+}
+
+#[test]
+fn test_thread_stats() {
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    assert!(ffv1_decoder.thread_stats().is_none());
+
+    ffv1_decoder.set_stats_enabled(true);
+    ffv1_decoder.set_threads(1);
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!(ffv1_decoder.thread_stats().unwrap().frames, 0);
+
+    ffv1_decoder.set_threads(2);
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    let stats = ffv1_decoder.thread_stats().unwrap();
+    assert_eq!(stats.frames, 2);
+    assert_eq!(stats.busy.len(), 2);
+    for thread in 0..2 {
+        let utilization = stats.utilization(thread);
+        assert!(utilization > 0.0 && utilization <= 1.0, "{}", utilization);
+    }
+    assert_eq!(stats.utilization(2), 0.0);
+}
+
+#[test]
+fn test_pipeline() {
+    use ffv1::decoder::FrameMetadata;
+    use ffv1::pipeline::{Packet, Pipeline};
+
+    let input = "data/ffv1_v3.mkv";
+    let (mut demuxer, mut ffv1_decoder) = open(input);
+    let (_, pipeline_decoder) = open(input);
+
+    // Keyframes every 12 frames
+    let packets: Vec<Packet> = (0..40)
+        .map(|i| Packet {
+            data: read_packet(&mut demuxer),
+            keyframe: i % 12 == 0,
+            metadata: FrameMetadata {
+                pts: Some(i),
+                opaque: None,
+            },
+        })
+        .collect();
+
+    let frames: Vec<_> = Pipeline::new(pipeline_decoder, 3)
+        .decode(packets.clone())
+        .collect();
+    assert_eq!(frames.len(), packets.len());
+
+    for (packet, frame) in packets.iter().zip(frames) {
+        let frame = frame.unwrap();
+        let expected = ffv1_decoder.decode_frame(&packet.data).unwrap();
+        assert_eq!(frame.frame_md5(), expected.frame_md5());
+        assert_eq!(frame.metadata().pts, packet.metadata.pts);
+    }
+}
+
+#[test]
+fn test_pipelined_decoder() {
+    use ffv1::decoder::FrameMetadata;
+    use ffv1::pipeline::{Packet, PipelinedDecoder};
+
+    let input = "data/ffv1_v3.mkv";
+    let (mut demuxer, mut ffv1_decoder) = open(input);
+    let (_, pipelined_decoder) = open(input);
+
+    let mut packets: Vec<Packet> = (0..6)
+        .map(|i| Packet {
+            data: read_packet(&mut demuxer),
+            keyframe: i == 0,
+            metadata: FrameMetadata {
+                pts: Some(i),
+                opaque: None,
+            },
+        })
+        .collect();
+
+    // Damaged slices are still caught by the front-end
+    let layout = ffv1_decoder.slice_layout(&packets[3].data).unwrap();
+    packets[3].data[layout[0].range.start + 10] ^= 0xFF;
+
+    let frames: Vec<_> = PipelinedDecoder::new(pipelined_decoder)
+        .decode(packets.clone())
+        .collect();
+    assert_eq!(frames.len(), packets.len());
+    assert!(frames[3].is_err());
+
+    for (packet, frame) in packets.iter().zip(frames) {
+        match ffv1_decoder.decode_frame(&packet.data) {
+            Ok(expected) => {
+                let frame = frame.unwrap();
+                assert_eq!(frame.frame_md5(), expected.frame_md5());
+                assert_eq!(frame.metadata().pts, packet.metadata.pts);
+            }
+            Err(err) => assert_eq!(
+                frame.unwrap_err().to_string(),
+                err.to_string()
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_packet_queue() {
+    use ffv1::decoder::FrameMetadata;
+    use ffv1::pipeline::{Packet, PacketQueue, Pipeline};
+
+    let input = "data/ffv1_v3.mkv";
+    let (mut demuxer, mut ffv1_decoder) = open(input);
+    let (_, pipeline_decoder) = open(input);
+
+    // The demuxer of the queue is opened on its reader thread
+    let queue = PacketQueue::new(4, move || {
+        let (mut demuxer, _) = open(input);
+        (0..20).map(move |i| Packet {
+            data: read_packet(&mut demuxer),
+            keyframe: i % 12 == 0,
+            metadata: FrameMetadata {
+                pts: Some(i),
+                opaque: None,
+            },
+        })
+    });
+
+    let frames: Vec<_> = Pipeline::new(pipeline_decoder, 2)
+        .decode(queue)
+        .collect();
+    assert_eq!(frames.len(), 20);
+
+    for (i, frame) in frames.into_iter().enumerate() {
+        let frame = frame.unwrap();
+        let packet = read_packet(&mut demuxer);
+        let expected = ffv1_decoder.decode_frame(&packet).unwrap();
+        assert_eq!(frame.frame_md5(), expected.frame_md5());
+        assert_eq!(frame.metadata().pts, Some(i as i64));
+    }
+}
+
+#[test]
+fn test_shared_packet_input() {
+    use std::sync::Arc;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+    let (_, mut shared_decoder) = open("data/ffv1_v3.mkv");
+
+    // Packets shared with e.g. a network stack are decoded in place
+    for _ in 0..3 {
+        let packet = read_packet(&mut demuxer);
+        let shared: Arc<[u8]> = packet.clone().into();
+        let expected = ffv1_decoder.decode_frame(packet).unwrap();
+        let frame = shared_decoder.decode_frame(Arc::clone(&shared)).unwrap();
+        assert_eq!(frame.frame_md5(), expected.frame_md5());
+    }
+}
+
+#[test]
+fn test_rewind_to_snapshot() {
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+
+    // Frame 0 is a keyframe, the following ones depend on its states
+    let packets: Vec<_> = (0..6).map(|_| read_packet(&mut demuxer)).collect();
+    ffv1_decoder.decode_frame(&packets[0]).unwrap();
+    ffv1_decoder.decode_frame(&packets[1]).unwrap();
+    let snapshot = ffv1_decoder.snapshot();
+
+    let expected: Vec<_> = packets[2..]
+        .iter()
+        .map(|packet| ffv1_decoder.decode_frame(packet).unwrap().frame_md5())
+        .collect();
+
+    ffv1_decoder.rewind(&snapshot).unwrap();
+    for (packet, md5) in packets[2..].iter().zip(&expected) {
+        assert_eq!(&ffv1_decoder.decode_frame(packet).unwrap().frame_md5(), md5);
+    }
+}
+
+#[test]
+fn test_frame_metadata() {
+    use std::sync::Arc;
+
+    use ffv1::decoder::FrameMetadata;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let metadata = FrameMetadata {
+        pts: Some(1001),
+        opaque: Some(Arc::new("user data")),
+    };
+    let frame = ffv1_decoder
+        .decode_frame_with(read_packet(&mut demuxer), metadata)
+        .unwrap();
+
+    assert_eq!(frame.metadata().pts, Some(1001));
+    let opaque = frame.metadata().opaque.clone().unwrap();
+    assert_eq!(opaque.downcast_ref::<&str>(), Some(&"user data"));
+}
+
+#[test]
+fn test_raw_writer() {
+    use ffv1::raw::{Endianness, Layout, RawWriter};
+
+    let frame = decode("data/ffv1_v3_gbrp16le.mkv");
+    let mut reference = Vec::new();
+    File::open("data/ffv1_v3_gbrp16le.ref")
+        .unwrap()
+        .read_to_end(&mut reference)
+        .unwrap();
+
+    let mut writer = RawWriter::new(Vec::new());
+    writer.write_frame(&frame).unwrap();
+    assert!(writer.into_inner() == reference);
+
+    let mut writer = RawWriter::new(Vec::new());
+    writer.set_endianness(Endianness::Big);
+    writer.set_layout(Layout::Interleaved);
+    writer.set_plane_order(&[2, 0, 1]);
+    writer.write_frame(&frame).unwrap();
+    let rgb = writer.into_inner();
+    assert_eq!(rgb.len(), reference.len());
+    for (i, &plane) in [2, 0, 1].iter().enumerate() {
+        let sample = u16::from_be_bytes([rgb[2 * i], rgb[2 * i + 1]]);
+        assert_eq!(sample, frame.buf16()[plane][0]);
+    }
+
+    writer = RawWriter::new(Vec::new());
+    writer.set_plane_order(&[3]);
+    assert!(writer.write_frame(&frame).is_err());
+}
+
+#[test]
+fn test_ffmpeg_pix_fmt() {
+    for name in &["yuv420p", "bgr0", "gbrp16le"] {
+        let (_, ffv1_decoder) = open(&format!("data/ffv1_v3_{}.mkv", name));
+        let pix_fmt = ffv1_decoder.config_record().ffmpeg_pix_fmt();
+        assert_eq!(pix_fmt.as_deref(), Some(*name));
+    }
+}
+
+#[test]
+fn test_repair_packet() {
+    use ffv1::repair::{check_packet, repair_packet, RepairOptions};
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    let status = check_packet(&packet).unwrap();
+    assert!(status.iter().all(|slice| slice.crc_valid));
+
+    // Mark the last slice as damaged, without updating its CRC
+    let mut damaged = packet.clone();
+    let error_status = damaged.len() - 5;
+    damaged[error_status] = 1;
+    let status = check_packet(&damaged).unwrap();
+    assert_eq!(status.last().unwrap().error_status, 1);
+    assert!(!status.last().unwrap().crc_valid);
+    assert!(ffv1_decoder.decode_frame(&damaged).is_err());
+
+    let options = RepairOptions {
+        clear_error_status: true,
+        recompute_crc: true,
+    };
+    repair_packet(&mut damaged, &options).unwrap();
+    assert!(damaged == packet);
+    ffv1_decoder.decode_frame(&damaged).unwrap();
+}
+
+#[test]
+fn test_slice_layout() {
+    let (mut demuxer, ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    let record = ffv1_decoder.config_record();
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+
+    let grid = (record.num_h_slices_minus1 as usize + 1)
+        * (record.num_v_slices_minus1 as usize + 1);
+    assert_eq!(layout.len(), grid);
+    assert_eq!(layout[0].range.start, 0);
+    assert_eq!(layout.last().unwrap().footer.end, packet.len());
+    for (slice, next) in layout.iter().zip(layout.iter().skip(1)) {
+        assert_eq!(slice.footer.end, next.range.start);
+    }
+
+    let mut covered = 0;
+    for slice in &layout {
+        assert_eq!(slice.crc_valid, Some(true));
+        let header = &slice.header;
+        assert!(header.slice_x() <= record.num_h_slices_minus1 as u32);
+        assert!(header.slice_y() <= record.num_v_slices_minus1 as u32);
+        covered += header.slice_width() * header.slice_height();
+    }
+    assert_eq!(covered as usize, grid);
+}
+
+#[test]
+fn test_slice_headers() {
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    assert_eq!(ffv1_decoder.slice_headers().count(), 0);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    ffv1_decoder.decode_frame(&packet).unwrap();
+
+    let record = ffv1_decoder.config_record();
+    let headers: Vec<_> = ffv1_decoder.slice_headers().collect();
+    assert_eq!(headers.len(), layout.len());
+    let mut area = 0;
+    for (header, slice) in headers.iter().zip(&layout) {
+        assert_eq!(header.slice_x(), slice.header.slice_x());
+        assert_eq!(header.slice_y(), slice.header.slice_y());
+        assert_eq!(
+            header.quant_table_set_index(),
+            slice.header.quant_table_set_index()
+        );
+        assert_eq!(header.sar(), slice.header.sar());
+        assert_eq!(header.rect(record), slice.rect);
+        let (_, _, width, height) = header.rect(record);
+        area += width * height;
+    }
+    assert_eq!(area, record.width * record.height);
+}
+
+#[test]
+fn test_slice_map() {
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let mut packet = read_packet(&mut demuxer);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    ffv1_decoder.decode_frame(&packet).unwrap();
+
+    let map = ffv1_decoder.slice_map();
+    assert_eq!(map.len(), layout.len());
+    for (info, slice) in map.iter().zip(&layout) {
+        assert_eq!(info.pos()..info.pos() + info.size(), slice.range);
+        assert_eq!(info.error_status(), slice.error_status);
+        assert_eq!(info.crc_valid(), Some(true));
+    }
+
+    // Slices after the damaged one are not reached on a single thread
+    packet[layout[1].range.start + 10] ^= 0xFF;
+    ffv1_decoder.set_threads(1);
+    assert!(ffv1_decoder.decode_frame(&packet).is_err());
+    let crcs: Vec<_> = ffv1_decoder
+        .slice_map()
+        .iter()
+        .map(|info| info.crc_valid())
+        .collect();
+    assert_eq!(crcs[..2], [Some(true), Some(false)]);
+    assert!(crcs[2..].iter().all(Option::is_none));
+
+    // All of them are checked before decoding on several threads
+    ffv1_decoder.set_threads(2);
+    assert!(ffv1_decoder.decode_frame(&packet).is_err());
+    let damaged: Vec<_> = ffv1_decoder
+        .slice_map()
+        .iter()
+        .map(|info| info.crc_valid() == Some(false))
+        .collect();
+    assert!(damaged[1] && damaged.iter().filter(|&&d| d).count() == 1);
+
+    assert!(ffv1_decoder.decode_frame(&packet[..2]).is_err());
+    assert!(ffv1_decoder.slice_map().is_empty());
+}
+
+#[test]
+fn test_config_record_builder() {
+    use ffv1::constants::ColorSpace;
+    use ffv1::quant_presets::{symmetric_table, table_set, QUANT11};
+    use ffv1::record::ConfigRecordBuilder;
+
+    let input = "data/ffv1_v3_yuv420p_states.mkv";
+    let (mut demuxer, ffv1_decoder) = open(input);
+    let parsed = ffv1_decoder.config_record().clone();
+    // The generated fixtures quantize to 7 levels on 3 inputs
+    let table = symmetric_table(&[1, 2, 4]);
+    let set = table_set(&[table, table, table]);
+    let mut builder = ConfigRecordBuilder::new(parsed.width, parsed.height)
+        .slices(2, 2)
+        .quant_tables(vec![set; 2])
+        .intra(1);
+    for (i, states) in parsed.initial_states.iter().enumerate() {
+        builder = builder.initial_states(i, states.clone());
+    }
+    let record = builder.build().unwrap();
+    assert_eq!(record, parsed);
+
+    // Frames decode the same without parsing the record
+    let mut built = Decoder::from_config_record(record).unwrap();
+    let packet = read_packet(&mut demuxer);
+    let frame = built.decode_frame(&packet).unwrap();
+    assert_eq!(frame.buf(), decode(input).buf());
+
+    let builder = || ConfigRecordBuilder::new(64, 48);
+    let rgb = builder().colorspace(ColorSpace::Rgb).chroma_subsample(0, 0);
+    assert_eq!(rgb.build().unwrap().ffmpeg_pix_fmt().unwrap(), "bgr0");
+    assert!(builder().coder_type(0).bits_per_raw_sample(10).build().is_err());
+    assert!(builder().colorspace(ColorSpace::Rgb).build().is_err());
+    assert!(builder().slices(0, 1).build().is_err());
+    assert!(builder().initial_states(0, vec![]).build().is_err());
+    let mut table = QUANT11;
+    table[3] = 7;
+    let sets = vec![table_set(&[table])];
+    assert!(builder().quant_tables(sets).build().is_err());
+}
+
+#[test]
+fn test_golomb_state() {
+    use ffv1::golombcoder::golomb::State;
+
+    let state = State::default();
+    let fields = (state.drift(), state.error_sum(), state.bias());
+    assert_eq!((fields, state.count()), ((0, 4, 0), 1));
+    assert_eq!(State::new(0, 4, 0, 1), Some(state.clone()));
+    assert_eq!(State::from_le_bytes(&state.to_le_bytes()), Some(state));
+
+    assert!(State::new(-127, 1000, -128, 128).is_some());
+    assert!(State::new(0, 4, 0, 0).is_none());
+    assert!(State::new(1, 4, 0, 1).is_none());
+    assert!(State::new(-1, 4, 0, 1).is_none());
+    assert!(State::new(0, -4, 0, 1).is_none());
+    assert!(State::new(0, 4, 128, 1).is_none());
+}
+
+#[test]
+fn test_golomb_run_overflow() {
+    use ffv1::golombcoder::golomb::{GolombCoder, State};
+
+    // Decodes a line of 13 samples in run mode: eight full runs covering 12
+    // samples, then a run of 'last' followed by a level.
+    let decode_line = |last: u8| {
+        let buf = [0xFF, last << 5];
+        let mut coder = GolombCoder::new(&buf);
+        let mut state = State::default();
+        coder.new_plane(13);
+        coder.new_line();
+        let line: Vec<_> =
+            (0..13).map(|_| coder.sg(0, &mut state, 8)).collect();
+        (line, coder.invalid_run())
+    };
+
+    // A run of 0 leaves room for the level
+    let (line, invalid) = decode_line(0);
+    assert!(line[..12].iter().all(|&diff| diff == 0));
+    assert!(line[12] != 0 && !invalid);
+
+    // A run of 3 would end past the line, it is clamped to it
+    let (line, invalid) = decode_line(3);
+    assert!(line.iter().all(|&diff| diff == 0));
+    assert!(invalid);
+}
+
+#[test]
+fn test_seal_damaged_slice() {
+    use ffv1::repair::seal_slice;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let mut packet = read_packet(&mut demuxer);
+
+    // Damage the content of the second slice
+    let slice = ffv1_decoder.slice_layout(&packet).unwrap()[1].clone();
+    packet[slice.range.start + 10] ^= 0xFF;
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    assert_eq!(layout[1].crc_valid, Some(false));
+
+    // Mark it, so its footer is consistent again
+    seal_slice(&mut packet[slice.range.start..slice.footer.end], 1).unwrap();
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    assert_eq!(layout[1].crc_valid, Some(true));
+    assert_eq!(layout[1].error_status, 1);
+    assert!(ffv1_decoder.decode_frame(&packet).is_err());
+
+    assert!(seal_slice(&mut [0; 4], 0).is_err());
+}
+
+#[test]
+fn test_packet_assembler() {
+    use ffv1::assembler::{Framing, PacketAssembler};
+
+    let input = "data/ffv1_v3.mkv";
+    let (mut demuxer, mut ffv1_decoder) = open(input);
+    let (_, mut assembler_decoder) = open(input);
+
+    let packets: Vec<_> = (0..4).map(|_| read_packet(&mut demuxer)).collect();
+
+    // Length-prefixed stream, received in small chunks
+    let mut stream = Vec::new();
+    for packet in &packets {
+        stream.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+        stream.extend_from_slice(packet);
+    }
+
+    let mut assembler = PacketAssembler::new(Framing::LengthPrefixed);
+    let mut frames = Vec::new();
+    for chunk in stream.chunks(1000) {
+        assembler.push(chunk);
+        while let Some(frame) =
+            assembler.decode_next(&mut assembler_decoder).unwrap()
+        {
+            frames.push(frame);
+        }
+    }
+    assert_eq!(assembler.pending(), 0);
+    assert_eq!(frames.len(), packets.len());
+
+    for (packet, frame) in packets.iter().zip(frames) {
+        let expected = ffv1_decoder.decode_frame(packet).unwrap();
+        assert_eq!(frame.frame_md5(), expected.frame_md5());
+    }
+
+    // Sizes provided by the container
+    let mut assembler = PacketAssembler::new(Framing::Sized);
+    assembler.push(&packets[0][..10]);
+    assembler.push_size(packets[0].len()).unwrap();
+    assert!(assembler.next_packet().unwrap().is_none());
+    assembler.push(&packets[0][10..]);
+    assert_eq!(assembler.next_packet().unwrap().as_ref(), Some(&packets[0]));
+}
+
+#[test]
+fn test_arc_frame() {
+    use std::sync::Arc;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+    let frame = ffv1_decoder
+        .decode_frame(read_packet(&mut demuxer))
+        .unwrap();
+    let md5 = frame.frame_md5();
+
+    let shared = frame.into_shared();
+    let consumer = shared.clone();
+    for (a, b) in shared.buf.iter().zip(&consumer.buf) {
+        assert!(Arc::ptr_eq(a, b));
+    }
+    assert_eq!(consumer.num_planes(), shared.buf.len());
+    assert_eq!(consumer.to_frame().frame_md5(), md5);
+}
+
+#[test]
+fn test_gray() {
+    use ffv1::constants::PixelFormat;
+    use ffv1::hash::md5;
+    for &(name, bit_depth) in &[("gray", 8), ("gray16le", 16)] {
+        let input = format!("data/ffv1_v3_{}.mkv", name);
+        let reference = format!("data/ffv1_v3_{}.ref", name);
+
+        let (mut demuxer, mut ffv1_decoder) = open(&input);
+        let record = ffv1_decoder.config_record();
+        assert_eq!(record.pixel_format(), PixelFormat::Gray, "{}", name);
+        assert_eq!(record.num_planes(), 1, "{}", name);
+        assert_eq!(record.ffmpeg_pix_fmt().as_deref(), Some(name));
+
+        let frame = ffv1_decoder
+            .decode_frame(read_packet(&mut demuxer))
+            .unwrap();
+        assert_eq!(frame.pixel_format(), PixelFormat::Gray, "{}", name);
+        assert_eq!(frame.bit_depth(), bit_depth, "{}", name);
+
+        let reference = std::fs::read(reference).unwrap();
+        assert_eq!(frame.frame_md5(), md5(&reference), "{}", name);
+    }
+}
+
+#[test]
+fn test_chroma_subsampling_and_odd_dimensions() {
+    use ffv1::hash::md5;
+    for name in &[
+        "yuv422p",
+        "yuv411p",
+        "yuv440p",
+        "yuv420p_33x17",
+        "yuv411p_35x19",
+    ] {
+        let input = format!("data/ffv1_v3_{}.mkv", name);
+        let reference = format!("data/ffv1_v3_{}.ref", name);
+
+        let reference = std::fs::read(reference).unwrap();
+        let frame = decode(&input);
+
+        // Chroma planes are rounded up
+        let size: usize = (0..frame.num_planes())
+            .map(|i| {
+                let (width, height) = frame.plane_dimensions(i);
+                width * height
+            })
+            .sum();
+        assert_eq!(size, reference.len(), "{}", name);
+        assert_eq!(frame.frame_md5(), md5(&reference), "{}", name);
+    }
+}
+
+#[test]
+fn test_states_coded() {
+    use ffv1::hash::md5;
+
+    // FFmpeg only codes the initial states in two-pass mode
+    let (_, ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let record = ffv1_decoder.config_record();
+    assert_eq!(record.states_coded, vec![false; 2]);
+    assert!(record.initial_state_delta.iter().all(Vec::is_empty));
+
+    let (mut demuxer, mut ffv1_decoder) =
+        open("data/ffv1_v3_yuv420p_states.mkv");
+    let record = ffv1_decoder.config_record();
+    assert_eq!(record.states_coded, vec![true; 2]);
+    for (i, deltas) in record.initial_state_delta.iter().enumerate() {
+        assert_eq!(deltas.len(), record.context_counts()[i] as usize);
+    }
+
+    let frame = ffv1_decoder
+        .decode_frame(read_packet(&mut demuxer))
+        .unwrap();
+    let reference = std::fs::read("data/ffv1_v3_yuv420p_states.ref").unwrap();
+    assert_eq!(frame.frame_md5(), md5(&reference));
+}
+
+#[test]
+fn test_extended_bit_depths() {
+    use ffv1::hash::md5;
+    for &(name, bit_depth) in &[
+        ("yuv420p10le", 10),
+        ("yuv422p12le", 12),
+        ("yuv444p14le", 14),
+        ("gbrp12le", 12),
+    ] {
+        let input = format!("data/ffv1_v3_{}.mkv", name);
+        let reference = format!("data/ffv1_v3_{}.ref", name);
+
+        let (mut demuxer, mut ffv1_decoder) = open(&input);
+        let record = ffv1_decoder.config_record();
+        assert_eq!(record.ffmpeg_pix_fmt().as_deref(), Some(name));
+
+        let frame = ffv1_decoder
+            .decode_frame(read_packet(&mut demuxer))
+            .unwrap();
+        assert_eq!(frame.bit_depth(), bit_depth, "{}", name);
+
+        let reference = std::fs::read(reference).unwrap();
+        assert_eq!(frame.frame_md5(), md5(&reference), "{}", name);
+    }
+}
+
+#[test]
+fn test_scan_keyframes() {
+    let (mut demuxer, _) = open("data/ffv1_v3.mkv");
+    let mut packets: Vec<Vec<u8>> =
+        (0..25).map(|_| read_packet(&mut demuxer)).collect();
+
+    // The stream has a keyframe every 12 frames
+    assert_eq!(ffv1::scan_keyframes(&packets), vec![0, 12, 24]);
+
+    // Truncated packets are never keyframes
+    packets[12].truncate(1);
+    packets[24].clear();
+    assert_eq!(ffv1::scan_keyframes(&packets), vec![0]);
+}
+
+#[test]
+fn test_scan_frame() {
+    let (mut demuxer, ffv1_decoder) = open("data/ffv1_v3_yuv420p_33x17.mkv");
+    let scan = ffv1_decoder.scan_frame(&read_packet(&mut demuxer)).unwrap();
+    assert!(scan.keyframe);
+
+    // The 2x2 slice grid covers the whole 33x17 frame
+    let rects: Vec<_> = scan.slices.iter().map(|slice| slice.rect).collect();
+    assert_eq!(
+        rects,
+        vec![(0, 0, 16, 8), (16, 0, 17, 8), (0, 8, 16, 9), (16, 8, 17, 9)]
+    );
+    for slice in &scan.slices {
+        assert_eq!(slice.crc_valid, Some(true));
+        assert_eq!(slice.header.quant_table_set_index(), &[0, 0]);
+        assert_eq!(slice.header.picture_structure(), 3);
+        assert_eq!(slice.header.sar(), (1, 1));
+    }
+
+    let (mut demuxer, ffv1_decoder) = open("data/ffv1_v3.mkv");
+    read_packet(&mut demuxer);
+    let scan = ffv1_decoder.scan_frame(&read_packet(&mut demuxer)).unwrap();
+    assert!(!scan.keyframe);
+    assert!(ffv1_decoder.scan_frame(&[0]).is_err());
+}
+
+#[cfg(feature = "trace-bitstream")]
+#[test]
+fn test_trace_bitstream() {
+    use std::io::{self, Write};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let (mut demuxer, params) = open_params("data/ffv1_v3_yuv420p_33x17.mkv");
+    let trace = Shared::default();
+    let mut ffv1_decoder = Decoder::with_trace(
+        &params.extradata,
+        params.width,
+        params.height,
+        trace.clone(),
+    )
+    .unwrap();
+    ffv1_decoder
+        .decode_frame(read_packet(&mut demuxer))
+        .unwrap();
+
+    let trace = String::from_utf8(trace.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines[0], "4.1.1. version: 3");
+    assert!(lines.contains(&"4.3. keyframe: 1"));
+    let slices = lines.iter().filter(|l| l.starts_with("4.4. Slice")).count();
+    assert_eq!(slices, 4);
+
+    // One sample difference per sample of the 33x17 4:2:0 frame
+    let samples = lines
+        .iter()
+        .filter(|l| l.starts_with("4.7.4. sample_difference"))
+        .count();
+    assert_eq!(samples, 33 * 17 + 2 * 17 * 9);
+}
+
+#[test]
+fn test_threads_bit_exact() {
+    let inputs = [
+        ("data/ffv1_v3.mkv", 13),
+        ("data/ffv1_v3_yuv420p.mkv", 1),
+        ("data/ffv1_v3_bgr0.mkv", 1),
+        ("data/ffv1_v3_gbrp16le.mkv", 1),
+        ("data/ffv1_v3_gbrp12le.mkv", 1),
+        ("data/ffv1_v3_gray16le.mkv", 1),
+        ("data/ffv1_v3_yuv420p_33x17.mkv", 1),
+        ("data/ffv1_v3_yuv411p_35x19.mkv", 1),
+    ];
+    for &(input, frames) in &inputs {
+        let (mut demuxer, _) = open(input);
+        let packets: Vec<Vec<u8>> =
+            (0..frames).map(|_| read_packet(&mut demuxer)).collect();
+
+        // The frames must not depend on the number of threads, even when
+        // there are more threads than slices
+        let mut hashes = Vec::new();
+        for &threads in &[1, 2, 3, 16] {
+            let (_, mut ffv1_decoder) = open(input);
+            ffv1_decoder.set_threads(threads);
+            let frames: Vec<_> = packets
+                .iter()
+                .map(|packet| ffv1_decoder.decode_frame(packet).unwrap())
+                .map(|frame| frame.frame_md5())
+                .collect();
+            hashes.push(frames);
+        }
+        assert!(hashes.iter().all(|h| *h == hashes[0]), "{}", input);
+    }
+}
+
+#[test]
+fn test_crc32_mpeg2() {
+    use ffv1::crc32mpeg2::crc32_mpeg2;
+
+    // Check value of CRC-32/MPEG-2 with a zero initial value, stored as
+    // little-endian
+    assert_eq!(crc32_mpeg2(b"123456789"), 0x7F89_A189);
+
+    // Lengths and offsets around the 8-byte chunks
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 37 + 11) as u8).collect();
+    assert_eq!(crc32_mpeg2(&data), 0x4675_54A2);
+    for start in 0..8 {
+        for len in 0..24 {
+            let mut sealed = data[start..start + len].to_vec();
+            let parity = crc32_mpeg2(&sealed);
+            sealed.extend_from_slice(&parity.to_le_bytes());
+            assert_eq!(crc32_mpeg2(&sealed), 0, "{} bytes at {}", len, start);
+        }
+    }
+}
+
+#[test]
+fn test_crc32_mpeg2_streaming() {
+    use ffv1::crc32mpeg2::{crc32_mpeg2, Crc32Mpeg2};
+
+    // Slices are sealed with their CRC parity, splitting them anywhere
+    // must give the same CRC
+    let (mut demuxer, ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    for slice in ffv1_decoder.slice_layout(&packet).unwrap() {
+        let data = &packet[slice.range.start..slice.footer.end];
+        for split in (0..data.len()).step_by(97) {
+            let mut crc = Crc32Mpeg2::new();
+            crc.update(&data[..split]);
+            crc.update(&data[split..]);
+            assert_eq!(crc.finalize(), crc32_mpeg2(data), "split {}", split);
+            assert_eq!(crc.finalize(), 0);
+        }
+    }
+}
+
+#[test]
+fn test_threads_check_slices_first() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use ffv1::metrics::Metrics;
+
+    #[derive(Default)]
+    struct Counters {
+        slices: AtomicUsize,
+        crc_failures: AtomicUsize,
+    }
+
+    impl Metrics for Counters {
+        fn slice_decoded(&self, _slice: usize, _bytes: usize) {
+            self.slices.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn slice_crc_failed(&self, _slice: usize) {
+            self.crc_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let counters = Arc::new(Counters::default());
+    ffv1_decoder.set_metrics(counters.clone());
+    ffv1_decoder.set_threads(4);
+
+    // Damage the last and the second slices
+    let mut packet = read_packet(&mut demuxer);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    for &i in &[layout.len() - 1, 1] {
+        packet[layout[i].range.start + 10] ^= 0xFF;
+    }
+
+    // Every CRC is checked before any slice is decoded, and the first
+    // damaged slice in coding order is reported
+    let err = ffv1_decoder.decode_frame(&packet).unwrap_err();
+    assert!(err.to_string().contains("slice 1 failed"), "{}", err);
+    assert_eq!(counters.crc_failures.load(Ordering::Relaxed), 2);
+    assert_eq!(counters.slices.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_plane_slice_borders() {
+    use ffv1::pred::{derive_borders, derive_row, Neighbours, PlaneSlice};
+
+    // A 3x3 plane, with rows padded to 4 samples
+    let data: [u16; 12] = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0];
+    let plane = PlaneSlice::new(&data, 3, 4);
+
+    // See: 3.1. Border
+    let n = derive_borders(&plane, 0, 0);
+    assert_eq!(n, Neighbours::default());
+    let n = derive_borders(&plane, 0, 2);
+    assert_eq!(
+        n,
+        Neighbours {
+            T: 1,
+            L: 0,
+            t: 4,
+            l: 4,
+            tr: 5,
+            tl: 1
+        }
+    );
+    let n = derive_borders(&plane, 2, 1);
+    assert_eq!(
+        n,
+        Neighbours {
+            T: 0,
+            L: 4,
+            t: 3,
+            l: 5,
+            tr: 3,
+            tl: 2
+        }
+    );
+
+    // Rows below the first two slide the same neighbours along
+    let (above, below) = data.split_at(8);
+    let mut row = below[..3].to_vec();
+    let mut x = 0;
+    derive_row(&mut row, &above[4..], above, |n| {
+        assert_eq!(*n, derive_borders(&plane, x, 2), "x {}", x);
+        x += 1;
+        below[x - 1]
+    });
+    assert_eq!((row, x), (vec![7, 8, 9], 3));
+}
+
+#[test]
+fn test_rct_rows() {
+    use ffv1::decoder::PlaneBuffer;
+    use ffv1::jpeg2000rct::{rct_rows, Rct};
+
+    // 9-bit RCT samples of a 5x7 frame with an alpha plane, converted in
+    // the 4x6 rectangle at (1, 1)
+    let (width, height) = (5, 7);
+    let src: Vec<Vec<u16>> = (0..4)
+        .map(|plane| {
+            (0..width * height)
+                .map(|i| ((i * 37 + plane * 101) % 512) as u16)
+                .collect()
+        })
+        .collect();
+    let convert = |threads| {
+        let mut planes = vec![vec![0u8; width * height]; 4];
+        let mut dst: Vec<_> = planes
+            .iter_mut()
+            .map(|data| PlaneBuffer {
+                data,
+                stride: width,
+            })
+            .collect();
+        match threads {
+            0 => u8::rct(&mut dst, &src, width, 1, 1, 4, 6, 8),
+            _ => rct_rows(&mut dst, &src, width, 1, 1, 4, 6, 8, threads),
+        }
+        planes
+    };
+
+    // Bands of rows give the same samples as a single conversion
+    let expected = convert(0);
+    assert!(expected[0].iter().any(|&sample| sample != 0));
+    for threads in 1..=8 {
+        assert_eq!(convert(threads), expected, "{} threads", threads);
+    }
+}
+
+#[test]
+fn test_error_categories() {
+    use ffv1::error::ErrorCategory;
+
+    let (mut demuxer, params) = open_params("data/ffv1_v3_yuv420p.mkv");
+
+    // A damaged configuration record
+    let mut record = params.extradata.clone();
+    record[4] ^= 0xFF;
+    let err = Decoder::new(&record, params.width, params.height).unwrap_err();
+    assert_eq!(err.category(), ErrorCategory::Integrity, "{}", err);
+    assert_eq!(err.code(), 5);
+
+    // A damaged slice, on one or several threads
+    let mut packet = read_packet(&mut demuxer);
+    for threads in 1..=2 {
+        let mut ffv1_decoder =
+            Decoder::new(&params.extradata, params.width, params.height)
+                .unwrap();
+        ffv1_decoder.set_threads(threads);
+        let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+        packet[layout[0].range.start + 10] ^= 0xFF;
+        let err = ffv1_decoder.decode_frame(&packet).unwrap_err();
+        assert!(err.to_string().contains("slice 0 failed"), "{}", err);
+        assert_eq!(err.category(), ErrorCategory::Integrity, "{}", err);
+        packet[layout[0].range.start + 10] ^= 0xFF;
+    }
+
+    // A truncated packet
+    let ffv1_decoder =
+        Decoder::new(&params.extradata, params.width, params.height).unwrap();
+    let err = ffv1_decoder.scan_frame(&packet[..1]).unwrap_err();
+    assert_eq!(err.category(), ErrorCategory::Bitstream, "{}", err);
+}
+
+#[test]
+fn test_display_crop() {
+    use ffv1::error::ErrorCategory;
+    use ffv1::geometry::Crop;
+
+    let (mut demuxer, mut ffv1_decoder) =
+        open("data/ffv1_v3_yuv420p_33x17.mkv");
+    let packet = read_packet(&mut demuxer);
+
+    // The crop must lie within the 33x17 coded frame
+    let err = ffv1_decoder.set_display_size(34, 17).unwrap_err();
+    assert_eq!(err.category(), ErrorCategory::Configuration);
+    let whole = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!((whole.display_width(), whole.display_height()), (33, 17));
+
+    let crop = Crop {
+        left: 1,
+        top: 1,
+        width: 31,
+        height: 15,
+    };
+    ffv1_decoder.set_crop(crop).unwrap();
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!((frame.width(), frame.height()), (33, 17));
+    assert_eq!((frame.display_width(), frame.display_height()), (31, 15));
+    assert_eq!(frame.crop(), crop);
+
+    // Display views start at the crop offsets of every plane
+    let luma = frame.plane(0).unwrap();
+    let display = frame.display_plane(0).unwrap();
+    assert_eq!((display.width(), display.height()), (31, 15));
+    for (y, row) in display.rows().enumerate() {
+        assert_eq!(row, &luma.row(y + 1)[1..32]);
+    }
+
+    // Chroma views hold every chroma sample covering a displayed pixel
+    let chroma = frame.display_plane(1).unwrap();
+    assert_eq!((chroma.width(), chroma.height()), (16, 8));
+    assert_eq!(chroma.row(0), &frame.plane(1).unwrap().row(0)[..16]);
+    assert!(frame.plane16(0).is_none());
+}
+
+#[test]
+fn test_quant_presets() {
+    use ffv1::quant_presets::{
+        context_count, ffmpeg_default, symmetric_table, table_set,
+    };
+
+    // The FFmpeg-made fixtures use the FFmpeg sets
+    for name in &["ffv1_v3_yuv420p", "ffv1_v3_bgr0", "ffv1_v3_gbrp16le"] {
+        let (_, ffv1_decoder) = open(&format!("data/{}.mkv", name));
+        let record = ffv1_decoder.config_record();
+        let sets = ffmpeg_default(record.bits_per_raw_sample);
+        assert_eq!(record.quant_table_set_count, sets.len(), "{}", name);
+        for (i, set) in sets.iter().enumerate() {
+            assert_eq!(&record.quant_tables[i], set, "{} set {}", name, i);
+            let count = context_count(set);
+            assert_eq!(record.context_count[i], count, "{}", name);
+        }
+    }
+
+    // A 3-level table on a single input
+    let table = symmetric_table(&[1]);
+    assert_eq!((table[0], table[1], table[127]), (0, 1, 1));
+    assert_eq!((table[128], table[255]), (-1, -1));
+    assert_eq!(context_count(&table_set(&[table])), 2);
+    assert_eq!(context_count(&table_set(&[table, table])), 5);
+}
+
+#[test]
+fn test_state_transition_presets() {
+    use ffv1::state_transition::{
+        from_delta, presets, to_delta, TransitionAnalysis,
+        FFMPEG_STATE_TRANSITION,
+    };
+
+    // FFmpeg stores its own table with coder_type 2
+    let (_, ffv1_decoder) = open("data/ffv1_v3_gbrp16le.mkv");
+    let record = ffv1_decoder.config_record();
+    assert_eq!(record.coder_type, 2);
+    let table = from_delta(&record.state_transition_delta).unwrap();
+    assert_eq!(table, FFMPEG_STATE_TRANSITION);
+    assert_eq!(to_delta(&table), record.state_transition_delta);
+
+    // A source of mostly zeros takes less than a bit per bit to code
+    let bits = (0..10_000).map(|i| i % 10 == 0);
+    for (name, table) in &presets() {
+        let mut analysis = TransitionAnalysis::new(table);
+        analysis.code_bits(128, bits.clone());
+        assert_eq!(analysis.visits(), 10_000, "{}", name);
+        assert!(analysis.code_length() < 6_000.0, "{}", name);
+        let stats = analysis.state_stats(128);
+        assert_eq!((stats.visits, stats.ones), (1, 1), "{}", name);
+        assert_eq!(analysis.expected_code_length(128), Some(1.0));
+    }
+}
+
+#[test]
+fn test_y4m_round_trip() {
+    use ffv1::convert::to_packed;
+    use ffv1::y4m::{Header, Reader, Writer};
+
+    for (name, colorspace) in &[
+        ("ffv1_v3_yuv420p10le", "420p10"),
+        ("ffv1_v3_yuv411p_35x19", "411"),
+        ("ffv1_v3_gray16le", "mono16"),
+    ] {
+        let frame = decode(&format!("data/{}.mkv", name));
+        let mut writer = Writer::new(Vec::new());
+        writer.set_framerate(30000, 1001);
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let stream = writer.into_inner();
+
+        let mut reader = Reader::new(&stream[..]).unwrap();
+        let header = *reader.header();
+        assert_eq!(Header::from_frame(&frame, (30000, 1001)), Some(header));
+        assert_eq!(header.colorspace().unwrap(), *colorspace);
+        let expected = to_packed(&frame);
+        for _ in 0..2 {
+            let read = reader.read_frame().unwrap().unwrap();
+            assert_eq!(read.buf(), expected.buf(), "{}", name);
+            assert_eq!(read.buf16(), expected.buf16(), "{}", name);
+        }
+        assert!(reader.read_frame().unwrap().is_none());
+    }
+
+    // Y4M has no RGB colorspace
+    let frame = decode("data/ffv1_v3_bgr0.mkv");
+    assert!(Writer::new(Vec::new()).write_frame(&frame).is_err());
+    let header = Header::parse("YUV4MPEG2 W2 H2 F25:1 C420paldv").unwrap();
+    assert_eq!((header.bit_depth, header.chroma_subsample_v), (8, 1));
+}
+
+#[test]
+fn test_nut_output() {
+    use ffv1::nut::{Header, Writer};
+    use ffv1::raw::RawWriter;
+
+    for (name, codec_tag) in &[
+        ("ffv1_v3_yuv420p10le", *b"Y3\x0b\x0a"),
+        ("ffv1_v3_bgr0", *b"G3\x00\x08"),
+    ] {
+        let frame = decode(&format!("data/{}.mkv", name));
+        let mut writer = Writer::new(Vec::new());
+        writer.set_framerate(30000, 1001);
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let header = *writer.header().unwrap();
+        assert_eq!(header.codec_tag, *codec_tag, "{}", name);
+        assert_eq!(header.time_base(), (1001, 30000));
+        assert_eq!(Header::from_frame(&frame, (30000, 1001)), Some(header));
+        let stream = writer.into_inner();
+        assert!(stream.starts_with(b"nut/multimedia container\0"));
+
+        // Frames are stored as planar little-endian samples
+        let mut raw = RawWriter::new(Vec::new());
+        raw.write_frame(&frame).unwrap();
+        let samples = raw.into_inner();
+        assert!(stream.ends_with(&samples), "{}", name);
+        let first = stream.len() - samples.len();
+        assert!(stream[..first].ends_with(&samples), "{}", name);
+    }
+}
+
+#[test]
+fn test_framemd5_checksums() {
+    use ffv1::framemd5::{Checksum, FrameChecksums};
+
+    let frame = decode("data/ffv1_v3_yuv420p.mkv");
+    let md5 = Checksum::Md5([0; 16]).of_frame(&frame);
+    let adler = Checksum::Adler32(0).of_frame(&frame);
+    assert_eq!(md5, Checksum::Md5(frame.frame_md5()));
+
+    // Audio frames of other streams are kept apart
+    let framemd5 = format!(
+        "#format: frame checksums\n#version: 2\n#hash: MD5\n\
+         #tb 0: 1/48000\n#media_type 0: audio\n#tb 1: 1/25\n\
+         #media_type 1: video\n\
+         #stream#, dts,        pts, duration,     size, hash\n\
+         0,          0,          0,     1024,     4096, {}\n\
+         1,          0,          0,        1,   115200, {}\n",
+        Checksum::Md5([0xAB; 16]),
+        md5
+    );
+    let checksums = FrameChecksums::parse(framemd5.as_bytes()).unwrap();
+    assert_eq!(checksums.video_streams, [1]);
+    let entries: Vec<_> = checksums.stream(1).collect();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].pts, entries[0].size), (0, 115200));
+    assert_eq!(entries[0].checksum, md5);
+    assert_eq!(entries[0].checksum.of_frame(&frame), md5);
+
+    // framecrc writes Adler-32 checksums, followed by any side data
+    let framecrc = format!("0, 0, 0, 1, 115200, {}, S=1, 8, 0x0\n", adler);
+    let checksums = FrameChecksums::parse(framecrc.as_bytes()).unwrap();
+    assert_eq!(checksums.entries[0].checksum, adler);
+    assert!(format!("{}", adler).starts_with("0x"));
+
+    let sha = "#hash: SHA256\n";
+    assert!(FrameChecksums::parse(sha.as_bytes()).is_err());
+    assert!(FrameChecksums::parse(&b"0, 0, 0, 1, 2, xyz\n"[..]).is_err());
+}
+
+#[test]
+fn test_thread_limit() {
+    use ffv1::threads::{default_threads, ThreadLimit};
+    use std::sync::Arc;
+
+    let (mut demuxer, ffv1_decoder) = open("data/ffv1_v3.mkv");
+    assert!(default_threads() >= 1);
+    assert_eq!(ffv1_decoder.threads(), default_threads());
+    let packet = read_packet(&mut demuxer);
+
+    // Decoders sharing a limit decode on the threads left free, or on the
+    // calling thread alone, and give them back
+    let mut hashes = Vec::new();
+    for &threads in &[0, 1, 3] {
+        let limit = Arc::new(ThreadLimit::new(threads));
+        let mut decoders = Vec::new();
+        for _ in 0..2 {
+            let (_, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+            ffv1_decoder.set_threads(4);
+            ffv1_decoder.set_thread_limit(limit.clone());
+            decoders.push(ffv1_decoder);
+        }
+        for ffv1_decoder in &mut decoders {
+            let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+            hashes.push(frame.frame_md5());
+        }
+        assert_eq!(limit.free(), threads);
+    }
+    assert!(hashes.iter().all(|hash| *hash == hashes[0]));
+}
+
+#[test]
+fn test_compliance() {
+    use ffv1::compliance::Compliance;
+    use ffv1::error::ErrorCategory;
+
+    let (mut demuxer, params) = open_params("data/ffv1_v3.mkv");
+    let packet = read_packet(&mut demuxer);
+    let expected = Decoder::new(&params.extradata, params.width, params.height)
+        .unwrap()
+        .decode_frame(&packet)
+        .unwrap()
+        .frame_md5();
+
+    // FFmpeg-made files comply with the specification
+    let mut ffv1_decoder = Decoder::with_compliance(
+        &params.extradata,
+        params.width,
+        params.height,
+        Compliance::Strict,
+    )
+    .unwrap();
+    assert_eq!(ffv1_decoder.compliance(), Compliance::Strict);
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!(frame.frame_md5(), expected);
+
+    // A damaged record CRC is only ignored by permissive decoders
+    let mut record = params.extradata.clone();
+    let last = record.len() - 1;
+    record[last] ^= 1;
+    for &compliance in &[Compliance::Strict, Compliance::Normal] {
+        let err = Decoder::with_compliance(
+            &record,
+            params.width,
+            params.height,
+            compliance,
+        )
+        .unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Integrity);
+    }
+    let mut ffv1_decoder = Decoder::with_compliance(
+        &record,
+        params.width,
+        params.height,
+        Compliance::Permissive,
+    )
+    .unwrap();
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!(frame.frame_md5(), expected);
+}
+
+#[test]
+fn test_diagnostics() {
+    use ffv1::diagnostics::Diagnostic;
+    use ffv1::repair::seal_slice;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_gbrp16le.mkv");
+    let packet = read_packet(&mut demuxer);
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert!(frame.diagnostics().is_empty());
+    let expected = frame.frame_md5();
+
+    // Pad the last slice with 16 bytes, resealing its footer. Only the
+    // end of range coded slices is known.
+    assert_ne!(ffv1_decoder.config_record().coder_type, 0);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    let slice = layout.last().unwrap();
+    let mut padded = packet[..slice.range.end].to_vec();
+    padded.extend_from_slice(&[0; 16]);
+    padded.extend_from_slice(&packet[slice.footer.clone()]);
+    let footer = padded.len() - 8;
+    let size = (slice.range.len() + 16) as u32;
+    padded[footer..footer + 3].copy_from_slice(&size.to_be_bytes()[1..]);
+    seal_slice(&mut padded[slice.range.start..], 0).unwrap();
+
+    let frame = ffv1_decoder.decode_frame(&padded).unwrap();
+    assert_eq!(frame.frame_md5(), expected);
+    let index = layout.len() - 1;
+    match frame.diagnostics() {
+        [Diagnostic::PaddingSkipped { slice, bytes }] => {
+            assert_eq!(*slice, index);
+            // The range coder may read a couple of bytes ahead
+            assert!((14..=16).contains(bytes), "{}", bytes);
+        }
+        diagnostics => panic!("unexpected diagnostics {:?}", diagnostics),
+    }
+    assert_eq!(ffv1_decoder.diagnostics(), frame.diagnostics());
+    let report = frame.diagnostics()[0].to_string();
+    assert!(report.starts_with(&format!("slice {}: ", index)), "{}", report);
+}
+
+#[test]
+fn test_output_sink() {
+    use ffv1::error::Result;
+    use ffv1::sink::OutputSink;
+
+    // Rows of every plane, widened to 16 bits, and the planes done
+    #[derive(Default)]
+    struct Rows {
+        planes: Vec<Vec<Vec<u16>>>,
+        done: Vec<usize>,
+    }
+
+    impl Rows {
+        fn push(&mut self, plane: usize, y: usize, row: Vec<u16>) {
+            if self.planes.len() <= plane {
+                self.planes.resize(plane + 1, Vec::new());
+            }
+            assert!(!self.done.contains(&plane));
+            assert_eq!(self.planes[plane].len(), y, "plane {}", plane);
+            self.planes[plane].push(row);
+        }
+    }
+
+    impl OutputSink for Rows {
+        fn write_row(
+            &mut self,
+            plane: usize,
+            y: usize,
+            row: &[u8],
+        ) -> Result<()> {
+            self.push(plane, y, row.iter().map(|&s| s as u16).collect());
+            Ok(())
+        }
+
+        fn write_row16(
+            &mut self,
+            plane: usize,
+            y: usize,
+            row: &[u16],
+        ) -> Result<()> {
+            self.push(plane, y, row.to_vec());
+            Ok(())
+        }
+
+        fn plane_done(&mut self, plane: usize) -> Result<()> {
+            assert!(!self.done.contains(&plane));
+            self.done.push(plane);
+            Ok(())
+        }
+    }
+
+    let inputs = [
+        ("data/ffv1_v3.mkv", 3),
+        ("data/ffv1_v3_gbrp16le.mkv", 1),
+        ("data/ffv1_v3_yuv420p_33x17.mkv", 1),
+        ("data/ffv1_v3_yuv411p_35x19.mkv", 1),
+    ];
+    for &(input, frames) in &inputs {
+        let (mut demuxer, mut ffv1_decoder) = open(input);
+        let (_, mut sink_decoder) = open(input);
+        for _ in 0..frames {
+            let packet = read_packet(&mut demuxer);
+            let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+            let mut rows = Rows::default();
+            sink_decoder.decode_frame_to_sink(&packet, &mut rows).unwrap();
+
+            assert_eq!(rows.planes.len(), frame.num_planes(), "{}", input);
+            rows.done.sort_unstable();
+            assert_eq!(rows.done, (0..frame.num_planes()).collect::<Vec<_>>());
+            for (plane, received) in rows.planes.iter().enumerate() {
+                let expected: Vec<Vec<u16>> = match frame.plane(plane) {
+                    Some(view) => view
+                        .rows()
+                        .map(|row| row.iter().map(|&s| s as u16).collect())
+                        .collect(),
+                    None => frame
+                        .plane16(plane)
+                        .unwrap()
+                        .rows()
+                        .map(|row| row.to_vec())
+                        .collect(),
+                };
+                assert!(*received == expected, "{} plane {}", input, plane);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_conformance() {
+    use ffv1::conformance::{ConformanceChecker, Rule};
+
+    let inputs = [
+        ("data/ffv1_v3.mkv", 13),
+        ("data/ffv1_v3_gbrp16le.mkv", 1),
+        ("data/ffv1_v3_yuv420p_33x17.mkv", 1),
+    ];
+    for &(input, frames) in &inputs {
+        let (mut demuxer, params) = open_params(input);
+        let mut checker = ConformanceChecker::new(
+            &params.extradata,
+            params.width,
+            params.height,
+        );
+        for _ in 0..frames {
+            checker.check_packet(&read_packet(&mut demuxer));
+        }
+        let report = checker.finish();
+        assert!(report.is_clean(), "{}:\n{}", input, report);
+        assert_eq!(report.frames, frames);
+        assert!(report.result(Rule::SliceTiling).passed());
+        assert!(report.result(Rule::FirstKeyframe).passed());
+    }
+
+    // A damaged slice fails its CRC, and a damaged record fails both its
+    // CRC and the whole stream
+    let (mut demuxer, params) = open_params("data/ffv1_v3_gbrp16le.mkv");
+    let mut packet = read_packet(&mut demuxer);
+    let (_, decoder) = open("data/ffv1_v3_gbrp16le.mkv");
+    let slice = decoder.slice_layout(&packet).unwrap()[0].range.clone();
+    packet[slice.end - 1] ^= 0x55;
+    let mut checker = ConformanceChecker::new(
+        &params.extradata,
+        params.width,
+        params.height,
+    );
+    checker.check_packet(&packet);
+    let report = checker.finish();
+    assert!(!report.conforms());
+    let crc = report.result(Rule::SliceCrc);
+    assert_eq!(crc.failures, 1);
+    assert!(crc.first_failure.as_ref().unwrap().contains("slice 0"));
+    assert!(report.to_string().contains("FAIL MUST   slice-crc"));
+
+    let mut record = params.extradata.clone();
+    record[4] ^= 0x01;
+    let report =
+        ConformanceChecker::new(&record, params.width, params.height).finish();
+    assert!(!report.conforms());
+    assert_eq!(report.result(Rule::RecordCrc).failures, 1);
+    assert!(report.result(Rule::SliceCrc).not_applicable());
+}
+
+#[test]
+fn test_feature_usage() {
+    use ffv1::usage::FeatureScanner;
+
+    let scan = |input: &str, frames: usize| {
+        let (mut demuxer, params) = open_params(input);
+        let mut scanner = FeatureScanner::new(
+            &params.extradata,
+            params.width,
+            params.height,
+        )
+        .unwrap();
+        for _ in 0..frames {
+            scanner.scan_packet(&read_packet(&mut demuxer));
+        }
+        scanner.finish()
+    };
+
+    let usage = scan("data/ffv1_v3.mkv", 13);
+    assert_eq!((usage.version, usage.coder_type), ((3, 4), 0));
+    assert_eq!(usage.chroma_subsampling.as_deref(), Some("4:2:0"));
+    assert_eq!((usage.ec, usage.intra), (1, 0));
+    assert_eq!((usage.frames, usage.keyframes), (13, 2));
+    assert_eq!(usage.invalid_frames, 0);
+    assert_eq!(usage.sars, vec![(1, 1)]);
+    assert!(!usage.custom_state_transition && !usage.states_coded);
+
+    let usage = scan("data/ffv1_v3_gbrp16le.mkv", 1);
+    assert!(usage.custom_state_transition);
+    assert_eq!(usage.bits_per_raw_sample, 16);
+    assert_eq!(usage.chroma_subsampling, None);
+
+    let usage = scan("data/ffv1_v3_yuv420p_states.mkv", 1);
+    assert!(usage.states_coded && usage.intra == 1);
+    let json = usage.to_json();
+    assert!(json.starts_with("{\"version\":\"3.4\",\"coder_type\":1,"));
+    assert!(json.ends_with("\"invalid_frames\":0,\"sars\":[[1,1]]}"));
+}
+
+#[test]
+fn test_damage_map() {
+    use ffv1::damage::DamageCause;
+    use ffv1::repair::seal_slice;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_gbrp16le.mkv");
+    let packet = read_packet(&mut demuxer);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    assert!(layout.len() > 1);
+    assert!(ffv1_decoder.damage_map().is_none());
+    ffv1_decoder.set_damage_map_enabled(true);
+
+    // Frame 0 is intact, frame 1 has a damaged slice and frame 2 has a
+    // slice marked as damaged by the encoder
+    ffv1_decoder.decode_frame(&packet).unwrap();
+    let mut damaged = packet.clone();
+    let slice = &layout[1];
+    damaged[slice.range.start + 4] ^= 0x55;
+    assert!(ffv1_decoder.decode_frame(&damaged).is_err());
+    let mut marked = packet.clone();
+    seal_slice(&mut marked[slice.range.start..slice.footer.end], 1).unwrap();
+    assert!(ffv1_decoder.decode_frame(&marked).is_err());
+
+    let map = ffv1_decoder.damage_map().unwrap();
+    assert_eq!(map.frames, 3);
+    assert_eq!(map.damaged_frames(), vec![1, 2]);
+    assert_eq!(map.damages.len(), 2);
+    for damage in &map.damages {
+        assert_eq!(damage.slice, Some(1));
+        assert_eq!(damage.rect, slice.rect);
+        assert_eq!(damage.bytes, slice.range.start..slice.footer.end);
+    }
+    assert_eq!(map.damages[0].cause, DamageCause::CrcMismatch);
+    assert_eq!(map.damages[1].cause, DamageCause::ErrorStatus(1));
+
+    let json = map.to_json();
+    assert!(json.starts_with("{\"frames\":3,\"damages\":[{\"frame\":1,"));
+    assert!(json.contains("\"cause\":\"CRC mismatch\""), "{}", json);
+}
+
+#[test]
+fn test_resync() {
+    use ffv1::damage::DamageCause;
+    use ffv1::diagnostics::Diagnostic;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    let layout = ffv1_decoder.slice_layout(&packet).unwrap();
+    let intact = ffv1_decoder.decode_frame(&packet).unwrap();
+    let mut damaged = packet.clone();
+    damaged[layout[1].range.start + 10] ^= 0xFF;
+    assert!(ffv1_decoder.decode_frame(&damaged).is_err());
+    assert!(!ffv1_decoder.resync_enabled());
+
+    ffv1_decoder.set_resync_enabled(true);
+    ffv1_decoder.set_damage_map_enabled(true);
+    let stride = intact.strides()[0];
+    for &threads in &[1, 2] {
+        ffv1_decoder.set_threads(threads);
+        let frame = ffv1_decoder.decode_frame(&damaged).unwrap();
+        match frame.diagnostics() {
+            [Diagnostic::SliceAbandoned { slice: 1, .. }] => {}
+            diagnostics => panic!("{:?}", diagnostics),
+        }
+
+        // The luma of the other slices is intact
+        for slice in layout.iter().enumerate().filter(|&(i, _)| i != 1) {
+            let (x, y, width, height) = slice.1.rect;
+            let (x, width) = (x as usize, width as usize);
+            for row in y as usize..(y + height) as usize {
+                let start = row * stride + x;
+                let (got, expected) = (&frame.buf()[0], &intact.buf()[0]);
+                assert_eq!(got[start..][..width], expected[start..][..width]);
+            }
+        }
+    }
+
+    let map = ffv1_decoder.damage_map().unwrap();
+    assert_eq!(map.damaged_frames(), vec![0, 1]);
+    for damage in &map.damages {
+        assert_eq!(damage.slice, Some(1));
+        assert_eq!(damage.cause, DamageCause::CrcMismatch);
+    }
+
+    // The next frames are decoded as usual
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert!(frame.diagnostics().is_empty());
+    assert_eq!(frame.buf(), intact.buf());
+}
+
+#[test]
+fn test_pixel_stats() {
+    use ffv1::sink::OutputSink;
+
+    // Discards the rows
+    struct Discard;
+
+    impl OutputSink for Discard {
+        fn write_row(
+            &mut self,
+            _: usize,
+            _: usize,
+            _: &[u8],
+        ) -> ffv1::error::Result<()> {
+            Ok(())
+        }
+
+        fn write_row16(
+            &mut self,
+            _: usize,
+            _: usize,
+            _: &[u16],
+        ) -> ffv1::error::Result<()> {
+            Ok(())
+        }
+    }
+
+    let inputs = [
+        "data/ffv1_v3.mkv",
+        "data/ffv1_v3_gbrp12le.mkv",
+        "data/ffv1_v3_bgr0.mkv",
+        "data/ffv1_v3_yuv420p_33x17.mkv",
+    ];
+    for input in &inputs {
+        let (mut demuxer, mut ffv1_decoder) = open(input);
+        let (_, mut sink_decoder) = open(input);
+        ffv1_decoder.set_pixel_stats_enabled(true);
+        sink_decoder.set_pixel_stats_enabled(true);
+        let packet = read_packet(&mut demuxer);
+        let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+        sink_decoder.decode_frame_to_sink(&packet, &mut Discard).unwrap();
+
+        let stats = ffv1_decoder.pixel_stats().unwrap();
+        assert_eq!(stats, sink_decoder.pixel_stats().unwrap());
+        assert_eq!(stats.len(), frame.num_planes());
+        for (plane, stats) in stats.iter().enumerate() {
+            let samples: Vec<u16> = match frame.plane(plane) {
+                Some(view) => view
+                    .rows()
+                    .flat_map(|row| row.iter().map(|&s| s as u16))
+                    .collect(),
+                None => frame
+                    .plane16(plane)
+                    .unwrap()
+                    .rows()
+                    .flat_map(|row| row.iter().copied())
+                    .collect(),
+            };
+            let sum: u64 = samples.iter().map(|&s| s as u64).sum();
+            assert_eq!(stats.samples, samples.len() as u64);
+            assert_eq!(stats.min, *samples.iter().min().unwrap());
+            assert_eq!(stats.max, *samples.iter().max().unwrap());
+            let mean = sum as f64 / stats.samples as f64;
+            assert!((stats.mean() - mean).abs() < 1e-9);
+            assert_eq!(stats.histogram.len(), 1 << frame.bit_depth());
+            assert_eq!(stats.histogram.iter().sum::<u64>(), stats.samples);
+            let max = samples.iter().filter(|&&s| s == stats.max).count();
+            assert_eq!(stats.histogram[stats.max as usize], max as u64);
+        }
+    }
+
+    let (_, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+    assert!(ffv1_decoder.pixel_stats().is_none());
+    ffv1_decoder.set_pixel_stats_enabled(true);
+    assert_eq!(ffv1_decoder.pixel_stats().unwrap()[0].samples, 0);
+}
+
+#[test]
+fn test_coding_stats() {
+    use ffv1::stats::CodingStats;
+
+    for input in &["data/ffv1_v3.mkv", "data/ffv1_v3_gbrp12le.mkv"] {
+        let (mut demuxer, mut ffv1_decoder) = open(input);
+        let (_, mut threaded_decoder) = open(input);
+        ffv1_decoder.set_threads(1);
+        ffv1_decoder.set_coding_stats_enabled(true);
+        threaded_decoder.set_threads(4);
+        threaded_decoder.set_coding_stats_enabled(true);
+        let packet = read_packet(&mut demuxer);
+        let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+        threaded_decoder.decode_frame(&packet).unwrap();
+
+        // Slices are reported in coding order whatever the threads
+        let slices = ffv1_decoder.coding_stats().unwrap();
+        assert_eq!(slices, threaded_decoder.coding_stats().unwrap());
+        assert!(slices.len() > 1);
+
+        // Every sample is coded by a symbol
+        let mut total = CodingStats::default();
+        slices.iter().for_each(|stats| total.add(stats));
+        let samples: u64 = (0..frame.num_planes())
+            .map(|plane| {
+                let (width, height) = frame.plane_dimensions(plane);
+                (width * height) as u64
+            })
+            .sum();
+        assert_eq!(total.symbols, samples);
+        let contexts: u64 = total.contexts.iter().flatten().sum();
+        assert_eq!(contexts, samples);
+        assert_eq!(total.magnitudes.iter().sum::<u64>(), samples);
+        assert!(total.used_contexts(0) > 1);
+        let (runs, mean) = total.run_lengths();
+        assert!(runs > 0 && mean >= 1.0);
+    }
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3.mkv");
+    ffv1_decoder.decode_frame(read_packet(&mut demuxer)).unwrap();
+    assert!(ffv1_decoder.coding_stats().is_none());
+}
+
+#[test]
+fn test_record_to_bytes() {
+    use ffv1::record::ConfigRecord;
+
+    // The record codes back to the bytes FFmpeg wrote in the fixture
+    let (_, params) = open_params("data/ffv1_v3.mkv");
+    let record = &params.extradata;
+    let parsed = ConfigRecord::parse_config_record(record, 640, 360).unwrap();
+    assert_eq!(&parsed.to_bytes(), record);
+}
+
+// Fixtures whose mutations are decoded by test_mutated_fixtures
+const MUTATED_FIXTURES: &[&str] = &[
+    "data/ffv1_v3_gray.mkv",
+    "data/ffv1_v3_gray16le.mkv",
+    "data/ffv1_v3_yuv411p_35x19.mkv",
+    "data/ffv1_v3_yuv420p_33x17.mkv",
+    "data/ffv1_v3_yuv420p_states.mkv",
+    "data/ffv1_v3_yuv420p10le.mkv",
+    "data/ffv1_v3_yuv422p12le.mkv",
+    "data/ffv1_v3_yuv444p14le.mkv",
+    "data/ffv1_v3_gbrp12le.mkv",
+];
+
+// Deterministic mutations of packets and configuration records
+struct Mutator(u64);
+
+impl Mutator {
+    // Returns the next value of a xorshift generator
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+
+    // Returns a copy of 'data' truncated or with a few bytes overwritten
+    fn mutate(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        let pos = self.next() % data.len().max(1);
+        match self.next() % 4 {
+            0 => data.truncate(pos),
+            1 => {
+                for _ in 0..1 + self.next() % 4 {
+                    let pos = self.next() % data.len().max(1);
+                    if let Some(byte) = data.get_mut(pos) {
+                        *byte = self.next() as u8;
+                    }
+                }
+            }
+            2 => {
+                if let Some(byte) = data.get_mut(pos) {
+                    *byte ^= 1 << (self.next() % 8);
+                }
+            }
+            _ => {
+                let fill = if self.next() & 1 == 0 { 0x00 } else { 0xFF };
+                let end = (pos + 1 + self.next() % 16).min(data.len());
+                data[pos.min(end)..end].iter_mut().for_each(|b| *b = fill);
+            }
+        }
+        data
+    }
+}
+
+#[test]
+fn test_mutated_fixtures() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use ffv1::crc32mpeg2::crc32_mpeg2;
+    use ffv1::repair::{repair_packet, RepairOptions};
+
+    let options = RepairOptions {
+        clear_error_status: true,
+        recompute_crc: true,
+    };
+    let mut mutator = Mutator(0x9E37_79B9_7F4A_7C15);
+    for input in MUTATED_FIXTURES {
+        let (mut demuxer, params) = open_params(input);
+        let mut packets = Vec::new();
+        while let Some(packet) = next_packet(&mut demuxer) {
+            packets.push(packet);
+        }
+        let (width, height) = (params.width, params.height);
+
+        // Damaged records and packets fail without panicking
+        for i in 0..300 {
+            let mut record = params.extradata.clone();
+            let mut packets = packets.clone();
+            match i % (packets.len() + 1) {
+                // Mostly reach the contents past their CRCs
+                0 => {
+                    record = mutator.mutate(&record);
+                    let len = record.len();
+                    if len >= 4 && mutator.next() & 3 != 0 {
+                        let parity = crc32_mpeg2(&record[..len - 4]);
+                        record[len - 4..]
+                            .copy_from_slice(&parity.to_le_bytes());
+                    }
+                }
+                n => {
+                    let packet = &mut packets[n - 1];
+                    *packet = mutator.mutate(packet);
+                    if mutator.next() & 3 != 0 {
+                        let _ = repair_packet(packet, &options);
+                    }
+                }
+            }
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                if let Ok(mut decoder) = Decoder::new(&record, width, height)
+                {
+                    for packet in &packets {
+                        let _ = decoder.decode_frame(packet);
+                    }
+                }
+            }));
+            assert!(
+                result.is_ok(),
+                "{}: record {:02x?}, packets {:02x?}",
+                input,
+                record,
+                packets
+            );
+        }
+    }
+
+    // Packet too short for the range coder to start
+    let (_, mut ffv1_decoder) = open("data/ffv1_v3_gray.mkv");
+    assert!(ffv1_decoder.decode_frame(&[0xFF][..]).is_err());
+}
+
+#[cfg(feature = "test-vectors")]
+#[test]
+fn test_mutated_quant_table_sets() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use ffv1::quant_presets::ffmpeg_8bit;
+    use ffv1::record::ConfigRecordBuilder;
+    use ffv1::test_vectors::custom;
+
+    // YCbCr with alpha, with 'count' sets cycling through 'sets'
+    let [small, large] = ffmpeg_8bit();
+    let builder = |coder_type, sets: &[_], count| {
+        let sets = sets.iter().cycle().take(count).copied().collect();
+        ConfigRecordBuilder::new(16, 16)
+            .coder_type(coder_type)
+            .extra_plane(true)
+            .quant_tables(sets)
+            .slices(2, 2)
+            .intra(1)
+    };
+    for &coder_type in &[0, 1] {
+        for count in 1..=3 {
+            // Every plane slot uses the set of the context model
+            for context_model in 0..count {
+                let record = builder(coder_type, &[small, large], count);
+                let vector = custom(record, context_model);
+                let name = format!(
+                    "coder_type {} sets {} context_model {}",
+                    coder_type, count, context_model
+                );
+                let mut ffv1_decoder =
+                    Decoder::new(&vector.record, vector.width, vector.height)
+                        .unwrap();
+                let frame = ffv1_decoder
+                    .decode_frame(&vector.packet)
+                    .unwrap_or_else(|e| panic!("{}: {}", name, e));
+                for plane in 0..vector.planes.len() {
+                    assert_eq!(
+                        frame.plane_md5(plane),
+                        vector.plane_md5(plane),
+                        "{} plane {}",
+                        name,
+                        plane
+                    );
+                }
+
+                // Records with other sets decode other samples, or fail
+                // when they lack the set of the slices, without panicking
+                for other in 1..=3 {
+                    let record = builder(coder_type, &[large, small], other)
+                        .build()
+                        .unwrap()
+                        .to_bytes();
+                    let decoded = catch_unwind(AssertUnwindSafe(|| {
+                        let mut decoder =
+                            Decoder::new(&record, vector.width, vector.height)
+                                .unwrap();
+                        decoder.decode_frame(&vector.packet).is_ok()
+                    }))
+                    .unwrap_or_else(|_| {
+                        panic!("{} panicked with {} sets", name, other)
+                    });
+                    assert!(!decoded || context_model < other, "{}", name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "test-vectors")]
+#[test]
+fn test_test_vectors() {
+    use ffv1::test_vectors::test_vectors;
+
+    for vector in test_vectors() {
+        let name = vector.name;
+        let mut ffv1_decoder =
+            Decoder::new(&vector.record, vector.width, vector.height)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+        let frame = ffv1_decoder
+            .decode_frame(&vector.packet)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+        assert_eq!(frame.num_planes(), vector.planes.len(), "{}", name);
+        for (plane, expected) in vector.planes.iter().enumerate() {
+            let decoded: Vec<u16> = frame
+                .plane(plane)
+                .unwrap()
+                .rows()
+                .flat_map(|row| row.iter().map(|&s| s as u16))
+                .collect();
+            assert!(decoded == *expected, "{} plane {}", name, plane);
+            assert_eq!(frame.plane_md5(plane), vector.plane_md5(plane));
+        }
+    }
+}
+
+#[test]
+fn test_decode_frame_with_deadline() {
+    use std::time::{Duration, Instant};
+
+    use ffv1::diagnostics::Diagnostic;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    let slices = ffv1_decoder.slice_layout(&packet).unwrap().len();
+    let intact = ffv1_decoder.decode_frame(&packet).unwrap();
+
+    // Frames decoded before their deadline are complete
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let frame = ffv1_decoder
+        .decode_frame_with_deadline(&packet, deadline)
+        .unwrap();
+    assert!(!frame.is_partial());
+    assert_eq!(frame.buf(), intact.buf());
+
+    // Slices left once the deadline has passed are concealed
+    for &threads in &[1, 2] {
+        ffv1_decoder.set_threads(threads);
+        let frame = ffv1_decoder
+            .decode_frame_with_deadline(&packet, Instant::now())
+            .unwrap();
+        assert!(frame.is_partial());
+        assert_eq!(frame.diagnostics().len(), slices);
+        for (i, diagnostic) in frame.diagnostics().iter().enumerate() {
+            assert_eq!(*diagnostic, Diagnostic::SliceConcealed { slice: i });
+        }
+        for plane in frame.buf() {
+            assert!(plane.iter().all(|&sample| sample == 128));
+        }
+    }
+
+    // The next frames are decoded as usual
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert!(!frame.is_partial());
+    assert_eq!(frame.buf(), intact.buf());
+}
+
+#[test]
+fn test_slice_budget() {
+    use ffv1::budget::SliceBudget;
+    use ffv1::error::ErrorCategory;
+
+    let (mut demuxer, mut ffv1_decoder) = open("data/ffv1_v3_yuv420p.mkv");
+    let packet = read_packet(&mut demuxer);
+    let intact = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!(ffv1_decoder.slice_budget(), SliceBudget::default());
+
+    // Intact slices never read past their end
+    let budget = SliceBudget {
+        max_symbols: None,
+        max_bits_per_byte: Some(8),
+    };
+    ffv1_decoder.set_slice_budget(budget);
+    let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+    assert_eq!(frame.buf(), intact.buf());
+
+    for &threads in &[1, 2] {
+        ffv1_decoder.set_threads(threads);
+        ffv1_decoder.set_slice_budget(SliceBudget::new(16, 8));
+        let err = ffv1_decoder.decode_frame(&packet).unwrap_err();
+        assert!(err.to_string().contains("symbols"), "{}", err);
+        assert_eq!(err.category(), ErrorCategory::Resource);
+        assert_eq!(err.code(), 7);
+
+        let budget = SliceBudget {
+            max_symbols: None,
+            max_bits_per_byte: Some(1),
+        };
+        ffv1_decoder.set_slice_budget(budget);
+        let err = ffv1_decoder.decode_frame(&packet).unwrap_err();
+        assert!(err.to_string().contains("bits"), "{}", err);
+        assert_eq!(err.code(), 7);
+    }
+}
@@ -0,0 +1,61 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use ffv1::allocator::Capacity;
+use ffv1::error::ErrorCategory;
+
+mod common;
+
+use common::{open, read_packet};
+
+// Counts the allocations of every thread
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_fixed_capacity() {
+    for &(input, frames) in
+        &[("data/ffv1_v3.mkv", 3), ("data/ffv1_v3_bgr0.mkv", 1)]
+    {
+        let (mut demuxer, mut ffv1_decoder) = open(input);
+        let (_, mut fixed_decoder) = open(input);
+        assert!(fixed_decoder.capacity().is_none());
+        assert!(fixed_decoder.decode_frame_fixed(&[0][..]).is_err());
+
+        // Streams larger than the capacity are rejected
+        let config = ffv1_decoder.config_record();
+        let (width, height) = (config.width, config.height);
+        let capacity = Capacity::new(width, height, 8, 64);
+        let small = Capacity::new(width - 1, height, 8, 64);
+        let err = fixed_decoder.set_capacity(small).unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Resource);
+        fixed_decoder.set_capacity(capacity).unwrap();
+        assert_eq!(fixed_decoder.capacity(), Some(capacity));
+
+        for _ in 0..frames {
+            let packet = read_packet(&mut demuxer);
+            let frame = ffv1_decoder.decode_frame(&packet).unwrap();
+            let before = ALLOCATIONS.with(Cell::get);
+            let fixed = fixed_decoder.decode_frame_fixed(&packet).unwrap();
+            assert_eq!(ALLOCATIONS.with(Cell::get), before, "{}", input);
+            assert_eq!(fixed.frame_md5(), frame.frame_md5(), "{}", input);
+        }
+    }
+}